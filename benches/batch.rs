@@ -0,0 +1,96 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use pr21::compiler::compile;
+use pr21::define_io_ctx;
+use pr21::io_ctx::IOContext;
+use pr21::r25_300::batch::BatchCombustor;
+use pr21::r25_300::compiled::Compiled;
+use pr21::r25_300::coroutine::{Coroutine, CoroutineStatus};
+
+define_io_ctx!(
+    #[derive(Clone)]
+    struct AnimCtx {
+        g_frame_id => frame_id: i32,
+        g_rotation_left_3 => rotation_left_3: f32
+    }
+);
+
+const EASED: &str = r#"
+    void entry() {
+        while (g_frame_id <= 30) {
+            float t = float(g_frame_id) / 30.0;
+            if (t < 0.5) {
+                g_rotation_left_3 = ease_in_quad(t * 2.0) * 45.0;
+            } else {
+                g_rotation_left_3 = 45.0 + smoothstep(0.5, 1.0, t) * 45.0;
+            }
+            yield;
+        }
+    }
+"#;
+
+const ENTITIES: usize = 5000;
+const FRAMES: i32 = 31;
+
+fn contexts() -> Vec<AnimCtx> {
+    (0..ENTITIES).map(|_| AnimCtx { frame_id: 0, rotation_left_3: 0.0 }).collect()
+}
+
+fn run_scalar(compiled: &Compiled, entry: usize) -> f32 {
+    let mut ctxs = contexts();
+    let mut coroutines = ctxs.iter_mut()
+        .map(|ctx| unsafe { Coroutine::new(compiled, entry, ctx) })
+        .collect::<Vec<_>>();
+    for frame in 0..=FRAMES {
+        for coroutine in coroutines.iter_mut() {
+            coroutine.io_ctx().frame_id = frame;
+            if coroutine.status() != CoroutineStatus::Finished {
+                coroutine.resume().unwrap();
+            }
+        }
+    }
+    drop(coroutines);
+    ctxs.iter().map(|ctx| ctx.rotation_left_3).sum()
+}
+
+fn run_batched(compiled: &Compiled, entry: usize) -> f32 {
+    let mut ctxs = contexts();
+    let mut batch = BatchCombustor::new();
+    let mut suspended = unsafe { batch.combust(compiled, entry, &mut ctxs) };
+    for frame in 1..=FRAMES {
+        if suspended == 0 {
+            break;
+        }
+        for ctx in ctxs.iter_mut() {
+            ctx.frame_id = frame;
+        }
+        suspended = unsafe { batch.combust_resume(compiled, &mut ctxs) };
+    }
+    ctxs.iter().map(|ctx| ctx.rotation_left_3).sum()
+}
+
+fn bench(name: &str, mut f: impl FnMut() -> f32) -> Duration {
+    black_box(f());
+    let iterations = 20;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(f());
+    }
+    let elapsed = start.elapsed() / iterations;
+    println!("{:<24} {:>10.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+    elapsed
+}
+
+fn main() {
+    for (name, source) in [("anim.bis", include_str!("../example/anim.bis")), ("eased", EASED)] {
+        let compiled = compile(source, AnimCtx::metadata()).unwrap();
+        let entry = compiled.find_func("entry").unwrap();
+        assert_eq!(run_scalar(&compiled, entry), run_batched(&compiled, entry));
+
+        println!("{} ({} entities, {} frames)", name, ENTITIES, FRAMES);
+        let scalar = bench("  scalar Combustor", || run_scalar(&compiled, entry));
+        let batched = bench("  BatchCombustor", || run_batched(&compiled, entry));
+        println!("  speedup {:.2}x", scalar.as_secs_f64() / batched.as_secs_f64());
+    }
+}
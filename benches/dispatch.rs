@@ -0,0 +1,126 @@
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use pr21::compiler::compile;
+use pr21::define_io_ctx;
+use pr21::io_ctx::IOContext;
+use pr21::r25_300::compact::CompactInsc;
+use pr21::r25_300::compiled::Compiled;
+use pr21::r25_300::cumbustor::{Combustor, Engine, EngineCode};
+use pr21::r25_300::decoded::DecodedInsc;
+use pr21::r25_300::insc::Insc;
+
+define_io_ctx!(
+    struct AnimCtx {
+        g_frame_id => frame_id: i32,
+        g_rotation_left_3 => rotation_left_3: f32
+    }
+);
+
+// samples a curve many times per frame, so that the time goes to dispatch rather than resumes
+const KEYFRAMES: &str = r#"
+    float ease(float t) {
+        if (t < 0.5) {
+            return 2.0 * t * t;
+        }
+        float u = 1.0 - t;
+        return 1.0 - 2.0 * u * u;
+    }
+
+    void entry() {
+        while (g_frame_id <= 30) {
+            float acc = 0.0;
+            int i;
+            for (i = 0; i < 200; i = i + 1) {
+                int k = i + g_frame_id;
+                float t = float(k) / 230.0;
+                acc = acc + ease(t) * 45.0;
+            }
+            g_rotation_left_3 = acc / 200.0;
+            yield;
+        }
+    }
+"#;
+
+// the same with `ease` inlined, so that nearly every instruction is arithmetic
+const INLINED: &str = r#"
+    void entry() {
+        while (g_frame_id <= 30) {
+            float acc = 0.0;
+            int i;
+            for (i = 0; i < 200; i = i + 1) {
+                int k = i + g_frame_id;
+                float t = float(k) / 230.0;
+                float eased = 2.0 * t * t;
+                if (t >= 0.5) {
+                    float u = 1.0 - t;
+                    eased = 1.0 - 2.0 * u * u;
+                }
+                acc = acc + eased * 45.0;
+            }
+            g_rotation_left_3 = acc / 200.0;
+            yield;
+        }
+    }
+"#;
+
+const ENTITIES: usize = 2000;
+const FRAMES: i32 = 31;
+
+fn run(compiled: &Compiled, entry: usize, engine: Engine) -> f32 {
+    // lowered once and shared by all entities, as by `Scheduler`
+    let code = EngineCode::new(compiled, engine);
+    let mut sum = 0.0;
+    for _ in 0..ENTITIES {
+        let mut ctx = AnimCtx { frame_id: 0, rotation_left_3: 0.0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        combustor.set_engine_code(code.clone());
+        let mut resume_ptr = unsafe { combustor.combust(compiled, entry) }.unwrap();
+        for frame in 1..=FRAMES {
+            let Some(insc_ptr) = resume_ptr else { break };
+            combustor.io_ctx.frame_id = frame;
+            resume_ptr = unsafe { combustor.combust_resume(compiled, insc_ptr) }.unwrap();
+        }
+        sum += combustor.io_ctx.rotation_left_3;
+    }
+    sum
+}
+
+// the fastest of several runs, which is the least disturbed by other processes
+fn bench(name: &str, mut f: impl FnMut() -> f32) -> Duration {
+    black_box(f());
+    let elapsed = (0..20)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    println!("{:<24} {:>10.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+    elapsed
+}
+
+fn main() {
+    println!(
+        "size_of::<Insc>() = {}, size_of::<CompactInsc>() = {}, size_of::<DecodedInsc>() = {}",
+        size_of::<Insc>(),
+        size_of::<CompactInsc>(),
+        size_of::<DecodedInsc>()
+    );
+    for (name, source) in [("anim.bis", include_str!("../example/anim.bis")), ("keyframes", KEYFRAMES), ("inlined", INLINED)] {
+        let compiled = compile(source, AnimCtx::metadata()).unwrap();
+        let entry = compiled.find_func("entry").unwrap();
+        let expected = run(&compiled, entry, Engine::Insc);
+        assert_eq!(run(&compiled, entry, Engine::Compact), expected);
+        assert_eq!(run(&compiled, entry, Engine::Decoded), expected);
+
+        println!("{} ({} entities, {} frames)", name, ENTITIES, FRAMES);
+        let insc = bench("  Insc", || run(&compiled, entry, Engine::Insc));
+        for engine in [Engine::Compact, Engine::Decoded] {
+            let elapsed = bench(&format!("  {:?}", engine), || run(&compiled, entry, engine));
+            println!("  speedup {:.2}x", insc.as_secs_f64() / elapsed.as_secs_f64());
+        }
+    }
+}
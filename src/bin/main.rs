@@ -1,3 +1,490 @@
+use std::fs::{read_to_string, write};
+use std::io::{stdin, BufRead};
+use std::process::exit;
+
+use pr21::compiler::{compile_with_warnings, CompileOptions};
+use pr21::io_ctx::{IOContext, IOContextMetadata, IOType, Type21};
+use pr21::native::NativeRegistry;
+use pr21::r25_300::asm::{disassemble, disassemble_with_source};
+use pr21::r25_300::compiled::Compiled;
+use pr21::r25_300::coroutine::{Coroutine, CoroutineStatus};
+use pr21::r25_300::debug::FrameInfo;
+use pr21::r25_300::debugger::{Debugger, PauseReason, StepMode};
+use pr21::r25_300::profile::Profiler;
+use pr21::value::RtValue;
+
+const USAGE: &str = "\
+用法: pr21 check [选项] <文件>
+      pr21 disasm [选项] <文件>
+      pr21 debug [选项] <文件>
+      pr21 run [选项] <文件>
+
+选项:
+  -W                     对可能损失精度的隐式类型转换给出警告
+  --io <名称:类型,...>    声明脚本可用的 IO 变量，类型为 int、float 或 bool
+  --source               反汇编时在指令之间穿插对应的源代码行
+  --entry <函数>          调试或运行的函数，默认为 entry
+  --frames <次数>         运行时最多恢复脚本的次数，默认运行到脚本结束
+  --profile              运行结束后打印各函数、各行执行的指令数和宿主函数的耗时
+  --folded <文件>         把各调用栈执行的指令数以火焰图工具的折叠格式写入文件
+
+调试命令从标准输入逐行读取:
+  break <行> | break *<指令>     设置断点          delete <行> | delete *<指令>  删除断点
+  watch <IO 变量>               设置监视点        unwatch <IO 变量>            删除监视点
+  run | continue | c            运行到下一次停下   step | s                     单步，进入调用
+  next | n                      单步，跳过调用     finish                       运行到当前函数返回
+  backtrace | bt                列出调用栈        locals [帧]                  列出局部变量
+  print | p <名称>              打印变量的值       set <IO 变量> <值>           修改 IO 变量
+  quit | q                      退出";
+
+// the IO variables of `--io` are scalars, so they fit in an array of values
+const MAX_IO: usize = 64;
+
+#[repr(C)]
+struct CliIO([RtValue; MAX_IO]);
+
+impl IOContext for CliIO {
+    fn metadata() -> IOContextMetadata {
+        Vec::new()
+    }
+}
+
+struct Args {
+    options: CompileOptions,
+    io_metadata: IOContextMetadata,
+    with_source: bool,
+    entry: String,
+    frames: Option<usize>,
+    profile: bool,
+    folded: Option<String>,
+    file: String
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2)
+}
+
+fn parse_io_spec(spec: &str) -> Result<IOContextMetadata, String> {
+    let mut io_metadata = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let Some((name, ty)) = item.split_once(':') else {
+            return Err(format!("无法解析 IO 变量声明 `{}`", item));
+        };
+
+        let ty = match ty.trim() {
+            "int" => Type21::Int32,
+            "float" => Type21::Float32,
+            "bool" => Type21::Bool,
+            ty => return Err(format!("IO 变量 `{}` 的类型 `{}` 无效", name, ty))
+        };
+        io_metadata.push((name.trim().to_string(), name.trim().to_string(), IOType::Scalar(ty)));
+    }
+
+    Ok(io_metadata)
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut options = CompileOptions::default();
+    let mut io_metadata = Vec::new();
+    let mut with_source = false;
+    let mut entry = "entry".to_string();
+    let mut frames = None;
+    let mut profile = false;
+    let mut folded = None;
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-W" => options.warn_lossy = true,
+            "--io" => {
+                let Some(spec) = args.next() else {
+                    return Err("`--io` 需要一个参数".into());
+                };
+                io_metadata.extend(parse_io_spec(spec)?);
+            },
+            "--source" => with_source = true,
+            "--entry" => {
+                let Some(name) = args.next() else {
+                    return Err("`--entry` 需要一个参数".into());
+                };
+                entry = name.clone();
+            },
+            "--frames" => {
+                let Some(count) = args.next() else {
+                    return Err("`--frames` 需要一个参数".into());
+                };
+                frames = Some(count.parse::<usize>().map_err(|_| format!("无效的次数 `{}`", count))?);
+            },
+            "--profile" => profile = true,
+            "--folded" => {
+                let Some(path) = args.next() else {
+                    return Err("`--folded` 需要一个参数".into());
+                };
+                folded = Some(path.clone());
+            },
+            arg if arg.starts_with('-') => return Err(format!("未知的选项 `{}`", arg)),
+            arg => if file.replace(arg.to_string()).is_some() {
+                return Err("只能指定一个源文件".into());
+            }
+        }
+    }
+
+    let Some(file) = file else {
+        return Err("没有指定源文件".into());
+    };
+    Ok(Args { options, io_metadata, with_source, entry, frames, profile, folded, file })
+}
+
+fn check(args: Args) -> Result<(), String> {
+    let source = read_to_string(&args.file).map_err(|e| format!("无法读取 `{}`: {}", args.file, e))?;
+    let (_, warnings) = compile_with_warnings(&source, args.io_metadata, &NativeRegistry::new(), args.options)
+        .map_err(|e| format!("{}: {}", args.file, e))?;
+    for warning in warnings {
+        eprintln!("{}: 警告: {}", args.file, warning);
+    }
+
+    Ok(())
+}
+
+fn disasm(args: Args) -> Result<(), String> {
+    let source = read_to_string(&args.file).map_err(|e| format!("无法读取 `{}`: {}", args.file, e))?;
+    let (compiled, warnings) =
+        compile_with_warnings(&source, args.io_metadata.clone(), &NativeRegistry::new(), args.options)
+            .map_err(|e| format!("{}: {}", args.file, e))?;
+    for warning in warnings {
+        eprintln!("{}: 警告: {}", args.file, warning);
+    }
+
+    if args.with_source {
+        print!("{}", disassemble_with_source(&compiled, &args.io_metadata, &source));
+    } else {
+        print!("{}", disassemble(&compiled, &args.io_metadata));
+    }
+    Ok(())
+}
+
+struct DebugSession<'a, 'ctx> {
+    compiled: &'a Compiled,
+    coroutine: Coroutine<'a, 'ctx, CliIO>,
+    source_lines: Vec<&'a str>,
+    // name, byte offset and type of each IO variable
+    io_vars: Vec<(String, usize, Type21)>
+}
+
+fn show_value(value: RtValue, ty: Type21) -> String {
+    unsafe {
+        match ty {
+            Type21::Int32 => value.i.to_string(),
+            Type21::Float32 => format!("{:?}", value.f),
+            Type21::Bool => value.b.to_string()
+        }
+    }
+}
+
+fn parse_value(s: &str, ty: Type21) -> Result<RtValue, String> {
+    let value = match ty {
+        Type21::Int32 => s.parse::<i32>().map(RtValue::from).ok(),
+        Type21::Float32 => s.parse::<f32>().map(RtValue::from).ok(),
+        Type21::Bool => s.parse::<bool>().map(RtValue::from).ok()
+    };
+    value.ok_or_else(|| format!("`{}` 不是 {} 类型的值", s, ty))
+}
+
+impl DebugSession<'_, '_> {
+    fn io_var(&self, name: &str) -> Result<(usize, Type21), String> {
+        self.io_vars.iter()
+            .find(|(var, ..)| var == name)
+            .map(|(_, offset, ty)| (*offset, *ty))
+            .ok_or_else(|| format!("没有名为 `{}` 的 IO 变量", name))
+    }
+
+    fn location(&self, insc_ptr: usize, func: &str, line: Option<usize>) -> String {
+        match line {
+            Some(line) => format!(
+                "{} 行 {}: {}",
+                func,
+                line,
+                self.source_lines.get(line - 1).map_or("", |line| line.trim())
+            ),
+            None => format!("{} 指令 {}", func, insc_ptr)
+        }
+    }
+
+    fn debugger(&mut self) -> &mut Debugger {
+        self.coroutine.debugger_mut().unwrap()
+    }
+
+    /// Runs one command, returning `false` if the session should end
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            [] => {},
+            ["break", target] => {
+                if let Some(addr) = target.strip_prefix('*') {
+                    let addr = addr.parse::<usize>().map_err(|_| format!("无效的指令地址 `{}`", addr))?;
+                    self.debugger().add_breakpoint(addr);
+                    println!("断点: 指令 {}", addr);
+                } else {
+                    let line = target.parse::<usize>().map_err(|_| format!("无效的行号 `{}`", target))?;
+                    let compiled = self.compiled;
+                    let addrs = self.debugger().add_line_breakpoint(compiled, line)?;
+                    let addrs = addrs.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                    println!("断点: 行 {} (指令 {})", line, addrs);
+                }
+            },
+            ["delete", target] => {
+                let removed = if let Some(addr) = target.strip_prefix('*') {
+                    let addr = addr.parse::<usize>().map_err(|_| format!("无效的指令地址 `{}`", addr))?;
+                    self.debugger().remove_breakpoint(addr)
+                } else {
+                    let line = target.parse::<usize>().map_err(|_| format!("无效的行号 `{}`", target))?;
+                    let compiled = self.compiled;
+                    !self.debugger().remove_line_breakpoint(compiled, line)?.is_empty()
+                };
+                if !removed {
+                    return Err(format!("`{}` 处没有断点", target));
+                }
+            },
+            ["watch", name] => {
+                let (offset, _) = self.io_var(name)?;
+                self.debugger().add_watch(offset);
+                println!("监视点: {}", name);
+            },
+            ["unwatch", name] => {
+                let (offset, _) = self.io_var(name)?;
+                if !self.debugger().remove_watch(offset) {
+                    return Err(format!("`{}` 没有被监视", name));
+                }
+            },
+            ["run" | "continue" | "c"] => self.resume()?,
+            ["step" | "s"] => self.step(StepMode::In)?,
+            ["next" | "n"] => self.step(StepMode::Over)?,
+            ["finish"] => self.step(StepMode::Out)?,
+            ["backtrace" | "bt"] => {
+                for (idx, frame) in self.coroutine.paused_frames().iter().rev().enumerate() {
+                    println!("#{} {}", idx, self.location(frame.insc_ptr, &frame.func, frame.line));
+                }
+            },
+            ["locals"] | ["locals", _] => {
+                let frame = match words.get(1) {
+                    Some(frame) => frame.parse::<usize>().map_err(|_| format!("无效的帧号 `{}`", frame))?,
+                    None => 0
+                };
+                let depth = self.coroutine.paused_frames().len();
+                if frame >= depth {
+                    return Err(format!("没有第 {} 帧", frame));
+                }
+                for (local, value) in self.coroutine.paused_locals(depth - 1 - frame) {
+                    println!("{}: {} = {}", local.name, local.ty, show_value(value, local.ty));
+                }
+            },
+            ["print" | "p", name] => {
+                let depth = self.coroutine.paused_frames().len();
+                let local = depth.checked_sub(1)
+                    .and_then(|frame| self.coroutine.paused_locals(frame).into_iter().find(|(local, _)| local.name == *name));
+                if let Some((local, value)) = local {
+                    println!("{} = {}", name, show_value(value, local.ty));
+                } else {
+                    let (offset, ty) = self.io_var(name)?;
+                    println!("{} = {}", name, show_value(self.coroutine.io_ctx().0[offset / 4], ty));
+                }
+            },
+            ["set", name, value] => {
+                let (offset, ty) = self.io_var(name)?;
+                self.coroutine.io_ctx().0[offset / 4] = parse_value(value, ty)?;
+            },
+            ["quit" | "q"] => return Ok(false),
+            _ => return Err(format!("无法识别的命令 `{}`", line.trim()))
+        }
+        Ok(true)
+    }
+
+    fn step(&mut self, mode: StepMode) -> Result<(), String> {
+        let compiled = self.compiled;
+        self.debugger().step(compiled, mode)?;
+        self.resume()
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        match self.coroutine.status() {
+            CoroutineStatus::Finished | CoroutineStatus::Faulted => return Err("脚本已经结束".to_string()),
+            CoroutineStatus::NotStarted | CoroutineStatus::Suspended => {}
+        }
+
+        match self.coroutine.resume() {
+            Ok(CoroutineStatus::Suspended) => {
+                let Some(pause) = self.coroutine.debugger().unwrap().pause().copied() else {
+                    println!("脚本 yield");
+                    return Ok(());
+                };
+
+                let frame = self.coroutine.paused_frames().pop().unwrap();
+                let mut location = self.location(frame.insc_ptr, &frame.func, frame.line);
+                if let Some(task) = pause.task {
+                    location = format!("任务 {} 的 {}", task, location);
+                }
+                match pause.reason {
+                    PauseReason::Breakpoint => println!("断点: {}", location),
+                    PauseReason::Step => println!("{}", location),
+                    PauseReason::Watch { offset, old, new, insc_ptr } => {
+                        // reported where the value was written rather than where the script stopped
+                        let frame = FrameInfo::at(self.compiled, insc_ptr);
+                        let location = self.location(insc_ptr, &frame.func, frame.line);
+                        let (name, _, ty) = self.io_vars.iter().find(|(_, var, _)| *var == offset).unwrap();
+                        println!("监视点 {}: {} -> {}, {}", name, show_value(old, *ty), show_value(new, *ty), location);
+                    }
+                }
+            },
+            Ok(_) => println!("脚本已结束"),
+            Err(e) => println!("运行错误: {:#}", e)
+        }
+        Ok(())
+    }
+}
+
+// compiles and links the file for running it from the entry function, returning the source too
+fn load(args: &Args) -> Result<(String, Compiled, usize), String> {
+    let source = read_to_string(&args.file).map_err(|e| format!("无法读取 `{}`: {}", args.file, e))?;
+    let natives = NativeRegistry::new();
+    let (mut compiled, warnings) = compile_with_warnings(&source, args.io_metadata.clone(), &natives, args.options)
+        .map_err(|e| format!("{}: {}", args.file, e))?;
+    for warning in warnings {
+        eprintln!("{}: 警告: {}", args.file, warning);
+    }
+    compiled.link(&natives)?;
+    if let Some(debug) = &mut compiled.debug {
+        debug.file = args.file.clone();
+    }
+
+    let Some(entry) = compiled.find_func(&args.entry) else {
+        return Err(format!("没有找到入口函数 `{}`", args.entry));
+    };
+    if compiled.func[entry].params != 0 {
+        return Err(format!("入口函数 `{}` 不能有参数", args.entry));
+    }
+    if args.io_metadata.len() > MAX_IO {
+        return Err(format!("最多只能声明 {} 个 IO 变量", MAX_IO));
+    }
+    Ok((source, compiled, entry))
+}
+
+fn io_vars(args: &Args) -> Vec<(String, usize, Type21)> {
+    args.io_metadata.iter()
+        .enumerate()
+        .map(|(idx, (name, _, ty))| match ty {
+            IOType::Scalar(ty) => (name.clone(), idx * 4, *ty),
+            _ => unreachable!()
+        })
+        .collect()
+}
+
+fn debug(args: Args) -> Result<(), String> {
+    let (source, compiled, entry) = load(&args)?;
+    let mut io = CliIO([RtValue::from(0); MAX_IO]);
+    let mut session = DebugSession {
+        compiled: &compiled,
+        coroutine: unsafe { Coroutine::new(&compiled, entry, &mut io) },
+        source_lines: source.lines().collect(),
+        io_vars: io_vars(&args)
+    };
+    session.coroutine.set_debugger(Some(Debugger::new()));
+
+    for line in stdin().lock().lines() {
+        let line = line.map_err(|e| format!("无法读取命令: {}", e))?;
+        match session.command(&line) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) => println!("错误: {}", e)
+        }
+    }
+    Ok(())
+}
+
+// counts come first, since the names of the columns are wider than they look to `format!`
+fn print_profile(profiler: &Profiler, compiled: &Compiled) {
+    let total = profiler.total();
+    let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+    println!("共执行 {} 条指令", total);
+
+    println!();
+    println!("      指令数     占比  函数");
+    for (func, count) in profiler.func_counts(compiled) {
+        println!("{:>12} {:>7.2}%  {}", count, percent(count), func);
+    }
+
+    let lines = profiler.line_counts(compiled);
+    if !lines.is_empty() {
+        println!();
+        println!("      指令数     占比  行");
+        for (func, line, count) in lines {
+            println!("{:>12} {:>7.2}%  {}:{}", count, percent(count), func, line);
+        }
+    }
+
+    let natives = profiler.native_times(compiled);
+    if !natives.is_empty() {
+        println!();
+        println!("    调用次数         耗时  宿主函数");
+        for (native, calls, time) in natives {
+            println!("{:>12} {:>12}  {}", calls, format!("{:?}", time), native);
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let (_, compiled, entry) = load(&args)?;
+    let mut io = CliIO([RtValue::from(0); MAX_IO]);
+    let mut coroutine = unsafe { Coroutine::new(&compiled, entry, &mut io) };
+    if args.profile || args.folded.is_some() {
+        coroutine.set_profiler(Some(Profiler::new()));
+    }
+
+    let mut resumes = 0;
+    let result = loop {
+        if args.frames.is_some_and(|frames| resumes >= frames) {
+            break Ok(());
+        }
+        resumes += 1;
+        match coroutine.resume() {
+            Ok(CoroutineStatus::Suspended) => {},
+            Ok(_) => break Ok(()),
+            Err(e) => break Err(format!("运行错误: {:#}", e))
+        }
+    };
+
+    for (name, offset, ty) in io_vars(&args) {
+        println!("{} = {}", name, show_value(coroutine.io_ctx().0[offset / 4], ty));
+    }
+    if let Some(profiler) = coroutine.profiler() {
+        if let Some(path) = &args.folded {
+            write(path, profiler.folded(&compiled)).map_err(|e| format!("无法写入 `{}`: {}", path, e))?;
+        }
+        if args.profile {
+            println!();
+            print_profile(profiler, &compiled);
+        }
+    }
+    result
+}
+
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let Some(command) = args.first() else {
+        usage()
+    };
+
+    let result = match command.as_str() {
+        "check" => parse_args(&args[1..]).and_then(check),
+        "disasm" => parse_args(&args[1..]).and_then(disasm),
+        "debug" => parse_args(&args[1..]).and_then(debug),
+        "run" => parse_args(&args[1..]).and_then(run),
+        _ => usage()
+    };
 
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
 }
@@ -1,12 +1,20 @@
 use std::ptr::slice_from_raw_parts;
 use crate::value::RtValue;
 
+/// Writes the smallest of the `n_args` integers at `args` to `rets`
+///
+/// # Safety
+/// `args` must point to `n_args` values, and `rets` to space for one.
 pub unsafe fn builtin_min(args: *mut RtValue, n_args: u32, rets: *mut RtValue) {
     assert!(n_args >= 2);
     let i32_data = &*slice_from_raw_parts::<i32>(args as *mut i32 as _, n_args as usize);
     (*rets).i = *i32_data.iter().min().unwrap_unchecked();
 }
 
+/// Writes the largest of the `n_args` integers at `args` to `rets`
+///
+/// # Safety
+/// `args` must point to `n_args` values, and `rets` to space for one.
 pub unsafe fn builtin_max(args: *mut RtValue, n_args: u32, rets: *mut RtValue) {
     assert!(n_args >= 2);
     let i32_data = &*slice_from_raw_parts::<i32>(args as *mut i32 as _, n_args as usize);
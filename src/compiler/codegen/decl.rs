@@ -2,34 +2,32 @@ use std::collections::HashMap;
 use smallvec::{SmallVec, smallvec};
 
 use crate::compiler::codegen::CodegenContext;
+use crate::compiler::codegen::ty::{EnumInfo, FieldInfo, StructInfo, Ty};
+use crate::compiler::codegen::expr_consteval::ConstEvalResult;
+use crate::compiler::codegen::intrinsic::is_intrinsic;
 use crate::compiler::parse::cst::*;
-
 use crate::io_ctx::Type21;
+use crate::native::{NativeImport, NativeSignature};
+
 use crate::r25_300::compiled::Function;
-use crate::value::RtValue;
+use crate::r25_300::debug::{FuncDebug, LineEntry, LocalVar};
+use crate::r25_300::insc::Insc;
 
 #[derive(Debug, Copy, Clone)]
 pub struct VarInfo {
     pub loc: usize,
-    pub ty: Type21
+    pub ty: Ty
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionInfo {
-    pub ty: SmallVec<[Type21; 2]>,
-    pub params: SmallVec<[(Type21, String); 2]>,
-
-    pub func_id: Option<usize>
-}
+    pub name: String,
+    pub ty: SmallVec<[Ty; 2]>,
+    pub params: SmallVec<[(Ty, String); 2]>,
+    pub yield_ty: SmallVec<[Ty; 2]>,
 
-impl From<&FuncDecl> for FunctionInfo {
-    fn from(func_decl: &FuncDecl) -> Self {
-        Self {
-            ty: func_decl.ty.clone(),
-            params: func_decl.params.clone(),
-            func_id: None,
-        }
-    }
+    pub func_id: usize,
+    pub defined: bool
 }
 
 #[derive(Debug, Clone)]
@@ -38,127 +36,371 @@ pub struct CompilingFunction {
 
     pub stack_usage: usize,
     pub max_stack_usage: usize,
-    pub frames: SmallVec<[FunctionFrame; 2]>
+    pub frames: SmallVec<[FunctionFrame; 2]>,
+    pub loops: SmallVec<[LoopInfo; 2]>,
+    // number of enclosing `try` blocks, each of which must be left with `Insc::TryEnd`
+    pub try_depth: usize,
+    pub lines: Vec<LineEntry>,
+    // variables of frames not popped yet have `end == usize::MAX`
+    pub locals: Vec<LocalVar>
 }
 
 impl CompilingFunction {
-    pub fn push_frame(&mut self, named_var_count: usize) {
+    pub fn push_frame(&mut self) {
         self.frames.push(FunctionFrame {
-            anonymous_count: 0,
             named_vars: HashMap::new(),
             frame_start: self.stack_usage,
-            named_var_count
+            first_local: self.locals.len()
         });
     }
 
-    pub fn pop_frame(&mut self) {
+    /// Pops the innermost frame, whose variables live until `end_addr`
+    pub fn pop_frame(&mut self, end_addr: usize) {
         let last_frame = self.frames.pop().unwrap();
-        self.stack_usage -= last_frame.anonymous_count + last_frame.named_vars.len();
+        self.stack_usage = last_frame.frame_start;
+        self.close_locals(last_frame.first_local, end_addr);
     }
 
-    pub fn try_add_var(&mut self, var_name: &str, ty: RtValue) -> Result<(), String> {
-        let last_frame = self.frames.last().unwrap();
+    fn close_locals(&mut self, first_local: usize, end_addr: usize) {
+        for local in self.locals[first_local..].iter_mut().filter(|local| local.end == usize::MAX) {
+            local.end = end_addr;
+        }
+    }
 
-        todo!()
+    pub fn add_line(&mut self, addr: usize, line: usize, col: usize) {
+        match self.lines.last_mut() {
+            Some(entry) if entry.end == addr && entry.line == line && entry.col == col => entry.end += 1,
+            _ => self.lines.push(LineEntry { start: addr, end: addr + 1, line, col })
+        }
+    }
+
+    pub fn alloc(&mut self, size: usize) -> usize {
+        let loc = self.stack_usage;
+        self.stack_usage += size;
+        if self.stack_usage > self.max_stack_usage {
+            self.max_stack_usage = self.stack_usage;
+        }
+        loc
+    }
+
+    pub fn try_add_var(&mut self, var_name: &str, var_info: VarInfo) -> Result<(), String> {
+        let last_frame = self.frames.last_mut().unwrap();
+        if last_frame.named_vars.contains_key(var_name) {
+            return Err(format!("重复的变量定义 `{}`", var_name));
+        }
+
+        last_frame.named_vars.insert(var_name.to_string(), var_info);
+        Ok(())
+    }
+
+    pub fn lookup_var(&self, var_name: &str) -> Option<VarInfo> {
+        self.frames.iter().rev().find_map(|frame| frame.named_vars.get(var_name).copied())
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionFrame {
-    pub anonymous_count: usize,
     pub named_vars: HashMap<String, VarInfo>,
-
     pub frame_start: usize,
-    pub named_var_count: usize
+    // index of the first of `CompilingFunction::locals` declared in this frame
+    pub first_local: usize
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoopInfo {
+    pub break_patches: SmallVec<[usize; 2]>,
+    pub continue_patches: SmallVec<[usize; 2]>,
+    // `switch` only captures `break`
+    pub is_switch: bool,
+    pub try_depth: usize
 }
 
 impl CodegenContext {
-    pub fn visit_func_decl(&mut self, func_decl: &FuncDecl) -> Result<(), String> {
-        let Some(func_body) = &func_decl.body else {
-            return Ok(if let Some(prev_info) = self.declared_func.get(&func_decl.name) {
-                Self::check_func_decl_coherence(func_decl, prev_info)?;
-            } else {
-                self.declared_func.insert(
-                    func_decl.name.to_string(),
-                    FunctionInfo::from(func_decl)
-                );
-            });
-        };
+    pub fn visit_struct_decl(&mut self, struct_decl: &StructDecl) -> Result<(), String> {
+        if self.declared_struct.contains_key(&struct_decl.name)
+            || self.declared_enum.contains_key(&struct_decl.name) {
+            return Err(format!(
+                "行 {}: 重复的类型定义 `{}`",
+                struct_decl.line,
+                struct_decl.name
+            ));
+        }
+
+        let mut fields: SmallVec<[FieldInfo; 4]> = SmallVec::new();
+        let mut size = 0;
+        for (ty, name) in struct_decl.fields.iter() {
+            if fields.iter().any(|field| &field.name == name) {
+                return Err(format!(
+                    "行 {}: 结构体 `{}` 中有重复的字段 `{}`",
+                    struct_decl.line,
+                    struct_decl.name,
+                    name
+                ));
+            }
+
+            let ty = self.resolve_type(ty).map_err(|e| format!("行 {}: {}", struct_decl.line, e))?;
+            fields.push(FieldInfo { name: name.clone(), ty, offset: size });
+            size += self.ty_size(ty);
+        }
+
+        self.declared_struct.insert(struct_decl.name.clone(), self.structs.len());
+        self.structs.push(StructInfo {
+            name: struct_decl.name.clone(),
+            fields,
+            size
+        });
+
+        Ok(())
+    }
+
+    pub fn visit_enum_decl(&mut self, enum_decl: &EnumDecl) -> Result<(), String> {
+        self.line = enum_decl.line;
+        if self.declared_enum.contains_key(&enum_decl.name) {
+            return Err(format!("行 {}: 重复的类型定义 `{}`", enum_decl.line, enum_decl.name));
+        }
 
-        let func_info = if let Some(func_info) = self.declared_func.get_mut(&func_decl.name) {
-            Self::check_func_decl_coherence(func_decl, func_info)?;
+        let mut variants: SmallVec<[(String, i32); 4]> = SmallVec::new();
+        let mut next_value = 0;
+        for (name, value) in enum_decl.variants.iter() {
+            if variants.iter().any(|(variant, _)| variant == name) {
+                return Err(format!(
+                    "行 {}: 枚举 `{}` 中有重复的成员 `{}`",
+                    enum_decl.line,
+                    enum_decl.name,
+                    name
+                ));
+            }
 
-            if func_info.func_id.is_some() {
+            let value = if let Some(value) = value {
+                match self.consteval_expr(value).map_err(|e| format!("行 {}: {}", enum_decl.line, e))? {
+                    Some(ConstEvalResult { ty: Ty::Scalar(Type21::Int32), value }) => unsafe { value.i },
+                    _ => return Err(format!(
+                        "行 {}: 枚举成员 `{}::{}` 的值必须是整数常量表达式",
+                        enum_decl.line,
+                        enum_decl.name,
+                        name
+                    ))
+                }
+            } else {
+                next_value
+            };
+
+            if let Some((variant, _)) = variants.iter().find(|(_, v)| *v == value) {
                 return Err(format!(
-                    "行 {}: 重复的函数定义 `{}`",
-                    func_decl.line,
-                    func_decl.name
+                    "行 {}: 枚举成员 `{}::{}` 与 `{}::{}` 的值相同",
+                    enum_decl.line,
+                    enum_decl.name,
+                    name,
+                    enum_decl.name,
+                    variant
                 ));
             }
 
-            func_info.func_id = Some(self.compiled.func.len());
-            func_info.clone()
+            variants.push((name.clone(), value));
+            next_value = value.wrapping_add(1);
+        }
+
+        self.declared_enum.insert(enum_decl.name.clone(), self.enums.len());
+        self.enums.push(EnumInfo {
+            name: enum_decl.name.clone(),
+            variants
+        });
+
+        Ok(())
+    }
+
+    pub fn visit_const_decl(&mut self, const_decl: &ConstDecl) -> Result<(), String> {
+        self.line = const_decl.line;
+        if self.constant.contains_key(&const_decl.name) {
+            return Err(format!("重复的常量定义 `{}`", const_decl.name));
+        }
+
+        let Some(result) = self.consteval_expr(&const_decl.value)
+            .map_err(|e| format!("常量 `{}`: {}", const_decl.name, e))? else {
+            return Err(format!("常量 `{}` 的值不是常量表达式", const_decl.name));
+        };
+
+        self.constant.insert(const_decl.name.clone(), result);
+        Ok(())
+    }
+
+    pub fn visit_func_decl(&mut self, func_decl: &FuncDecl) -> Result<(), String> {
+        self.line = func_decl.line;
+        self.col = func_decl.col;
+        if is_intrinsic(&func_decl.name) {
+            return Err(format!("行 {}: 函数名 `{}` 与内建函数重名", func_decl.line, func_decl.name));
+        }
+        if self.natives.contains(&func_decl.name) {
+            return Err(format!("行 {}: 函数名 `{}` 与宿主函数重名", func_decl.line, func_decl.name));
+        }
+        if self.declared_extern.contains_key(&func_decl.name) {
+            return Err(format!("行 {}: 函数 `{}` 已被声明为外部函数", func_decl.line, func_decl.name));
+        }
+
+        let func_info = self.resolve_func_info(func_decl)
+            .map_err(|e| format!("行 {}: {}", func_decl.line, e))?;
+
+        if let Some(prev_info) = self.declared_func.get(&func_decl.name) {
+            Self::check_func_decl_coherence(func_decl, &func_info, prev_info)?;
         } else {
-            let mut func_info = FunctionInfo::from(func_decl);
-            func_info.func_id = Some(self.compiled.func.len());
-            self.declared_func.insert(func_decl.name.clone(), func_info.clone());
-            func_info
+            let func_id = self.compiled.func.len();
+            self.compiled.func.push(Function {
+                name: func_decl.name.clone(),
+                addr: 0,
+                frame_size: 0,
+                code_len: 0,
+                params: func_info.params.iter().map(|(ty, _)| self.ty_size(*ty)).sum(),
+                rets: func_info.ty.iter().map(|ty| self.ty_size(*ty)).sum()
+            });
+            self.func_debug.push(FuncDebug::default());
+            self.declared_func.insert(func_decl.name.clone(), FunctionInfo { func_id, ..func_info });
+        }
+
+        let Some(func_body) = &func_decl.body else {
+            return Ok(());
         };
 
+        let func_info = self.declared_func.get_mut(&func_decl.name).unwrap();
+        if func_info.defined {
+            return Err(format!(
+                "行 {}: 重复的函数定义 `{}`",
+                func_decl.line,
+                func_decl.name
+            ));
+        }
+        func_info.defined = true;
+        let func_info = func_info.clone();
+
+        let mut params = HashMap::new();
+        let mut param_vars = Vec::new();
+        let mut param_slots = 0;
+        for (ty, name) in func_info.params.iter() {
+            let var_info = VarInfo { loc: param_slots, ty: *ty };
+            if params.insert(name.clone(), var_info).is_some() {
+                return Err(format!("行 {}: 重复的参数 `{}`", func_decl.line, name));
+            }
+            param_vars.push((name.clone(), var_info));
+            param_slots += self.ty_size(*ty);
+        }
+
         self.compiling_func = Some(CompilingFunction {
             func_info,
 
-            stack_usage: func_decl.params.len(),
-            max_stack_usage: func_decl.params.len(),
+            stack_usage: param_slots,
+            max_stack_usage: param_slots,
             frames: smallvec![
                 FunctionFrame {
-                    anonymous_count: 0,
-                    named_vars: func_decl.params.iter().enumerate().map(|(loc, (ty, name))| (
-                        (name.clone(), VarInfo { loc, ty: *ty })
-                    )).collect::<_>(),
+                    named_vars: params,
                     frame_start: 0,
-                    named_var_count: func_decl.params.len()
+                    first_local: 0
                 }
-            ]
+            ],
+            loops: SmallVec::new(),
+            try_depth: 0,
+            lines: Vec::new(),
+            locals: Vec::new()
         });
+        for (name, var_info) in param_vars {
+            self.record_var(&name, var_info);
+        }
+
+        let start_addr = self.current_addr();
+        self.codegen_block_stmt(func_body)?;
+        // the implicit `return` at the end belongs to the declaration
+        self.line = func_decl.line;
+        self.col = func_decl.col;
+        self.emit(Insc::Return { rets: Box::new([]) });
+        let end_addr = self.current_addr();
+
+        let mut compiling_func = self.compiling_func.take().unwrap();
+        compiling_func.close_locals(0, end_addr);
+        let func_id = compiling_func.func_info.func_id;
+        let function = &mut self.compiled.func[func_id];
+        function.addr = start_addr;
+        function.frame_size = compiling_func.max_stack_usage;
+        function.code_len = end_addr - start_addr;
+        self.func_debug[func_id] = FuncDebug {
+            lines: compiling_func.lines,
+            locals: compiling_func.locals
+        };
+
+        Ok(())
+    }
 
-        self.compiling_func.as_mut().unwrap().push_frame(self.count_named_var(&func_body.stmts));
+    pub fn visit_extern_decl(&mut self, extern_decl: &FuncDecl) -> Result<(), String> {
+        if is_intrinsic(&extern_decl.name) {
+            return Err(format!("行 {}: 函数名 `{}` 与内建函数重名", extern_decl.line, extern_decl.name));
+        }
+        if self.declared_extern.contains_key(&extern_decl.name) {
+            return Err(format!("行 {}: 重复的外部函数声明 `{}`", extern_decl.line, extern_decl.name));
+        }
 
-        let start_addr = self.compiled.code.len();
-        for stmt in func_body.stmts {
-            self.codegen_stmt(&stmt)?;
+        if !extern_decl.yield_ty.is_empty() {
+            return Err(format!("行 {}: 外部函数 `{}` 不能 yield 值", extern_decl.line, extern_decl.name));
         }
-        let end_addr = self.compiled.code.len();
 
-        self.compiled.func.push(Function {
-            name: func_decl.name.clone(),
-            addr: start_addr,
-            frame_size: self.compiling_func.as_ref().unwrap().max_stack_usage,
-            code_len: end_addr - start_addr
-        });
-        self.compiling_func = None;
+        let func_info = self.resolve_func_info(extern_decl)
+            .map_err(|e| format!("行 {}: {}", extern_decl.line, e))?;
+        let native_ty = |ty: &Ty| match ty {
+            Ty::Scalar(ty @ (Type21::Int32 | Type21::Float32)) => Ok(*ty),
+            ty => Err(format!(
+                "行 {}: 外部函数 `{}` 的参数和返回值只能是 int 或 float，不能是 `{}`",
+                extern_decl.line,
+                extern_decl.name,
+                self.ty_display(*ty)
+            ))
+        };
+        let signature = NativeSignature {
+            params: func_info.params.iter().map(|(ty, _)| native_ty(ty)).collect::<Result<_, _>>()?,
+            rets: func_info.ty.iter().map(native_ty).collect::<Result<_, _>>()?
+        };
 
+        let func_id = self.import_native(&extern_decl.name, signature);
+        self.declared_extern.insert(extern_decl.name.clone(), FunctionInfo { func_id, ..func_info });
         Ok(())
     }
 
-    fn count_named_var(&self, stmts: &[Stmt]) -> usize {
-        let mut count = 0;
-        for stmt in stmts {
-            if let Stmt::DeclStmt(_) = stmt {
-                count += 1;
-            }
+    pub fn import_native(&mut self, name: &str, signature: NativeSignature) -> usize {
+        if let Some(import_id) = self.compiled.imports.iter()
+            .position(|import| import.name == name && import.signature == signature) {
+            return import_id;
         }
-        count
+
+        self.compiled.imports.push(NativeImport { name: name.to_string(), signature });
+        self.compiled.imports.len() - 1
     }
 
-    pub fn visit_var_decl(&mut self, var_decl: &VarDecl) -> Result<(), String> {
-        let compiling_func = self.compiling_func.as_mut().unwrap();
-        let last_frame = compiling_func.frames.last_mut().unwrap();
+    fn resolve_func_info(&self, func_decl: &FuncDecl) -> Result<FunctionInfo, String> {
+        let mut ty = SmallVec::new();
+        for ret_ty in func_decl.ty.iter() {
+            ty.push(self.resolve_type(ret_ty)?);
+        }
 
-        if last_frame.named_vars.get(&var_decl.name).is_some() {
-            return Err(format!("行 {}: 重复的变量定义 `{}`", var_decl.line, var_decl.name));
+        let mut params = SmallVec::new();
+        for (param_ty, name) in func_decl.params.iter() {
+            params.push((self.resolve_type(param_ty)?, name.clone()));
         }
 
+        let mut yield_ty = SmallVec::new();
+        for ty in func_decl.yield_ty.iter() {
+            let ty = self.resolve_type(ty)?;
+            if !matches!(ty, Ty::Scalar(_)) {
+                return Err(format!("yield 的值只能是 int、float 或 bool，不能是 `{}`", self.ty_display(ty)));
+            }
+            yield_ty.push(ty);
+        }
+
+        Ok(FunctionInfo {
+            name: func_decl.name.clone(),
+            ty,
+            params,
+            yield_ty,
+            func_id: 0,
+            defined: false
+        })
+    }
+
+    pub fn visit_var_decl(&mut self, var_decl: &VarDecl) -> Result<(), String> {
         if var_decl.ty.is_none() && var_decl.init.is_none() {
             return Err(format!(
                 "行 {}: 必须初始化变量 `{}` 或为其指定类型",
@@ -167,17 +409,61 @@ impl CodegenContext {
             ));
         }
 
-        let var_info = VarInfo {
-            loc: last_frame.named_vars.len(),
-            ty: todo!()
+        let var_info = if let Some(ty) = &var_decl.ty {
+            let ty = self.resolve_type(ty).map_err(|e| format!("行 {}: {}", var_decl.line, e))?;
+            let loc = self.alloc_temp(self.ty_size(ty));
+
+            if let Some(init) = &var_decl.init {
+                let mark = self.stack_mark();
+                let init = self.codegen_expr(init)
+                    .map_err(|e| format!("行 {}: {}", var_decl.line, e))?;
+                self.check_assign_type(ty, init.ty)
+                    .map_err(|e| format!("行 {}: {}", var_decl.line, e))?;
+                self.codegen_copy(init.value_loc, loc, self.ty_size(ty));
+                self.stack_release(mark);
+            }
+
+            VarInfo { loc, ty }
+        } else {
+            // the variable takes the place of the temporaries, so the value is copied down
+            let mark = self.stack_mark();
+            let init = self.codegen_expr(var_decl.init.as_ref().unwrap())
+                .map_err(|e| format!("行 {}: {}", var_decl.line, e))?;
+            self.stack_release(mark);
+            let size = self.ty_size(init.ty);
+            let loc = self.alloc_temp(size);
+            self.codegen_copy(init.value_loc, loc, size);
+
+            VarInfo { loc, ty: init.ty }
         };
-        last_frame.named_vars.insert(var_decl.name.clone(), var_info);
 
+        self.compiling_func()
+            .try_add_var(&var_decl.name, var_info)
+            .map_err(|e| format!("行 {}: {}", var_decl.line, e))?;
+        self.record_var(&var_decl.name, var_info);
         Ok(())
     }
 
-    fn check_func_decl_coherence(decl: &FuncDecl, func_info: &FunctionInfo) -> Result<(), String> {
-        if decl.params.len() != func_info.params.len() {
+    /// Records a variable alive from the current address on in the debug info
+    fn record_var(&mut self, var_name: &str, var_info: VarInfo) {
+        let mut fields = Vec::new();
+        self.flatten_named(var_name, var_info.ty, var_info.loc, &mut fields);
+        let start = self.current_addr();
+        self.compiling_func().locals.extend(fields.into_iter().map(|(name, slot, ty)| LocalVar {
+            name,
+            slot,
+            ty,
+            start,
+            end: usize::MAX
+        }));
+    }
+
+    fn check_func_decl_coherence(
+        decl: &FuncDecl,
+        func_info: &FunctionInfo,
+        prev_info: &FunctionInfo
+    ) -> Result<(), String> {
+        if decl.params.len() != prev_info.params.len() {
             return Err(format!(
                 "行 {}: 函数 `{}` 先后以不同的参数个数被声明",
                 decl.line,
@@ -185,6 +471,16 @@ impl CodegenContext {
             ));
         }
 
+        if func_info.ty != prev_info.ty
+            || func_info.yield_ty != prev_info.yield_ty
+            || func_info.params.iter().zip(prev_info.params.iter()).any(|(p1, p2)| p1.0 != p2.0) {
+            return Err(format!(
+                "行 {}: 函数 `{}` 先后以不同的类型被声明",
+                decl.line,
+                decl.name
+            ));
+        }
+
         Ok(())
     }
 }
@@ -1,16 +1,548 @@
-use crate::compiler::codegen::CodegenContext;
-use crate::compiler::parse::cst::Expr;
+use std::mem::size_of;
+use smallvec::{smallvec, SmallVec};
+
+use crate::compiler::codegen::{CodegenContext, ExprResult};
+use crate::compiler::codegen::decl::FunctionInfo;
+use crate::compiler::codegen::expr_consteval::ConstEvalResult;
+use crate::compiler::codegen::intrinsic::is_intrinsic;
+use crate::compiler::codegen::ty::Ty;
+use crate::compiler::op::{int_fits_float, BinaryOp, UnaryOp};
+use crate::compiler::parse::cst::*;
 use crate::io_ctx::Type21;
+use crate::r25_300::insc::Insc;
 use crate::value::RtValue;
 
-pub struct ExprResult {
-    ty: Type21,
-    loc: Option<usize>,
-    consteval_value: Option<RtValue>
+#[derive(Debug, Clone, Copy)]
+pub enum PlaceLoc {
+    Local(usize),
+    IO(usize)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Place {
+    pub loc: PlaceLoc,
+    pub ty: Ty
 }
 
 impl CodegenContext {
     pub fn codegen_expr(&mut self, expr: &Expr) -> Result<ExprResult, String> {
-        todo!()
+        if let Some(ConstEvalResult { ty, value }) = self.consteval_expr(expr)? {
+            let dst = self.alloc_temp(1);
+            self.emit(Insc::Const { value, dst });
+            return Ok(ExprResult {
+                ty,
+                value_loc: dst,
+                consteval_value: Some(value)
+            });
+        }
+
+        match expr {
+            Expr::AtomicExpr(atomic_expr) => self.codegen_atomic_expr(atomic_expr),
+            Expr::AssignExpr(assign_expr) => self.codegen_assign_expr(assign_expr),
+            Expr::MultiAssignExpr(_) => Err("多重赋值表达式不能作为值使用".into()),
+            Expr::BinaryExpr(bin_expr) => self.codegen_bin_expr(bin_expr),
+            Expr::UnaryExpr(unary_expr) => self.codegen_unary_expr(unary_expr),
+            Expr::FuncCall(func_call) => self.codegen_func_call_expr(func_call),
+            Expr::Spawn(func_call) => self.codegen_spawn_expr(func_call),
+            Expr::FieldAccess(field_access) => self.codegen_field_access(field_access)
+        }
+    }
+
+    pub fn codegen_atomic_expr(&mut self, atomic_expr: &AtomicExpr) -> Result<ExprResult, String> {
+        match atomic_expr {
+            AtomicExpr::Ident(ident) => {
+                let Some(place) = self.resolve_place(ident, &[])? else {
+                    return Err(format!("未定义的变量 `{}`", ident));
+                };
+                Ok(self.codegen_load(place))
+            },
+            AtomicExpr::Integer(_)
+            | AtomicExpr::Float(_)
+            | AtomicExpr::Bool(_)
+            | AtomicExpr::EnumVariant(_, _) => {
+                let ConstEvalResult { ty, value } = self.consteval_atomic_expr(atomic_expr)?.unwrap();
+                let dst = self.alloc_temp(1);
+                self.emit(Insc::Const { value, dst });
+                Ok(ExprResult { ty, value_loc: dst, consteval_value: Some(value) })
+            },
+            AtomicExpr::Paren(expr) => self.codegen_expr(expr),
+            AtomicExpr::TypeCast(type_cast) => self.codegen_type_cast(type_cast),
+            AtomicExpr::FuncCall(func_call) => self.codegen_func_call_expr(func_call)
+        }
+    }
+
+    pub fn codegen_field_access(&mut self, field_access: &FieldAccess) -> Result<ExprResult, String> {
+        let mut fields = SmallVec::<[String; 2]>::new();
+        let mut base = &field_access.base;
+        fields.push(field_access.field.clone());
+        while let Expr::FieldAccess(inner) = base {
+            fields.insert(0, inner.field.clone());
+            base = &inner.base;
+        }
+
+        if let Expr::AtomicExpr(atomic_expr) = base {
+            if let AtomicExpr::Ident(ident) = atomic_expr.as_ref() {
+                let Some(place) = self.resolve_place(ident, &fields)? else {
+                    return Err(format!("未定义的变量 `{}`", ident));
+                };
+                return Ok(self.codegen_load(place));
+            }
+        }
+
+        let base = self.codegen_expr(base)?;
+        let mut ty = base.ty;
+        let mut value_loc = base.value_loc;
+        for field in fields.iter() {
+            let (offset, field_ty) = self.resolve_field(ty, field)?;
+            value_loc += offset;
+            ty = field_ty;
+        }
+
+        Ok(ExprResult { ty, value_loc, consteval_value: None })
+    }
+
+    pub fn codegen_assign_expr(&mut self, assign_expr: &AssignExpr) -> Result<ExprResult, String> {
+        let value = self.codegen_expr(&assign_expr.value)?;
+        let Some(place) = self.resolve_place(&assign_expr.name, &assign_expr.fields)? else {
+            return if self.constant.contains_key(&assign_expr.name) {
+                Err(format!("不能对常量 `{}` 赋值", assign_expr.name))
+            } else {
+                Err(format!("未定义的变量 `{}`", assign_expr.name))
+            };
+        };
+
+        self.check_assign_type(place.ty, value.ty)?;
+        Ok(self.codegen_store(place, value.value_loc))
+    }
+
+    pub fn codegen_multi_assign_expr(&mut self, multi_assign_expr: &MultiAssignExpr) -> Result<(), String> {
+        let Expr::FuncCall(func_call) = &multi_assign_expr.value else {
+            unreachable!()
+        };
+
+        let (tys, mut value_loc) = self.codegen_func_call(func_call)?;
+        if tys.len() != multi_assign_expr.names.len() {
+            return Err(format!(
+                "函数 `{}` 返回 {} 个值，但赋值目标有 {} 个",
+                func_call.name,
+                tys.len(),
+                multi_assign_expr.names.len()
+            ));
+        }
+
+        for (name, ty) in multi_assign_expr.names.iter().zip(tys.iter()) {
+            let Some(place) = self.resolve_place(name, &[])? else {
+                return Err(format!("未定义的变量 `{}`", name));
+            };
+
+            self.check_assign_type(place.ty, *ty)?;
+            self.codegen_store(place, value_loc);
+            value_loc += self.ty_size(*ty);
+        }
+
+        Ok(())
+    }
+
+    pub fn codegen_bin_expr(&mut self, bin_expr: &BinaryExpr) -> Result<ExprResult, String> {
+        if let BinaryOp::And | BinaryOp::Or = bin_expr.op {
+            return self.codegen_logic_expr(bin_expr);
+        }
+
+        let lhs = self.codegen_expr(&bin_expr.lhs)?;
+        let rhs = self.codegen_expr(&bin_expr.rhs)?;
+
+        if let (Ty::Enum(lhs_enum), Ty::Enum(rhs_enum)) = (lhs.ty, rhs.ty) {
+            if lhs_enum != rhs_enum {
+                return Err(format!(
+                    "二元表达式的两个操作数类型不一致 ({} 和 {})",
+                    self.ty_display(lhs.ty),
+                    self.ty_display(rhs.ty)
+                ));
+            }
+
+            let (lhs, rhs) = (lhs.value_loc, rhs.value_loc);
+            let dst = self.alloc_temp(1);
+            match bin_expr.op {
+                BinaryOp::Eq => self.emit(Insc::Eq { lhs, rhs, dst }),
+                BinaryOp::Ne => self.emit(Insc::Ne { lhs, rhs, dst }),
+                op => return Err(format!("无法对枚举类型应用运算 {:?}", op))
+            };
+            return Ok(ExprResult { ty: Ty::Scalar(Type21::Bool), value_loc: dst, consteval_value: None });
+        }
+
+        let (Ty::Scalar(lhs_ty), Ty::Scalar(rhs_ty)) = (lhs.ty, rhs.ty) else {
+            return Err(format!(
+                "无法对 `{}` 和 `{}` 应用二元运算",
+                self.ty_display(lhs.ty),
+                self.ty_display(rhs.ty)
+            ));
+        };
+
+        let operand_ty = bin_expr.op.operand_type(lhs_ty, rhs_ty)?;
+        let lhs = self.codegen_promote(lhs, operand_ty);
+        let rhs = self.codegen_promote(rhs, operand_ty);
+        let dst = self.alloc_temp(1);
+        let (insc, ty) = match (bin_expr.op, operand_ty) {
+            (BinaryOp::Add, Type21::Int32) => (Insc::AddInt { lhs, rhs, dst }, Type21::Int32),
+            (BinaryOp::Add, Type21::Float32) => (Insc::AddFloat { lhs, rhs, dst }, Type21::Float32),
+            (BinaryOp::Sub, Type21::Int32) => (Insc::SubInt { lhs, rhs, dst }, Type21::Int32),
+            (BinaryOp::Sub, Type21::Float32) => (Insc::SubFloat { lhs, rhs, dst }, Type21::Float32),
+            (BinaryOp::Mul, Type21::Int32) => (Insc::MulInt { lhs, rhs, dst }, Type21::Int32),
+            (BinaryOp::Mul, Type21::Float32) => (Insc::MulFloat { lhs, rhs, dst }, Type21::Float32),
+            (BinaryOp::Div, Type21::Int32) => (Insc::DivInt { lhs, rhs, dst }, Type21::Int32),
+            (BinaryOp::Div, Type21::Float32) => (Insc::DivFloat { lhs, rhs, dst }, Type21::Float32),
+            (BinaryOp::Mod, Type21::Int32) => (Insc::ModInt { lhs, rhs, dst }, Type21::Int32),
+            (BinaryOp::Eq, _) => (Insc::Eq { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Ne, _) => (Insc::Ne { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Lt, Type21::Int32) => (Insc::LtInt { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Lt, Type21::Float32) => (Insc::LtFloat { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Le, Type21::Int32) => (Insc::LeInt { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Le, Type21::Float32) => (Insc::LeFloat { lhs, rhs, dst }, Type21::Bool),
+            (BinaryOp::Gt, Type21::Int32) => (Insc::LtInt { lhs: rhs, rhs: lhs, dst }, Type21::Bool),
+            (BinaryOp::Gt, Type21::Float32) => (Insc::LtFloat { lhs: rhs, rhs: lhs, dst }, Type21::Bool),
+            (BinaryOp::Ge, Type21::Int32) => (Insc::LeInt { lhs: rhs, rhs: lhs, dst }, Type21::Bool),
+            (BinaryOp::Ge, Type21::Float32) => (Insc::LeFloat { lhs: rhs, rhs: lhs, dst }, Type21::Bool),
+            (op, ty) => return Err(format!("无法对 {} 类型应用运算 {:?}", ty, op))
+        };
+
+        self.emit(insc);
+        Ok(ExprResult { ty: Ty::Scalar(ty), value_loc: dst, consteval_value: None })
+    }
+
+    pub fn codegen_promote(&mut self, operand: ExprResult, to: Type21) -> usize {
+        if operand.ty == Ty::Scalar(to) {
+            return operand.value_loc;
+        }
+
+        if self.options.warn_lossy {
+            match operand.consteval_value {
+                Some(value) if int_fits_float(unsafe { value.i }) => {},
+                Some(value) => self.warn(format!("整数常量 {} 隐式转换为 float 时损失了精度", unsafe { value.i })),
+                None => self.warn("int 值隐式转换为 float 可能损失精度".into())
+            }
+        }
+
+        let dst = self.alloc_temp(1);
+        self.emit(Insc::ToFloat { src: operand.value_loc, dst });
+        dst
+    }
+
+    fn codegen_logic_expr(&mut self, bin_expr: &BinaryExpr) -> Result<ExprResult, String> {
+        let dst = self.alloc_temp(1);
+
+        let lhs = self.codegen_expr(&bin_expr.lhs)?;
+        if lhs.ty != Ty::Scalar(Type21::Bool) {
+            return Err(format!("逻辑运算的操作数必须是布尔类型，实际为 `{}`", self.ty_display(lhs.ty)));
+        }
+        self.codegen_copy(lhs.value_loc, dst, 1);
+
+        let jmp_addr = if let BinaryOp::And = bin_expr.op {
+            let check = self.alloc_temp(1);
+            self.emit(Insc::Not { src: dst, dst: check });
+            self.emit(Insc::JmpIf { check, dst: 0 })
+        } else {
+            self.emit(Insc::JmpIf { check: dst, dst: 0 })
+        };
+
+        let rhs = self.codegen_expr(&bin_expr.rhs)?;
+        if rhs.ty != Ty::Scalar(Type21::Bool) {
+            return Err(format!("逻辑运算的操作数必须是布尔类型，实际为 `{}`", self.ty_display(rhs.ty)));
+        }
+        self.codegen_copy(rhs.value_loc, dst, 1);
+        self.patch_jmp(jmp_addr, self.current_addr());
+
+        Ok(ExprResult { ty: Ty::Scalar(Type21::Bool), value_loc: dst, consteval_value: None })
+    }
+
+    pub fn codegen_unary_expr(&mut self, unary_expr: &UnaryExpr) -> Result<ExprResult, String> {
+        let src = self.codegen_expr(&unary_expr.expr)?;
+        let Ty::Scalar(ty) = src.ty else {
+            return Err(format!("无法对 `{}` 类型应用一元运算", self.ty_display(src.ty)));
+        };
+
+        let src = src.value_loc;
+        let dst = self.alloc_temp(1);
+        let insc = match (unary_expr.op, ty) {
+            (UnaryOp::Negate, Type21::Int32) => Insc::NegateInt { src, dst },
+            (UnaryOp::Negate, Type21::Float32) => Insc::NegateFloat { src, dst },
+            (UnaryOp::Negate, Type21::Bool) => return Err("无法对布尔类型取负".into()),
+            (UnaryOp::Not, Type21::Bool) => Insc::Not { src, dst },
+            (UnaryOp::Not, _) => return Err("只能对布尔类型应用逻辑非".into())
+        };
+
+        self.emit(insc);
+        Ok(ExprResult { ty: Ty::Scalar(ty), value_loc: dst, consteval_value: None })
+    }
+
+    pub fn codegen_type_cast(&mut self, type_cast: &TypeCast) -> Result<ExprResult, String> {
+        let src = self.codegen_expr(&type_cast.expr)?;
+        let ty = match src.ty {
+            Ty::Scalar(ty) => ty,
+            Ty::Enum(_) if type_cast.dest == Type21::Int32 => {
+                return Ok(ExprResult { ty: Ty::Scalar(Type21::Int32), ..src });
+            },
+            _ => return Err(format!("无法将 `{}` 转换为 {}", self.ty_display(src.ty), type_cast.dest))
+        };
+
+        if ty == type_cast.dest {
+            return Ok(src);
+        }
+
+        let src = src.value_loc;
+        let dst = self.alloc_temp(1);
+        match (ty, type_cast.dest) {
+            (Type21::Int32, Type21::Float32) => {
+                self.emit(Insc::ToFloat { src, dst });
+            },
+            (Type21::Int32, Type21::Bool) => {
+                self.emit(Insc::Int2Bool { src, dst });
+            },
+            (Type21::Bool, Type21::Int32) => {
+                self.emit(Insc::Bool2Int { src, dst });
+            },
+            (Type21::Bool, Type21::Float32) => {
+                self.emit(Insc::Bool2Float { src, dst });
+            },
+            (Type21::Float32, Type21::Int32) => {
+                self.emit(Insc::Float2Int { src, dst });
+            },
+            (Type21::Float32, Type21::Bool) => {
+                self.emit(Insc::Float2Bool { src, dst });
+            },
+            (_, _) => unreachable!()
+        }
+
+        Ok(ExprResult { ty: Ty::Scalar(type_cast.dest), value_loc: dst, consteval_value: None })
+    }
+
+    pub fn codegen_func_call_expr(&mut self, func_call: &FuncCall) -> Result<ExprResult, String> {
+        if is_intrinsic(&func_call.name) {
+            return self.codegen_intrinsic_call(func_call);
+        }
+
+        let (tys, value_loc) = self.codegen_func_call(func_call)?;
+        match tys.len() {
+            0 => Err(format!("函数 `{}` 没有返回值", func_call.name)),
+            1 => Ok(ExprResult { ty: tys[0], value_loc, consteval_value: None }),
+            _ => Err(format!("函数 `{}` 返回多个值，只能用于多重赋值", func_call.name))
+        }
+    }
+
+    pub fn codegen_func_call(
+        &mut self,
+        func_call: &FuncCall
+    ) -> Result<(SmallVec<[Ty; 2]>, usize), String> {
+        if is_intrinsic(&func_call.name) {
+            let result = self.codegen_intrinsic_call(func_call)?;
+            return Ok((smallvec![result.ty], result.value_loc));
+        }
+
+        if self.natives.contains(&func_call.name) {
+            return self.codegen_native_call(func_call);
+        }
+
+        let (func_info, is_extern) = if let Some(func_info) = self.declared_extern.get(&func_call.name) {
+            (func_info.clone(), true)
+        } else if let Some(func_info) = self.declared_func.get(&func_call.name) {
+            (func_info.clone(), false)
+        } else {
+            return Err(format!("未定义的函数 `{}`", func_call.name));
+        };
+        if !func_info.yield_ty.is_empty() {
+            return Err(format!("函数 `{}` 会 yield 值，只能通过 `yield from` 或 `spawn` 调用", func_call.name));
+        }
+
+        let args = self.codegen_call_args(&func_info, func_call)?;
+        self.codegen_call_insc(&func_info, is_extern, args)
+    }
+
+    /// `yield from` and `spawn` only work with functions defined in the script
+    pub fn resolve_script_func(&self, func_call: &FuncCall, usage: &str) -> Result<FunctionInfo, String> {
+        if let Some(func_info) = self.declared_func.get(&func_call.name) {
+            return Ok(func_info.clone());
+        }
+
+        if is_intrinsic(&func_call.name)
+            || self.natives.contains(&func_call.name)
+            || self.declared_extern.contains_key(&func_call.name) {
+            Err(format!("`{}` 只能用于脚本中定义的函数，`{}` 不是", usage, func_call.name))
+        } else {
+            Err(format!("未定义的函数 `{}`", func_call.name))
+        }
+    }
+
+    pub fn codegen_call_args(&mut self, func_info: &FunctionInfo, func_call: &FuncCall) -> Result<Box<[usize]>, String> {
+        if func_call.args.len() != func_info.params.len() {
+            return Err(format!(
+                "函数 `{}` 需要 {} 个参数，但提供了 {} 个",
+                func_call.name,
+                func_info.params.len(),
+                func_call.args.len()
+            ));
+        }
+
+        let mut args = Vec::new();
+        for (arg, (param_ty, param_name)) in func_call.args.iter().zip(func_info.params.iter()) {
+            let arg = self.codegen_expr(arg)?;
+            self.check_assign_type(*param_ty, arg.ty)
+                .map_err(|e| format!("参数 `{}`: {}", param_name, e))?;
+            args.extend(arg.value_loc..arg.value_loc + self.ty_size(arg.ty));
+        }
+        Ok(args.into_boxed_slice())
+    }
+
+    pub fn codegen_call_insc(
+        &mut self,
+        func_info: &FunctionInfo,
+        is_extern: bool,
+        args: Box<[usize]>
+    ) -> Result<(SmallVec<[Ty; 2]>, usize), String> {
+        let ret_size = self.ty_list_size(&func_info.ty);
+        let ret_loc = self.alloc_temp(ret_size);
+        let (func, ret_locs) = (func_info.func_id, (ret_loc..ret_loc + ret_size).collect());
+        self.emit(if is_extern {
+            Insc::CallFFI { func, args, ret_locs }
+        } else {
+            Insc::Call { func, args, ret_locs }
+        });
+
+        Ok((func_info.ty.clone(), ret_loc))
+    }
+
+    pub fn codegen_spawn_expr(&mut self, func_call: &FuncCall) -> Result<ExprResult, String> {
+        let func_info = self.resolve_script_func(func_call, "spawn")?;
+        let args = self.codegen_call_args(&func_info, func_call)?;
+
+        let dst = self.alloc_temp(1);
+        self.emit(Insc::Spawn { func: func_info.func_id, args, dst });
+        Ok(ExprResult { ty: Ty::Scalar(Type21::Int32), value_loc: dst, consteval_value: None })
+    }
+
+    fn codegen_native_call(
+        &mut self,
+        func_call: &FuncCall
+    ) -> Result<(SmallVec<[Ty; 2]>, usize), String> {
+        let mut args = SmallVec::<[ExprResult; 4]>::new();
+        for arg in func_call.args.iter() {
+            args.push(self.codegen_expr(arg)?);
+        }
+
+        let Some(native) = self.natives.overloads(&func_call.name).find(|native| {
+            native.signature.params.len() == args.len()
+                && native.signature.params.iter().zip(args.iter()).all(|(param, arg)| Ty::Scalar(*param) == arg.ty)
+        }) else {
+            return Err(format!(
+                "宿主函数 `{}` 不接受参数类型 ({})",
+                func_call.name,
+                args.iter().map(|arg| self.ty_display(arg.ty).to_string()).collect::<Vec<_>>().join(", ")
+            ));
+        };
+        let rets = native.signature.rets.iter().map(|ty| Ty::Scalar(*ty)).collect::<SmallVec<[Ty; 2]>>();
+
+        let func = self.import_native(&func_call.name, native.signature.clone());
+        let ret_loc = self.alloc_temp(rets.len());
+        self.emit(Insc::CallFFI {
+            func,
+            args: args.iter().map(|arg| arg.value_loc).collect(),
+            ret_locs: (ret_loc..ret_loc + rets.len()).collect()
+        });
+
+        Ok((rets, ret_loc))
+    }
+
+    pub fn resolve_place(&self, name: &str, fields: &[String]) -> Result<Option<Place>, String> {
+        let mut place = if let Some(var_info) = self.compiling_func.as_ref()
+            .and_then(|compiling_func| compiling_func.lookup_var(name)) {
+            Place { loc: PlaceLoc::Local(var_info.loc), ty: var_info.ty }
+        } else if let Some(io_var_info) = self.lookup_io_var(name)? {
+            Place { loc: PlaceLoc::IO(io_var_info.offset), ty: io_var_info.ty }
+        } else {
+            return Ok(None);
+        };
+
+        for field in fields {
+            let (offset, ty) = self.resolve_field(place.ty, field)?;
+            place = Place {
+                loc: match place.loc {
+                    PlaceLoc::Local(loc) => PlaceLoc::Local(loc + offset),
+                    PlaceLoc::IO(offset_bytes) => PlaceLoc::IO(offset_bytes + offset * size_of::<RtValue>())
+                },
+                ty
+            };
+        }
+
+        Ok(Some(place))
+    }
+
+    fn resolve_field(&self, ty: Ty, field: &str) -> Result<(usize, Ty), String> {
+        let Ty::Struct(struct_id) = ty else {
+            return Err(format!("类型 `{}` 没有字段 `{}`", self.ty_display(ty), field));
+        };
+
+        let struct_info = &self.structs[struct_id];
+        let Some(field_info) = struct_info.field(field) else {
+            return Err(format!("结构体 `{}` 没有字段 `{}`", struct_info.name, field));
+        };
+
+        Ok((field_info.offset, field_info.ty))
+    }
+
+    pub fn codegen_load(&mut self, place: Place) -> ExprResult {
+        match place.loc {
+            PlaceLoc::Local(loc) => ExprResult { ty: place.ty, value_loc: loc, consteval_value: None },
+            PlaceLoc::IO(offset) => {
+                let size = self.ty_size(place.ty);
+                let dst = self.alloc_temp(size);
+                for i in 0..size {
+                    self.emit(Insc::IOGetValue { offset: offset + i * size_of::<RtValue>(), dst: dst + i });
+                }
+                ExprResult { ty: place.ty, value_loc: dst, consteval_value: None }
+            }
+        }
+    }
+
+    pub fn codegen_store(&mut self, place: Place, src: usize) -> ExprResult {
+        let size = self.ty_size(place.ty);
+        match place.loc {
+            PlaceLoc::Local(loc) => {
+                self.codegen_copy(src, loc, size);
+                ExprResult { ty: place.ty, value_loc: loc, consteval_value: None }
+            },
+            PlaceLoc::IO(offset) => {
+                for i in 0..size {
+                    self.emit(Insc::IOSetValue { offset: offset + i * size_of::<RtValue>(), src: src + i });
+                }
+                ExprResult { ty: place.ty, value_loc: src, consteval_value: None }
+            }
+        }
+    }
+
+    pub fn codegen_copy(&mut self, src: usize, dst: usize, size: usize) {
+        if src == dst {
+            return;
+        }
+
+        for i in 0..size {
+            self.emit(Insc::Dup { src: src + i, dst: dst + i });
+        }
+    }
+
+    pub fn check_assign_type(&self, dst_ty: Ty, src_ty: Ty) -> Result<(), String> {
+        if dst_ty != src_ty {
+            let hint = match (dst_ty, src_ty) {
+                (Ty::Scalar(Type21::Int32 | Type21::Float32), Ty::Scalar(Type21::Int32 | Type21::Float32)) =>
+                    format!("；赋值、传参、返回和 yield 时不会进行隐式转换，请使用 `{}(...)`", self.ty_display(dst_ty)),
+                _ => String::new()
+            };
+
+            return Err(format!(
+                "类型不匹配: 需要 `{}`，实际为 `{}`{}",
+                self.ty_display(dst_ty),
+                self.ty_display(src_ty),
+                hint
+            ));
+        }
+
+        Ok(())
     }
 }
@@ -1,94 +1,122 @@
 use crate::compiler::parse::cst::*;
 use crate::compiler::codegen::CodegenContext;
-use crate::compiler::op::{BinaryOp, UnaryOp};
+use crate::compiler::codegen::intrinsic::is_intrinsic;
+use crate::compiler::codegen::ty::Ty;
+use crate::compiler::op::{int_fits_float, BinaryOp, UnaryOp};
 use crate::io_ctx::Type21;
-use crate::value::RtValue;
+use crate::r25_300::error::int_division_fault;
+use crate::value::{bool_to_float, float_to_bool, float_to_int, RtValue};
 
 #[derive(Debug, Clone, Copy)]
 pub struct ConstEvalResult {
-    pub ty: Type21,
+    pub ty: Ty,
     pub value: RtValue
 }
 
 impl CodegenContext {
-    pub fn consteval_expr(&self, expr: &Expr) -> Result<Option<ConstEvalResult>, String> {
+    pub fn consteval_expr(&mut self, expr: &Expr) -> Result<Option<ConstEvalResult>, String> {
         match expr {
             Expr::AtomicExpr(atomic_expr) => self.consteval_atomic_expr(atomic_expr),
             Expr::AssignExpr(_) => Ok(None),
             Expr::MultiAssignExpr(_) => Ok(None),
             Expr::BinaryExpr(bin_expr) => self.consteval_bin_expr(bin_expr),
             Expr::UnaryExpr(unary_expr) => self.consteval_unary_expr(unary_expr),
-            Expr::FuncCall(_) => Ok(None)
+            Expr::FuncCall(func_call) => self.consteval_func_call(func_call),
+            Expr::Spawn(_) => Ok(None),
+            Expr::FieldAccess(_) => Ok(None)
         }
     }
 
     pub fn consteval_atomic_expr(
-        &self,
+        &mut self,
         atomic_expr: &AtomicExpr
     ) -> Result<Option<ConstEvalResult>, String> {
         match atomic_expr {
-            AtomicExpr::Ident(ident) => if let Some(result) = self.constant.get(ident) {
+            AtomicExpr::Ident(ident) => if self.resolve_place(ident, &[])?.is_some() {
+                Ok(None)
+            } else if let Some(result) = self.constant.get(ident) {
                 Ok(Some(*result))
+            } else if self.compiling_func.is_some() {
+                Err(format!("未定义的变量 `{}`", ident))
             } else {
                 Err(format!("未定义的常量 {}", ident))
             },
             AtomicExpr::Integer(int) => Ok(Some(ConstEvalResult {
-                ty: Type21::Int32,
+                ty: Ty::Scalar(Type21::Int32),
                 value: RtValue::from(*int)
             })),
             AtomicExpr::Float(float) => Ok(Some(ConstEvalResult {
-                ty: Type21::Float32,
+                ty: Ty::Scalar(Type21::Float32),
                 value: RtValue::from(*float)
             })),
             AtomicExpr::Bool(bool) => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
+                ty: Ty::Scalar(Type21::Bool),
                 value: RtValue::from(*bool)
             })),
+            AtomicExpr::EnumVariant(enum_name, variant) => {
+                let Some(enum_id) = self.declared_enum.get(enum_name) else {
+                    return Err(format!("未定义的枚举 `{}`", enum_name));
+                };
+                let Some(value) = self.enums[*enum_id].variant(variant) else {
+                    return Err(format!("枚举 `{}` 没有成员 `{}`", enum_name, variant));
+                };
+
+                Ok(Some(ConstEvalResult {
+                    ty: Ty::Enum(*enum_id),
+                    value: RtValue::from(value)
+                }))
+            },
             AtomicExpr::Paren(inner) => self.consteval_expr(inner),
             AtomicExpr::TypeCast(TypeCast { dest, expr }) => {
-                let Some(ConstEvalResult { ty, value }) = self.consteval_expr(&expr)? else {
+                let Some(ConstEvalResult { ty, value }) = self.consteval_expr(expr)? else {
                     return Ok(None);
                 };
 
+                let ty = match ty {
+                    Ty::Scalar(ty) => ty,
+                    Ty::Enum(_) if *dest == Type21::Int32 => Type21::Int32,
+                    _ => return Err(format!("无法将 `{}` 转换为 {}", self.ty_display(ty), dest))
+                };
+
                 if ty == *dest {
-                    return Ok(Some(ConstEvalResult { ty, value }));
+                    return Ok(Some(ConstEvalResult { ty: Ty::Scalar(ty), value }));
                 }
 
                 Ok(Some(match (ty, dest) {
                     (Type21::Int32, Type21::Float32) => {
                         ConstEvalResult {
-                            ty: Type21::Float32,
+                            ty: Ty::Scalar(Type21::Float32),
                             value: RtValue::from(unsafe { value.i } as f32)
                         }
                     },
                     (Type21::Int32, Type21::Bool) => {
                         ConstEvalResult {
-                            ty: Type21::Bool,
+                            ty: Ty::Scalar(Type21::Bool),
                             value: RtValue::from(unsafe { value.i } != 0)
                         }
                     },
                     (Type21::Float32, Type21::Int32) => {
                         ConstEvalResult {
-                            ty: Type21::Int32,
-                            value: RtValue::from(unsafe { value.f } as i32)
+                            ty: Ty::Scalar(Type21::Int32),
+                            value: RtValue::from(float_to_int(unsafe { value.f }))
                         }
                     },
                     (Type21::Float32, Type21::Bool) => {
                         ConstEvalResult {
-                            ty: Type21::Bool,
-                            value: RtValue::from(unsafe { value.f } != 0.0)
+                            ty: Ty::Scalar(Type21::Bool),
+                            value: RtValue::from(float_to_bool(unsafe { value.f }))
                         }
                     },
                     (Type21::Bool, Type21::Int32) => {
                         ConstEvalResult {
-                            ty: Type21::Int32,
+                            ty: Ty::Scalar(Type21::Int32),
                             value: RtValue::from(if unsafe { value.b } { 1 } else { 0 })
                         }
                     },
                     (Type21::Bool, Type21::Float32) => {
                         ConstEvalResult {
-                            ty: Type21::Float32,
-                            value: RtValue::from(if unsafe { value.b } { 1.0 } else { 0.0 })
+                            ty: Ty::Scalar(Type21::Float32),
+                            value: RtValue::from(bool_to_float(unsafe { value.b }))
                         }
                     },
                     (_, _) => {
@@ -97,12 +125,12 @@ impl CodegenContext {
                     }
                 }))
             },
-            AtomicExpr::FuncCall(_) => Ok(None)
+            AtomicExpr::FuncCall(func_call) => self.consteval_func_call(func_call)
         }
     }
 
     pub fn consteval_bin_expr(
-        &self,
+        &mut self,
         bin_expr: &BinaryExpr
     ) -> Result<Option<ConstEvalResult>, String> {
         let lhs = self.consteval_expr(&bin_expr.lhs)?;
@@ -111,53 +139,79 @@ impl CodegenContext {
         let Some(lhs) = lhs else { return Ok(None) };
         let Some(rhs) = rhs else { return Ok(None) };
 
-        if lhs.ty != rhs.ty {
-            return Err(format!("二元表达式的两个操作数类型不一致 ({} 和 {})", lhs.ty, rhs.ty));
+        if let (Ty::Enum(_), _) | (_, Ty::Enum(_)) = (lhs.ty, rhs.ty) {
+            if lhs.ty != rhs.ty {
+                return Err(format!(
+                    "二元表达式的两个操作数类型不一致 ({} 和 {})",
+                    self.ty_display(lhs.ty),
+                    self.ty_display(rhs.ty)
+                ));
+            }
+
+            return match bin_expr.op {
+                BinaryOp::Eq => Ok(Some(ConstEvalResult {
+                    ty: Ty::Scalar(Type21::Bool),
+                    value: RtValue::from(unsafe { lhs.value.i == rhs.value.i })
+                })),
+                BinaryOp::Ne => Ok(Some(ConstEvalResult {
+                    ty: Ty::Scalar(Type21::Bool),
+                    value: RtValue::from(unsafe { lhs.value.i != rhs.value.i })
+                })),
+                op => Err(format!("无法对枚举类型 `{}` 应用运算 {:?}", self.ty_display(lhs.ty), op))
+            };
         }
 
+        let (Ty::Scalar(lhs_ty), Ty::Scalar(rhs_ty)) = (lhs.ty, rhs.ty) else {
+            unreachable!()
+        };
+        let ty = bin_expr.op.operand_type(lhs_ty, rhs_ty)?;
+        let lhs = ConstEvalResult { ty: Ty::Scalar(ty), value: self.consteval_promote(lhs_ty, lhs.value, ty) };
+        let rhs = ConstEvalResult { ty: Ty::Scalar(ty), value: self.consteval_promote(rhs_ty, rhs.value, ty) };
+
         match bin_expr.op {
-            BinaryOp::Add => match lhs.ty {
+            BinaryOp::Add => match ty {
                 Type21::Int32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Int32,
+                    ty: Ty::Scalar(Type21::Int32),
                     value: RtValue::from(unsafe { lhs.value.i + rhs.value.i })
                 })),
                 Type21::Float32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Float32,
+                    ty: Ty::Scalar(Type21::Float32),
                     value: RtValue::from(unsafe { lhs.value.f + rhs.value.f })
                 })),
                 Type21::Bool => Err("无法对布尔类型应用加法".into())
             },
-            BinaryOp::Sub => match lhs.ty {
+            BinaryOp::Sub => match ty {
                 Type21::Int32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Int32,
+                    ty: Ty::Scalar(Type21::Int32),
                     value: RtValue::from(unsafe { lhs.value.i - rhs.value.i })
                 })),
                 Type21::Float32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Float32,
-                    value: RtValue::from(unsafe { lhs.value.i - rhs.value.i })
+                    ty: Ty::Scalar(Type21::Float32),
+                    value: RtValue::from(unsafe { lhs.value.f - rhs.value.f })
                 })),
                 Type21::Bool => Err("无法对布尔类型应用减法".into())
             },
-            BinaryOp::Mul => match lhs.ty {
+            BinaryOp::Mul => match ty {
                 Type21::Int32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Int32,
+                    ty: Ty::Scalar(Type21::Int32),
                     value: RtValue::from(unsafe { lhs.value.i * rhs.value.i })
                 })),
                 Type21::Float32 => Ok(Some(ConstEvalResult {
-                    ty: Type21::Float32,
-                    value: RtValue::from(unsafe { lhs.value.i * rhs.value.i })
+                    ty: Ty::Scalar(Type21::Float32),
+                    value: RtValue::from(unsafe { lhs.value.f * rhs.value.f })
                 })),
                 Type21::Bool => Err("无法对布尔类型应用乘法".into())
             },
-            BinaryOp::Div => match lhs.ty {
+            BinaryOp::Div => match ty {
                 Type21::Int32 => {
-                    if unsafe { rhs.value.i == 0 } {
-                        return Err("不能除以 0".into());
-                    }
+                    let (lhs, rhs) = unsafe { (lhs.value.i, rhs.value.i) };
+                    let Some(value) = lhs.checked_div(rhs) else {
+                        return Err(int_division_fault(rhs));
+                    };
 
                     Ok(Some(ConstEvalResult {
-                        ty: Type21::Int32,
-                        value: RtValue::from(unsafe { lhs.value.i / rhs.value.i })
+                        ty: Ty::Scalar(Type21::Int32),
+                        value: RtValue::from(value)
                     }))
                 },
                 Type21::Float32 => {
@@ -166,77 +220,78 @@ impl CodegenContext {
                     }
 
                     Ok(Some(ConstEvalResult {
-                        ty: Type21::Float32,
-                        value: RtValue::from(unsafe { lhs.value.i / rhs.value.i })
+                        ty: Ty::Scalar(Type21::Float32),
+                        value: RtValue::from(unsafe { lhs.value.f / rhs.value.f })
                     }))
                 },
                 Type21::Bool => Err("无法对布尔类型应用除法".into())
             },
-            BinaryOp::Mod => match lhs.ty {
+            BinaryOp::Mod => match ty {
                 Type21::Int32 => {
-                    if unsafe { rhs.value.i == 0 } {
-                        return Err("不能除以 0".into());
-                    }
+                    let (lhs, rhs) = unsafe { (lhs.value.i, rhs.value.i) };
+                    let Some(value) = lhs.checked_rem(rhs) else {
+                        return Err(int_division_fault(rhs));
+                    };
 
                     Ok(Some(ConstEvalResult {
-                        ty: Type21::Int32,
-                        value: RtValue::from(unsafe { lhs.value.i % rhs.value.i })
+                        ty: Ty::Scalar(Type21::Int32),
+                        value: RtValue::from(value)
                     }))
                 }
                 Type21::Float32 => Err("无法对浮点类型应用取余".into()),
                 Type21::Bool => Err("无法对布尔类型应用取余".into())
             },
             BinaryOp::Eq => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
+                ty: Ty::Scalar(Type21::Bool),
                 value: RtValue::from(unsafe { lhs.value.repr == rhs.value.repr })
             })),
             BinaryOp::Ne => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
+                ty: Ty::Scalar(Type21::Bool),
                 value: RtValue::from(unsafe { lhs.value.repr != rhs.value.repr })
             })),
             BinaryOp::Lt => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
-                value: RtValue::from(match lhs.ty {
+                ty: Ty::Scalar(Type21::Bool),
+                value: RtValue::from(match ty {
                     Type21::Int32 => unsafe { lhs.value.i < rhs.value.i }
                     Type21::Float32 => unsafe { lhs.value.f < rhs.value.f }
                     Type21::Bool => unsafe { !lhs.value.b && rhs.value.b }
                 })
             })),
             BinaryOp::Le => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
-                value: RtValue::from(match lhs.ty {
+                ty: Ty::Scalar(Type21::Bool),
+                value: RtValue::from(match ty {
                     Type21::Int32 => unsafe { lhs.value.i <= rhs.value.i }
                     Type21::Float32 => unsafe { lhs.value.f <= rhs.value.f }
-                    Type21::Bool => unsafe { !(lhs.value.b && !rhs.value.b) }
+                    Type21::Bool => unsafe { !lhs.value.b || rhs.value.b }
                 })
             })),
             BinaryOp::Gt => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
-                value: RtValue::from(match lhs.ty {
+                ty: Ty::Scalar(Type21::Bool),
+                value: RtValue::from(match ty {
                     Type21::Int32 => unsafe { lhs.value.i > rhs.value.i }
                     Type21::Float32 => unsafe { lhs.value.f > rhs.value.f }
                     Type21::Bool => unsafe { lhs.value.b && !rhs.value.b }
                 })
             })),
             BinaryOp::Ge => Ok(Some(ConstEvalResult {
-                ty: Type21::Bool,
-                value: RtValue::from(match lhs.ty {
+                ty: Ty::Scalar(Type21::Bool),
+                value: RtValue::from(match ty {
                     Type21::Int32 => unsafe { lhs.value.i >= rhs.value.i }
                     Type21::Float32 => unsafe { lhs.value.f >= rhs.value.f }
-                    Type21::Bool => unsafe { !(!lhs.value.b && rhs.value.b) }
+                    Type21::Bool => unsafe { lhs.value.b || !rhs.value.b }
                 })
             })),
-            BinaryOp::And => if let Type21::Bool = lhs.ty {
+            BinaryOp::And => if let Type21::Bool = ty {
                 Ok(Some(ConstEvalResult {
-                    ty: Type21::Bool,
+                    ty: Ty::Scalar(Type21::Bool),
                     value: RtValue::from(unsafe { lhs.value.b && rhs.value.b })
                 }))
             } else {
                 Err("仅能对布尔类型应用逻辑与".into())
             }
-            BinaryOp::Or => if let Type21::Bool = lhs.ty {
+            BinaryOp::Or => if let Type21::Bool = ty {
                 Ok(Some(ConstEvalResult {
-                    ty: Type21::Bool,
+                    ty: Ty::Scalar(Type21::Bool),
                     value: RtValue::from(unsafe { lhs.value.b || rhs.value.b })
                 }))
             } else {
@@ -245,17 +300,40 @@ impl CodegenContext {
         }
     }
 
+    pub fn consteval_promote(&mut self, from: Type21, value: RtValue, to: Type21) -> RtValue {
+        if from == to {
+            return value;
+        }
+
+        let value = unsafe { value.i };
+        if self.options.warn_lossy && !int_fits_float(value) {
+            self.warn(format!("整数常量 {} 隐式转换为 float 时损失了精度", value));
+        }
+        RtValue::from(value as f32)
+    }
+
+    pub fn consteval_func_call(&mut self, func_call: &FuncCall) -> Result<Option<ConstEvalResult>, String> {
+        if is_intrinsic(&func_call.name) {
+            self.consteval_intrinsic_call(func_call)
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn consteval_unary_expr(
-        &self,
+        &mut self,
         unary_expr: &UnaryExpr
     ) -> Result<Option<ConstEvalResult>, String> {
         let Some(ConstEvalResult { ty, value }) = self.consteval_expr(&unary_expr.expr)? else {
             return Ok(None);
         };
+        let Ty::Scalar(ty) = ty else {
+            return Err(format!("无法对枚举类型 `{}` 应用一元运算", self.ty_display(ty)));
+        };
 
         match unary_expr.op {
             UnaryOp::Negate => Ok(Some(ConstEvalResult {
-                ty,
+                ty: Ty::Scalar(ty),
                 value: match ty {
                     Type21::Int32 => RtValue::from(unsafe { -value.i }),
                     Type21::Float32 => RtValue::from(unsafe { -value.f }),
@@ -264,7 +342,7 @@ impl CodegenContext {
             })),
             UnaryOp::Not => if let Type21::Bool = ty {
                 Ok(Some(ConstEvalResult {
-                    ty,
+                    ty: Ty::Scalar(ty),
                     value: RtValue::from(unsafe { !value.b })
                 }))
             } else {
@@ -0,0 +1,207 @@
+use smallvec::SmallVec;
+
+use crate::compiler::codegen::{CodegenContext, ExprResult};
+use crate::compiler::codegen::expr_consteval::ConstEvalResult;
+use crate::compiler::codegen::ty::Ty;
+use crate::compiler::parse::cst::FuncCall;
+use crate::io_ctx::Type21;
+use crate::r25_300::insc::Insc;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::value::{float_ceil_to_int, float_floor_to_int, float_round_to_int, RtValue};
+
+/// Rounding intrinsics, lowered to the dedicated conversion instructions
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Rounding {
+    Floor,
+    Ceil,
+    Round,
+    Floor2Int,
+    Ceil2Int,
+    Round2Int
+}
+
+impl Rounding {
+    pub fn eval(self, x: RtValue) -> RtValue {
+        let f = unsafe { x.f };
+        match self {
+            Rounding::Floor => RtValue::from(f.floor()),
+            Rounding::Ceil => RtValue::from(f.ceil()),
+            Rounding::Round => RtValue::from(f.round()),
+            Rounding::Floor2Int => RtValue::from(float_floor_to_int(f)),
+            Rounding::Ceil2Int => RtValue::from(float_ceil_to_int(f)),
+            Rounding::Round2Int => RtValue::from(float_round_to_int(f))
+        }
+    }
+
+    pub fn insc(self, src: usize, dst: usize) -> Insc {
+        match self {
+            Rounding::Floor => Insc::Floor { src, dst },
+            Rounding::Ceil => Insc::Ceil { src, dst },
+            Rounding::Round => Insc::Round { src, dst },
+            Rounding::Floor2Int => Insc::Floor2Int { src, dst },
+            Rounding::Ceil2Int => Insc::Ceil2Int { src, dst },
+            Rounding::Round2Int => Insc::Round2Int { src, dst }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IntrinsicOp {
+    Rounding(Rounding),
+    Unary(MathOp1),
+    Binary(MathOp2),
+    Ternary(MathOp3)
+}
+
+#[derive(Debug)]
+pub struct Intrinsic {
+    pub name: &'static str,
+    pub params: &'static [Type21],
+    pub ret: Type21,
+    pub op: IntrinsicOp
+}
+
+const I: Type21 = Type21::Int32;
+const F: Type21 = Type21::Float32;
+
+macro_rules! intrinsic {
+    ($name:literal, [$($param:ident),*] -> $ret:ident, $kind:ident($op:expr)) => {
+        Intrinsic { name: $name, params: &[$($param),*], ret: $ret, op: IntrinsicOp::$kind($op) }
+    }
+}
+
+// Intrinsics only accepting float get their int arguments promoted, like binary operators do
+pub static INTRINSICS: &[Intrinsic] = &[
+    intrinsic!("sin", [F] -> F, Unary(MathOp1::Sin)),
+    intrinsic!("cos", [F] -> F, Unary(MathOp1::Cos)),
+    intrinsic!("tan", [F] -> F, Unary(MathOp1::Tan)),
+    intrinsic!("asin", [F] -> F, Unary(MathOp1::Asin)),
+    intrinsic!("acos", [F] -> F, Unary(MathOp1::Acos)),
+    intrinsic!("atan", [F] -> F, Unary(MathOp1::Atan)),
+    intrinsic!("atan2", [F, F] -> F, Binary(MathOp2::Atan2)),
+    intrinsic!("sqrt", [F] -> F, Unary(MathOp1::Sqrt)),
+    intrinsic!("pow", [F, F] -> F, Binary(MathOp2::Pow)),
+    intrinsic!("exp", [F] -> F, Unary(MathOp1::Exp)),
+    intrinsic!("log", [F] -> F, Unary(MathOp1::Ln)),
+    intrinsic!("fmod", [F, F] -> F, Binary(MathOp2::Fmod)),
+    intrinsic!("abs", [I] -> I, Unary(MathOp1::AbsInt)),
+    intrinsic!("abs", [F] -> F, Unary(MathOp1::AbsFloat)),
+    intrinsic!("min", [I, I] -> I, Binary(MathOp2::MinInt)),
+    intrinsic!("min", [F, F] -> F, Binary(MathOp2::MinFloat)),
+    intrinsic!("max", [I, I] -> I, Binary(MathOp2::MaxInt)),
+    intrinsic!("max", [F, F] -> F, Binary(MathOp2::MaxFloat)),
+    intrinsic!("clamp", [I, I, I] -> I, Ternary(MathOp3::ClampInt)),
+    intrinsic!("clamp", [F, F, F] -> F, Ternary(MathOp3::ClampFloat)),
+    intrinsic!("floor", [F] -> F, Rounding(Rounding::Floor)),
+    intrinsic!("ceil", [F] -> F, Rounding(Rounding::Ceil)),
+    intrinsic!("round", [F] -> F, Rounding(Rounding::Round)),
+    intrinsic!("ifloor", [F] -> I, Rounding(Rounding::Floor2Int)),
+    intrinsic!("iceil", [F] -> I, Rounding(Rounding::Ceil2Int)),
+    intrinsic!("iround", [F] -> I, Rounding(Rounding::Round2Int)),
+    intrinsic!("lerp", [F, F, F] -> F, Ternary(MathOp3::Lerp)),
+    intrinsic!("smoothstep", [F, F, F] -> F, Ternary(MathOp3::Smoothstep)),
+    intrinsic!("ease_in_quad", [F] -> F, Unary(MathOp1::EaseInQuad)),
+    intrinsic!("ease_out_quad", [F] -> F, Unary(MathOp1::EaseOutQuad)),
+    intrinsic!("ease_in_out_quad", [F] -> F, Unary(MathOp1::EaseInOutQuad)),
+    intrinsic!("ease_in_cubic", [F] -> F, Unary(MathOp1::EaseInCubic)),
+    intrinsic!("ease_out_cubic", [F] -> F, Unary(MathOp1::EaseOutCubic)),
+    intrinsic!("ease_in_out_cubic", [F] -> F, Unary(MathOp1::EaseInOutCubic)),
+    intrinsic!("ease_in_sine", [F] -> F, Unary(MathOp1::EaseInSine)),
+    intrinsic!("ease_out_sine", [F] -> F, Unary(MathOp1::EaseOutSine)),
+    intrinsic!("ease_in_out_sine", [F] -> F, Unary(MathOp1::EaseInOutSine)),
+    intrinsic!("ease_in_expo", [F] -> F, Unary(MathOp1::EaseInExpo)),
+    intrinsic!("ease_out_expo", [F] -> F, Unary(MathOp1::EaseOutExpo)),
+    intrinsic!("ease_in_out_expo", [F] -> F, Unary(MathOp1::EaseInOutExpo)),
+];
+
+pub fn is_intrinsic(name: &str) -> bool {
+    INTRINSICS.iter().any(|intrinsic| intrinsic.name == name)
+}
+
+impl CodegenContext {
+    /// An exact overload wins; otherwise int arguments are promoted to the all-float overload
+    fn resolve_intrinsic(&self, name: &str, arg_tys: &[Ty]) -> Result<&'static Intrinsic, String> {
+        let candidates = INTRINSICS.iter()
+            .filter(|intrinsic| intrinsic.name == name && intrinsic.params.len() == arg_tys.len())
+            .collect::<SmallVec<[&Intrinsic; 2]>>();
+        if candidates.is_empty() {
+            let arity = INTRINSICS.iter().find(|intrinsic| intrinsic.name == name).unwrap().params.len();
+            return Err(format!(
+                "内建函数 `{}` 需要 {} 个参数，但提供了 {} 个",
+                name,
+                arity,
+                arg_tys.len()
+            ));
+        }
+
+        if let Some(exact) = candidates.iter().find(|intrinsic| {
+            intrinsic.params.iter().zip(arg_tys).all(|(param, arg)| Ty::Scalar(*param) == *arg)
+        }) {
+            return Ok(exact);
+        }
+
+        let numeric = arg_tys.iter().all(|ty| matches!(ty, Ty::Scalar(Type21::Int32 | Type21::Float32)));
+        let promoted = candidates.iter().find(|intrinsic| {
+            intrinsic.params.iter().all(|param| *param == Type21::Float32)
+        });
+        if let (true, Some(promoted)) = (numeric, promoted) {
+            return Ok(promoted);
+        }
+
+        Err(format!(
+            "内建函数 `{}` 不接受参数类型 ({})",
+            name,
+            arg_tys.iter().map(|ty| self.ty_display(*ty).to_string()).collect::<Vec<_>>().join(", ")
+        ))
+    }
+
+    pub fn consteval_intrinsic_call(
+        &mut self,
+        func_call: &FuncCall
+    ) -> Result<Option<ConstEvalResult>, String> {
+        let mut args = SmallVec::<[ConstEvalResult; 3]>::new();
+        for arg in func_call.args.iter() {
+            let Some(arg) = self.consteval_expr(arg)? else {
+                return Ok(None);
+            };
+            args.push(arg);
+        }
+
+        let arg_tys = args.iter().map(|arg| arg.ty).collect::<SmallVec<[Ty; 3]>>();
+        let intrinsic = self.resolve_intrinsic(&func_call.name, &arg_tys)?;
+        let args = args.iter().zip(intrinsic.params).map(|(arg, param)| {
+            let Ty::Scalar(ty) = arg.ty else { unreachable!() };
+            self.consteval_promote(ty, arg.value, *param)
+        }).collect::<SmallVec<[_; 3]>>();
+
+        let value = match intrinsic.op {
+            IntrinsicOp::Rounding(op) => op.eval(args[0]),
+            IntrinsicOp::Unary(op) => op.eval(args[0]),
+            IntrinsicOp::Binary(op) => op.eval(args[0], args[1]),
+            IntrinsicOp::Ternary(op) => op.eval(args[0], args[1], args[2])
+        };
+        Ok(Some(ConstEvalResult { ty: Ty::Scalar(intrinsic.ret), value }))
+    }
+
+    pub fn codegen_intrinsic_call(&mut self, func_call: &FuncCall) -> Result<ExprResult, String> {
+        let mut args = SmallVec::<[ExprResult; 3]>::new();
+        for arg in func_call.args.iter() {
+            args.push(self.codegen_expr(arg)?);
+        }
+
+        let arg_tys = args.iter().map(|arg| arg.ty).collect::<SmallVec<[Ty; 3]>>();
+        let intrinsic = self.resolve_intrinsic(&func_call.name, &arg_tys)?;
+        let args = args.into_iter().zip(intrinsic.params).map(|(arg, param)| {
+            self.codegen_promote(arg, *param)
+        }).collect::<SmallVec<[usize; 3]>>();
+
+        let dst = self.alloc_temp(1);
+        self.emit(match intrinsic.op {
+            IntrinsicOp::Rounding(op) => op.insc(args[0], dst),
+            IntrinsicOp::Unary(op) => Insc::Math1 { op, src: args[0], dst },
+            IntrinsicOp::Binary(op) => Insc::Math2 { op, lhs: args[0], rhs: args[1], dst },
+            IntrinsicOp::Ternary(op) => Insc::Math3 { op, a: args[0], b: args[1], c: args[2], dst }
+        });
+        Ok(ExprResult { ty: Ty::Scalar(intrinsic.ret), value_loc: dst, consteval_value: None })
+    }
+}
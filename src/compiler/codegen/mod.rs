@@ -1,14 +1,23 @@
 pub mod decl;
 pub mod expr;
 pub mod expr_consteval;
+pub mod intrinsic;
 pub mod stmt;
+pub mod ty;
 
 use std::collections::HashMap;
 
 use crate::compiler::codegen::decl::{CompilingFunction, FunctionInfo};
 use crate::compiler::codegen::expr_consteval::ConstEvalResult;
-use crate::io_ctx::Type21;
+use crate::compiler::codegen::intrinsic::is_intrinsic;
+use crate::compiler::codegen::ty::{EnumInfo, StructInfo, Ty};
+use crate::compiler::CompileOptions;
+use crate::compiler::parse::cst::Program;
+use crate::io_ctx::{IOContextMetadata, IOType};
+use crate::native::NativeRegistry;
 use crate::r25_300::compiled::Compiled;
+use crate::r25_300::debug::{DebugInfo, FuncDebug};
+use crate::r25_300::insc::Insc;
 use crate::value::RtValue;
 
 #[derive(Debug)]
@@ -17,28 +26,240 @@ pub struct CodegenContext {
 
     constant: HashMap<String, ConstEvalResult>,
     declared_func: HashMap<String, FunctionInfo>,
-    compiling_func: Option<CompilingFunction>
+    // `func_id` of an extern function is its index in `compiled.imports`
+    declared_extern: HashMap<String, FunctionInfo>,
+    declared_struct: HashMap<String, usize>,
+    structs: Vec<StructInfo>,
+    declared_enum: HashMap<String, usize>,
+    enums: Vec<EnumInfo>,
+    io_metadata: IOContextMetadata,
+    compiling_func: Option<CompilingFunction>,
+    // `func_debug[i]` describes `compiled.func[i]`
+    func_debug: Vec<FuncDebug>,
+    // source position of the declaration or statement being compiled, for warnings and the line
+    // table
+    line: usize,
+    col: usize,
+
+    pub natives: NativeRegistry,
+    pub options: CompileOptions,
+    pub warnings: Vec<String>
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct ExprResult {
-    pub ty: Type21,
+    pub ty: Ty,
     pub value_loc: usize,
     pub consteval_value: Option<RtValue>
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct IOVarInfo {
+    pub offset: usize,
+    pub ty: Ty
+}
+
 impl CodegenContext {
     pub fn new() -> Self {
+        Self::with_io_metadata(Vec::new())
+    }
+
+    pub fn with_io_metadata(io_metadata: IOContextMetadata) -> Self {
         Self {
             compiled: Compiled::new(),
             constant: HashMap::new(),
             declared_func: HashMap::new(),
-            compiling_func: None
+            declared_extern: HashMap::new(),
+            declared_struct: HashMap::new(),
+            structs: Vec::new(),
+            declared_enum: HashMap::new(),
+            enums: Vec::new(),
+            io_metadata,
+            compiling_func: None,
+            func_debug: Vec::new(),
+            line: 0,
+            col: 0,
+
+            natives: NativeRegistry::new(),
+            options: CompileOptions::default(),
+            warnings: Vec::new()
         }
     }
 
     pub fn take(self) -> Compiled {
-        self.compiled
+        let mut compiled = self.compiled;
+        compiled.debug = Some(DebugInfo { file: String::new(), funcs: self.func_debug });
+        compiled
+    }
+
+    pub fn visit_program(&mut self, program: &Program) -> Result<(), String> {
+        if let Some(native) = self.natives.natives().iter().find(|native| is_intrinsic(&native.name)) {
+            return Err(format!("宿主函数 `{}` 与内建函数重名", native.name));
+        }
+
+        for enum_decl in program.enum_decl.iter() {
+            self.visit_enum_decl(enum_decl)?;
+        }
+
+        for struct_decl in program.struct_decl.iter() {
+            self.visit_struct_decl(struct_decl)?;
+        }
+
+        for const_decl in program.const_decl.iter() {
+            self.visit_const_decl(const_decl)?;
+        }
+
+        for extern_decl in program.extern_decl.iter() {
+            self.visit_extern_decl(extern_decl)?;
+        }
+
+        for func_decl in program.func_decl.iter() {
+            self.visit_func_decl(func_decl)?;
+        }
+
+        for func_decl in program.func_decl.iter() {
+            if !self.declared_func[&func_decl.name].defined {
+                return Err(format!(
+                    "行 {}: 函数 `{}` 只有声明而没有定义",
+                    func_decl.line,
+                    func_decl.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup_io_var(&self, name: &str) -> Result<Option<IOVarInfo>, String> {
+        let mut offset = 0;
+        for (rename, _, io_ty) in self.io_metadata.iter() {
+            if rename == name {
+                let ty = match io_ty {
+                    IOType::Scalar(ty) => Ty::Scalar(*ty),
+                    IOType::Struct(struct_name, fields) => {
+                        let Some(struct_id) = self.declared_struct.get(struct_name) else {
+                            return Err(format!(
+                                "IO 变量 `{}` 的类型 `{}` 没有对应的结构体定义",
+                                name,
+                                struct_name
+                            ));
+                        };
+
+                        if !self.check_io_struct(*struct_id, fields) {
+                            return Err(format!(
+                                "结构体 `{}` 的定义与宿主中的 IO 结构不一致",
+                                struct_name
+                            ));
+                        }
+
+                        Ty::Struct(*struct_id)
+                    },
+                    IOType::Enum(enum_name, variants) => {
+                        let Some(enum_id) = self.declared_enum.get(enum_name) else {
+                            return Err(format!(
+                                "IO 变量 `{}` 的类型 `{}` 没有对应的枚举定义",
+                                name,
+                                enum_name
+                            ));
+                        };
+
+                        if !self.check_io_enum(*enum_id, variants) {
+                            return Err(format!(
+                                "枚举 `{}` 的定义与宿主中的枚举不一致",
+                                enum_name
+                            ));
+                        }
+
+                        Ty::Enum(*enum_id)
+                    }
+                };
+
+                return Ok(Some(IOVarInfo { offset, ty }));
+            }
+
+            offset += io_ty.size();
+        }
+
+        Ok(None)
+    }
+
+    fn check_io_struct(&self, struct_id: usize, fields: &IOContextMetadata) -> bool {
+        let struct_info = &self.structs[struct_id];
+        if struct_info.fields.len() != fields.len() {
+            return false;
+        }
+
+        for (field, (rename, _, io_ty)) in struct_info.fields.iter().zip(fields.iter()) {
+            if &field.name != rename {
+                return false;
+            }
+
+            match (field.ty, io_ty) {
+                (Ty::Scalar(ty), IOType::Scalar(io_ty)) if ty == *io_ty => {},
+                (Ty::Struct(field_struct_id), IOType::Struct(struct_name, io_fields))
+                    if &self.structs[field_struct_id].name == struct_name
+                        && self.check_io_struct(field_struct_id, io_fields) => {},
+                (Ty::Enum(field_enum_id), IOType::Enum(enum_name, variants))
+                    if &self.enums[field_enum_id].name == enum_name
+                        && self.check_io_enum(field_enum_id, variants) => {},
+                _ => return false
+            }
+        }
+
+        true
+    }
+
+    fn warn(&mut self, message: String) {
+        let message = format!("行 {}: {}", self.line, message);
+        if !self.warnings.contains(&message) {
+            self.warnings.push(message);
+        }
+    }
+
+    fn check_io_enum(&self, enum_id: usize, variants: &[(String, i32)]) -> bool {
+        self.enums[enum_id].variants.as_slice() == variants
+    }
+
+    fn emit(&mut self, insc: Insc) -> usize {
+        self.compiled.code.push(insc);
+        let addr = self.compiled.code.len() - 1;
+        if let Some(compiling_func) = &mut self.compiling_func {
+            compiling_func.add_line(addr, self.line, self.col);
+        }
+        addr
+    }
+
+    fn current_addr(&self) -> usize {
+        self.compiled.code.len()
+    }
+
+    fn patch_jmp(&mut self, addr: usize, target: usize) {
+        match &mut self.compiled.code[addr] {
+            Insc::Jmp { dst } | Insc::JmpIf { dst, .. } | Insc::TryBegin { handler: dst } => *dst = target,
+            _ => unreachable!()
+        }
+    }
+
+    fn compiling_func(&mut self) -> &mut CompilingFunction {
+        self.compiling_func.as_mut().unwrap()
+    }
+
+    fn alloc_temp(&mut self, size: usize) -> usize {
+        self.compiling_func().alloc(size)
+    }
+
+    fn stack_mark(&self) -> usize {
+        self.compiling_func.as_ref().unwrap().stack_usage
+    }
+
+    fn stack_release(&mut self, mark: usize) {
+        self.compiling_func().stack_usage = mark;
+    }
+}
+
+impl Default for CodegenContext {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
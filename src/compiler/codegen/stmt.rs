@@ -1,20 +1,507 @@
+use smallvec::SmallVec;
+
 use crate::compiler::codegen::CodegenContext;
+use crate::compiler::codegen::decl::LoopInfo;
+use crate::compiler::codegen::expr_consteval::ConstEvalResult;
+use crate::compiler::codegen::ty::Ty;
 use crate::compiler::parse::cst::*;
+use crate::io_ctx::Type21;
+use crate::r25_300::insc::Insc;
 
 impl CodegenContext {
     pub fn codegen_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        if let Some((line, col)) = stmt_pos(stmt) {
+            self.line = line;
+            self.col = col;
+        }
+
         match stmt {
             Stmt::DeclStmt(var_decl) => self.visit_var_decl(var_decl),
-            Stmt::ExprStmt(expr, line) => self.codegen_expr(expr).map_err(|e| format!("行 {}: {}", line, e)),
+            Stmt::ExprStmt(expr, line, _) => self.codegen_expr_stmt(expr).map_err(|e| format!("行 {}: {}", line, e)),
             Stmt::IfStmt(if_stmt) => self.codegen_if_stmt(if_stmt),
             Stmt::BlockStmt(block_stmt) => self.codegen_block_stmt(block_stmt),
             Stmt::WhileStmt(while_stmt) => self.codegen_while_stmt(while_stmt),
             Stmt::ForStmt(for_stmt) => self.codegen_for_stmt(for_stmt),
-            Stmt::ReturnStmt(return_stmt) => self.codegen_return_stmt(return_stmt),
-            Stmt::MultiReturnStmt(return_stmt) => self.codegen_multi_return_stmt(return_stmt),
-            Stmt::BreakStmt(break_stmt) => self.codegen_break_stmt(break_stmt),
-            Stmt::ContinueStmt(continue_stmt) => self.codegen_continue_stmt(continue_stmt),
-            Stmt::YieldStmt(yield_stmt) => self.codegen_yield_stmt(yield_stmt)
+            Stmt::SwitchStmt(switch_stmt) => self.codegen_switch_stmt(switch_stmt),
+            Stmt::TryStmt(try_stmt) => self.codegen_try_stmt(try_stmt),
+            Stmt::ReturnStmt(return_stmt, line, _) => self.codegen_return_stmt(return_stmt.as_ref(), *line),
+            Stmt::MultiReturnStmt(return_stmt, line, _) => self.codegen_multi_return_stmt(return_stmt, *line),
+            Stmt::BreakStmt(break_stmt, _) => self.codegen_break_stmt(*break_stmt),
+            Stmt::ContinueStmt(continue_stmt, _) => self.codegen_continue_stmt(*continue_stmt),
+            Stmt::YieldStmt(expr, line, _) => self.codegen_yield_stmt(expr.as_ref(), *line),
+            Stmt::MultiYieldStmt(names, line, _) => self.codegen_multi_yield_stmt(names, *line),
+            Stmt::YieldFromStmt(func_call, line, _) => self.codegen_yield_from_stmt(func_call, *line),
+            Stmt::JoinStmt(expr, line, _) => self.codegen_join_stmt(expr, *line),
+            Stmt::ParallelStmt(body, line, _) => self.codegen_parallel_stmt(body, *line)
+        }
+    }
+
+    fn codegen_expr_stmt(&mut self, expr: &Expr) -> Result<(), String> {
+        let mark = self.stack_mark();
+        match expr {
+            Expr::FuncCall(func_call) => { self.codegen_func_call(func_call)?; },
+            Expr::MultiAssignExpr(multi_assign_expr) => self.codegen_multi_assign_expr(multi_assign_expr)?,
+            _ => { self.codegen_expr(expr)?; }
+        }
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    fn codegen_cond(&mut self, cond: &Expr, line: usize) -> Result<usize, String> {
+        let cond = self.codegen_expr(cond).map_err(|e| format!("行 {}: {}", line, e))?;
+        if cond.ty != Ty::Scalar(Type21::Bool) {
+            return Err(format!("行 {}: 条件表达式必须是布尔类型，实际为 `{}`", line, self.ty_display(cond.ty)));
+        }
+
+        Ok(cond.value_loc)
+    }
+
+    fn codegen_scoped_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+        self.compiling_func().push_frame();
+        self.codegen_stmt(stmt)?;
+        let end_addr = self.current_addr();
+        self.compiling_func().pop_frame(end_addr);
+        Ok(())
+    }
+
+    pub fn codegen_if_stmt(&mut self, if_stmt: &IfStmt) -> Result<(), String> {
+        let mark = self.stack_mark();
+        let check = self.codegen_cond(&if_stmt.cond, if_stmt.line)?;
+        let jmp_then = self.emit(Insc::JmpIf { check, dst: 0 });
+        self.stack_release(mark);
+
+        if let Some(else_) = &if_stmt.else_ {
+            self.codegen_scoped_stmt(else_)?;
+        }
+        let jmp_end = self.emit(Insc::Jmp { dst: 0 });
+
+        self.patch_jmp(jmp_then, self.current_addr());
+        self.codegen_scoped_stmt(&if_stmt.then)?;
+        self.patch_jmp(jmp_end, self.current_addr());
+
+        Ok(())
+    }
+
+    pub fn codegen_block_stmt(&mut self, block_stmt: &BlockStmt) -> Result<(), String> {
+        self.compiling_func().push_frame();
+        for stmt in block_stmt.stmts.iter() {
+            self.codegen_stmt(stmt)?;
+        }
+        let end_addr = self.current_addr();
+        self.compiling_func().pop_frame(end_addr);
+
+        Ok(())
+    }
+
+    pub fn codegen_while_stmt(&mut self, while_stmt: &WhileStmt) -> Result<(), String> {
+        let cond_addr = self.current_addr();
+        let mark = self.stack_mark();
+        let check = self.codegen_cond(&while_stmt.cond, while_stmt.line)?;
+        let negated = self.alloc_temp(1);
+        self.emit(Insc::Not { src: check, dst: negated });
+        let jmp_end = self.emit(Insc::JmpIf { check: negated, dst: 0 });
+        self.stack_release(mark);
+
+        let try_depth = self.compiling_func().try_depth;
+        self.compiling_func().loops.push(LoopInfo { try_depth, ..LoopInfo::default() });
+        self.codegen_scoped_stmt(&while_stmt.body)?;
+        self.line = while_stmt.line;
+        self.col = while_stmt.col;
+        self.emit(Insc::Jmp { dst: cond_addr });
+        let loop_info = self.compiling_func().loops.pop().unwrap();
+
+        let end_addr = self.current_addr();
+        self.patch_jmp(jmp_end, end_addr);
+        self.patch_loop(loop_info, cond_addr, end_addr);
+
+        Ok(())
+    }
+
+    pub fn codegen_for_stmt(&mut self, for_stmt: &ForStmt) -> Result<(), String> {
+        if let Some(init) = &for_stmt.init {
+            self.codegen_expr_stmt(init).map_err(|e| format!("行 {}: {}", for_stmt.line, e))?;
+        }
+
+        let cond_addr = self.current_addr();
+        let jmp_end = if let Some(cond) = &for_stmt.cond {
+            let mark = self.stack_mark();
+            let check = self.codegen_cond(cond, for_stmt.line)?;
+            let negated = self.alloc_temp(1);
+            self.emit(Insc::Not { src: check, dst: negated });
+            let jmp_end = self.emit(Insc::JmpIf { check: negated, dst: 0 });
+            self.stack_release(mark);
+            Some(jmp_end)
+        } else {
+            None
+        };
+
+        let try_depth = self.compiling_func().try_depth;
+        self.compiling_func().loops.push(LoopInfo { try_depth, ..LoopInfo::default() });
+        self.codegen_scoped_stmt(&for_stmt.body)?;
+        let loop_info = self.compiling_func().loops.pop().unwrap();
+
+        let step_addr = self.current_addr();
+        self.line = for_stmt.line;
+        self.col = for_stmt.col;
+        if let Some(step) = &for_stmt.step {
+            self.codegen_expr_stmt(step).map_err(|e| format!("行 {}: {}", for_stmt.line, e))?;
+        }
+        self.emit(Insc::Jmp { dst: cond_addr });
+
+        let end_addr = self.current_addr();
+        if let Some(jmp_end) = jmp_end {
+            self.patch_jmp(jmp_end, end_addr);
+        }
+        self.patch_loop(loop_info, step_addr, end_addr);
+
+        Ok(())
+    }
+
+    pub fn codegen_switch_stmt(&mut self, switch_stmt: &SwitchStmt) -> Result<(), String> {
+        let line = switch_stmt.line;
+        let mark = self.stack_mark();
+        let cond = self.codegen_expr(&switch_stmt.cond).map_err(|e| format!("行 {}: {}", line, e))?;
+        if !matches!(cond.ty, Ty::Scalar(Type21::Int32) | Ty::Enum(_)) {
+            return Err(format!(
+                "行 {}: switch 的条件必须是整数或枚举类型，实际为 `{}`",
+                line,
+                self.ty_display(cond.ty)
+            ));
+        }
+
+        let case_value = self.alloc_temp(1);
+        let check = self.alloc_temp(1);
+        let mut covered: SmallVec<[i32; 8]> = SmallVec::new();
+        let mut case_jmps: SmallVec<[SmallVec<[usize; 1]>; 4]> = SmallVec::new();
+        for case in switch_stmt.cases.iter() {
+            let mut jmps = SmallVec::new();
+            for value in case.values.iter() {
+                let Some(ConstEvalResult { ty, value }) = self.consteval_expr(value)
+                    .map_err(|e| format!("行 {}: {}", case.line, e))? else {
+                    return Err(format!("行 {}: case 标签必须是常量表达式", case.line));
+                };
+                self.check_assign_type(cond.ty, ty).map_err(|e| format!("行 {}: {}", case.line, e))?;
+
+                let raw = unsafe { value.i };
+                if covered.contains(&raw) {
+                    return Err(format!("行 {}: 重复的 case 标签", case.line));
+                }
+                covered.push(raw);
+
+                self.emit(Insc::Const { value, dst: case_value });
+                self.emit(Insc::Eq { lhs: cond.value_loc, rhs: case_value, dst: check });
+                jmps.push(self.emit(Insc::JmpIf { check, dst: 0 }));
+            }
+            case_jmps.push(jmps);
         }
+        let jmp_default = self.emit(Insc::Jmp { dst: 0 });
+        self.stack_release(mark);
+
+        if let (Ty::Enum(enum_id), None) = (cond.ty, &switch_stmt.default) {
+            let enum_info = &self.enums[enum_id];
+            let missing = enum_info.variants.iter()
+                .filter(|(_, value)| !covered.contains(value))
+                .map(|(name, _)| format!("`{}`", name))
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                let message = format!(
+                    "switch 语句没有处理枚举 `{}` 的成员 {}",
+                    enum_info.name,
+                    missing.join(", ")
+                );
+                self.warn(message);
+            }
+        }
+
+        let try_depth = self.compiling_func().try_depth;
+        self.compiling_func().loops.push(LoopInfo { is_switch: true, try_depth, ..LoopInfo::default() });
+        let mut end_jmps: SmallVec<[usize; 4]> = SmallVec::new();
+        for (case, jmps) in switch_stmt.cases.iter().zip(case_jmps) {
+            let case_addr = self.current_addr();
+            for jmp in jmps {
+                self.patch_jmp(jmp, case_addr);
+            }
+            self.codegen_case_body(&case.body)?;
+            end_jmps.push(self.emit(Insc::Jmp { dst: 0 }));
+        }
+
+        if let Some(default) = &switch_stmt.default {
+            self.patch_jmp(jmp_default, self.current_addr());
+            self.codegen_case_body(default)?;
+        } else {
+            end_jmps.push(jmp_default);
+        }
+        let loop_info = self.compiling_func().loops.pop().unwrap();
+
+        let end_addr = self.current_addr();
+        for jmp in end_jmps {
+            self.patch_jmp(jmp, end_addr);
+        }
+        for jmp in loop_info.break_patches {
+            self.patch_jmp(jmp, end_addr);
+        }
+
+        Ok(())
+    }
+
+    fn codegen_case_body(&mut self, body: &[Stmt]) -> Result<(), String> {
+        self.compiling_func().push_frame();
+        for stmt in body {
+            self.codegen_stmt(stmt)?;
+        }
+        let end_addr = self.current_addr();
+        self.compiling_func().pop_frame(end_addr);
+        Ok(())
+    }
+
+    pub fn codegen_try_stmt(&mut self, try_stmt: &TryStmt) -> Result<(), String> {
+        let try_begin = self.emit(Insc::TryBegin { handler: 0 });
+        self.compiling_func().try_depth += 1;
+        self.codegen_block_stmt(&try_stmt.body)?;
+        self.compiling_func().try_depth -= 1;
+        self.emit(Insc::TryEnd);
+        let jmp_end = self.emit(Insc::Jmp { dst: 0 });
+
+        // the VM removes the handler itself before jumping here
+        self.patch_jmp(try_begin, self.current_addr());
+        self.codegen_block_stmt(&try_stmt.handler)?;
+        self.patch_jmp(jmp_end, self.current_addr());
+
+        Ok(())
+    }
+
+    fn leave_try_blocks(&mut self, outer_depth: usize) {
+        for _ in outer_depth..self.compiling_func().try_depth {
+            self.emit(Insc::TryEnd);
+        }
+    }
+
+    fn patch_loop(&mut self, loop_info: LoopInfo, continue_addr: usize, break_addr: usize) {
+        for addr in loop_info.continue_patches {
+            self.patch_jmp(addr, continue_addr);
+        }
+
+        for addr in loop_info.break_patches {
+            self.patch_jmp(addr, break_addr);
+        }
+    }
+
+    pub fn codegen_return_stmt(&mut self, expr: Option<&Expr>, line: usize) -> Result<(), String> {
+        let func_info = &self.compiling_func.as_ref().unwrap().func_info;
+        let func_name = func_info.name.clone();
+        let ret_ty = func_info.ty.clone();
+
+        let mark = self.stack_mark();
+        let rets = match (expr, ret_ty.len()) {
+            (None, 0) => Box::new([]) as Box<[usize]>,
+            (None, _) => return Err(format!("行 {}: 函数 `{}` 需要返回值", line, func_name)),
+            (Some(_), 0) => return Err(format!("行 {}: 函数 `{}` 没有返回值", line, func_name)),
+            (Some(expr), 1) => {
+                let value = self.codegen_expr(expr).map_err(|e| format!("行 {}: {}", line, e))?;
+                self.check_assign_type(ret_ty[0], value.ty).map_err(|e| format!("行 {}: {}", line, e))?;
+                (value.value_loc..value.value_loc + self.ty_size(value.ty)).collect()
+            },
+            (Some(_), count) => return Err(format!(
+                "行 {}: 函数 `{}` 需要返回 {} 个值",
+                line,
+                func_name,
+                count
+            ))
+        };
+
+        self.leave_try_blocks(0);
+        self.emit(Insc::Return { rets });
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_multi_return_stmt(&mut self, names: &SmallVec<[String; 2]>, line: usize) -> Result<(), String> {
+        let func_info = &self.compiling_func.as_ref().unwrap().func_info;
+        let func_name = func_info.name.clone();
+        let ret_ty = func_info.ty.clone();
+
+        if ret_ty.len() != names.len() {
+            return Err(format!(
+                "行 {}: 函数 `{}` 需要返回 {} 个值，实际返回了 {} 个",
+                line,
+                func_name,
+                ret_ty.len(),
+                names.len()
+            ));
+        }
+
+        let mark = self.stack_mark();
+        let mut rets = Vec::new();
+        for (name, ty) in names.iter().zip(ret_ty.iter()) {
+            let value = self.codegen_atomic_expr(&AtomicExpr::Ident(name.clone()))
+                .map_err(|e| format!("行 {}: {}", line, e))?;
+            self.check_assign_type(*ty, value.ty).map_err(|e| format!("行 {}: {}", line, e))?;
+            rets.extend(value.value_loc..value.value_loc + self.ty_size(value.ty));
+        }
+
+        self.leave_try_blocks(0);
+        self.emit(Insc::Return { rets: rets.into_boxed_slice() });
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_break_stmt(&mut self, line: usize) -> Result<(), String> {
+        let Some(loop_info) = self.compiling_func().loops.last() else {
+            return Err(format!("行 {}: `break` 只能出现在循环或 switch 中", line));
+        };
+
+        let outer_depth = loop_info.try_depth;
+        self.leave_try_blocks(outer_depth);
+        let addr = self.emit(Insc::Jmp { dst: 0 });
+        self.compiling_func().loops.last_mut().unwrap().break_patches.push(addr);
+        Ok(())
+    }
+
+    pub fn codegen_continue_stmt(&mut self, line: usize) -> Result<(), String> {
+        let Some(loop_idx) = self.compiling_func().loops.iter().rposition(|l| !l.is_switch) else {
+            return Err(format!("行 {}: `continue` 只能出现在循环中", line));
+        };
+
+        let outer_depth = self.compiling_func().loops[loop_idx].try_depth;
+        self.leave_try_blocks(outer_depth);
+        let addr = self.emit(Insc::Jmp { dst: 0 });
+        self.compiling_func().loops[loop_idx].continue_patches.push(addr);
+        Ok(())
+    }
+
+    pub fn codegen_yield_stmt(&mut self, expr: Option<&Expr>, line: usize) -> Result<(), String> {
+        let func_info = &self.compiling_func.as_ref().unwrap().func_info;
+        let func_name = func_info.name.clone();
+        let yield_ty = func_info.yield_ty.clone();
+
+        let mark = self.stack_mark();
+        let values = match (expr, yield_ty.len()) {
+            (None, 0) => Box::new([]) as Box<[usize]>,
+            (None, _) => return Err(format!("行 {}: 函数 `{}` 需要 yield 值", line, func_name)),
+            (Some(_), 0) => return Err(format!("行 {}: 函数 `{}` 没有声明 yield 的值", line, func_name)),
+            (Some(expr), 1) => {
+                let value = self.codegen_expr(expr).map_err(|e| format!("行 {}: {}", line, e))?;
+                self.check_assign_type(yield_ty[0], value.ty).map_err(|e| format!("行 {}: {}", line, e))?;
+                Box::new([value.value_loc])
+            },
+            (Some(_), count) => return Err(format!(
+                "行 {}: 函数 `{}` 需要 yield {} 个值",
+                line,
+                func_name,
+                count
+            ))
+        };
+
+        self.emit(Insc::Yield { values });
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_multi_yield_stmt(&mut self, names: &SmallVec<[String; 2]>, line: usize) -> Result<(), String> {
+        let func_info = &self.compiling_func.as_ref().unwrap().func_info;
+        let func_name = func_info.name.clone();
+        let yield_ty = func_info.yield_ty.clone();
+
+        if yield_ty.len() != names.len() {
+            return Err(format!(
+                "行 {}: 函数 `{}` 需要 yield {} 个值，实际 yield 了 {} 个",
+                line,
+                func_name,
+                yield_ty.len(),
+                names.len()
+            ));
+        }
+
+        let mark = self.stack_mark();
+        let mut values = Vec::new();
+        for (name, ty) in names.iter().zip(yield_ty.iter()) {
+            let value = self.codegen_atomic_expr(&AtomicExpr::Ident(name.clone()))
+                .map_err(|e| format!("行 {}: {}", line, e))?;
+            self.check_assign_type(*ty, value.ty).map_err(|e| format!("行 {}: {}", line, e))?;
+            values.push(value.value_loc);
+        }
+
+        self.emit(Insc::Yield { values: values.into_boxed_slice() });
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_yield_from_stmt(&mut self, func_call: &FuncCall, line: usize) -> Result<(), String> {
+        let func_info = &self.compiling_func.as_ref().unwrap().func_info;
+        let func_name = func_info.name.clone();
+        let yield_ty = func_info.yield_ty.clone();
+
+        let callee = self.resolve_script_func(func_call, "yield from").map_err(|e| format!("行 {}: {}", line, e))?;
+        if !callee.yield_ty.is_empty() && callee.yield_ty != yield_ty {
+            return Err(format!(
+                "行 {}: 函数 `{}` yield 的值与函数 `{}` 声明的不一致",
+                line,
+                callee.name,
+                func_name
+            ));
+        }
+
+        // the callee's `yield`s suspend the whole stack, so this is just a call
+        let mark = self.stack_mark();
+        let args = self.codegen_call_args(&callee, func_call).map_err(|e| format!("行 {}: {}", line, e))?;
+        self.codegen_call_insc(&callee, false, args)?;
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_join_stmt(&mut self, expr: &Expr, line: usize) -> Result<(), String> {
+        let mark = self.stack_mark();
+        let task = self.codegen_expr(expr).map_err(|e| format!("行 {}: {}", line, e))?;
+        if task.ty != Ty::Scalar(Type21::Int32) {
+            return Err(format!("行 {}: `join` 需要 spawn 返回的 int 类型任务编号，实际为 `{}`", line, self.ty_display(task.ty)));
+        }
+
+        self.emit(Insc::Join { task: task.value_loc });
+        self.stack_release(mark);
+        Ok(())
+    }
+
+    pub fn codegen_parallel_stmt(&mut self, body: &BlockStmt, line: usize) -> Result<(), String> {
+        let mark = self.stack_mark();
+        let mut tasks = Vec::new();
+        for stmt in body.stmts.iter() {
+            let Stmt::ExprStmt(Expr::FuncCall(func_call), call_line, call_col) = stmt else {
+                let line = stmt_pos(stmt).map_or(line, |(line, _)| line);
+                return Err(format!("行 {}: parallel 块中只能包含函数调用", line));
+            };
+
+            self.line = *call_line;
+            self.col = *call_col;
+            let task = self.codegen_spawn_expr(func_call).map_err(|e| format!("行 {}: {}", call_line, e))?;
+            tasks.push(task.value_loc);
+        }
+
+        for task in tasks {
+            self.emit(Insc::Join { task });
+        }
+        self.stack_release(mark);
+        Ok(())
+    }
+}
+
+fn stmt_pos(stmt: &Stmt) -> Option<(usize, usize)> {
+    match stmt {
+        Stmt::DeclStmt(var_decl) => Some((var_decl.line, var_decl.col)),
+        Stmt::ExprStmt(_, line, col)
+        | Stmt::ReturnStmt(_, line, col)
+        | Stmt::MultiReturnStmt(_, line, col)
+        | Stmt::BreakStmt(line, col)
+        | Stmt::ContinueStmt(line, col)
+        | Stmt::YieldStmt(_, line, col)
+        | Stmt::MultiYieldStmt(_, line, col)
+        | Stmt::YieldFromStmt(_, line, col)
+        | Stmt::JoinStmt(_, line, col)
+        | Stmt::ParallelStmt(_, line, col) => Some((*line, *col)),
+        Stmt::IfStmt(if_stmt) => Some((if_stmt.line, if_stmt.col)),
+        Stmt::WhileStmt(while_stmt) => Some((while_stmt.line, while_stmt.col)),
+        Stmt::ForStmt(for_stmt) => Some((for_stmt.line, for_stmt.col)),
+        Stmt::SwitchStmt(switch_stmt) => Some((switch_stmt.line, switch_stmt.col)),
+        Stmt::TryStmt(try_stmt) => Some((try_stmt.line, try_stmt.col)),
+        Stmt::BlockStmt(_) => None
     }
 }
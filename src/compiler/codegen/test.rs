@@ -1,6 +1,32 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
 use crate::compiler::codegen::CodegenContext;
+use crate::compiler::{compile, compile_with_natives, compile_with_warnings, CompileOptions};
 use crate::compiler::lex::tokenize;
 use crate::compiler::parse::expr::parse_expr;
+use crate::{define_io_ctx, define_io_enum};
+use crate::io_ctx::{EnumValue, IOContext, IOType, Type21};
+use crate::native::{NativeRegistry, NativeSignature, NativeType, Suspend};
+use crate::r25_300::asm::{assemble, disassemble, disassemble_with_source};
+use crate::r25_300::batch::BatchCombustor;
+use crate::r25_300::builder::CompiledBuilder;
+use crate::r25_300::compact::CompactCode;
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::coroutine::{Coroutine, CoroutineStatus};
+use crate::r25_300::cumbustor::{Combustor, CombustorState, Engine, EngineCode};
+use crate::r25_300::debugger::{Debugger, PauseReason, StepMode};
+use crate::r25_300::decoded::DecodedCode;
+use crate::r25_300::error::RuntimeError;
+use crate::r25_300::insc::Insc;
+use crate::r25_300::math::MathOp1;
+use crate::r25_300::p21c::P21c;
+use crate::r25_300::parallel::resume_all;
+use crate::r25_300::profile::Profiler;
+use crate::r25_300::scheduler::{Event, Scheduler, Wait};
+use crate::r25_300::snapshot::Snapshot;
+use crate::r25_300::verify::MAX_FRAME_SIZE;
+use crate::value::{float_to_bool, float_to_int, RtValue};
 
 #[test]
 fn test_consteval() {
@@ -8,6 +34,2216 @@ fn test_consteval() {
     let mut cursor = 0;
     let expr = parse_expr(&tokens, &mut cursor).unwrap();
 
-    let ctx = CodegenContext::new();
+    let mut ctx = CodegenContext::new();
     dbg!(ctx.consteval_expr(&expr).unwrap());
 }
+
+#[test]
+fn test_codegen_loop() {
+    define_io_ctx!(
+        struct Ctx {
+            g_a => a: i32,
+            g_b => b: i32,
+            g_c => c: i32
+        }
+    );
+
+    let compiled = compile(r#"
+        int add(int a, int b) {
+            return a + b;
+        }
+
+        [int, int] swap(int a, int b) {
+            return [b, a];
+        }
+
+        void entry() {
+            int sum = 0;
+            int i;
+            for (i = 0; i < 10; i = i + 1) {
+                if (i == 5) {
+                    continue;
+                }
+                sum = add(sum, i);
+            }
+            g_c = sum;
+            [g_a, g_b] = swap(g_a, g_b);
+            while (true) {
+                yield;
+                g_c = g_c + 1;
+                if (g_c >= 42 || g_a == 0) {
+                    break;
+                }
+            }
+        }
+    "#, Ctx::metadata()).unwrap();
+    eprintln!("{}", compiled);
+
+    let mut ctx = Ctx { a: 1, b: 2, c: 0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    let mut resume = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(combustor.io_ctx.c, 40);
+    assert_eq!(combustor.io_ctx.a, 2);
+    assert_eq!(combustor.io_ctx.b, 1);
+
+    let mut yields = 0;
+    while let Some(insc_ptr) = resume {
+        yields += 1;
+        resume = unsafe { combustor.combust_resume(&compiled, insc_ptr) }.unwrap();
+    }
+    assert_eq!(yields, 2);
+    assert_eq!(ctx.c, 42);
+}
+
+#[test]
+fn test_struct() {
+    define_io_ctx!(
+        struct Vec3 {
+            x => x: f32,
+            y => y: f32,
+            z => z: f32
+        }
+    );
+
+    define_io_ctx!(
+        struct Transform {
+            pos => pos: Vec3,
+            angle => angle: f32
+        }
+    );
+
+    define_io_ctx!(
+        struct Ctx {
+            g_frame_id => frame_id: i32,
+            g_bone => bone: Transform,
+            g_angle => angle: f32
+        }
+    );
+
+    let source = r#"
+        struct Vec3 {
+            float x;
+            float y;
+            float z;
+        }
+
+        struct Transform {
+            Vec3 pos;
+            float angle;
+        }
+
+        Transform make(Vec3 pos, float angle) {
+            Transform t;
+            t.pos = pos;
+            t.angle = angle;
+            return t;
+        }
+
+        Transform rotate(Transform t, float delta) {
+            t.angle = t.angle + delta;
+            return t;
+        }
+
+        void entry() {
+            Vec3 pos;
+            pos.x = 1.0;
+            pos.y = 2.0;
+            pos.z = g_bone.pos.z;
+
+            var t = rotate(make(pos, 30.0), 15.0);
+            g_bone = t;
+            g_bone.pos.y = t.pos.x + t.pos.y;
+            g_angle = rotate(t, 45.0).angle;
+        }
+    "#;
+    let compiled = compile(source, Ctx::metadata()).unwrap();
+    eprintln!("{}", compiled);
+
+    // temporaries of an untyped initializer are released like those of a typed one
+    let typed = compile(&source.replace("var t =", "Transform t ="), Ctx::metadata()).unwrap();
+    let entry_frame = |compiled: &Compiled| compiled.func[compiled.find_func("entry").unwrap()].frame_size;
+    assert!(entry_frame(&compiled) <= entry_frame(&typed));
+
+    let mut ctx = Ctx {
+        frame_id: 0,
+        bone: Transform { pos: Vec3 { x: 0.0, y: 0.0, z: 5.0 }, angle: 0.0 },
+        angle: 0.0
+    };
+    let mut combustor = Combustor::new(&mut ctx);
+    let resume = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert!(resume.is_none());
+
+    assert_eq!(ctx.bone.pos.x, 1.0);
+    assert_eq!(ctx.bone.pos.y, 3.0);
+    assert_eq!(ctx.bone.pos.z, 5.0);
+    assert_eq!(ctx.bone.angle, 45.0);
+    assert_eq!(ctx.angle, 90.0);
+}
+
+#[test]
+fn test_struct_mismatch() {
+    define_io_ctx!(
+        struct Vec2 {
+            x => x: f32,
+            y => y: f32
+        }
+    );
+
+    define_io_ctx!(
+        struct Ctx {
+            g_pos => pos: Vec2
+        }
+    );
+
+    let err = compile(r#"
+        struct Vec2 {
+            float y;
+            float x;
+        }
+
+        void entry() {
+            g_pos.x = 1.0;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("Vec2"));
+
+    let err = compile(r#"
+        struct Vec2 {
+            float x;
+            float y;
+        }
+
+        void entry() {
+            Vec2 v;
+            v.z = 1.0;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("z"));
+}
+
+#[test]
+fn test_enum_switch() {
+    define_io_enum!(
+        enum State {
+            Idle,
+            Walk,
+            Run = 5
+        }
+    );
+
+    define_io_ctx!(
+        struct Ctx {
+            g_state => state: EnumValue<State>,
+            g_speed => speed: i32,
+            g_hits => hits: i32
+        }
+    );
+
+    let (compiled, warnings) = compile_with_warnings(r#"
+        enum State {
+            Idle,
+            Walk,
+            Run = 5
+        }
+
+        const FAST = State::Run;
+
+        int speed_of(State state) {
+            switch (state) {
+                case State::Idle:
+                    return 0;
+                case State::Walk:
+                    return 2;
+                default:
+                    return int(FAST) * 2;
+            }
+        }
+
+        void entry() {
+            int i;
+            for (i = 0; i < 3; i = i + 1) {
+                switch (i) {
+                    case 0:
+                        continue;
+                    case 1: case 2:
+                        g_hits = g_hits + i;
+                        break;
+                }
+                g_hits = g_hits + 10;
+            }
+
+            g_speed = speed_of(g_state);
+            if (g_state != State::Run) {
+                g_state = State::Run;
+            }
+
+            switch (g_state) {
+                case State::Idle:
+                    g_speed = 0;
+            }
+        }
+    "#, Ctx::metadata(), &NativeRegistry::new(), CompileOptions::default()).unwrap();
+    eprintln!("{}", compiled);
+
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("`Walk`, `Run`"));
+
+    let mut ctx = Ctx { state: EnumValue::new(State::Walk), speed: 0, hits: 0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    let resume = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert!(resume.is_none());
+
+    assert_eq!(ctx.hits, 23);
+    assert_eq!(ctx.speed, 2);
+    assert_eq!(ctx.state.get(), Some(State::Run));
+}
+
+#[test]
+fn test_enum_mismatch() {
+    define_io_enum!(
+        enum State {
+            Idle,
+            Run
+        }
+    );
+
+    define_io_ctx!(
+        struct Ctx {
+            g_state => state: EnumValue<State>
+        }
+    );
+
+    let err = compile(r#"
+        enum State { Idle, Walk, Run }
+
+        void entry() {
+            g_state = State::Idle;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("State"));
+
+    let err = compile(r#"
+        enum State { Idle, Run }
+
+        void entry() {
+            g_state = 1;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("类型不匹配"));
+
+    let err = compile(r#"
+        enum State { Idle, Run = 0 }
+    "#, Vec::new()).unwrap_err();
+    assert!(err.contains("Idle"));
+}
+
+#[test]
+fn test_promotion() {
+    define_io_ctx!(
+        struct Ctx {
+            g_frame_id => frame_id: i32,
+            g_rotation_left_3 => rotation_left_3: f32
+        }
+    );
+
+    let compiled = compile(include_str!("../../../example/anim.bis"), Ctx::metadata()).unwrap();
+    eprintln!("{}", compiled);
+
+    let mut ctx = Ctx { frame_id: 0, rotation_left_3: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    let mut resume = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    for frame_id in 1..=3 {
+        combustor.io_ctx.frame_id = frame_id;
+        resume = unsafe { combustor.combust_resume(&compiled, resume.unwrap()) }.unwrap();
+    }
+    assert!(resume.is_some());
+    assert_eq!(ctx.rotation_left_3, 1.5);
+
+    let compiled = compile(r#"
+        const HALF = 1 / 2.0;
+        const BIG = 16777217 * 1.0;
+
+        void entry() {
+            g_rotation_left_3 = HALF;
+            if (g_frame_id < 0.5 && BIG > 1) {
+                g_rotation_left_3 = g_rotation_left_3 + g_frame_id;
+            }
+        }
+    "#, Ctx::metadata()).unwrap();
+
+    let mut ctx = Ctx { frame_id: 0, rotation_left_3: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(ctx.rotation_left_3, 0.5);
+}
+
+#[test]
+fn test_promotion_warnings() {
+    define_io_ctx!(
+        struct Ctx {
+            g_frame_id => frame_id: i32,
+            g_rotation => rotation: f32
+        }
+    );
+
+    let source = r#"
+        const BIG = 16777217 * 1.0;
+        const SMALL = 3 * 1.0;
+
+        void entry() {
+            g_rotation = 0.5 * g_frame_id;
+        }
+    "#;
+
+    let (_, warnings) = compile_with_warnings(source, Ctx::metadata(), &NativeRegistry::new(), CompileOptions::default()).unwrap();
+    assert!(warnings.is_empty());
+
+    let options = CompileOptions { warn_lossy: true };
+    let (_, warnings) = compile_with_warnings(source, Ctx::metadata(), &NativeRegistry::new(), options).unwrap();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].starts_with("行 2:") && warnings[0].contains("16777217"));
+    assert!(warnings[1].starts_with("行 6:"));
+
+    let err = compile(r#"
+        void entry() {
+            g_rotation = g_frame_id;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("float(...)"));
+
+    let err = compile(r#"
+        void entry() {
+            g_frame_id = g_frame_id % 2.0;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("Mod"));
+
+    let err = compile(r#"
+        void entry() {
+            g_rotation = g_rotation + true;
+        }
+    "#, Ctx::metadata()).unwrap_err();
+    assert!(err.contains("bool"));
+}
+
+#[test]
+fn test_conversion() {
+    define_io_ctx!(
+        struct Ctx {
+            g_x => x: f32,
+            g_i => i: i32,
+            g_b => b: i32,
+            g_f => f: f32
+        }
+    );
+
+    let compiled = compile(r#"
+        void entry() {
+            g_i = int(g_x);
+            bool b = bool(g_x);
+            g_b = int(b);
+            g_f = float(b);
+        }
+    "#, Ctx::metadata()).unwrap();
+
+    for x in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 3e9, -3e9, -2.7, 0.0, -0.0, 0.5] {
+        let mut ctx = Ctx { x, i: 0, b: 0, f: 0.0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+        assert_eq!(ctx.i, float_to_int(x));
+        assert_eq!(ctx.b, float_to_bool(x) as i32);
+        assert_eq!(ctx.f, if float_to_bool(x) { 1.0 } else { 0.0 });
+    }
+
+    let compiled = compile(r#"
+        const BIG = int(3000000000.0);
+        const SMALL = int(-3000000000.0);
+        const TRUNC = int(-2.7);
+        const ZERO = bool(-0.0);
+
+        void entry() {
+            g_i = BIG;
+            if (SMALL == -2147483647 - 1 && TRUNC == -2 && !ZERO) {
+                g_b = 1;
+            }
+        }
+    "#, Ctx::metadata()).unwrap();
+
+    let mut ctx = Ctx { x: 0.0, i: 0, b: 0, f: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(ctx.i, i32::MAX);
+    assert_eq!(ctx.b, 1);
+}
+
+#[test]
+fn test_intrinsics() {
+    define_io_ctx!(
+        struct Ctx {
+            g_t => t: f32,
+            g_i => i: i32,
+            g_f => f: f32,
+            g_e => e: f32,
+            g_k => k: i32
+        }
+    );
+
+    let compiled = compile(r#"
+        const HALF = sqrt(0.25);
+        const BOUNDED = clamp(15, 0, 10);
+
+        void entry() {
+            g_i = clamp(ifloor(g_t * 10.0), 0, 5) + abs(-3);
+            g_f = lerp(0, 10, g_t) + max(1, 2.5) + HALF;
+            g_e = ease_in_out_quad(g_t);
+            g_k = BOUNDED + min(g_i, 2);
+        }
+    "#, Ctx::metadata()).unwrap();
+
+    let entry = compiled.find_func("entry").unwrap();
+    assert!(!compiled.code.iter().any(|insc| matches!(insc, Insc::Math1 { op: MathOp1::Sqrt, .. })));
+
+    let mut ctx = Ctx { t: 0.75, i: 0, f: 0.0, e: 0.0, k: 0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    unsafe { combustor.combust(&compiled, entry) }.unwrap();
+    assert_eq!(ctx.i, 8);
+    assert_eq!(ctx.f, 7.5 + 2.5 + 0.5);
+    assert_eq!(ctx.e, 1.0 - 0.5 * 0.5 / 2.0);
+    assert_eq!(ctx.k, 12);
+
+    for source in [
+        "void entry() { g_i = sin(1.0); }",
+        "void entry() { g_f = abs(true); }",
+        "void entry() { g_f = lerp(1.0, 2.0); }",
+        "int sqrt(int x) { return x; }"
+    ] {
+        assert!(compile(source, Ctx::metadata()).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_natives() {
+    define_io_ctx!(
+        struct Ctx {
+            g_x => x: f32,
+            g_n => n: i32,
+            g_s => s: f32,
+            g_c => c: f32
+        }
+    );
+
+    let calls = Arc::new(AtomicI32::new(0));
+    let mut natives = NativeRegistry::new();
+    natives.register("sincos", |x: f32| (x.sin(), x.cos())).unwrap();
+    natives.register("twice", |x: i32| x * 2).unwrap();
+    natives.register("twice", |x: f32| x * 2.0).unwrap();
+    let counter = calls.clone();
+    natives.register("tick", move || { counter.fetch_add(1, Ordering::Relaxed); }).unwrap();
+
+    let compiled = compile_with_natives(r#"
+        void entry() {
+            float s;
+            float c;
+            [s, c] = sincos(twice(g_x));
+            g_s = s;
+            g_c = c;
+            g_n = twice(21);
+            tick();
+            tick();
+        }
+    "#, Ctx::metadata(), &natives).unwrap();
+    assert_eq!(compiled.ffi.len(), 4);
+
+    let mut ctx = Ctx { x: 0.25, n: 0, s: 0.0, c: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(ctx.s, 0.5f32.sin());
+    assert_eq!(ctx.c, 0.5f32.cos());
+    assert_eq!(ctx.n, 42);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    for source in [
+        "void entry() { g_n = twice(true); }",
+        "void entry() { g_x = sincos(1.0); }",
+        "int tick() { return 0; }"
+    ] {
+        assert!(compile_with_natives(source, Ctx::metadata(), &natives).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_extern() {
+    define_io_ctx!(
+        struct Ctx {
+            g_x => x: f32,
+            g_y => y: f32
+        }
+    );
+
+    let source = r#"
+        extern float noise(float x, float y);
+        extern [int, int] divmod(int a, int b);
+
+        void entry() {
+            int q;
+            int r;
+            [q, r] = divmod(17, 5);
+            g_y = noise(g_x, float(q) * 10.0 + float(r));
+        }
+    "#;
+    let mut compiled = compile(source, Ctx::metadata()).unwrap();
+    assert_eq!(compiled.imports.len(), 2);
+    assert!(!compiled.is_linked());
+
+    let mut natives = NativeRegistry::new();
+    natives.register("noise", |x: f32, y: f32| x + y).unwrap();
+    let err = compiled.link(&natives).unwrap_err();
+    assert!(err.contains("divmod"), "{}", err);
+    assert!(!compiled.is_linked());
+
+    natives.register("divmod", |a: i32, b: f32| (a, b as i32)).unwrap();
+    let err = compiled.link(&natives).unwrap_err();
+    assert!(err.contains("(int, float) -> (int, int)") && err.contains("(int, int) -> (int, int)"), "{}", err);
+
+    natives.register("divmod", |a: i32, b: i32| (a / b, a % b)).unwrap();
+    compiled.link(&natives).unwrap();
+    assert!(compiled.is_linked());
+
+    let mut ctx = Ctx { x: 0.5, y: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(ctx.y, 32.5);
+
+    assert!(compile_with_natives(source, Ctx::metadata(), &NativeRegistry::new()).is_err());
+    for source in [
+        "extern bool check(int x);",
+        "extern float sin(float x);",
+        "extern int f(int x); extern int f(int x);",
+        "extern int f(int x); int f(int x) { return x; }"
+    ] {
+        assert!(compile(source, Ctx::metadata()).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_native_errors() {
+    define_io_ctx!(
+        struct Ctx {
+            g_x => x: f32,
+            g_n => n: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+
+    let run = |source: &str, x: f32| {
+        let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+        let mut ctx = Ctx { x, n: 0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        let result = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) };
+        (result.map(|_| ()), ctx.x, ctx.n)
+    };
+
+    let uncaught = "void entry() { g_x = checked_sqrt(g_x); }";
+    assert_eq!(run(uncaught, 4.0), (Ok(()), 2.0, 0));
+    let err = run(uncaught, -1.0).0.unwrap_err();
+    assert_eq!(err.native.as_deref(), Some("checked_sqrt"));
+    assert_eq!(err.func, "entry");
+    assert_eq!(err.message, "负数没有平方根");
+
+    let caught = r#"
+        float root(float x) {
+            float r = checked_sqrt(x);
+            g_n = 100;
+            return r;
+        }
+
+        void entry() {
+            try {
+                g_x = root(g_x);
+                g_n = g_n + 1;
+            } catch {
+                g_n = -1;
+            }
+            g_n = g_n * 2;
+        }
+    "#;
+    assert_eq!(run(caught, 9.0), (Ok(()), 3.0, 202));
+    assert_eq!(run(caught, -9.0), (Ok(()), -9.0, -2));
+
+    // leaving a `try` block early must remove its handler
+    let early_exit = r#"
+        int find() {
+            int i;
+            for (i = 0; i < 10; i = i + 1) {
+                try {
+                    if (i == 3) {
+                        return i;
+                    }
+                    if (i == 1) {
+                        continue;
+                    }
+                    g_n = g_n + 10;
+                } catch {
+                    g_n = -100;
+                }
+            }
+            return -1;
+        }
+
+        void entry() {
+            int found = find();
+            while (true) {
+                try {
+                    break;
+                } catch {
+                }
+            }
+            g_n = g_n + found;
+            g_x = checked_sqrt(g_x);
+        }
+    "#;
+    assert_eq!(run(early_exit, 16.0), (Ok(()), 4.0, 23));
+    assert!(run(early_exit, -16.0).0.is_err());
+}
+
+#[test]
+fn test_backtrace() {
+    define_io_ctx!(
+        struct Ctx {
+            g_x => x: f32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+
+    let source = r#"
+        float root(float x) {
+            float r = checked_sqrt(x);
+            return r;
+        }
+
+        float twice(float x) {
+            return root(x) + root(-x);
+        }
+
+        void entry() {
+            g_x = twice(g_x);
+        }
+    "#;
+    let line = |needle: &str| source.lines().position(|line| line.contains(needle)).unwrap() + 1;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+    let expected = [("root", line("checked_sqrt(x)")), ("twice", line("root(-x)")), ("entry", line("twice(g_x)"))];
+    let check = |err: &RuntimeError| {
+        let frames = err.backtrace.iter().map(|frame| (frame.func.as_str(), frame.line.unwrap())).collect::<Vec<_>>();
+        assert_eq!(frames, expected);
+        assert_eq!((err.func.as_str(), err.insc_ptr), ("root", err.backtrace[0].insc_ptr));
+        assert!(matches!(&compiled.code[err.backtrace[1].insc_ptr], Insc::Call { .. }));
+    };
+
+    for engine in [Engine::Insc, Engine::Compact, Engine::Decoded] {
+        let mut ctx = Ctx { x: 4.0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        combustor.set_engine(engine);
+        check(&unsafe { combustor.combust(&compiled, entry) }.unwrap_err());
+    }
+
+    let mut ctxs = vec![Ctx { x: 4.0 }, Ctx { x: 0.0 }];
+    let mut batch = BatchCombustor::new();
+    unsafe { batch.combust(&compiled, entry, &mut ctxs) };
+    check(batch.error(0).unwrap());
+    assert_eq!(batch.status(1), CoroutineStatus::Finished);
+
+    let mut ctx = Ctx { x: 4.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    let err = unsafe { combustor.combust(&compiled, entry) }.unwrap_err();
+    let text = format!("{:#}", err);
+    assert!(text.starts_with(&format!("{}\n栈回溯:\n   0: root (指令 {})\n             at 行 {}\n", err, err.insc_ptr, expected[0].1)));
+    assert!(text.ends_with(&format!("   2: entry (指令 {})\n             at 行 {}", err.backtrace[2].insc_ptr, expected[2].1)));
+
+    // without debug info there are no lines to print
+    let mut stripped = compiled.clone();
+    stripped.debug = None;
+    let err = unsafe { Combustor::new(&mut ctx).combust(&stripped, entry) }.unwrap_err();
+    assert!(err.backtrace.iter().all(|frame| frame.line.is_none()));
+    assert_eq!(format!("{:#}", err).lines().count(), 5);
+}
+
+#[test]
+fn test_division_faults() {
+    define_io_ctx!(
+        #[derive(Clone)]
+        struct Ctx {
+            g_a => a: i32,
+            g_b => b: i32,
+            g_out => out: i32
+        }
+    );
+
+    let source = r#"
+        int quot(int a, int b) {
+            return a / b;
+        }
+
+        void entry() {
+            try {
+                g_out = quot(g_a, g_b);
+            } catch {
+                g_out = -1;
+            }
+            try {
+                g_out = g_out * 10 + g_a % g_b;
+            } catch {
+                g_out = g_out - 1;
+            }
+            g_out = g_out + quot(g_a, g_b);
+        }
+    "#;
+    let compiled = compile(source, Ctx::metadata()).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+    let ctxs = vec![Ctx { a: 7, b: 2, out: 0 }, Ctx { a: 7, b: 0, out: 0 }, Ctx { a: i32::MIN, b: -1, out: 0 }];
+    let run = |engine: Engine, ctx: &Ctx| {
+        let mut ctx = ctx.clone();
+        let mut combustor = Combustor::new(&mut ctx);
+        combustor.set_engine(engine);
+        let result = unsafe { combustor.combust(&compiled, entry) };
+        (result.map(|_| ()), ctx.out)
+    };
+
+    for engine in [Engine::Insc, Engine::Compact, Engine::Decoded] {
+        assert_eq!(run(engine, &ctxs[0]), (Ok(()), 34));
+        for (ctx, message) in ctxs[1..].iter().zip(["不能除以 0", "整数除法溢出"]) {
+            let (result, out) = run(engine, ctx);
+            let err = result.unwrap_err();
+            assert_eq!((err.native, err.message.as_str(), err.func.as_str()), (None, message, "quot"));
+            assert_eq!(err.backtrace.iter().map(|frame| frame.func.as_str()).collect::<Vec<_>>(), ["quot", "entry"]);
+            assert_eq!(out, -2);
+        }
+    }
+
+    let mut batch_ctxs = ctxs.clone();
+    let mut batch = BatchCombustor::new();
+    unsafe { batch.combust(&compiled, entry, &mut batch_ctxs) };
+    for (lane, ctx) in ctxs.iter().enumerate() {
+        let (result, out) = run(Engine::Insc, ctx);
+        assert_eq!(batch.error(lane), result.as_ref().err());
+        assert_eq!(batch_ctxs[lane].out, out);
+    }
+
+    // folded constants are checked as well
+    assert!(compile("void entry() { g_out = 7 % 0; }", Ctx::metadata()).is_err());
+}
+
+#[test]
+fn test_suspending_natives() {
+    define_io_ctx!(
+        struct Ctx {
+            g_step => step: i32,
+            g_answer => answer: i32
+        }
+    );
+
+    let waiting = Arc::new(AtomicI32::new(0));
+    let mut natives = NativeRegistry::new();
+    let waiting2 = waiting.clone();
+    natives.register("wait_frames", move |n: i32| {
+        waiting2.store(n, Ordering::Relaxed);
+        Suspend::new()
+    }).unwrap();
+    natives.register("ask", |_options: i32| Suspend::<i32>::with_results()).unwrap();
+
+    let source = r#"
+        void entry() {
+            g_step = 1;
+            wait_frames(3);
+            g_step = 2;
+            g_answer = ask(4) * 10;
+            g_step = 3;
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+
+    let mut ctx = Ctx { step: 0, answer: 0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    let resume = unsafe { combustor.combust(&compiled, compiled.find_func("entry").unwrap()) }.unwrap();
+    assert_eq!(combustor.io_ctx.step, 1);
+    assert_eq!(combustor.suspended_native().unwrap().name, "wait_frames");
+    assert_eq!(waiting.load(Ordering::Relaxed), 3);
+    assert!(combustor.finish_native(1).is_err());
+
+    let resume = unsafe { combustor.combust_resume(&compiled, resume.unwrap()) }.unwrap();
+    assert_eq!(combustor.io_ctx.step, 2);
+    assert_eq!(combustor.suspended_native().unwrap().name, "ask");
+    assert!(combustor.finish_native(1.0f32).is_err());
+    assert!(combustor.finish_native(()).is_err());
+    combustor.finish_native(7).unwrap();
+    assert!(combustor.suspended_native().is_none());
+
+    let resume = unsafe { combustor.combust_resume(&compiled, resume.unwrap()) }.unwrap();
+    assert!(resume.is_none());
+    assert_eq!(ctx.step, 3);
+    assert_eq!(ctx.answer, 70);
+}
+
+#[test]
+fn test_coroutine() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_x => x: f32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+
+    let source = r#"
+        int count(int n) {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                g_n = g_n + 1;
+                yield;
+            }
+            return i;
+        }
+
+        void entry() {
+            g_n = count(3) * 10;
+            g_x = checked_sqrt(g_x);
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+
+    let mut ctx = Ctx { n: 0, x: 4.0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("entry").unwrap(), &mut ctx) };
+    assert_eq!(coroutine.status(), CoroutineStatus::NotStarted);
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+    assert_eq!(coroutine.io_ctx().n, 2);
+
+    coroutine.cancel();
+    assert_eq!(coroutine.status(), CoroutineStatus::Finished);
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert_eq!(coroutine.io_ctx().n, 2);
+
+    coroutine.reset();
+    coroutine.io_ctx().n = 0;
+    while coroutine.resume() == Ok(CoroutineStatus::Suspended) {}
+    assert_eq!(coroutine.status(), CoroutineStatus::Finished);
+    assert_eq!(coroutine.io_ctx().n, 30);
+    assert_eq!(coroutine.io_ctx().x, 2.0);
+
+    coroutine.reset();
+    coroutine.io_ctx().x = -1.0;
+    while coroutine.status() != CoroutineStatus::Faulted {
+        if let Err(err) = coroutine.resume() {
+            assert_eq!(err.native.as_deref(), Some("checked_sqrt"));
+        }
+    }
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Faulted));
+
+    coroutine.reset();
+    coroutine.io_ctx().x = 9.0;
+    while coroutine.resume() == Ok(CoroutineStatus::Suspended) {}
+    assert_eq!(coroutine.status(), CoroutineStatus::Finished);
+    assert_eq!(ctx.x, 3.0);
+}
+
+#[test]
+fn test_snapshot() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_answer => answer: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("ask", |_options: i32| Suspend::<i32>::with_results()).unwrap();
+    natives.register("fail", || Err::<(), _>("失败")).unwrap();
+
+    let source = r#"
+        int count(int n) {
+            int i;
+            int sum = 0;
+            for (i = 0; i < n; i = i + 1) {
+                sum = sum + i;
+                g_n = sum;
+                yield;
+            }
+            return sum;
+        }
+
+        void entry() {
+            try {
+                int a = count(4);
+                int b = ask(a);
+                g_answer = a * 100 + b;
+                fail();
+            } catch {
+                g_answer = -g_answer;
+            }
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+
+    let mut ctx = Ctx { n: 0, answer: 0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, entry, &mut ctx) };
+    assert!(coroutine.snapshot().is_none());
+    coroutine.resume().unwrap();
+    coroutine.resume().unwrap();
+    let in_call = coroutine.snapshot().unwrap().to_bytes();
+    while coroutine.suspended_native().is_none() {
+        coroutine.resume().unwrap();
+    }
+    let in_native = coroutine.snapshot().unwrap();
+    coroutine.finish_native(5).unwrap();
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert_eq!(ctx.answer, -605);
+
+    let snapshot = Snapshot::from_bytes(&in_call).unwrap();
+    assert_eq!(snapshot.frames.len(), 2);
+    let mut ctx = Ctx { n: 1, answer: 0 };
+    let mut coroutine = unsafe { Coroutine::restore(&compiled, &mut ctx, &snapshot) }.unwrap();
+    assert_eq!(coroutine.status(), CoroutineStatus::Suspended);
+    while coroutine.suspended_native().is_none() {
+        coroutine.resume().unwrap();
+    }
+    assert_eq!(coroutine.io_ctx().n, 6);
+    coroutine.finish_native(7).unwrap();
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert_eq!(ctx.answer, -607);
+
+    let mut ctx = Ctx { n: 0, answer: 0 };
+    let mut coroutine = unsafe { Coroutine::restore(&compiled, &mut ctx, &in_native) }.unwrap();
+    assert_eq!(coroutine.suspended_native().unwrap().name, "ask");
+    coroutine.finish_native(9).unwrap();
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert_eq!(ctx.answer, -609);
+
+    let mut ctx = Ctx { n: 0, answer: 0 };
+    let other = compile_with_natives(&source.replace("100", "1000"), Ctx::metadata(), &natives).unwrap();
+    assert!(unsafe { Coroutine::restore(&other, &mut ctx, &snapshot) }.is_err());
+    assert!(Snapshot::from_bytes(&in_call[..in_call.len() - 1]).is_err());
+    let mut broken = snapshot.clone();
+    broken.frames[1].end_idx += 1;
+    assert!(unsafe { Coroutine::restore(&compiled, &mut ctx, &broken) }.is_err());
+    let mut broken = snapshot.clone();
+    broken.resume_ptr = compiled.func[entry].addr + 1;
+    assert!(unsafe { Coroutine::restore(&compiled, &mut ctx, &broken) }.is_err());
+
+    // the handler of the `try` in `entry` cannot be moved into `count`
+    let count = &compiled.func[compiled.find_func("count").unwrap()];
+    assert_eq!(snapshot.handlers.len(), 1);
+    assert!(snapshot.validate(&compiled).is_ok());
+    let mut broken = snapshot.clone();
+    broken.handlers[0].1 = count.addr + 1;
+    assert!(broken.validate(&compiled).is_err());
+
+    // the hash only depends on the encoded code, not on the platform or on debug info
+    let mut builder = CompiledBuilder::new();
+    let mut func = builder.function("entry", 0, 0).unwrap();
+    let one = func.constant(1);
+    func.emit(Insc::IOSetValue { offset: 0, src: one });
+    func.finish().unwrap();
+    let mut compiled = builder.finish().unwrap();
+    assert_eq!(compiled.code_hash(), 0xc16a_ed4c_9b99_534f);
+    compiled.debug = None;
+    assert_eq!(compiled.code_hash(), 0xc16a_ed4c_9b99_534f);
+}
+
+#[test]
+fn test_yield_values() {
+    define_io_ctx!(
+        struct Ctx {
+            g_samples => samples: i32
+        }
+    );
+
+    let source = r#"
+        float curve(float t) {
+            return 3.0 * t * t - 2.0 * t * t * t;
+        }
+
+        void sample(int n) yield [float, float] {
+            int i;
+            for (i = 0; i <= n; i = i + 1) {
+                float t = float(i) / float(n);
+                float y = curve(t);
+                yield [t, y];
+            }
+        }
+
+        void entry() yield [float, float] {
+            yield from sample(g_samples);
+        }
+
+        int count() yield int {
+            yield 1;
+            yield 2 + 3;
+            return 0;
+        }
+    "#;
+    let compiled = compile(source, Ctx::metadata()).unwrap();
+
+    let mut ctx = Ctx { samples: 4 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("entry").unwrap(), &mut ctx) };
+    let mut samples = Vec::new();
+    while coroutine.resume() == Ok(CoroutineStatus::Suspended) {
+        let [t, y] = coroutine.yielded() else { unreachable!() };
+        samples.push((f32::from_value(*t), f32::from_value(*y)));
+    }
+    assert_eq!(samples, vec![(0.0, 0.0), (0.25, 0.15625), (0.5, 0.5), (0.75, 0.84375), (1.0, 1.0)]);
+    assert!(coroutine.yielded().is_empty());
+
+    let mut ctx = Ctx { samples: 0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("count").unwrap(), &mut ctx) };
+    coroutine.resume().unwrap();
+    assert_eq!(coroutine.yielded(), &[RtValue::from(1)]);
+    coroutine.resume().unwrap();
+    assert_eq!(coroutine.yielded(), &[RtValue::from(5)]);
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+
+    for source in [
+        "void f() yield int { yield; }",
+        "void f() { yield 1; }",
+        "void f() yield float { yield 1; }",
+        "void f() yield [int, int] { yield 1; }",
+        "void f() yield [int, int] { int a = 1; yield [a]; }",
+        "struct S { int x; } void f() yield S { }",
+        "extern void f() yield int;",
+        "void f() yield int; void f() yield float { yield 1.0; }"
+    ] {
+        assert!(compile(source, Ctx::metadata()).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_spawn_join() {
+    define_io_ctx!(
+        struct Ctx {
+            g_a => a: i32,
+            g_b => b: i32,
+            g_done => done: i32,
+            g_result => result: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("ask", |_options: i32| Suspend::<i32>::with_results()).unwrap();
+
+    let source = r#"
+        void walk(int n) {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                g_a = g_a + 1;
+                yield;
+            }
+        }
+
+        void fly(int n) {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                g_b = g_b + 10;
+                yield;
+            }
+        }
+
+        int load(int options) {
+            g_result = ask(options);
+            return g_result;
+        }
+
+        void entry() {
+            parallel {
+                walk(2);
+                fly(3);
+            }
+            g_done = 1;
+            int task = spawn load(5);
+            join task;
+            join task;
+            g_done = 2;
+        }
+
+        int digits() yield int {
+            yield 1;
+            yield 2;
+            return 3;
+        }
+
+        void count() yield int {
+            yield from digits();
+            yield 4;
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+
+    let mut ctx = Ctx { a: 0, b: 0, done: 0, result: 0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("entry").unwrap(), &mut ctx) };
+    let mut progress = Vec::new();
+    for _ in 0..3 {
+        assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+        let ctx = coroutine.io_ctx();
+        progress.push((ctx.a, ctx.b, ctx.done));
+    }
+    assert_eq!(progress, vec![(1, 10, 0), (2, 20, 0), (2, 30, 0)]);
+    assert_eq!(coroutine.task_count(), 1);
+    assert!(coroutine.snapshot().is_none());
+
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+    assert_eq!(coroutine.io_ctx().done, 1);
+    assert!(coroutine.suspended_native().is_none());
+    let (task, native) = coroutine.suspended_tasks().next().unwrap();
+    assert_eq!(native.name, "ask");
+    assert!(coroutine.finish_task_native(task + 1, 42).is_err());
+    coroutine.finish_task_native(task, 42).unwrap();
+
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert_eq!(coroutine.task_count(), 0);
+    assert_eq!((ctx.a, ctx.b, ctx.done, ctx.result), (2, 30, 2, 42));
+
+    let mut ctx = Ctx { a: 0, b: 0, done: 0, result: 0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("count").unwrap(), &mut ctx) };
+    let mut values = Vec::new();
+    while coroutine.resume() == Ok(CoroutineStatus::Suspended) {
+        values.push(i32::from_value(coroutine.yielded()[0]));
+    }
+    assert_eq!(values, vec![1, 2, 4]);
+
+    for source in [
+        "void g() yield int { yield 1; } void f() { g(); }",
+        "void g() yield int { yield 1; } void f() yield float { yield from g(); }",
+        "void f() { yield from sin(1.0); }",
+        "void f() { int t = spawn sin(1.0); }",
+        "void g() {} void f() { float t = spawn g(); }",
+        "void f() { join 1.0; }",
+        "void g() {} void f() { parallel { g(); int a; } }"
+    ] {
+        assert!(compile_with_natives(source, Ctx::metadata(), &natives).is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_x => x: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("wait_frames", |_n: i32| Suspend::new()).unwrap();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+
+    let source = r#"
+        void step() {
+            int i;
+            for (i = 0; i < g_n; i = i + 1) {
+                g_x = g_x + 1;
+                yield;
+            }
+        }
+
+        void spin() {
+            while (true) {
+                g_x = g_x + 1;
+            }
+        }
+
+        void fail() {
+            float y = checked_sqrt(-1.0);
+        }
+
+        void wait() {
+            wait_frames(2);
+            g_x = 100;
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let find = |name: &str| compiled.find_func(name).unwrap();
+
+    let mut scheduler = unsafe { Scheduler::new(&compiled) };
+    scheduler.set_fuel(1000);
+    let steps = (0..1000).map(|i| scheduler.start(find("step"), Ctx { n: i % 4, x: 0 })).collect::<Vec<_>>();
+    let spin = scheduler.start(find("spin"), Ctx { n: 0, x: 0 });
+    let fail = scheduler.start(find("fail"), Ctx { n: 0, x: 0 });
+    let wait = scheduler.start(find("wait"), Ctx { n: 0, x: 0 });
+    assert_eq!(scheduler.len(), 1003);
+
+    let mut finished = Vec::new();
+    for tick in 0..4 {
+        for event in scheduler.tick() {
+            match event {
+                Event::Finished(id, ctx) => finished.push((tick, id, ctx.x)),
+                Event::Faulted(id, _, err) => {
+                    assert_eq!(id, fail);
+                    assert_eq!(err.native.as_deref(), Some("checked_sqrt"));
+                },
+                Event::OutOfFuel(id) => assert_eq!(id, spin)
+            }
+        }
+
+        if tick == 0 {
+            assert_eq!(scheduler.suspended_native(wait).unwrap().name, "wait_frames");
+            assert!(scheduler.set_wait(wait, Wait::Frames(2)).is_err());
+            scheduler.finish_native(wait, ()).unwrap();
+            scheduler.set_wait(wait, Wait::Frames(2)).unwrap();
+        }
+    }
+
+    for (i, id) in steps.iter().enumerate() {
+        let n = i as i32 % 4;
+        assert!(finished.contains(&(n, *id, n)), "{}", i);
+    }
+    assert!(finished.contains(&(3, wait, 100)));
+    assert_eq!(finished.len(), 1001);
+    assert!(!scheduler.contains(fail));
+    assert_eq!(scheduler.ids().collect::<Vec<_>>(), vec![spin]);
+    assert!(scheduler.ctx(spin).unwrap().x > 0);
+
+    let step = scheduler.start(find("step"), Ctx { n: 2, x: 0 });
+    assert!(scheduler.ctx(steps[0]).is_none());
+    assert_eq!(scheduler.tick().count(), 1);
+    scheduler.set_wait(step, Wait::Until(|ctx| ctx.x >= 10)).unwrap();
+    assert_eq!(scheduler.tick().count(), 1);
+    assert_eq!(scheduler.ctx(step).unwrap().x, 1);
+    scheduler.ctx_mut(step).unwrap().x = 10;
+    scheduler.tick();
+    assert_eq!(scheduler.ctx(step).unwrap().x, 11);
+
+    assert!(scheduler.remove(spin).is_some());
+    assert!(scheduler.remove(spin).is_none());
+    assert_eq!(scheduler.len(), 1);
+}
+
+#[test]
+fn test_batch_matches_scalar() {
+    define_io_ctx!(
+        #[derive(Debug, Clone, PartialEq)]
+        struct Ctx {
+            g_seed => seed: i32,
+            g_steps => steps: i32,
+            g_out => out: f32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+    natives.register("wait_frames", |_n: i32| Suspend::new()).unwrap();
+
+    let source = r#"
+        int collatz(int n) {
+            int steps = 0;
+            while (n != 1) {
+                if (n % 2 == 0) {
+                    n = n / 2;
+                } else {
+                    n = 3 * n + 1;
+                }
+                steps = steps + 1;
+            }
+            return steps;
+        }
+
+        int clamp_steps(int steps) {
+            if (steps > 50) {
+                return 50;
+            }
+            return steps;
+        }
+
+        void wait_some(int n) {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                g_out = g_out + 1.0;
+                yield;
+            }
+        }
+
+        float root(int x) {
+            return checked_sqrt(float(x));
+        }
+
+        void entry() {
+            g_steps = clamp_steps(collatz(g_seed));
+            yield;
+            if (g_seed % 3 == 0) {
+                wait_some(g_seed % 4);
+            }
+            g_out = g_out + float(g_steps) * 0.5;
+            yield;
+            try {
+                g_out = root(g_seed - 20);
+            } catch {
+                g_out = -1.0;
+            }
+            if (g_seed == 7) {
+                wait_frames(1);
+            }
+            yield;
+            int d = g_seed - 40;
+            g_out = checked_sqrt(float(d));
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+    let ctxs = (1..=64).map(|seed| Ctx { seed, steps: 0, out: 0.0 }).collect::<Vec<_>>();
+
+    let mut expected = Vec::new();
+    for ctx in ctxs.iter() {
+        let mut ctx = ctx.clone();
+        let mut coroutine = unsafe { Coroutine::new(&compiled, entry, &mut ctx) };
+        let mut states = Vec::new();
+        loop {
+            let status = coroutine.resume().map_err(|err| err.native.unwrap());
+            if coroutine.suspended_native().is_some() {
+                coroutine.finish_native(()).unwrap();
+            }
+            let done = status != Ok(CoroutineStatus::Suspended);
+            states.push((status, coroutine.io_ctx().clone()));
+            if done {
+                break;
+            }
+        }
+        expected.push(states);
+    }
+
+    let mut batch = BatchCombustor::new();
+    let mut ctxs = ctxs;
+    let mut suspended = unsafe { batch.combust(&compiled, entry, &mut ctxs) };
+    let mut resume = 0;
+    while suspended != 0 {
+        for (lane, states) in expected.iter().enumerate() {
+            let (status, ctx) = &states[resume.min(states.len() - 1)];
+            let actual = match batch.status(lane) {
+                CoroutineStatus::Faulted => Err(batch.error(lane).unwrap().native.clone().unwrap()),
+                status => Ok(status)
+            };
+            assert_eq!((&actual, &ctxs[lane]), (status, ctx), "lane {} resume {}", lane, resume);
+            if batch.suspended_native(lane).is_some() {
+                batch.finish_native(lane, ()).unwrap();
+            }
+        }
+
+        suspended = unsafe { batch.combust_resume(&compiled, &mut ctxs) };
+        resume += 1;
+    }
+    assert_eq!(resume, expected.iter().map(Vec::len).max().unwrap() - 1);
+
+    // lanes waiting in `wait_some` and the lane suspended by `wait_frames` had to leave the batch
+    assert!(batch.detached_count() > 0 && batch.detached_count() < 64);
+}
+
+#[test]
+fn test_parallel_resume() {
+    define_io_ctx!(
+        #[derive(Debug, Clone, PartialEq)]
+        struct Ctx {
+            g_seed => seed: i32,
+            g_acc => acc: i32
+        }
+    );
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>() {}
+    assert_send_sync::<Compiled>();
+    assert_send::<Combustor<'static, 'static, Ctx>>();
+    assert_send::<Coroutine<'static, 'static, Ctx>>();
+    assert_send::<CombustorState<'static>>();
+    assert_send::<BatchCombustor<'static>>();
+
+    let calls = Arc::new(AtomicI32::new(0));
+    let counter = calls.clone();
+    let mut natives = NativeRegistry::new();
+    natives.register("mix", move |x: i32| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if x % 7 == 0 { Err("不能被 7 整除") } else { Ok(x * 31 % 1000) }
+    }).unwrap();
+
+    let source = r#"
+        void entry() {
+            int i;
+            for (i = 0; i < g_seed % 5 + 1; i = i + 1) {
+                try {
+                    g_acc = g_acc + mix(g_seed + i);
+                } catch {
+                    g_acc = g_acc - 1;
+                }
+                yield;
+            }
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+
+    let mut sequential = (0..200).map(|seed| Ctx { seed, acc: 0 }).collect::<Vec<_>>();
+    let mut parallel = sequential.clone();
+
+    let mut expected = Vec::new();
+    for ctx in sequential.iter_mut() {
+        let mut coroutine = unsafe { Coroutine::new(&compiled, entry, ctx) };
+        let mut statuses = Vec::new();
+        while coroutine.status() != CoroutineStatus::Finished {
+            statuses.push(coroutine.resume().unwrap());
+        }
+        expected.push(statuses);
+    }
+    let sequential_calls = calls.swap(0, Ordering::Relaxed);
+
+    let mut coroutines = parallel.iter_mut()
+        .map(|ctx| unsafe { Coroutine::new(&compiled, entry, ctx) })
+        .collect::<Vec<_>>();
+    for resume in 0..6 {
+        let results = resume_all(&mut coroutines, 4);
+        for (i, result) in results.into_iter().enumerate() {
+            let status = expected[i].get(resume).copied().unwrap_or(CoroutineStatus::Finished);
+            assert_eq!(result, Ok(status), "coroutine {} resume {}", i, resume);
+        }
+    }
+    drop(coroutines);
+
+    assert_eq!(parallel, sequential);
+    assert_eq!(calls.load(Ordering::Relaxed), sequential_calls);
+}
+
+#[test]
+fn test_p21c() {
+    define_io_ctx!(
+        #[derive(Debug, Clone, PartialEq)]
+        struct Ctx {
+            g_n => n: i32,
+            g_x => x: f32,
+            g_acc => acc: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("half", |x: i32| if x % 2 == 0 { Ok(x / 2) } else { Err("奇数") }).unwrap();
+
+    let source = r#"
+        float ease(float t) {
+            return lerp(0.0, 10.0, ease_in_out_quad(t));
+        }
+
+        int collatz(int n) {
+            int steps = 0;
+            while (n != 1) {
+                try {
+                    n = half(n);
+                } catch {
+                    n = 3 * n + 1;
+                }
+                steps = steps + 1;
+            }
+            return steps;
+        }
+
+        void entry() {
+            int i;
+            for (i = 1; i <= g_n; i = i + 1) {
+                g_acc = g_acc + collatz(i);
+                g_x = ease(float(i) / 8.0);
+                yield;
+            }
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+
+    let bytes = P21c::new(compiled.clone()).to_bytes();
+    let read = P21c::from_bytes(&bytes).unwrap();
+    assert_eq!(read.compiled.code_hash(), compiled.code_hash());
+    assert_eq!(read.compiled.to_string(), compiled.to_string());
+    assert_eq!(read.compiled.debug, compiled.debug);
+    assert!(read.compiled.ffi.is_empty());
+    assert_eq!(read.to_bytes(), bytes);
+
+    let loaded = P21c::load::<Ctx>(&bytes, &natives).unwrap().compiled;
+    let run = |compiled: &Compiled| {
+        let mut ctx = Ctx { n: 8, x: 0.0, acc: 0 };
+        let mut states = Vec::new();
+        let mut coroutine = unsafe { Coroutine::new(compiled, entry, &mut ctx) };
+        while coroutine.resume().unwrap() == CoroutineStatus::Suspended {
+            states.push(coroutine.io_ctx().clone());
+        }
+        states
+    };
+    assert_eq!(run(&loaded), run(&compiled));
+
+    let compiled = compile(include_str!("../../../example/anim.bis"), vec![
+        ("g_frame_id".into(), "frame_id".into(), IOType::Scalar(Type21::Int32)),
+        ("g_rotation_left_3".into(), "rotation_left_3".into(), IOType::Scalar(Type21::Float32))
+    ]).unwrap();
+    let read = P21c::from_bytes(&P21c::new(compiled.clone()).to_bytes()).unwrap();
+    assert_eq!(read.compiled.code_hash(), compiled.code_hash());
+    assert_eq!(read.compiled.debug, compiled.debug);
+    let read = P21c::from_bytes(&P21c { compiled: compiled.clone(), debug: None }.to_bytes()).unwrap();
+    assert!(read.debug.is_none() && read.compiled.debug.is_none());
+
+    // damaged files
+    assert!(P21c::from_bytes(b"P21S\x01\0\0\0").unwrap_err().contains("不是有效"));
+    let mut wrong_version = bytes.clone();
+    wrong_version[4] = 2;
+    assert!(P21c::from_bytes(&wrong_version).unwrap_err().contains("版本"));
+    assert!(P21c::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().contains("不完整"));
+
+    let mut broken = P21c::new(loaded.clone());
+    let ret = broken.compiled.func[entry].addr + broken.compiled.func[entry].code_len - 1;
+    broken.compiled.code[ret] = Insc::Jmp { dst: 0 };
+    assert!(P21c::from_bytes(&broken.to_bytes()).unwrap_err().contains("跳转目标"));
+    let mut broken = P21c::new(loaded.clone());
+    broken.compiled.func[entry].frame_size = MAX_FRAME_SIZE + 1;
+    assert!(P21c::from_bytes(&broken.to_bytes()).unwrap_err().contains("上限"));
+
+    // debug info must decode and agree with the code
+    let mut broken = P21c::new(loaded.clone());
+    broken.debug = Some(b"debug".to_vec());
+    assert!(P21c::from_bytes(&broken.to_bytes()).unwrap_err().contains("调试信息无效"));
+    let mut inconsistent = loaded.clone();
+    inconsistent.debug.as_mut().unwrap().funcs[entry].locals[0].slot = loaded.func[entry].frame_size;
+    let broken = P21c::new(inconsistent);
+    assert!(P21c::from_bytes(&broken.to_bytes()).unwrap_err().contains("变量"));
+    let mut inconsistent = loaded.clone();
+    inconsistent.debug.as_mut().unwrap().funcs[entry].lines[0].start = 0;
+    let broken = P21c::new(inconsistent);
+    assert!(P21c::from_bytes(&broken.to_bytes()).unwrap_err().contains("行号表"));
+
+    // the IO context of the host is too small for the script
+    define_io_ctx!(
+        struct Small {
+            g_n => n: i32
+        }
+    );
+    assert!(P21c::load::<Small>(&bytes, &natives).unwrap_err().contains("IO 偏移"));
+    assert!(P21c::load::<Ctx>(&bytes, &NativeRegistry::new()).unwrap_err().contains("half"));
+}
+
+#[test]
+fn test_asm() {
+    define_io_ctx!(
+        struct Ctx {
+            g_frame_id => frame_id: i32,
+            g_rotation_left_3 => rotation_left_3: f32
+        }
+    );
+
+    // golden file
+    let compiled = compile(include_str!("../../../example/anim.bis"), Ctx::metadata()).unwrap();
+    let golden = include_str!("../../../example/anim.p21s");
+    assert_eq!(disassemble(&compiled, &Ctx::metadata()), golden);
+    let assembled = assemble(golden, &Ctx::metadata()).unwrap();
+    assert_eq!(assembled.code_hash(), compiled.code_hash());
+    assert_eq!(assemble(&compiled.to_string(), &Vec::new()).unwrap().code_hash(), compiled.code_hash());
+
+    // everything the compiler emits reads back the same
+    let mut natives = NativeRegistry::new();
+    natives.register("half", |x: i32| if x % 2 == 0 { Ok(x / 2) } else { Err("奇数") }).unwrap();
+    natives.register("ask", |_options: i32| Suspend::<i32>::with_results()).unwrap();
+    let compiled = compile_with_natives(r#"
+        int collatz(int n) {
+            int steps = 0;
+            while (n != 1) {
+                try {
+                    n = half(n);
+                } catch {
+                    n = 3 * n + 1;
+                }
+                steps = steps + 1;
+            }
+            return steps;
+        }
+
+        void walk(int n) yield [int, float] {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                float t = lerp(0.0, 1.0, smoothstep(0.0, 4.0, float(i)));
+                yield [i, t];
+            }
+        }
+
+        void entry() yield [int, float] {
+            int task = spawn collatz(ask(27));
+            join task;
+            g_frame_id = abs(ifloor(-2.5)) + max(1, 2);
+            yield from walk(g_frame_id);
+        }
+    "#, Ctx::metadata(), &natives).unwrap();
+    let text = disassemble(&compiled, &Ctx::metadata());
+    let assembled = assemble(&text, &Ctx::metadata()).unwrap();
+    assert_eq!(assembled.code_hash(), compiled.code_hash());
+    assert_eq!(disassemble(&assembled, &Ctx::metadata()), text);
+
+    // hand-written, with a label used before it is defined and a call to a later function
+    let mut natives = NativeRegistry::new();
+    natives.register("scale", |x: i32| x * 2).unwrap();
+    natives.register("scale", |x: f32| x * 2.0).unwrap();
+    let mut compiled = assemble(r#"
+        extern scale(int) -> int
+        extern scale(float) -> float
+
+        fn entry [params 0, rets 0, frame 3]:
+            mov $3, %0              # loop counter
+            mov $0, %1
+        loop:
+            jmpif %1, done
+            call @tick(%0; [%0])
+            eq %0, %2, %1           # %2 is still zero
+            yield
+            jmp loop
+        done:
+            call-ffi @1(%0; [%0])   # `scale` is overloaded
+            ioset !g_rotation_left_3 %0
+            ret
+
+        fn tick [params 1, rets 1, frame 2]:
+            mov $1, %1
+            sub %0, %1, %1
+            ioget !g_frame_id %0
+            call-ffi @0(%0; [%0])
+            ioset !g_frame_id %0
+            ret %1
+    "#, &Ctx::metadata()).unwrap();
+    compiled.link(&natives).unwrap();
+
+    let mut ctx = Ctx { frame_id: 1, rotation_left_3: 0.0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("entry").unwrap(), &mut ctx) };
+    let mut resumes = 0;
+    while coroutine.resume().unwrap() == CoroutineStatus::Suspended {
+        resumes += 1;
+    }
+    assert_eq!(resumes, 3);
+    assert_eq!(ctx.frame_id, 8);
+    assert_eq!(ctx.rotation_left_3, 0.0);
+
+    // `mov` of a slot and of a constant are different instructions
+    let compiled = assemble("fn f [params 0, rets 0, frame 2]:\n  mov $1, %0\n  mov %0, %1\n  ret", &Vec::new()).unwrap();
+    assert!(matches!(compiled.code[0], Insc::Const { .. }));
+    assert!(matches!(compiled.code[1], Insc::Dup { src: 0, dst: 1 }));
+
+    let err = |source: &str| assemble(source, &Ctx::metadata()).unwrap_err();
+    assert!(err("fn f [params 0, rets 0, frame 1]:\n  jmp nowhere").contains("行 2: 未定义的标签 `nowhere`"));
+    assert!(err("fn f [params 0, rets 0, frame 1]:\n  mov $1, %1\n  ret").contains("槽位 %1"));
+    assert!(err("fn f [params 0, rets 0]:\n  ret").contains("行 1: 无法解析函数头"));
+    assert!(err("fn f [params 0, rets 0, frame 1]:\n  ioget !g_missing %0\n  ret").contains("g_missing"));
+    assert!(err("fn f [params 0, rets 0, frame 1]:\n  frobnicate %0\n  ret").contains("未知的指令"));
+    assert!(err(concat!(
+        "extern scale(int) -> int\nextern scale(float) -> float\n",
+        "fn f [params 0, rets 0, frame 1]:\n  call-ffi @scale(%0; [%0])\n  ret"
+    )).contains("只能用序号引用"));
+}
+
+#[test]
+fn test_debug_info() {
+    let source = r#"
+        struct Point {
+            int x;
+            float y;
+        }
+
+        int sum(int n) {
+            int total = 0;
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                Point p;
+                p.x = i;
+                total = total + p.x;
+            }
+            while (total > 100) {
+                int n = 3;
+                total = total - n;
+            }
+            return total;
+        }
+    "#;
+    let pos = |needle: &str| source.lines()
+        .enumerate()
+        .find_map(|(idx, line)| line.find(needle).map(|col| (idx + 1, col + 1)))
+        .unwrap();
+    let compiled = compile(source, Vec::new()).unwrap();
+    let debug = compiled.debug.as_ref().unwrap();
+    let func = &compiled.func[compiled.find_func("sum").unwrap()];
+    let func_debug = &debug.funcs[0];
+
+    // every instruction has a position, statements in order of the code
+    let addrs = func.addr..func.addr + func.code_len;
+    assert!(addrs.clone().all(|addr| debug.line_at(&compiled, addr).is_some()));
+    assert!(func_debug.lines.windows(2).all(|w| w[0].end <= w[1].start));
+    let at = |addr: usize| {
+        let entry = func_debug.line_at(addr).unwrap();
+        (entry.line, entry.col)
+    };
+    assert_eq!(at(func.addr), pos("int total"));
+    let ret = addrs.clone()
+        .find(|addr| matches!(&compiled.code[*addr], Insc::Return { rets } if !rets.is_empty()))
+        .unwrap();
+    assert_eq!(at(ret), pos("return total"));
+    assert_eq!(at(func.addr + func.code_len - 1), pos("int sum"));
+    assert_eq!(debug.line_at(&compiled, compiled.code.len()), None);
+
+    let entry_of = |needle: &str| *func_debug.lines.iter().find(|entry| (entry.line, entry.col) == pos(needle)).unwrap();
+    let names = |addr: usize| {
+        let mut names = func_debug.locals_at(addr)
+            .into_iter()
+            .map(|local| (local.name.as_str(), local.slot, local.ty))
+            .collect::<Vec<_>>();
+        names.sort_by_key(|(name, slot, _)| (*name, *slot));
+        names
+    };
+    let in_loop = entry_of("total = total + p.x").start;
+    assert_eq!(names(in_loop), [
+        ("i", 2, Type21::Int32),
+        ("n", 0, Type21::Int32),
+        ("p.x", 3, Type21::Int32),
+        ("p.y", 4, Type21::Float32),
+        ("total", 1, Type21::Int32)
+    ]);
+    // the inner `n` shadows the parameter until the end of its block
+    let shadowed = entry_of("total = total - n").start;
+    assert_eq!(names(shadowed)[1], ("n", 3, Type21::Int32));
+    assert_eq!(names(ret), [("i", 2, Type21::Int32), ("n", 0, Type21::Int32), ("total", 1, Type21::Int32)]);
+    assert!(names(entry_of("int total").start).iter().all(|(name, ..)| *name == "n"));
+
+    let text = disassemble_with_source(&compiled, &Vec::new(), source);
+    assert!(text.contains(&format!("  # {}: total = total + p.x;\n  add ", pos("total = total + p").0)));
+    assert_eq!(assemble(&text, &Vec::new()).unwrap().code_hash(), compiled.code_hash());
+
+    let read = P21c::from_bytes(&P21c::new(compiled.clone()).to_bytes()).unwrap();
+    assert_eq!(read.compiled.debug.as_ref(), Some(debug));
+
+    // assembled code has no positions to print
+    let assembled = assemble(&text, &Vec::new()).unwrap();
+    assert!(assembled.debug.is_none());
+    assert_eq!(disassemble_with_source(&assembled, &Vec::new(), source), disassemble(&assembled, &Vec::new()));
+}
+
+#[test]
+fn test_debugger() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_out => out: i32
+        }
+    );
+
+    let source = r#"
+        int square(int x) {
+            int y = x * x;
+            return y;
+        }
+
+        void entry() {
+            int i;
+            int total = 0;
+            for (i = 0; i < g_n; i = i + 1) {
+                total = total + square(i);
+            }
+            g_out = total;
+            g_out = g_out + 1;
+        }
+    "#;
+    let line = |needle: &str| source.lines().position(|line| line.contains(needle)).unwrap() + 1;
+    let compiled = compile(source, Ctx::metadata()).unwrap();
+    let mut ctx = Ctx { n: 3, out: 0 };
+    let mut coroutine = unsafe { Coroutine::new(&compiled, compiled.find_func("entry").unwrap(), &mut ctx) };
+    coroutine.set_debugger(Some(Debugger::new()));
+    let at_line = |coroutine: &Coroutine<Ctx>| coroutine.paused_frames().last().unwrap().line.unwrap();
+
+    let debugger = coroutine.debugger_mut().unwrap();
+    assert!(debugger.add_line_breakpoint(&compiled, line("int y")).is_ok());
+    assert!(debugger.add_line_breakpoint(&compiled, 1).is_err());
+    assert!(debugger.step(&compiled, StepMode::In).is_err());
+
+    // stops at the breakpoint on every call, with the caller below
+    for i in 0..2 {
+        assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+        let pause = *coroutine.debugger().unwrap().pause().unwrap();
+        assert_eq!((pause.reason, pause.task, pause.depth), (PauseReason::Breakpoint, None, 2));
+        let frames = coroutine.paused_frames();
+        assert_eq!(frames.iter().map(|frame| frame.func.as_str()).collect::<Vec<_>>(), ["entry", "square"]);
+        assert_eq!(frames.iter().map(|frame| frame.line.unwrap()).collect::<Vec<_>>(), [
+            line("total = total + square"),
+            line("int y")
+        ]);
+        let locals = coroutine.paused_locals(1);
+        assert_eq!(locals.len(), 1);
+        assert_eq!((locals[0].0.name.as_str(), unsafe { locals[0].1.i }), ("x", i));
+        let callers = coroutine.paused_locals(0)
+            .into_iter()
+            .map(|(local, value)| (local.name.as_str(), unsafe { value.i }))
+            .collect::<Vec<_>>();
+        assert!(callers.contains(&("i", i)));
+    }
+
+    // stepping over the return lands back in the caller, finishing leaves the function
+    let step = |coroutine: &mut Coroutine<Ctx>, mode| {
+        coroutine.debugger_mut().unwrap().step(&compiled, mode).unwrap();
+        assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+        assert_eq!(coroutine.debugger().unwrap().pause().unwrap().reason, PauseReason::Step);
+    };
+    step(&mut coroutine, StepMode::Over);
+    assert_eq!(at_line(&coroutine), line("return y"));
+    step(&mut coroutine, StepMode::Out);
+    assert_eq!(coroutine.debugger().unwrap().pause().unwrap().depth, 1);
+    assert_eq!(at_line(&coroutine), line("total = total + square"));
+
+    let debugger = coroutine.debugger_mut().unwrap();
+    let breakpoint = debugger.breakpoints().next().unwrap();
+    assert_eq!(debugger.remove_line_breakpoint(&compiled, line("int y")), Ok(vec![breakpoint]));
+    assert_eq!(debugger.remove_line_breakpoint(&compiled, line("int y")), Ok(vec![]));
+    assert!(debugger.breakpoints().next().is_none());
+    step(&mut coroutine, StepMode::Over);
+    assert_eq!(at_line(&coroutine), line("for (i = 0"));
+    step(&mut coroutine, StepMode::Over);
+    assert_eq!(at_line(&coroutine), line("total = total + square"));
+    step(&mut coroutine, StepMode::In);
+    assert_eq!(coroutine.paused_frames().last().unwrap().func, "square");
+
+    // watchpoints stop after the instruction that made the change, and report that instruction
+    coroutine.debugger_mut().unwrap().add_watch(4);
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+    let pause = *coroutine.debugger().unwrap().pause().unwrap();
+    let PauseReason::Watch { offset, old, new, insc_ptr } = pause.reason else { panic!() };
+    assert_eq!((offset, unsafe { (old.i, new.i) }), (4, (0, 5)));
+    assert!(matches!(compiled.code[insc_ptr], Insc::IOSetValue { offset: 4, .. }));
+    assert_eq!(compiled.debug.as_ref().unwrap().line_at(&compiled, insc_ptr).unwrap().line, line("g_out = total"));
+    assert_eq!(at_line(&coroutine), line("g_out = g_out + 1"));
+    coroutine.io_ctx().out = 100;
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Suspended));
+    let PauseReason::Watch { old, new, .. } = coroutine.debugger().unwrap().pause().unwrap().reason else { panic!() };
+    assert_eq!(unsafe { (old.i, new.i) }, (100, 101));
+
+    assert_eq!(coroutine.resume(), Ok(CoroutineStatus::Finished));
+    assert!(coroutine.debugger().unwrap().pause().is_none());
+    assert_eq!(ctx.out, 101);
+}
+
+#[test]
+fn test_profiler() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_x => x: f32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("slow_sqrt", |x: f32| {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        x.sqrt()
+    }).unwrap();
+
+    let source = r#"
+        int square(int x) {
+            return x * x;
+        }
+
+        void worker() {
+            g_n = g_n + square(3);
+        }
+
+        void entry() {
+            int i;
+            int task = spawn worker();
+            for (i = 0; i < 10; i = i + 1) {
+                g_n = g_n + square(i);
+            }
+            join task;
+            g_x = slow_sqrt(float(g_n));
+        }
+    "#;
+    let line = |needle: &str| source.lines().position(|line| line.contains(needle)).unwrap() + 1;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    let entry = compiled.find_func("entry").unwrap();
+
+    let mut ctx = Ctx { n: 0, x: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    combustor.set_profiler(Some(Profiler::new()));
+    combustor.set_fuel(1000);
+    unsafe { combustor.combust(&compiled, entry) }.unwrap();
+    let profiler = combustor.profiler().unwrap();
+
+    // every executed instruction is counted once
+    assert_eq!(profiler.total(), 1000 - combustor.fuel());
+    let func_total = profiler.func_counts(&compiled).iter().map(|(_, count)| count).sum::<u64>();
+    let line_total = profiler.line_counts(&compiled).iter().map(|(.., count)| count).sum::<u64>();
+    assert_eq!((func_total, line_total), (profiler.total(), profiler.total()));
+
+    let square = &compiled.func[compiled.find_func("square").unwrap()];
+    let square_count = profiler.insc_counts()[square.addr..square.addr + square.code_len].iter().sum::<u64>();
+    assert_eq!(square_count % 11, 0);
+    assert!(profiler.func_counts(&compiled).contains(&("square", square_count)));
+    let lines = profiler.line_counts(&compiled);
+    assert_eq!(lines.iter().find(|(_, at, _)| *at == line("return x * x")).unwrap().0, "square");
+    assert!(lines[0].2 >= lines[lines.len() - 1].2);
+
+    let natives = profiler.native_times(&compiled);
+    assert_eq!((natives.len(), natives[0].0, natives[0].1), (1, "slow_sqrt", 1));
+    assert!(natives[0].2 >= std::time::Duration::from_millis(1));
+
+    // tasks run on their own stacks
+    let folded = profiler.folded(&compiled);
+    let weights = folded.lines()
+        .map(|line| line.rsplit_once(' ').unwrap())
+        .map(|(path, count)| (path, count.parse::<u64>().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(weights.iter().map(|(path, _)| *path).collect::<Vec<_>>(), [
+        "entry",
+        "entry;square",
+        "worker",
+        "worker;square"
+    ]);
+    assert_eq!(weights[1].1 + weights[3].1, square_count);
+    assert_eq!(weights.iter().map(|(_, count)| count).sum::<u64>(), profiler.total());
+
+    // the results match the engines without a profiler
+    let profiled = (ctx.n, ctx.x);
+    let mut ctx = Ctx { n: 0, x: 0.0 };
+    unsafe { Combustor::new(&mut ctx).combust(&compiled, entry) }.unwrap();
+    assert_eq!((ctx.n, ctx.x), profiled);
+
+    let mut ctx = Ctx { n: 0, x: 0.0 };
+    let mut combustor = Combustor::new(&mut ctx);
+    combustor.set_profiler(Some(Profiler::new()));
+    combustor.set_fuel(10);
+    unsafe { combustor.combust(&compiled, entry) }.unwrap();
+    assert_eq!(combustor.profiler().unwrap().total(), 10);
+    combustor.profiler_mut().unwrap().clear();
+    assert!(combustor.profiler().unwrap().folded(&compiled).is_empty());
+}
+
+#[test]
+fn test_builder() {
+    define_io_ctx!(
+        struct Ctx {
+            g_n => n: i32,
+            g_out => out: i32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("check", |x: i32| if x < 100 { Ok(x) } else { Err("太大") }).unwrap();
+
+    let mut builder = CompiledBuilder::new();
+    let check = builder.import("check", NativeSignature { params: vec![Type21::Int32], rets: vec![Type21::Int32] });
+    // `fib` is called before it is built
+    let fib = builder.declare("fib", 1, 1).unwrap();
+
+    let mut entry = builder.function("entry", 0, 0).unwrap();
+    let n = entry.alloc();
+    let result = entry.alloc();
+    let caught = entry.new_label();
+    let done = entry.new_label();
+    entry.emit(Insc::IOGetValue { offset: 0, dst: n });
+    entry.try_begin(caught);
+    entry.call(fib, &[n], &[result]);
+    entry.call_ffi(check, &[result], &[result]);
+    entry.emit(Insc::TryEnd);
+    entry.jmp(done);
+    entry.bind(caught).unwrap();
+    let minus_one = entry.constant(-1);
+    entry.emit(Insc::Dup { src: minus_one, dst: result });
+    entry.bind(done).unwrap();
+    entry.emit(Insc::IOSetValue { offset: 4, src: result });
+    entry.finish().unwrap();
+
+    let mut body = builder.function("fib", 1, 1).unwrap();
+    let [a, b, i, tmp, cond] = [(); 5].map(|_| body.alloc());
+    let (zero, one) = (body.constant(0), body.constant(1));
+    assert_eq!(body.constant(0), zero);
+    let head = body.new_label();
+    let exit = body.new_label();
+    body.emit(Insc::Dup { src: zero, dst: a });
+    body.emit(Insc::Dup { src: one, dst: b });
+    body.emit(Insc::Dup { src: zero, dst: i });
+    body.bind(head).unwrap();
+    body.emit(Insc::LtInt { lhs: i, rhs: 0, dst: cond });
+    body.emit(Insc::Not { src: cond, dst: cond });
+    body.jmp_if(cond, exit);
+    body.emit(Insc::AddInt { lhs: a, rhs: b, dst: tmp });
+    body.emit(Insc::Dup { src: b, dst: a });
+    body.emit(Insc::Dup { src: tmp, dst: b });
+    body.emit(Insc::AddInt { lhs: i, rhs: one, dst: i });
+    body.jmp(head);
+    body.bind(exit).unwrap();
+    body.ret(&[a]);
+    let fib_id = body.func_id();
+    body.finish().unwrap();
+    assert_eq!(fib_id, fib);
+
+    let mut compiled = builder.finish().unwrap();
+    assert_eq!(compiled.func[fib].frame_size, 8);
+    compiled.link(&natives).unwrap();
+    // `fib` comes first in the function table but after `entry` in the code
+    let text = compiled.to_string();
+    assert_eq!(assemble(&text, &Ctx::metadata()).unwrap().to_string(), text);
+
+    let entry = compiled.find_func("entry").unwrap();
+    for (n, out) in [(0, 0), (1, 1), (10, 55), (11, 89), (12, -1)] {
+        let mut ctx = Ctx { n, out: 0 };
+        unsafe { Combustor::new(&mut ctx).combust(&compiled, entry) }.unwrap();
+        assert_eq!(ctx.out, out, "fib({})", n);
+    }
+
+    let mut builder = CompiledBuilder::new();
+    builder.declare("missing", 0, 0).unwrap();
+    assert!(builder.finish().unwrap_err().contains("`missing` 只有声明"));
+
+    let mut builder = CompiledBuilder::new();
+    let mut func = builder.function("f", 0, 0).unwrap();
+    let label = func.new_label();
+    func.jmp(label);
+    assert!(func.finish().unwrap_err().contains("没有绑定"));
+    assert!(builder.declare("f", 1, 0).unwrap_err().contains("声明不一致"));
+
+    let mut builder = CompiledBuilder::new();
+    let mut func = builder.function("f", 0, 1).unwrap();
+    func.ret(&[]);
+    func.emit(Insc::Return { rets: Box::new([0, 1]) });
+    func.finish().unwrap();
+    assert!(builder.finish().unwrap_err().contains("返回值个数不正确"));
+}
+
+#[test]
+fn test_engines() {
+    define_io_ctx!(
+        #[derive(Debug, Clone, PartialEq)]
+        struct Ctx {
+            g_seed => seed: i32,
+            g_acc => acc: i32,
+            g_out => out: f32
+        }
+    );
+
+    let mut natives = NativeRegistry::new();
+    natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数没有平方根") } else { Ok(x.sqrt()) }).unwrap();
+
+    let source = r#"
+        void tick(int n) {
+            int i;
+            for (i = 0; i < n; i = i + 1) {
+                g_acc = g_acc + i * 3 % 7;
+                yield;
+            }
+        }
+
+        [int, float] step(int i) {
+            float t = float(i) / 8.0;
+            int a = i * i - g_seed + 16 / (i % 3 - 1);
+            float b = smoothstep(0.0, 1.0, t) + lerp(1.0, 2.0, t);
+            return [a, b];
+        }
+
+        void entry() yield [int, float] {
+            int task = spawn tick(g_seed % 4 + 1);
+            int i;
+            for (i = 0; i <= 8; i = i + 1) {
+                int a = 0;
+                float b = 0.0;
+                try {
+                    [a, b] = step(i);
+                    g_out = checked_sqrt(float(a)) + b;
+                } catch {
+                    g_out = -b;
+                }
+                yield [a, b];
+            }
+            join task;
+        }
+
+        void mix() {
+            int i;
+            for (i = -6; i < 6; i = i + 1) {
+                float x = float(i) * 0.7 + float(g_seed);
+                bool odd = i % 2 != 0;
+                bool big = x > 2.5 || x <= -1.0;
+                if (odd && !big) {
+                    g_acc = g_acc + ifloor(x) + iceil(x) * iround(x) - -i / 3;
+                } else {
+                    g_acc = g_acc - int(x) + int(odd) + abs(i);
+                }
+                g_out = g_out + sin(x) + pow(2.0, x) + max(x, 1.0) + ease_in_out_cubic(float(big));
+                g_out = g_out + round(x) - floor(x) * ceil(x) + clamp(x, -2.0, 2.0) / -1.5;
+                yield;
+            }
+        }
+    "#;
+    let compiled = compile_with_natives(source, Ctx::metadata(), &natives).unwrap();
+    assert!(CompactCode::lower(&compiled).is_some() && DecodedCode::lower(&compiled).is_some());
+
+    // every resume is cut short by the fuel, so that runs continue in the middle of functions
+    let trace = |entry: usize, seed: i32, engine: fn(usize) -> Engine| {
+        let mut ctx = Ctx { seed, acc: 0, out: 0.0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        let mut trace = Vec::new();
+        combustor.set_engine(engine(0));
+        combustor.set_fuel(7);
+        let mut resume_ptr = unsafe { combustor.combust(&compiled, entry) }.unwrap();
+        for resume in 1.. {
+            trace.push((resume_ptr, combustor.yielded().to_vec(), combustor.io_ctx.clone()));
+            let Some(insc_ptr) = resume_ptr else { break };
+            combustor.set_engine(engine(resume));
+            combustor.set_fuel(7);
+            resume_ptr = unsafe { combustor.combust_resume(&compiled, insc_ptr) }.unwrap();
+        }
+        trace
+    };
+    for (entry, seed) in ["entry", "mix"].into_iter().flat_map(|name| [0, 3, 17].map(|seed| (name, seed))) {
+        let func = compiled.find_func(entry).unwrap();
+        let expected = trace(func, seed, |_| Engine::Insc);
+        assert_eq!(expected.last().unwrap().0, None);
+        assert_eq!(trace(func, seed, |_| Engine::Compact), expected, "{} seed {}", entry, seed);
+        assert_eq!(trace(func, seed, |_| Engine::Decoded), expected, "{} seed {}", entry, seed);
+        let rotate = |resume: usize| [Engine::Insc, Engine::Compact, Engine::Decoded][resume % 3];
+        assert_eq!(trace(func, seed, rotate), expected, "{} seed {}", entry, seed);
+    }
+
+    // code which cannot be lowered is run as is, here slots beyond `u16`, and slots whose byte
+    // offsets are beyond `u16`
+    let run = |compiled: &Compiled, engine_code: Option<EngineCode>| {
+        let mut ctx = Ctx { seed: 0, acc: 0, out: 0.0 };
+        let mut combustor = Combustor::new(&mut ctx);
+        assert_eq!(combustor.engine(), Engine::Compact);
+        if let Some(engine_code) = engine_code {
+            combustor.set_engine_code(engine_code);
+        }
+        unsafe { combustor.combust(compiled, 0) }.unwrap();
+        ctx.acc
+    };
+    for slots in [70000, 20000] {
+        let mut builder = CompiledBuilder::new();
+        let mut func = builder.function("entry", 0, 0).unwrap();
+        let far = func.alloc_n(slots) + slots - 1;
+        let one = func.constant(1);
+        func.emit(Insc::AddInt { lhs: one, rhs: one, dst: far });
+        func.emit(Insc::IOSetValue { offset: 4, src: far });
+        func.finish().unwrap();
+        let compiled = builder.finish().unwrap();
+        assert_eq!(CompactCode::lower(&compiled).is_some(), slots < 65536);
+        assert!(DecodedCode::lower(&compiled).is_none());
+        for engine in [Engine::Compact, Engine::Decoded] {
+            assert_eq!(run(&compiled, Some(EngineCode::new(&compiled, engine))), 2);
+        }
+    }
+
+    // shared lowered code is only used with the code it was lowered from
+    let mut builder = CompiledBuilder::new();
+    let mut func = builder.function("entry", 0, 0).unwrap();
+    let one = func.constant(1);
+    func.emit(Insc::IOSetValue { offset: 4, src: one });
+    func.finish().unwrap();
+    let compiled = builder.finish().unwrap();
+    let mut changed = compiled.clone();
+    let addr = changed.func[0].addr;
+    changed.code[addr] = Insc::Const { value: RtValue::from(5), dst: one };
+    let engine_code = EngineCode::new(&compiled, Engine::Compact);
+    assert!(engine_code.is_for(&compiled, Engine::Compact));
+    assert!(!engine_code.is_for(&changed, Engine::Compact));
+    assert_eq!(run(&compiled, Some(engine_code.clone())), 1);
+    assert_eq!(run(&changed, Some(engine_code)), 5);
+}
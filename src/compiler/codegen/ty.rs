@@ -0,0 +1,112 @@
+use std::fmt::{Display, Formatter};
+use smallvec::SmallVec;
+
+use crate::compiler::codegen::CodegenContext;
+use crate::compiler::parse::cst::TypeRef;
+use crate::io_ctx::Type21;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Ty {
+    Scalar(Type21),
+    Struct(usize),
+    Enum(usize)
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldInfo {
+    pub name: String,
+    pub ty: Ty,
+    pub offset: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct StructInfo {
+    pub name: String,
+    pub fields: SmallVec<[FieldInfo; 4]>,
+    pub size: usize
+}
+
+impl StructInfo {
+    pub fn field(&self, name: &str) -> Option<&FieldInfo> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumInfo {
+    pub name: String,
+    pub variants: SmallVec<[(String, i32); 4]>
+}
+
+impl EnumInfo {
+    pub fn variant(&self, name: &str) -> Option<i32> {
+        self.variants.iter().find(|(variant, _)| variant == name).map(|(_, value)| *value)
+    }
+}
+
+pub struct TyDisplay<'a> {
+    ty: Ty,
+    ctx: &'a CodegenContext
+}
+
+impl Display for TyDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.ty {
+            Ty::Scalar(ty) => write!(f, "{}", ty),
+            Ty::Struct(struct_id) => write!(f, "{}", self.ctx.structs[struct_id].name),
+            Ty::Enum(enum_id) => write!(f, "{}", self.ctx.enums[enum_id].name)
+        }
+    }
+}
+
+impl CodegenContext {
+    pub fn resolve_type(&self, ty: &TypeRef) -> Result<Ty, String> {
+        match ty {
+            TypeRef::Scalar(ty) => Ok(Ty::Scalar(*ty)),
+            TypeRef::Named(name) => if let Some(struct_id) = self.declared_struct.get(name) {
+                Ok(Ty::Struct(*struct_id))
+            } else if let Some(enum_id) = self.declared_enum.get(name) {
+                Ok(Ty::Enum(*enum_id))
+            } else {
+                Err(format!("未定义的类型 `{}`", name))
+            }
+        }
+    }
+
+    pub fn ty_size(&self, ty: Ty) -> usize {
+        match ty {
+            Ty::Scalar(_) | Ty::Enum(_) => 1,
+            Ty::Struct(struct_id) => self.structs[struct_id].size
+        }
+    }
+
+    pub fn ty_display(&self, ty: Ty) -> TyDisplay<'_> {
+        TyDisplay { ty, ctx: self }
+    }
+
+    pub fn ty_list_size(&self, tys: &[Ty]) -> usize {
+        tys.iter().map(|ty| self.ty_size(*ty)).sum()
+    }
+
+    pub fn flatten_ty(&self, ty: Ty, dst: &mut Vec<Type21>) {
+        match ty {
+            Ty::Scalar(ty) => dst.push(ty),
+            Ty::Enum(_) => dst.push(Type21::Int32),
+            Ty::Struct(struct_id) => for field in self.structs[struct_id].fields.iter() {
+                self.flatten_ty(field.ty, dst);
+            }
+        }
+    }
+
+    /// Like `flatten_ty`, also naming each scalar after the path to it, such as `p.pos.x`, and
+    /// giving its slot counted from `loc`
+    pub fn flatten_named(&self, name: &str, ty: Ty, loc: usize, dst: &mut Vec<(String, usize, Type21)>) {
+        match ty {
+            Ty::Scalar(ty) => dst.push((name.to_string(), loc, ty)),
+            Ty::Enum(_) => dst.push((name.to_string(), loc, Type21::Int32)),
+            Ty::Struct(struct_id) => for field in self.structs[struct_id].fields.iter() {
+                self.flatten_named(&format!("{}.{}", name, field.name), field.ty, loc + field.offset, dst);
+            }
+        }
+    }
+}
@@ -9,6 +9,7 @@ pub struct FunctionInfo {
 
     pub mangled_name: String,
     pub is_generator: bool,
+    pub yield_ty: SmallVec<[Type21; 2]>,
     pub num_state: usize
 }
 
@@ -1,16 +1,14 @@
-use std::collections::HashMap;
-use crate::compiler::codegen::expr_consteval::ConstEvalResult;
-use crate::compiler::codegen_c::decl::FunctionInfo;
+use crate::io_ctx::Type21;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
 
 pub mod decl;
 
+pub const COMMON_INC: &str = include_str!("common.inc");
+
 #[derive(Debug)]
 pub struct CCodegenContext {
     code: String,
-    indent: u32,
-
-    constant: HashMap<String, ConstEvalResult>,
-    declared_func: HashMap<String, FunctionInfo>,
+    indent: u32
 }
 
 impl CCodegenContext {
@@ -33,3 +31,93 @@ impl CCodegenContext {
         }
     }
 }
+
+/// C expression converting `operand` from `from` to `to`, with the same semantics as the
+/// corresponding VM instruction
+pub fn c_type_cast(from: Type21, to: Type21, operand: &str) -> String {
+    match (from, to) {
+        (Type21::Int32, Type21::Float32) => format!("((float)({}))", operand),
+        (Type21::Int32, Type21::Bool) => format!("(({}) != 0)", operand),
+        (Type21::Float32, Type21::Int32) => format!("pr21_f2i({})", operand),
+        (Type21::Float32, Type21::Bool) => format!("pr21_f2b({})", operand),
+        (Type21::Bool, Type21::Int32) => format!("((int32_t)({}))", operand),
+        (Type21::Bool, Type21::Float32) => format!("pr21_b2f({})", operand),
+        (_, _) => operand.to_string()
+    }
+}
+
+pub fn c_type_name(ty: Type21) -> &'static str {
+    match ty {
+        Type21::Int32 => "int32_t",
+        Type21::Float32 => "float",
+        Type21::Bool => "bool"
+    }
+}
+
+/// Output of one resume of the generator `func_name`, the C counterpart of `Combustor::yielded`:
+/// `state` is `PR21_POLL_PENDING` with the yielded values filled in when the generator yields,
+/// and `PR21_POLL_READY` once it returns. Fields are named like the `pr21_poll_*_t` structs.
+pub fn c_yield_struct(func_name: &str, yield_ty: &[Type21]) -> String {
+    let mut code = String::from("typedef struct {\n  pr21_poll_state_t state;\n");
+    if let [ty] = yield_ty {
+        code.push_str(&format!("  {} value;\n", c_type_name(*ty)));
+    } else {
+        for (idx, ty) in yield_ty.iter().enumerate() {
+            code.push_str(&format!("  {} value{};\n", c_type_name(*ty), idx + 1));
+        }
+    }
+    code.push_str(&format!("}} pr21_yield_{}_t;\n", func_name));
+    code
+}
+
+/// C function implementing a math intrinsic, from `math.h` or `common.inc`
+pub fn c_math1_func(op: MathOp1) -> &'static str {
+    match op {
+        MathOp1::Sin => "sinf",
+        MathOp1::Cos => "cosf",
+        MathOp1::Tan => "tanf",
+        MathOp1::Asin => "asinf",
+        MathOp1::Acos => "acosf",
+        MathOp1::Atan => "atanf",
+        MathOp1::Sqrt => "sqrtf",
+        MathOp1::Exp => "expf",
+        MathOp1::Ln => "logf",
+        MathOp1::AbsInt => "pr21_absi",
+        MathOp1::AbsFloat => "fabsf",
+        MathOp1::EaseInQuad => "pr21_ease_in_quad",
+        MathOp1::EaseOutQuad => "pr21_ease_out_quad",
+        MathOp1::EaseInOutQuad => "pr21_ease_in_out_quad",
+        MathOp1::EaseInCubic => "pr21_ease_in_cubic",
+        MathOp1::EaseOutCubic => "pr21_ease_out_cubic",
+        MathOp1::EaseInOutCubic => "pr21_ease_in_out_cubic",
+        MathOp1::EaseInSine => "pr21_ease_in_sine",
+        MathOp1::EaseOutSine => "pr21_ease_out_sine",
+        MathOp1::EaseInOutSine => "pr21_ease_in_out_sine",
+        MathOp1::EaseInExpo => "pr21_ease_in_expo",
+        MathOp1::EaseOutExpo => "pr21_ease_out_expo",
+        MathOp1::EaseInOutExpo => "pr21_ease_in_out_expo"
+    }
+}
+
+pub fn c_math2_func(op: MathOp2) -> &'static str {
+    match op {
+        MathOp2::Atan2 => "atan2f",
+        MathOp2::Pow => "powf",
+        MathOp2::Fmod => "fmodf",
+        MathOp2::MinInt => "pr21_mini",
+        MathOp2::MinFloat => "fminf",
+        MathOp2::MaxInt => "pr21_maxi",
+        MathOp2::MaxFloat => "fmaxf"
+    }
+}
+
+pub fn c_math3_func(op: MathOp3) -> &'static str {
+    match op {
+        MathOp3::Lerp => "pr21_lerp",
+        MathOp3::ClampInt => "pr21_clampi",
+        MathOp3::ClampFloat => "pr21_clampf",
+        MathOp3::Smoothstep => "pr21_smoothstep"
+    }
+}
+
+#[cfg(test)] mod test;
@@ -0,0 +1,183 @@
+use std::fs::write;
+use std::process::Command;
+
+use crate::compiler::codegen_c::{c_math1_func, c_math2_func, c_math3_func, c_type_cast, c_yield_struct, COMMON_INC};
+use crate::io_ctx::Type21;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::value::{
+    float_ceil_to_int,
+    float_floor_to_int,
+    float_round_to_int,
+    float_to_bool,
+    float_to_int,
+    RtValue
+};
+
+const EDGE_VALUES: [(&str, f32); 12] = [
+    ("NAN", f32::NAN),
+    ("INFINITY", f32::INFINITY),
+    ("-INFINITY", f32::NEG_INFINITY),
+    ("3e9f", 3e9),
+    ("-3e9f", -3e9),
+    ("2147483520.0f", 2147483520.0),
+    ("-2147483648.0f", -2147483648.0),
+    ("2.5f", 2.5),
+    ("-2.5f", -2.5),
+    ("-0.5f", -0.5),
+    ("0.0f", 0.0),
+    ("-0.0f", -0.0)
+];
+
+#[test]
+fn test_conversion_matches_vm() {
+    let mut program = String::from(COMMON_INC);
+    program.push_str("\n#include <stdio.h>\n\nint main(void) {\n  float x;\n");
+    for (literal, _) in EDGE_VALUES.iter() {
+        program.push_str(&format!(
+            "  x = {};\n  printf(\"%d %d %d %d %d\\n\", {}, pr21_round2i(x), pr21_floor2i(x), pr21_ceil2i(x), (int){});\n",
+            literal,
+            c_type_cast(Type21::Float32, Type21::Int32, "x"),
+            c_type_cast(Type21::Float32, Type21::Bool, "x")
+        ));
+    }
+    program.push_str("  return 0;\n}\n");
+
+    let Some(output) = compile_and_run("conv", &program) else {
+        return;
+    };
+    for (line, (literal, x)) in output.lines().zip(EDGE_VALUES.iter()) {
+        let expected = format!(
+            "{} {} {} {} {}",
+            float_to_int(*x),
+            float_round_to_int(*x),
+            float_floor_to_int(*x),
+            float_ceil_to_int(*x),
+            float_to_bool(*x) as i32
+        );
+        assert_eq!(line, expected, "x = {}", literal);
+    }
+    assert_eq!(output.lines().count(), EDGE_VALUES.len());
+}
+
+#[test]
+fn test_math_matches_vm() {
+    let unary = [
+        MathOp1::Sin, MathOp1::Cos, MathOp1::Atan, MathOp1::Sqrt, MathOp1::Exp, MathOp1::AbsFloat,
+        MathOp1::EaseInQuad, MathOp1::EaseOutQuad, MathOp1::EaseInOutQuad, MathOp1::EaseInCubic,
+        MathOp1::EaseOutCubic, MathOp1::EaseInOutCubic, MathOp1::EaseInSine, MathOp1::EaseOutSine,
+        MathOp1::EaseInOutSine, MathOp1::EaseInExpo, MathOp1::EaseOutExpo, MathOp1::EaseInOutExpo
+    ];
+    let inputs = [0.0f32, 0.25, 0.5, 0.75, 1.0, 2.5, -0.5];
+    let pairs = [(1.0f32, 2.0f32), (-3.0, 0.5), (0.0, -1.0), (7.5, 2.0)];
+    let ternary = [(0.0f32, 10.0f32, 0.25f32), (1.0, 3.0, 2.0), (-1.0, 1.0, 5.0), (2.0, 2.0, 2.0)];
+
+    let mut program = String::from(COMMON_INC);
+    program.push_str("\n#include <stdio.h>\n\nint main(void) {\n");
+    let mut expected = Vec::new();
+    for op in unary {
+        for x in inputs {
+            program.push_str(&format!("  printf(\"%.9g\\n\", (double){}({:?}f));\n", c_math1_func(op), x));
+            expected.push(unsafe { op.eval(RtValue::from(x)).f });
+        }
+    }
+    for op in [MathOp2::Atan2, MathOp2::Pow, MathOp2::Fmod, MathOp2::MinFloat, MathOp2::MaxFloat] {
+        for (x, y) in pairs {
+            program.push_str(&format!(
+                "  printf(\"%.9g\\n\", (double){}({:?}f, {:?}f));\n",
+                c_math2_func(op), x, y
+            ));
+            expected.push(unsafe { op.eval(RtValue::from(x), RtValue::from(y)).f });
+        }
+    }
+    for op in [MathOp3::Lerp, MathOp3::ClampFloat, MathOp3::Smoothstep] {
+        for (x, y, z) in ternary {
+            program.push_str(&format!(
+                "  printf(\"%.9g\\n\", (double){}({:?}f, {:?}f, {:?}f));\n",
+                c_math3_func(op), x, y, z
+            ));
+            expected.push(unsafe { op.eval(RtValue::from(x), RtValue::from(y), RtValue::from(z)).f });
+        }
+    }
+    for (x, lo, hi) in [(5, 0, 3), (-5, 0, 3), (1, 0, 3), (1, 3, 0)] {
+        program.push_str(&format!("  printf(\"%d\\n\", pr21_clampi({}, {}, {}));\n", x, lo, hi));
+        expected.push(unsafe { MathOp3::ClampInt.eval(RtValue::from(x), RtValue::from(lo), RtValue::from(hi)).i } as f32);
+    }
+    program.push_str("  printf(\"%d\\n\", pr21_absi(INT32_MIN));\n");
+    expected.push(unsafe { MathOp1::AbsInt.eval(RtValue::from(i32::MIN)).i } as f32);
+    program.push_str("  return 0;\n}\n");
+
+    let Some(output) = compile_and_run("math", &program) else {
+        return;
+    };
+    let actual = output.lines().map(|line| line.parse::<f32>().unwrap()).collect::<Vec<_>>();
+    assert_eq!(actual.len(), expected.len());
+    for (idx, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+        assert!(
+            actual == expected
+                || (actual.is_nan() && expected.is_nan())
+                || (actual - expected).abs() <= 1e-6 * expected.abs().max(1.0),
+            "case {}: C gives {}, VM gives {}", idx, actual, expected
+        );
+    }
+}
+
+#[test]
+fn test_yield_struct() {
+    assert_eq!(
+        c_yield_struct("count", &[Type21::Int32]),
+        "typedef struct {\n  pr21_poll_state_t state;\n  int32_t value;\n} pr21_yield_count_t;\n"
+    );
+
+    let mut program = String::from(COMMON_INC);
+    program.push_str(&c_yield_struct("curve", &[Type21::Float32, Type21::Bool]));
+    program.push_str(r#"
+#include <stdio.h>
+
+static pr21_yield_curve_t curve_resume(int32_t *step) {
+  pr21_yield_curve_t out;
+  if (*step == 3) {
+    out.state = PR21_POLL_READY;
+    return out;
+  }
+  out.state = PR21_POLL_PENDING;
+  out.value1 = (float)*step * 0.5f;
+  out.value2 = *step == 2;
+  *step += 1;
+  return out;
+}
+
+int main(void) {
+  int32_t step = 0;
+  pr21_yield_curve_t out;
+  while ((out = curve_resume(&step)).state == PR21_POLL_PENDING) {
+    printf("%g %d\n", (double)out.value1, (int)out.value2);
+  }
+  return 0;
+}
+"#);
+
+    let Some(output) = compile_and_run("yield", &program) else {
+        return;
+    };
+    assert_eq!(output, "0 0\n0.5 0\n1 1\n");
+}
+
+/// Compiles `program` with the system C compiler and returns its stdout, or `None` if there is no
+/// C compiler available
+fn compile_and_run(name: &str, program: &str) -> Option<String> {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("pr21_{}_{}.c", name, std::process::id()));
+    let binary = dir.join(format!("pr21_{}_{}", name, std::process::id()));
+    write(&source, program).unwrap();
+
+    let Ok(status) = Command::new("cc").arg(&source).arg("-o").arg(&binary).arg("-lm").status() else {
+        eprintln!("no C compiler available, skipping");
+        return None;
+    };
+    assert!(status.success());
+
+    let output = Command::new(&binary).output().unwrap();
+    let _ = std::fs::remove_file(source);
+    let _ = std::fs::remove_file(binary);
+    Some(String::from_utf8(output.stdout).unwrap())
+}
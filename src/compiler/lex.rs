@@ -23,6 +23,17 @@ pub enum TokenData {
     KwdYield,
     KwdTrue,
     KwdFalse,
+    KwdStruct,
+    KwdEnum,
+    KwdSwitch,
+    KwdCase,
+    KwdDefault,
+    KwdExtern,
+    KwdTry,
+    KwdCatch,
+    KwdSpawn,
+    KwdJoin,
+    KwdParallel,
 
     // Operators
     OpAssign,
@@ -50,6 +61,9 @@ pub enum TokenData {
     SymRBrace,
     SymLBracket,
     SymRBracket,
+    SymDot,
+    SymColon,
+    SymColonColon,
 
     // End of Input
     EOI
@@ -58,12 +72,13 @@ pub enum TokenData {
 #[derive(Debug, Clone)]
 pub struct Token {
     pub data: TokenData,
-    pub line: usize
+    pub line: usize,
+    pub col: usize
 }
 
 impl Token {
     pub fn new(data: TokenData, line: usize) -> Self {
-        Self { data, line }
+        Self { data, line, col: 0 }
     }
 
     pub fn lit_int(value: i32, line: usize) -> Self {
@@ -82,12 +97,14 @@ impl Token {
 pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
     let mut tokens = Vec::new();
     let mut line = 1;
+    let mut line_start = 0;
     let mut input = input.chars().collect::<Vec<char>>();
     input.push('\0');
 
     let mut idx = 0;
     loop {
         let current_char = input[idx];
+        let (col, token_count) = (idx - line_start + 1, tokens.len());
         match current_char {
             '\0' => break,
             '#' => {
@@ -99,9 +116,10 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
             '\n' => {
                 idx += 1;
                 line += 1;
+                line_start = idx;
             },
-            '0'..='9' => lex_number(&mut tokens, &mut idx, &mut input, line)?,
-            'a'..='z' | 'A'..='Z' | '_' => lex_kwd_or_ident(&mut tokens, &mut idx, &mut input, line),
+            '0'..='9' => lex_number(&mut tokens, &mut idx, &input, line)?,
+            'a'..='z' | 'A'..='Z' | '_' => lex_kwd_or_ident(&mut tokens, &mut idx, &input, line),
             '+' => {
                 idx += 1;
                 tokens.push(Token::new(TokenData::OpAdd, line));
@@ -204,8 +222,24 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
                 idx += 1;
                 tokens.push(Token::new(TokenData::SymRBracket, line));
             },
+            '.' => {
+                idx += 1;
+                tokens.push(Token::new(TokenData::SymDot, line));
+            },
+            ':' => {
+                idx += 1;
+                if input[idx] == ':' {
+                    idx += 1;
+                    tokens.push(Token::new(TokenData::SymColonColon, line));
+                } else {
+                    tokens.push(Token::new(TokenData::SymColon, line));
+                }
+            },
             _ => return Err(SyntaxError::new(line))
         }
+        for token in tokens[token_count..].iter_mut() {
+            token.col = col;
+        }
     }
 
     tokens.push(Token::new(TokenData::EOI, line));
@@ -215,7 +249,7 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, SyntaxError> {
 pub fn lex_number(
     tokens: &mut Vec<Token>,
     idx: &mut usize,
-    input: &mut Vec<char>,
+    input: &[char],
     line: usize
 ) -> Result<(), SyntaxError> {
     let mut value = String::new();
@@ -250,7 +284,7 @@ pub fn lex_number(
 pub fn lex_kwd_or_ident(
     tokens: &mut Vec<Token>,
     idx: &mut usize,
-    input: &mut Vec<char>,
+    input: &[char],
     line: usize
 ) {
     let mut value = String::new();
@@ -269,6 +303,7 @@ pub fn lex_kwd_or_ident(
         "const" => tokens.push(Token::new(TokenData::KwdConst, line)),
         "int" => tokens.push(Token::new(TokenData::KwdInt, line)),
         "float" => tokens.push(Token::new(TokenData::KwdFloat, line)),
+        "bool" => tokens.push(Token::new(TokenData::KwdBool, line)),
         "var" => tokens.push(Token::new(TokenData::KwdVar, line)),
         "void" => tokens.push(Token::new(TokenData::KwdVoid, line)),
         "return" => tokens.push(Token::new(TokenData::KwdReturn, line)),
@@ -281,6 +316,17 @@ pub fn lex_kwd_or_ident(
         "yield" => tokens.push(Token::new(TokenData::KwdYield, line)),
         "true" => tokens.push(Token::new(TokenData::KwdTrue, line)),
         "false" => tokens.push(Token::new(TokenData::KwdFalse, line)),
+        "struct" => tokens.push(Token::new(TokenData::KwdStruct, line)),
+        "enum" => tokens.push(Token::new(TokenData::KwdEnum, line)),
+        "switch" => tokens.push(Token::new(TokenData::KwdSwitch, line)),
+        "case" => tokens.push(Token::new(TokenData::KwdCase, line)),
+        "default" => tokens.push(Token::new(TokenData::KwdDefault, line)),
+        "extern" => tokens.push(Token::new(TokenData::KwdExtern, line)),
+        "try" => tokens.push(Token::new(TokenData::KwdTry, line)),
+        "catch" => tokens.push(Token::new(TokenData::KwdCatch, line)),
+        "spawn" => tokens.push(Token::new(TokenData::KwdSpawn, line)),
+        "join" => tokens.push(Token::new(TokenData::KwdJoin, line)),
+        "parallel" => tokens.push(Token::new(TokenData::KwdParallel, line)),
         _ => tokens.push(Token::ident(value, line))
     }
 }
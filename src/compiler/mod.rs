@@ -6,6 +6,20 @@ pub mod codegen_c;
 
 use std::fmt::Debug;
 
+use crate::compiler::codegen::CodegenContext;
+use crate::compiler::lex::tokenize;
+use crate::compiler::parse::parse;
+use crate::io_ctx::IOContextMetadata;
+use crate::native::NativeRegistry;
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::verify::verify;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompileOptions {
+    /// `-W`: warn about implicit conversions that may lose precision
+    pub warn_lossy: bool
+}
+
 #[derive(Debug, Clone)]
 pub struct SyntaxError {
     pub line: usize
@@ -18,3 +32,41 @@ impl SyntaxError {
         Self { line }
     }
 }
+
+pub fn compile(source: &str, io_metadata: IOContextMetadata) -> Result<Compiled, String> {
+    compile_with_warnings(source, io_metadata, &NativeRegistry::new(), CompileOptions::default())
+        .map(|(compiled, _)| compiled)
+}
+
+/// Compiles and links against `natives`, so `extern` functions must be provided by `natives` too
+pub fn compile_with_natives(
+    source: &str,
+    io_metadata: IOContextMetadata,
+    natives: &NativeRegistry
+) -> Result<Compiled, String> {
+    let (mut compiled, _) = compile_with_warnings(source, io_metadata, natives, CompileOptions::default())?;
+    compiled.link(natives)?;
+    Ok(compiled)
+}
+
+/// Compiles without linking: calls to `natives` and `extern` functions are recorded in
+/// `Compiled::imports` and must be bound with `Compiled::link` before running
+pub fn compile_with_warnings(
+    source: &str,
+    io_metadata: IOContextMetadata,
+    natives: &NativeRegistry,
+    options: CompileOptions
+) -> Result<(Compiled, Vec<String>), String> {
+    let tokens = tokenize(source).map_err(|e| format!("行 {}: 词法错误", e.line))?;
+    let program = parse(&tokens).map_err(|e| format!("行 {}: 语法错误", e.line))?;
+
+    let io_size = io_metadata.iter().map(|(_, _, ty)| ty.size()).sum();
+    let mut codegen_ctx = CodegenContext::with_io_metadata(io_metadata);
+    codegen_ctx.natives = natives.clone();
+    codegen_ctx.options = options;
+    codegen_ctx.visit_program(&program)?;
+    let warnings = std::mem::take(&mut codegen_ctx.warnings);
+    let compiled = codegen_ctx.take();
+    debug_assert_eq!(verify(&compiled, Some(io_size)), Ok(()));
+    Ok((compiled, warnings))
+}
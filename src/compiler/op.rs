@@ -1,4 +1,5 @@
 use crate::compiler::lex::TokenData;
+use crate::io_ctx::Type21;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum UnaryOp {
@@ -68,4 +69,38 @@ impl BinaryOp {
             BinaryOp::Or => 60
         }
     }
+
+    /// Implicit promotion policy, shared by the type checker and consteval:
+    ///
+    /// - in arithmetic (`+`, `-`, `*`, `/`) and comparisons, an `int` operand meeting a `float`
+    ///   operand is promoted to `float`;
+    /// - `%` only accepts two `int`s, `&&` and `||` only two `bool`s;
+    /// - `bool` never takes part in an implicit conversion.
+    ///
+    /// Returns the type both operands are converted to before the operation.
+    pub fn operand_type(&self, lhs: Type21, rhs: Type21) -> Result<Type21, String> {
+        if lhs == rhs {
+            return Ok(lhs);
+        }
+
+        match (self, lhs, rhs) {
+            (BinaryOp::Mod | BinaryOp::And | BinaryOp::Or, _, _) => Err(format!(
+                "运算 {:?} 的两个操作数类型不一致 ({} 和 {})，该运算不进行隐式类型提升",
+                self,
+                lhs,
+                rhs
+            )),
+            (_, Type21::Int32, Type21::Float32) | (_, Type21::Float32, Type21::Int32) => Ok(Type21::Float32),
+            _ => Err(format!(
+                "二元表达式的两个操作数类型不一致 ({} 和 {})，bool 不会被隐式转换为数值",
+                lhs,
+                rhs
+            ))
+        }
+    }
+}
+
+/// Whether `value` survives an int → float conversion unchanged.
+pub fn int_fits_float(value: i32) -> bool {
+    value as f32 as i64 == value as i64
 }
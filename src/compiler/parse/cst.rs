@@ -3,41 +3,97 @@ use smallvec::SmallVec;
 use crate::compiler::op::{BinaryOp, UnaryOp};
 use crate::io_ctx::Type21;
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TypeRef {
+    Scalar(Type21),
+    Named(String)
+}
+
+impl Display for TypeRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeRef::Scalar(ty) => write!(f, "{}", ty),
+            TypeRef::Named(name) => write!(f, "{}", name)
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Program {
     pub const_decl: Vec<ConstDecl>,
-    pub func_decl: Vec<FuncDecl>
+    pub func_decl: Vec<FuncDecl>,
+    pub struct_decl: Vec<StructDecl>,
+    pub enum_decl: Vec<EnumDecl>,
+    pub extern_decl: Vec<FuncDecl>
+}
+
+#[derive(Debug, Clone)]
+pub enum TopLevelDecl {
+    ConstDecl(ConstDecl),
+    FuncDecl(FuncDecl),
+    StructDecl(StructDecl),
+    EnumDecl(EnumDecl),
+    ExternDecl(FuncDecl)
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstDecl {
     pub name: String,
-    pub value: Expr
+    pub value: Expr,
+
+    pub line: usize
 }
 
 #[derive(Debug, Clone)]
 pub struct FuncDecl {
     pub name: String,
-    pub ty: SmallVec<[Type21; 2]>,
-    pub params: SmallVec<[(Type21, String); 2]>,
+    pub ty: SmallVec<[TypeRef; 2]>,
+    pub params: SmallVec<[(TypeRef, String); 2]>,
+    // types of the values handed to the host by `yield`
+    pub yield_ty: SmallVec<[TypeRef; 2]>,
     pub body: Option<Box<BlockStmt>>,
 
+    pub line: usize,
+    pub col: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: SmallVec<[(TypeRef, String); 4]>,
+
     pub line: usize
 }
 
+#[derive(Debug, Clone)]
+pub struct EnumDecl {
+    pub name: String,
+    pub variants: SmallVec<[(String, Option<Expr>); 4]>,
+
+    pub line: usize
+}
+
+// the trailing `usize`s are the line and column of the statement
 #[derive(Debug, Clone)]
 pub enum Stmt {
     DeclStmt(Box<VarDecl>),
-    ExprStmt(Expr, usize),
+    ExprStmt(Expr, usize, usize),
     IfStmt(Box<IfStmt>),
     BlockStmt(Box<BlockStmt>),
     WhileStmt(Box<WhileStmt>),
     ForStmt(Box<ForStmt>),
-    ReturnStmt(Option<Expr>),
-    MultiReturnStmt(SmallVec<[String; 2]>),
-    BreakStmt(usize),
-    ContinueStmt(usize),
-    YieldStmt(usize)
+    SwitchStmt(Box<SwitchStmt>),
+    TryStmt(Box<TryStmt>),
+    ReturnStmt(Option<Expr>, usize, usize),
+    MultiReturnStmt(SmallVec<[String; 2]>, usize, usize),
+    BreakStmt(usize, usize),
+    ContinueStmt(usize, usize),
+    YieldStmt(Option<Expr>, usize, usize),
+    MultiYieldStmt(SmallVec<[String; 2]>, usize, usize),
+    YieldFromStmt(Box<FuncCall>, usize, usize),
+    JoinStmt(Expr, usize, usize),
+    // every statement must be a function call; they run as separate tasks, then all are joined
+    ParallelStmt(Box<BlockStmt>, usize, usize)
 }
 
 #[derive(Debug, Clone)]
@@ -47,11 +103,12 @@ pub struct BlockStmt {
 
 #[derive(Debug, Clone)]
 pub struct VarDecl {
-    pub ty: Option<Type21>,
+    pub ty: Option<TypeRef>,
     pub name: String,
     pub init: Option<Expr>,
 
-    pub line: usize
+    pub line: usize,
+    pub col: usize
 }
 
 #[derive(Debug, Clone)]
@@ -59,12 +116,18 @@ pub struct IfStmt {
     pub cond: Expr,
     pub then: Stmt,
     pub else_: Option<Stmt>,
+
+    pub line: usize,
+    pub col: usize
 }
 
 #[derive(Debug, Clone)]
 pub struct WhileStmt {
     pub cond: Expr,
     pub body: Stmt,
+
+    pub line: usize,
+    pub col: usize
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +136,36 @@ pub struct ForStmt {
     pub cond: Option<Expr>,
     pub step: Option<Expr>,
     pub body: Stmt,
+
+    pub line: usize,
+    pub col: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchStmt {
+    pub cond: Expr,
+    pub cases: SmallVec<[SwitchCase; 4]>,
+    pub default: Option<SmallVec<[Stmt; 4]>>,
+
+    pub line: usize,
+    pub col: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct SwitchCase {
+    pub values: SmallVec<[Expr; 1]>,
+    pub body: SmallVec<[Stmt; 4]>,
+
+    pub line: usize
+}
+
+#[derive(Debug, Clone)]
+pub struct TryStmt {
+    pub body: Box<BlockStmt>,
+    pub handler: Box<BlockStmt>,
+
+    pub line: usize,
+    pub col: usize
 }
 
 #[derive(Debug, Clone)]
@@ -82,7 +175,9 @@ pub enum Expr {
     MultiAssignExpr(Box<MultiAssignExpr>),
     BinaryExpr(Box<BinaryExpr>),
     UnaryExpr(Box<UnaryExpr>),
-    FuncCall(Box<FuncCall>)
+    FuncCall(Box<FuncCall>),
+    FieldAccess(Box<FieldAccess>),
+    Spawn(Box<FuncCall>)
 }
 
 impl Display for Expr {
@@ -94,6 +189,8 @@ impl Display for Expr {
             Expr::BinaryExpr(e) => write!(f, "{}", e),
             Expr::UnaryExpr(e) => write!(f, "{}", e),
             Expr::FuncCall(e) => write!(f, "{}", e),
+            Expr::FieldAccess(e) => write!(f, "{}", e),
+            Expr::Spawn(e) => write!(f, "(spawn {})", e),
         }
     }
 }
@@ -101,12 +198,17 @@ impl Display for Expr {
 #[derive(Debug, Clone)]
 pub struct AssignExpr {
     pub name: String,
+    pub fields: SmallVec<[String; 2]>,
     pub value: Expr,
 }
 
 impl Display for AssignExpr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "(set! {} {})", self.name, self.value)
+        write!(f, "(set! {}", self.name)?;
+        for field in self.fields.iter() {
+            write!(f, ".{}", field)?;
+        }
+        write!(f, " {})", self.value)
     }
 }
 
@@ -135,6 +237,18 @@ impl Display for BinaryExpr {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct FieldAccess {
+    pub base: Expr,
+    pub field: String,
+}
+
+impl Display for FieldAccess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(. {} {})", self.base, self.field)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UnaryExpr {
     pub op: UnaryOp,
@@ -153,6 +267,7 @@ pub enum AtomicExpr {
     Integer(i32),
     Float(f32),
     Bool(bool),
+    EnumVariant(String, String),
     Paren(Expr),
     TypeCast(TypeCast),
     FuncCall(FuncCall)
@@ -165,6 +280,7 @@ impl Display for AtomicExpr {
             AtomicExpr::Integer(i) => write!(f, "{}", i),
             AtomicExpr::Float(fl) => write!(f, "{}", fl),
             AtomicExpr::Bool(b) => write!(f, "{}", b),
+            AtomicExpr::EnumVariant(e, v) => write!(f, "{}::{}", e, v),
             AtomicExpr::Paren(e) => write!(f, "({})", e),
             AtomicExpr::TypeCast(c) => write!(f, "{}", c),
             AtomicExpr::FuncCall(c) => write!(f, "{}", c),
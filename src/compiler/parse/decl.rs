@@ -1,25 +1,28 @@
-use smallvec::SmallVec;
-use xjbutil::either::Either;
+use smallvec::{SmallVec, smallvec};
 use crate::compiler::SyntaxError;
 use crate::compiler::lex::{Token, TokenData};
-use crate::compiler::parse::cst::{ConstDecl, FuncDecl};
+use crate::compiler::parse::cst::{ConstDecl, EnumDecl, Expr, FuncDecl, StructDecl, TopLevelDecl, TypeRef};
 use crate::compiler::parse::expect_n_consume;
 use crate::compiler::parse::expr::parse_expr;
-use crate::io_ctx::Type21;
 use super::stmt::parse_block_stmt;
-use super::ty::parse_function_type;
+use super::ty::{parse_function_type, parse_type, parse_type_list};
 
 pub fn parse_top_level_decl(
     tokens: &[Token],
     cursor: &mut usize
-) -> Result<Either<ConstDecl, FuncDecl>, SyntaxError> {
+) -> Result<TopLevelDecl, SyntaxError> {
     let cur_token = &tokens[*cursor];
     match cur_token.data {
         TokenData::SymLBracket
         | TokenData::KwdVoid
         | TokenData::KwdInt
-        | TokenData::KwdFloat => Ok(Either::Right(parse_func_decl(tokens, cursor)?)),
-        TokenData::KwdConst => Ok(Either::Left(parse_const_decl(tokens, cursor)?)),
+        | TokenData::KwdFloat
+        | TokenData::KwdBool
+        | TokenData::Ident(_) => Ok(TopLevelDecl::FuncDecl(parse_func_decl(tokens, cursor)?)),
+        TokenData::KwdConst => Ok(TopLevelDecl::ConstDecl(parse_const_decl(tokens, cursor)?)),
+        TokenData::KwdStruct => Ok(TopLevelDecl::StructDecl(parse_struct_decl(tokens, cursor)?)),
+        TokenData::KwdEnum => Ok(TopLevelDecl::EnumDecl(parse_enum_decl(tokens, cursor)?)),
+        TokenData::KwdExtern => Ok(TopLevelDecl::ExternDecl(parse_extern_decl(tokens, cursor)?)),
         _ => Err(SyntaxError::new(cur_token.line))
     }
 }
@@ -28,7 +31,7 @@ pub fn parse_func_decl(
     tokens: &[Token],
     cursor: &mut usize
 ) -> Result<FuncDecl, SyntaxError> {
-    let line = tokens[*cursor].line;
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
 
     let ret_types = parse_function_type(tokens, cursor)?;
 
@@ -40,7 +43,7 @@ pub fn parse_func_decl(
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
 
-    let mut params: SmallVec<[(Type21, String); 2]> = SmallVec::new();
+    let mut params: SmallVec<[(TypeRef, String); 2]> = SmallVec::new();
     loop {
         let cur_token = &tokens[*cursor];
         match cur_token.data {
@@ -48,15 +51,15 @@ pub fn parse_func_decl(
                 *cursor += 1;
                 break;
             },
-            TokenData::KwdInt | TokenData::KwdFloat => {
-                let TokenData::Ident(name) = &tokens[*cursor + 1].data else {
+            _ => {
+                let ty = parse_type(tokens, cursor)?;
+                let TokenData::Ident(name) = &tokens[*cursor].data else {
                     return Err(SyntaxError::new(cur_token.line));
                 };
 
-                *cursor += 2;
-                params.push((Type21::from_token(cur_token), name.to_string()));
-            },
-            _ => return Err(SyntaxError::new(cur_token.line))
+                *cursor += 1;
+                params.push((ty, name.to_string()));
+            }
         }
 
         let cur_token = &tokens[*cursor];
@@ -67,6 +70,16 @@ pub fn parse_func_decl(
         }
     }
 
+    let mut yield_ty = SmallVec::new();
+    if tokens[*cursor].data == TokenData::KwdYield {
+        *cursor += 1;
+        yield_ty = if tokens[*cursor].data == TokenData::SymLBracket {
+            parse_type_list(tokens, cursor)?
+        } else {
+            smallvec![parse_type(tokens, cursor)?]
+        };
+    }
+
     let cur_token = &tokens[*cursor];
     let body = match cur_token.data {
         TokenData::SymSemi => {
@@ -83,16 +96,34 @@ pub fn parse_func_decl(
         name: name.to_string(),
         ty: ret_types,
         params,
+        yield_ty,
         body,
 
-        line
+        line,
+        col
     })
 }
 
+pub fn parse_extern_decl(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<FuncDecl, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
+    *cursor += 1;
+
+    let func_decl = parse_func_decl(tokens, cursor)?;
+    if func_decl.body.is_some() {
+        return Err(SyntaxError::new(line));
+    }
+
+    Ok(FuncDecl { line, col, ..func_decl })
+}
+
 pub fn parse_const_decl(
     tokens: &[Token],
     cursor: &mut usize
 ) -> Result<ConstDecl, SyntaxError> {
+    let line = tokens[*cursor].line;
     *cursor += 1;
 
     let cur_token = &tokens[*cursor];
@@ -108,6 +139,101 @@ pub fn parse_const_decl(
 
     Ok(ConstDecl {
         name: name.to_string(),
-        value
+        value,
+
+        line
+    })
+}
+
+pub fn parse_struct_decl(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<StructDecl, SyntaxError> {
+    let line = tokens[*cursor].line;
+    *cursor += 1;
+
+    let cur_token = &tokens[*cursor];
+    let TokenData::Ident(name) = &cur_token.data else {
+        return Err(SyntaxError::new(cur_token.line));
+    };
+
+    *cursor += 1;
+    expect_n_consume(tokens, TokenData::SymLBrace, cursor)?;
+
+    let mut fields: SmallVec<[(TypeRef, String); 4]> = SmallVec::new();
+    while tokens[*cursor].data != TokenData::SymRBrace {
+        let ty = parse_type(tokens, cursor)?;
+        let cur_token = &tokens[*cursor];
+        let TokenData::Ident(field_name) = &cur_token.data else {
+            return Err(SyntaxError::new(cur_token.line));
+        };
+
+        *cursor += 1;
+        expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
+        fields.push((ty, field_name.to_string()));
+    }
+    *cursor += 1;
+
+    if tokens[*cursor].data == TokenData::SymSemi {
+        *cursor += 1;
+    }
+
+    Ok(StructDecl {
+        name: name.to_string(),
+        fields,
+
+        line
+    })
+}
+
+pub fn parse_enum_decl(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<EnumDecl, SyntaxError> {
+    let line = tokens[*cursor].line;
+    *cursor += 1;
+
+    let cur_token = &tokens[*cursor];
+    let TokenData::Ident(name) = &cur_token.data else {
+        return Err(SyntaxError::new(cur_token.line));
+    };
+
+    *cursor += 1;
+    expect_n_consume(tokens, TokenData::SymLBrace, cursor)?;
+
+    let mut variants: SmallVec<[(String, Option<Expr>); 4]> = SmallVec::new();
+    while tokens[*cursor].data != TokenData::SymRBrace {
+        let cur_token = &tokens[*cursor];
+        let TokenData::Ident(variant_name) = &cur_token.data else {
+            return Err(SyntaxError::new(cur_token.line));
+        };
+
+        *cursor += 1;
+        let value = if tokens[*cursor].data == TokenData::OpAssign {
+            *cursor += 1;
+            Some(parse_expr(tokens, cursor)?)
+        } else {
+            None
+        };
+        variants.push((variant_name.to_string(), value));
+
+        let cur_token = &tokens[*cursor];
+        if cur_token.data == TokenData::SymComma {
+            *cursor += 1;
+        } else if cur_token.data != TokenData::SymRBrace {
+            return Err(SyntaxError::new(cur_token.line));
+        }
+    }
+    *cursor += 1;
+
+    if tokens[*cursor].data == TokenData::SymSemi {
+        *cursor += 1;
+    }
+
+    Ok(EnumDecl {
+        name: name.to_string(),
+        variants,
+
+        line
     })
 }
@@ -2,8 +2,8 @@ use smallvec::SmallVec;
 use crate::compiler::SyntaxError;
 use crate::compiler::lex::{Token, TokenData};
 use crate::compiler::op::BinaryOp;
-use crate::compiler::parse::cst::{AssignExpr, AtomicExpr, BinaryExpr, Expr, FuncCall, MultiAssignExpr, TypeCast, UnaryExpr};
-use crate::compiler::parse::{expect_n_consume, parse_ident_list};
+use crate::compiler::parse::cst::{AssignExpr, AtomicExpr, BinaryExpr, Expr, FieldAccess, FuncCall, MultiAssignExpr, TypeCast, UnaryExpr};
+use crate::compiler::parse::{expect_n_consume, expect_token, parse_ident_list};
 use crate::io_ctx::Type21;
 
 fn token_as_lit_bool(token_data: &TokenData) -> bool {
@@ -20,8 +20,7 @@ pub fn parse_expr(tokens: &[Token], cursor: &mut usize) -> Result<Expr, SyntaxEr
     match &current_token.data {
         TokenData::SymLBracket => Ok(Expr::MultiAssignExpr(parse_multi_assign_expr(tokens, cursor)?)),
         TokenData::Ident(_) => {
-            let next_token = &tokens[*cursor + 1];
-            if next_token.data == TokenData::OpAssign {
+            if is_assign_expr(tokens, *cursor) {
                 Ok(Expr::AssignExpr(parse_single_assign_expr(tokens, cursor)?))
             } else {
                 parse_bin_expr(tokens, cursor)
@@ -41,6 +40,7 @@ pub fn parse_multi_assign_expr(
     let TokenData::Ident(name) = &tokens[*cursor].data else {
         return Err(SyntaxError::new(tokens[*cursor].line));
     };
+    *cursor += 1;
 
     let expr = parse_func_call(tokens, cursor, name)?;
 
@@ -50,6 +50,19 @@ pub fn parse_multi_assign_expr(
     }))
 }
 
+fn is_assign_expr(tokens: &[Token], mut cursor: usize) -> bool {
+    // IDENT ('.' IDENT)* '='
+    cursor += 1;
+    while tokens[cursor].data == TokenData::SymDot {
+        let TokenData::Ident(_) = &tokens[cursor + 1].data else {
+            return false;
+        };
+        cursor += 2;
+    }
+
+    tokens[cursor].data == TokenData::OpAssign
+}
+
 pub fn parse_single_assign_expr(
     tokens: &[Token],
     cursor: &mut usize
@@ -57,12 +70,20 @@ pub fn parse_single_assign_expr(
     let TokenData::Ident(ident) = &tokens[*cursor].data else { unreachable!() };
     *cursor += 1;
 
+    let mut fields = SmallVec::new();
+    while tokens[*cursor].data == TokenData::SymDot {
+        let TokenData::Ident(field) = &tokens[*cursor + 1].data else { unreachable!() };
+        fields.push(field.clone());
+        *cursor += 2;
+    }
+
     expect_n_consume(tokens, TokenData::OpAssign, cursor)?;
 
     let expr = parse_bin_expr(tokens, cursor)?;
 
     Ok(Box::new(AssignExpr {
         name: ident.clone(),
+        fields,
         value: expr
     }))
 }
@@ -114,10 +135,30 @@ pub fn parse_unary_expr(tokens: &[Token], cursor: &mut usize) -> Result<Expr, Sy
                 expr
             })))
         },
-        _ => parse_atom_expr(tokens, cursor)
+        _ => parse_postfix_expr(tokens, cursor)
     }
 }
 
+pub fn parse_postfix_expr(tokens: &[Token], cursor: &mut usize) -> Result<Expr, SyntaxError> {
+    let mut expr = parse_atom_expr(tokens, cursor)?;
+
+    while tokens[*cursor].data == TokenData::SymDot {
+        *cursor += 1;
+        let current_token = &tokens[*cursor];
+        let TokenData::Ident(field) = &current_token.data else {
+            return Err(SyntaxError::new(current_token.line));
+        };
+        *cursor += 1;
+
+        expr = Expr::FieldAccess(Box::new(FieldAccess {
+            base: expr,
+            field: field.clone()
+        }));
+    }
+
+    Ok(expr)
+}
+
 pub fn parse_atom_expr(
     tokens: &[Token],
     cursor: &mut usize
@@ -128,6 +169,14 @@ pub fn parse_atom_expr(
             *cursor += 1;
             if let TokenData::SymLParen = &tokens[*cursor].data {
                 Ok(Expr::FuncCall(parse_func_call(tokens, cursor, name)?))
+            } else if let TokenData::SymColonColon = &tokens[*cursor].data {
+                *cursor += 1;
+                let current_token = &tokens[*cursor];
+                let TokenData::Ident(variant) = &current_token.data else {
+                    return Err(SyntaxError::new(current_token.line));
+                };
+                *cursor += 1;
+                Ok(Expr::AtomicExpr(Box::new(AtomicExpr::EnumVariant(name.to_string(), variant.to_string()))))
             } else {
                 Ok(Expr::AtomicExpr(Box::new(AtomicExpr::Ident(name.to_string()))))
             }
@@ -145,7 +194,7 @@ pub fn parse_atom_expr(
             *cursor += 1;
             Ok(Expr::AtomicExpr(Box::new(AtomicExpr::Bool(b))))
         },
-        TokenData::KwdInt | TokenData::KwdFloat => {
+        TokenData::KwdInt | TokenData::KwdFloat | TokenData::KwdBool => {
             *cursor += 1;
             expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
             let expr = parse_unary_expr(tokens, cursor)?;
@@ -162,10 +211,25 @@ pub fn parse_atom_expr(
             expect_n_consume(tokens, TokenData::SymRParen, cursor)?;
             Ok(expr)
         },
+        TokenData::KwdSpawn => {
+            *cursor += 1;
+            Ok(Expr::Spawn(parse_named_func_call(tokens, cursor)?))
+        },
         _ => Err(SyntaxError::new(current_token.line))
     }
 }
 
+/// `IDENT '(' args ')'`, as required after `spawn` and `yield from`
+pub fn parse_named_func_call(tokens: &[Token], cursor: &mut usize) -> Result<Box<FuncCall>, SyntaxError> {
+    let current_token = &tokens[*cursor];
+    let TokenData::Ident(name) = &current_token.data else {
+        return Err(SyntaxError::new(current_token.line));
+    };
+    *cursor += 1;
+    expect_token(tokens, TokenData::SymLParen, cursor)?;
+    parse_func_call(tokens, cursor, name)
+}
+
 fn parse_func_call(
     tokens: &[Token],
     cursor: &mut usize,
@@ -5,9 +5,8 @@ pub mod stmt;
 pub mod ty;
 
 use smallvec::SmallVec;
-use xjbutil::either::Either;
 use crate::compiler::lex::Token;
-use crate::compiler::parse::cst::Program;
+use crate::compiler::parse::cst::{Program, TopLevelDecl};
 use crate::compiler::SyntaxError;
 use super::lex::TokenData;
 use self::decl::parse_top_level_decl;
@@ -19,8 +18,11 @@ pub fn parse(tokens: &[Token]) -> Result<Program, SyntaxError> {
     while cursor < tokens.len() && tokens[cursor].data != TokenData::EOI {
         let decl = parse_top_level_decl(tokens, &mut cursor)?;
         match decl {
-            Either::Left(const_decl) => program.const_decl.push(const_decl),
-            Either::Right(func_decl) => program.func_decl.push(func_decl)
+            TopLevelDecl::ConstDecl(const_decl) => program.const_decl.push(const_decl),
+            TopLevelDecl::FuncDecl(func_decl) => program.func_decl.push(func_decl),
+            TopLevelDecl::StructDecl(struct_decl) => program.struct_decl.push(struct_decl),
+            TopLevelDecl::EnumDecl(enum_decl) => program.enum_decl.push(enum_decl),
+            TopLevelDecl::ExternDecl(extern_decl) => program.extern_decl.push(extern_decl)
         }
     }
 
@@ -1,12 +1,12 @@
 use smallvec::SmallVec;
 use crate::compiler::lex::{Token, TokenData};
-use crate::compiler::parse::cst::{BlockStmt, VarDecl, Stmt, IfStmt, WhileStmt, ForStmt};
+use crate::compiler::parse::cst::{BlockStmt, VarDecl, Stmt, IfStmt, WhileStmt, ForStmt, SwitchStmt, SwitchCase, TryStmt};
 use crate::compiler::parse::parse_ident_list;
 use crate::compiler::SyntaxError;
-use crate::io_ctx::Type21;
 
-use super::expect_n_consume;
-use super::expr::parse_expr;
+use super::{expect_n_consume, expect_token};
+use super::expr::{parse_expr, parse_named_func_call};
+use super::ty::parse_type;
 
 pub fn parse_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
     let cur_token = &tokens[*cursor];
@@ -15,13 +15,19 @@ pub fn parse_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxEr
         | TokenData::KwdInt
         | TokenData::KwdFloat
         | TokenData::KwdBool => Ok(Stmt::DeclStmt(parse_decl_stmt(tokens, cursor)?)),
+        TokenData::Ident(_) if matches!(tokens[*cursor + 1].data, TokenData::Ident(_)) =>
+            Ok(Stmt::DeclStmt(parse_decl_stmt(tokens, cursor)?)),
         TokenData::KwdIf => Ok(Stmt::IfStmt(parse_if_stmt(tokens, cursor)?)),
         TokenData::KwdWhile => Ok(Stmt::WhileStmt(parse_while_stmt(tokens, cursor)?)),
         TokenData::KwdFor => Ok(Stmt::ForStmt(parse_for_stmt(tokens, cursor)?)),
+        TokenData::KwdSwitch => Ok(Stmt::SwitchStmt(parse_switch_stmt(tokens, cursor)?)),
+        TokenData::KwdTry => Ok(Stmt::TryStmt(parse_try_stmt(tokens, cursor)?)),
         TokenData::KwdReturn => parse_return_stmt(tokens, cursor),
         TokenData::KwdBreak => parse_break_stmt(tokens, cursor),
         TokenData::KwdContinue => parse_continue_stmt(tokens, cursor),
         TokenData::KwdYield => parse_yield_stmt(tokens, cursor),
+        TokenData::KwdJoin => parse_join_stmt(tokens, cursor),
+        TokenData::KwdParallel => parse_parallel_stmt(tokens, cursor),
         TokenData::SymLBrace => Ok(Stmt::BlockStmt(parse_block_stmt(tokens, cursor)?)),
         _ => parse_expr_stmt(tokens, cursor)
     }
@@ -32,15 +38,14 @@ pub fn parse_decl_stmt(
     cursor: &mut usize
 ) -> Result<Box<VarDecl>, SyntaxError> {
     let cur_token = &tokens[*cursor];
-    let line = cur_token.line;
+    let (line, col) = (cur_token.line, cur_token.col);
     let ty = if let TokenData::KwdVar = cur_token.data {
+        *cursor += 1;
         None
     } else {
-        Some(Type21::from_token(cur_token))
+        Some(parse_type(tokens, cursor)?)
     };
 
-    *cursor += 1;
-
     let cur_token = &tokens[*cursor];
     let TokenData::Ident(name) = &cur_token.data else {
         return Err(SyntaxError::new(cur_token.line));
@@ -64,11 +69,13 @@ pub fn parse_decl_stmt(
         name: name.to_string(),
         init,
 
-        line
+        line,
+        col
     }))
 }
 
 pub fn parse_if_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Box<IfStmt>, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
     let cond = parse_expr(tokens, cursor)?;
@@ -81,23 +88,40 @@ pub fn parse_if_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Box<IfStmt>
         None
     };
 
-    Ok(Box::new(IfStmt { cond, then, else_ }))
+    Ok(Box::new(IfStmt { cond, then, else_, line, col }))
 }
 
 pub fn parse_while_stmt(
     tokens: &[Token],
     cursor: &mut usize
 ) -> Result<Box<WhileStmt>, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
     let cond = parse_expr(tokens, cursor)?;
     expect_n_consume(tokens, TokenData::SymRParen, cursor)?;
     let body = parse_stmt(tokens, cursor)?;
 
-    Ok(Box::new(WhileStmt { cond, body }))
+    Ok(Box::new(WhileStmt { cond, body, line, col }))
+}
+
+pub fn parse_try_stmt(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<Box<TryStmt>, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
+    *cursor += 1;
+    expect_token(tokens, TokenData::SymLBrace, cursor)?;
+    let body = parse_block_stmt(tokens, cursor)?;
+    expect_n_consume(tokens, TokenData::KwdCatch, cursor)?;
+    expect_token(tokens, TokenData::SymLBrace, cursor)?;
+    let handler = parse_block_stmt(tokens, cursor)?;
+
+    Ok(Box::new(TryStmt { body, handler, line, col }))
 }
 
 pub fn parse_for_stmt(tokens: &[Token], cursor: &mut usize)-> Result<Box<ForStmt>, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
     let init = if let TokenData::SymSemi = tokens[*cursor].data {
@@ -120,53 +144,151 @@ pub fn parse_for_stmt(tokens: &[Token], cursor: &mut usize)-> Result<Box<ForStmt
     expect_n_consume(tokens, TokenData::SymRParen, cursor)?;
     let body = parse_stmt(tokens, cursor)?;
 
-    Ok(Box::new(ForStmt { init, cond, step, body }))
+    Ok(Box::new(ForStmt { init, cond, step, body, line, col }))
+}
+
+pub fn parse_switch_stmt(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<Box<SwitchStmt>, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
+    *cursor += 1;
+    expect_n_consume(tokens, TokenData::SymLParen, cursor)?;
+    let cond = parse_expr(tokens, cursor)?;
+    expect_n_consume(tokens, TokenData::SymRParen, cursor)?;
+    expect_n_consume(tokens, TokenData::SymLBrace, cursor)?;
+
+    let mut cases: SmallVec<[SwitchCase; 4]> = SmallVec::new();
+    let mut default = None;
+    loop {
+        let cur_token = &tokens[*cursor];
+        match cur_token.data {
+            TokenData::KwdCase => {
+                // `case A: case B: ...` shares one body
+                let mut values = SmallVec::new();
+                while tokens[*cursor].data == TokenData::KwdCase {
+                    *cursor += 1;
+                    values.push(parse_expr(tokens, cursor)?);
+                    expect_n_consume(tokens, TokenData::SymColon, cursor)?;
+                }
+
+                let body = parse_case_body(tokens, cursor)?;
+                cases.push(SwitchCase { values, body, line: cur_token.line });
+            },
+            TokenData::KwdDefault => {
+                if default.is_some() {
+                    return Err(SyntaxError::new(cur_token.line));
+                }
+
+                *cursor += 1;
+                expect_n_consume(tokens, TokenData::SymColon, cursor)?;
+                default = Some(parse_case_body(tokens, cursor)?);
+            },
+            TokenData::SymRBrace => {
+                *cursor += 1;
+                break;
+            },
+            _ => return Err(SyntaxError::new(cur_token.line))
+        }
+    }
+
+    Ok(Box::new(SwitchStmt { cond, cases, default, line, col }))
+}
+
+fn parse_case_body(tokens: &[Token], cursor: &mut usize) -> Result<SmallVec<[Stmt; 4]>, SyntaxError> {
+    let mut stmts = SmallVec::new();
+    while !matches!(tokens[*cursor].data, TokenData::KwdCase | TokenData::KwdDefault | TokenData::SymRBrace) {
+        stmts.push(parse_stmt(tokens, cursor)?);
+    }
+
+    Ok(stmts)
 }
 
 pub fn parse_return_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
 
     match tokens[*cursor].data {
         TokenData::SymSemi => {
             *cursor += 1;
-            Ok(Stmt::ReturnStmt(None))
+            Ok(Stmt::ReturnStmt(None, line, col))
         },
         TokenData::SymLBracket => {
             let ident_list = parse_ident_list(tokens, cursor)?;
-            dbg!(&tokens[*cursor]);
             expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
-            Ok(Stmt::MultiReturnStmt(ident_list))
+            Ok(Stmt::MultiReturnStmt(ident_list, line, col))
         },
         _ => {
             let expr = parse_expr(tokens, cursor)?;
             expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
-            Ok(Stmt::ReturnStmt(Some(expr)))
+            Ok(Stmt::ReturnStmt(Some(expr), line, col))
         }
     }
 }
 
 pub fn parse_break_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
-    let line = tokens[*cursor].line;
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
 
-    Ok(Stmt::BreakStmt(line))
+    Ok(Stmt::BreakStmt(line, col))
 }
 
 pub fn parse_continue_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
-    let line = tokens[*cursor].line;
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
     expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
 
-    Ok(Stmt::ContinueStmt(line))
+    Ok(Stmt::ContinueStmt(line, col))
 }
 
 pub fn parse_yield_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
-    let line = tokens[*cursor].line;
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
+    *cursor += 1;
+
+    match &tokens[*cursor].data {
+        TokenData::SymSemi => {
+            *cursor += 1;
+            Ok(Stmt::YieldStmt(None, line, col))
+        },
+        // `from` is not a keyword, and `from f(` cannot start an expression
+        TokenData::Ident(from) if from == "from"
+            && matches!(tokens[*cursor + 1].data, TokenData::Ident(_))
+            && tokens[*cursor + 2].data == TokenData::SymLParen => {
+            *cursor += 1;
+            let func_call = parse_named_func_call(tokens, cursor)?;
+            expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
+            Ok(Stmt::YieldFromStmt(func_call, line, col))
+        },
+        TokenData::SymLBracket => {
+            let ident_list = parse_ident_list(tokens, cursor)?;
+            expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
+            Ok(Stmt::MultiYieldStmt(ident_list, line, col))
+        },
+        _ => {
+            let expr = parse_expr(tokens, cursor)?;
+            expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
+            Ok(Stmt::YieldStmt(Some(expr), line, col))
+        }
+    }
+}
+
+pub fn parse_join_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
     *cursor += 1;
+    let expr = parse_expr(tokens, cursor)?;
     expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
 
-    Ok(Stmt::YieldStmt(line))
+    Ok(Stmt::JoinStmt(expr, line, col))
+}
+
+pub fn parse_parallel_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
+    *cursor += 1;
+    expect_token(tokens, TokenData::SymLBrace, cursor)?;
+    let body = parse_block_stmt(tokens, cursor)?;
+
+    Ok(Stmt::ParallelStmt(body, line, col))
 }
 
 pub fn parse_block_stmt(
@@ -184,10 +306,10 @@ pub fn parse_block_stmt(
 }
 
 pub fn parse_expr_stmt(tokens: &[Token], cursor: &mut usize) -> Result<Stmt, SyntaxError> {
-    let line = tokens[*cursor].line;
+    let (line, col) = (tokens[*cursor].line, tokens[*cursor].col);
 
     let expr = parse_expr(tokens, cursor)?;
     expect_n_consume(tokens, TokenData::SymSemi, cursor)?;
 
-    Ok(Stmt::ExprStmt(expr, line))
+    Ok(Stmt::ExprStmt(expr, line, col))
 }
@@ -2,27 +2,43 @@ use smallvec::{SmallVec, smallvec};
 use crate::io_ctx::Type21;
 use crate::compiler::SyntaxError;
 use crate::compiler::lex::{Token, TokenData};
+use crate::compiler::parse::cst::TypeRef;
 
 impl Type21 {
     pub fn from_token(token: &Token) -> Self {
         match token.data {
             TokenData::KwdInt => Type21::Int32,
             TokenData::KwdFloat => Type21::Float32,
+            TokenData::KwdBool => Type21::Bool,
             _ => unreachable!()
         }
     }
 }
 
-pub fn parse_function_type(
+pub fn parse_type(
     tokens: &[Token],
     cursor: &mut usize
-) -> Result<SmallVec<[Type21; 2]>, SyntaxError> {
+) -> Result<TypeRef, SyntaxError> {
     let cur_token = &tokens[*cursor];
-    match cur_token.data {
-        TokenData::KwdInt | TokenData::KwdFloat => {
+    match &cur_token.data {
+        TokenData::KwdInt | TokenData::KwdFloat | TokenData::KwdBool => {
+            *cursor += 1;
+            Ok(TypeRef::Scalar(Type21::from_token(cur_token)))
+        },
+        TokenData::Ident(name) => {
             *cursor += 1;
-            Ok(smallvec![Type21::from_token(cur_token)])
+            Ok(TypeRef::Named(name.to_string()))
         },
+        _ => Err(SyntaxError::new(cur_token.line))
+    }
+}
+
+pub fn parse_function_type(
+    tokens: &[Token],
+    cursor: &mut usize
+) -> Result<SmallVec<[TypeRef; 2]>, SyntaxError> {
+    let cur_token = &tokens[*cursor];
+    match cur_token.data {
         TokenData::KwdVoid => {
             *cursor += 1;
             Ok(smallvec![])
@@ -30,14 +46,14 @@ pub fn parse_function_type(
         TokenData::SymLBracket => {
             Ok(parse_type_list(tokens, cursor)?)
         },
-        _ => Err(SyntaxError::new(cur_token.line))
+        _ => Ok(smallvec![parse_type(tokens, cursor)?])
     }
 }
 
 pub fn parse_type_list(
     tokens: &[Token],
     cursor: &mut usize
-) -> Result<SmallVec<[Type21; 2]>, SyntaxError> {
+) -> Result<SmallVec<[TypeRef; 2]>, SyntaxError> {
     assert_eq!(tokens[*cursor].data, TokenData::SymLBracket);
     *cursor += 1;
 
@@ -45,25 +61,16 @@ pub fn parse_type_list(
     loop {
         let cur_token = &tokens[*cursor];
         match cur_token.data {
-            TokenData::KwdInt => {
+            TokenData::SymRBracket => {
                 *cursor += 1;
-                types.push(Type21::Int32);
-                if tokens[*cursor].data == TokenData::SymComma {
-                    *cursor += 1;
-                }
+                break;
             },
-            TokenData::KwdFloat => {
-                *cursor += 1;
-                types.push(Type21::Float32);
+            _ => {
+                types.push(parse_type(tokens, cursor)?);
                 if tokens[*cursor].data == TokenData::SymComma {
                     *cursor += 1;
                 }
-            },
-            TokenData::SymRBracket => {
-                *cursor += 1;
-                break;
-            },
-            _ => return Err(SyntaxError::new(cur_token.line))
+            }
         }
     }
 
@@ -1,4 +1,5 @@
-use std::fmt::{Display, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
 use xjbutil::void::Void;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -34,7 +35,92 @@ impl Reflektor<f32> for Void {
     #[inline(always)] fn reflected_type() -> Type21 { Type21::Float32 }
 }
 
-pub type IOContextMetadata = Vec<(String, String, Type21)>;
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IOType {
+    Scalar(Type21),
+    // a nested `define_io_ctx!` struct, mapped onto the script `struct` of the same name
+    Struct(String, IOContextMetadata),
+    // a `define_io_enum!` enum, mapped onto the script `enum` of the same name
+    Enum(String, Vec<(String, i32)>)
+}
+
+impl IOType {
+    pub fn size(&self) -> usize {
+        match self {
+            IOType::Scalar(ty) => ty.size(),
+            IOType::Struct(_, fields) => fields.iter().map(|(_, _, ty)| ty.size()).sum(),
+            IOType::Enum(_, _) => Type21::Int32.size()
+        }
+    }
+}
+
+pub trait IOReflektor<T> {
+    fn reflected_io_type() -> IOType;
+}
+
+impl<T> IOReflektor<T> for Void where Void: Reflektor<T> {
+    #[inline(always)] fn reflected_io_type() -> IOType {
+        IOType::Scalar(<Void as Reflektor<T>>::reflected_type())
+    }
+}
+
+pub trait EnumReflektor: Sized + Copy {
+    fn enum_name() -> &'static str;
+    fn variants() -> Vec<(String, i32)>;
+    fn from_i32(value: i32) -> Option<Self>;
+    fn to_i32(self) -> i32;
+}
+
+/// An IO field holding a script enum value. Stored as a plain `i32`, so a value the host
+/// does not know about never becomes an invalid Rust enum.
+#[repr(transparent)]
+pub struct EnumValue<E> {
+    raw: i32,
+    _phantom: PhantomData<E>
+}
+
+impl<E: EnumReflektor> EnumValue<E> {
+    pub fn new(value: E) -> Self {
+        Self { raw: value.to_i32(), _phantom: PhantomData }
+    }
+
+    pub fn get(&self) -> Option<E> {
+        E::from_i32(self.raw)
+    }
+
+    pub fn set(&mut self, value: E) {
+        self.raw = value.to_i32();
+    }
+
+    pub fn raw(&self) -> i32 {
+        self.raw
+    }
+}
+
+impl<E> Clone for EnumValue<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for EnumValue<E> {}
+
+impl<E: EnumReflektor + Debug> Debug for EnumValue<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.get() {
+            Some(value) => write!(f, "{:?}", value),
+            None => write!(f, "{}({})", E::enum_name(), self.raw)
+        }
+    }
+}
+
+impl<E: EnumReflektor> IOReflektor<EnumValue<E>> for Void {
+    #[inline(always)] fn reflected_io_type() -> IOType {
+        IOType::Enum(E::enum_name().to_string(), E::variants())
+    }
+}
+
+pub type IOContextMetadata = Vec<(String, String, IOType)>;
 
 pub trait IOContext {
     fn metadata() -> IOContextMetadata;
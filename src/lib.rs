@@ -2,6 +2,7 @@ pub mod builtin;
 pub mod compiler;
 pub mod makro;
 pub mod io_ctx;
+pub mod native;
 pub mod r25_300;
 
 pub mod reexport;
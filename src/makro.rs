@@ -14,17 +14,58 @@ macro_rules! define_io_ctx {
                     $((
                         stringify!($rename).to_string(),
                         stringify!($field).to_string(),
-                        <$crate::Void as $crate::io_ctx::Reflektor<$t>>::reflected_type()
+                        <$crate::Void as $crate::io_ctx::IOReflektor<$t>>::reflected_io_type()
                     ),)*
                 ]
             }
         }
+
+        impl $crate::io_ctx::IOReflektor<$name> for $crate::Void {
+            fn reflected_io_type() -> $crate::io_ctx::IOType {
+                $crate::io_ctx::IOType::Struct(
+                    stringify!($name).to_string(),
+                    <$name as $crate::io_ctx::IOContext>::metadata()
+                )
+            }
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! define_io_enum {
+    ($(#[$m:meta])? enum $name:ident { $($variant:ident $(= $value:expr)?),* $(,)? }) => {
+        $(#[$m])?
+        #[repr(i32)]
+        #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+        #[allow(dead_code)]
+        pub enum $name {
+            $($variant $(= $value)?),*
+        }
+
+        impl $crate::io_ctx::EnumReflektor for $name {
+            fn enum_name() -> &'static str {
+                stringify!($name)
+            }
+
+            fn variants() -> Vec<(String, i32)> {
+                vec![$((stringify!($variant).to_string(), $name::$variant as i32)),*]
+            }
+
+            fn from_i32(value: i32) -> Option<Self> {
+                $(if value == $name::$variant as i32 { return Some($name::$variant); })*
+                None
+            }
+
+            fn to_i32(self) -> i32 {
+                self as i32
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::io_ctx::IOContext;
+    use crate::io_ctx::{EnumReflektor, EnumValue, IOContext, IOType, Type21};
 
     #[test] fn test() {
         define_io_ctx!(
@@ -39,4 +80,61 @@ mod test {
 
         eprintln!("{:?}", <S as IOContext>::metadata());
     }
+
+    #[test] fn test_nested() {
+        define_io_ctx!(
+            struct Vec2 {
+                x => x: f32,
+                y => y: f32
+            }
+        );
+
+        define_io_ctx!(
+            struct S {
+                g_pos => pos: Vec2,
+                g_frame => frame: i32
+            }
+        );
+
+        let metadata = <S as IOContext>::metadata();
+        let IOType::Struct(name, fields) = &metadata[0].2 else { panic!() };
+        assert_eq!(name, "Vec2");
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].2, IOType::Scalar(Type21::Float32));
+        assert_eq!(metadata[0].2.size(), 8);
+        assert_eq!(metadata[1].2, IOType::Scalar(Type21::Int32));
+    }
+
+    #[test] fn test_enum() {
+        define_io_enum!(
+            enum State {
+                Idle,
+                Walk = 4,
+                Run
+            }
+        );
+
+        define_io_ctx!(
+            struct S {
+                g_state => state: EnumValue<State>
+            }
+        );
+
+        assert_eq!(State::variants(), vec![
+            ("Idle".to_string(), 0),
+            ("Walk".to_string(), 4),
+            ("Run".to_string(), 5)
+        ]);
+        assert_eq!(State::from_i32(5), Some(State::Run));
+        assert_eq!(State::from_i32(1), None);
+
+        let metadata = <S as IOContext>::metadata();
+        assert_eq!(metadata[0].2, IOType::Enum("State".to_string(), State::variants()));
+        assert_eq!(metadata[0].2.size(), 4);
+
+        let mut s = S { state: EnumValue::new(State::Walk) };
+        assert_eq!(s.state.raw(), 4);
+        s.state.set(State::Idle);
+        assert_eq!(s.state.get(), Some(State::Idle));
+    }
 }
@@ -0,0 +1,314 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::io_ctx::Type21;
+use crate::value::{RawFunction, RtValue};
+
+/// Values which can cross the script/host boundary as native function arguments or results
+pub trait NativeType: Copy {
+    const TYPE: Type21;
+
+    fn from_value(value: RtValue) -> Self;
+    fn into_value(self) -> RtValue;
+}
+
+impl NativeType for i32 {
+    const TYPE: Type21 = Type21::Int32;
+
+    #[inline(always)] fn from_value(value: RtValue) -> Self { unsafe { value.i } }
+    #[inline(always)] fn into_value(self) -> RtValue { RtValue::from(self) }
+}
+
+impl NativeType for f32 {
+    const TYPE: Type21 = Type21::Float32;
+
+    #[inline(always)] fn from_value(value: RtValue) -> Self { unsafe { value.f } }
+    #[inline(always)] fn into_value(self) -> RtValue { RtValue::from(self) }
+}
+
+impl NativeType for bool {
+    const TYPE: Type21 = Type21::Bool;
+
+    #[inline(always)] fn from_value(value: RtValue) -> Self { unsafe { value.b } }
+    #[inline(always)] fn into_value(self) -> RtValue { RtValue::from(self) }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NativeStatus {
+    Returned,
+    // the script is suspended until the host provides the results, see `Combustor::finish_native`
+    Suspended
+}
+
+/// Return types of native functions: `()`, a single value, a tuple of values or `Suspend`,
+/// optionally wrapped in a `Result` whose error aborts the script with a `RuntimeError`
+pub trait NativeReturn {
+    fn reflected_types() -> Vec<Type21>;
+    fn write_values(self, rets: &mut [RtValue]) -> Result<NativeStatus, String>;
+}
+
+impl NativeReturn for () {
+    fn reflected_types() -> Vec<Type21> { Vec::new() }
+    fn write_values(self, _rets: &mut [RtValue]) -> Result<NativeStatus, String> { Ok(NativeStatus::Returned) }
+}
+
+impl<R: NativeReturn, E: Display> NativeReturn for Result<R, E> {
+    fn reflected_types() -> Vec<Type21> {
+        R::reflected_types()
+    }
+
+    fn write_values(self, rets: &mut [RtValue]) -> Result<NativeStatus, String> {
+        self.map_err(|e| e.to_string())?.write_values(rets)
+    }
+}
+
+impl<T: NativeType> NativeReturn for T {
+    fn reflected_types() -> Vec<Type21> {
+        vec![T::TYPE]
+    }
+
+    fn write_values(self, rets: &mut [RtValue]) -> Result<NativeStatus, String> {
+        rets[0] = self.into_value();
+        Ok(NativeStatus::Returned)
+    }
+}
+
+/// Returned by natives which suspend the script like a `yield` does, e.g. `wait_frames(n)`. When
+/// the wait is over the host provides results of type `R` with `Combustor::finish_native` and
+/// resumes the script.
+pub struct Suspend<R = ()>(PhantomData<R>);
+
+impl Suspend {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl Default for Suspend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: NativeReturn> Suspend<R> {
+    pub fn with_results() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<R: NativeReturn> NativeReturn for Suspend<R> {
+    fn reflected_types() -> Vec<Type21> {
+        R::reflected_types()
+    }
+
+    fn write_values(self, _rets: &mut [RtValue]) -> Result<NativeStatus, String> {
+        Ok(NativeStatus::Suspended)
+    }
+}
+
+macro_rules! impl_native_return_tuple {
+    ($($t:ident : $idx:tt),+) => {
+        impl<$($t: NativeType),+> NativeReturn for ($($t,)+) {
+            fn reflected_types() -> Vec<Type21> {
+                vec![$($t::TYPE),+]
+            }
+
+            fn write_values(self, rets: &mut [RtValue]) -> Result<NativeStatus, String> {
+                $(rets[$idx] = self.$idx.into_value();)+
+                Ok(NativeStatus::Returned)
+            }
+        }
+    }
+}
+
+impl_native_return_tuple!(R0: 0, R1: 1);
+impl_native_return_tuple!(R0: 0, R1: 1, R2: 2);
+impl_native_return_tuple!(R0: 0, R1: 1, R2: 2, R3: 3);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NativeSignature {
+    pub params: Vec<Type21>,
+    pub rets: Vec<Type21>
+}
+
+impl Display for NativeSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let join = |tys: &[Type21]| tys.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        match self.rets.len() {
+            0 => write!(f, "({}) -> void", join(&self.params)),
+            1 => write!(f, "({}) -> {}", join(&self.params), self.rets[0]),
+            _ => write!(f, "({}) -> ({})", join(&self.params), join(&self.rets))
+        }
+    }
+}
+
+/// Trampoline called by the VM: reads arguments from the first slice and writes results into the
+/// second one, both already sized according to the signature. An `Err` carries the error message.
+/// Natives may be called from several threads at once when instances run in parallel.
+pub type NativeFn = Arc<dyn Fn(&[RtValue], &mut [RtValue]) -> Result<NativeStatus, String> + Send + Sync>;
+
+/// Rust functions and closures which can be registered as natives, with the signature derived
+/// from their parameter and return types
+pub trait IntoNative<Args> {
+    fn signature() -> NativeSignature;
+    fn into_native(self) -> NativeFn;
+}
+
+macro_rules! impl_into_native {
+    ($($a:ident : $idx:tt),*) => {
+        impl<F, R, $($a),*> IntoNative<($($a,)*)> for F
+            where F: Fn($($a),*) -> R + Send + Sync + 'static,
+                  R: NativeReturn,
+                  $($a: NativeType),*
+        {
+            fn signature() -> NativeSignature {
+                NativeSignature {
+                    params: vec![$($a::TYPE),*],
+                    rets: R::reflected_types()
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn into_native(self) -> NativeFn {
+                Arc::new(move |args: &[RtValue], rets: &mut [RtValue]| {
+                    self($($a::from_value(args[$idx])),*).write_values(rets)
+                })
+            }
+        }
+    }
+}
+
+impl_into_native!();
+impl_into_native!(A0: 0);
+impl_into_native!(A0: 0, A1: 1);
+impl_into_native!(A0: 0, A1: 1, A2: 2);
+impl_into_native!(A0: 0, A1: 1, A2: 2, A3: 3);
+impl_into_native!(A0: 0, A1: 1, A2: 2, A3: 3, A4: 4);
+impl_into_native!(A0: 0, A1: 1, A2: 2, A3: 3, A4: 4, A5: 5);
+
+/// A native function a compiled script calls, bound to a registered native by `Compiled::link`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NativeImport {
+    pub name: String,
+    pub signature: NativeSignature
+}
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub signature: NativeSignature,
+    pub func: NativeFn
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.name, self.signature)
+    }
+}
+
+/// Host functions callable from scripts. Natives may be overloaded by parameter types; calls are
+/// resolved by name and exact argument types at compile time.
+#[derive(Debug, Clone, Default)]
+pub struct NativeRegistry {
+    natives: Vec<NativeFunction>
+}
+
+impl NativeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<Args, F: IntoNative<Args>>(&mut self, name: &str, func: F) -> Result<(), String> {
+        self.add(name, F::signature(), func.into_native())
+    }
+
+    /// Registers a raw function such as those in `builtin`. It is called with the arguments and a
+    /// buffer for the results, sized according to `signature`, and cannot fail.
+    ///
+    /// # Safety
+    /// `func` must be safe to call from any thread with any arguments of the types in
+    /// `signature`, must not write to the arguments, and must write only `signature.rets.len()`
+    /// results.
+    pub unsafe fn register_raw(&mut self, name: &str, signature: NativeSignature, func: RawFunction) -> Result<(), String> {
+        self.add(name, signature, Arc::new(move |args: &[RtValue], rets: &mut [RtValue]| {
+            unsafe { func(args.as_ptr() as *mut RtValue, args.len() as u32, rets.as_mut_ptr()) };
+            Ok(NativeStatus::Returned)
+        }))
+    }
+
+    fn add(&mut self, name: &str, signature: NativeSignature, func: NativeFn) -> Result<(), String> {
+        if self.natives.iter().any(|native| native.name == name && native.signature.params == signature.params) {
+            return Err(format!("宿主函数 `{}{}` 重复注册", name, signature));
+        }
+
+        self.natives.push(NativeFunction { name: name.to_string(), signature, func });
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.natives.iter().any(|native| native.name == name)
+    }
+
+    pub fn overloads<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a NativeFunction> + 'a {
+        self.natives.iter().filter(move |native| native.name == name)
+    }
+
+    pub fn natives(&self) -> &[NativeFunction] {
+        &self.natives
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::builtin::{builtin_max, builtin_min};
+    use crate::io_ctx::Type21;
+    use crate::native::{NativeRegistry, NativeSignature, NativeStatus, NativeType, Suspend};
+    use crate::value::RtValue;
+
+    #[test]
+    fn test_registry() {
+        let mut natives = NativeRegistry::new();
+        natives.register("sincos", |x: f32| (x.sin(), x.cos())).unwrap();
+        natives.register("scale", |x: i32, k: f32| x as f32 * k).unwrap();
+        natives.register("scale", |x: f32, k: f32| x * k).unwrap();
+        assert!(natives.register("scale", |x: f32, _k: f32| x).is_err());
+
+        let sincos = natives.overloads("sincos").next().unwrap();
+        assert_eq!(sincos.signature.params, vec![Type21::Float32]);
+        assert_eq!(sincos.signature.rets, vec![Type21::Float32, Type21::Float32]);
+        assert_eq!(sincos.signature.to_string(), "(float) -> (float, float)");
+
+        let mut rets = [RtValue::from(0); 2];
+        assert_eq!((sincos.func)(&[RtValue::from(0.0f32)], &mut rets), Ok(NativeStatus::Returned));
+        assert_eq!(f32::from_value(rets[0]), 0.0);
+        assert_eq!(f32::from_value(rets[1]), 1.0);
+        assert_eq!(natives.overloads("scale").count(), 2);
+
+        natives.register("checked_sqrt", |x: f32| if x < 0.0 { Err("负数") } else { Ok(x.sqrt()) }).unwrap();
+        let checked_sqrt = natives.overloads("checked_sqrt").next().unwrap();
+        assert_eq!(checked_sqrt.signature.to_string(), "(float) -> float");
+        assert_eq!((checked_sqrt.func)(&[RtValue::from(-1.0f32)], &mut rets), Err("负数".to_string()));
+
+        natives.register("wait_frames", |_n: i32| Suspend::new()).unwrap();
+        natives.register("ask", |_options: i32| Suspend::<i32>::with_results()).unwrap();
+        let ask = natives.overloads("ask").next().unwrap();
+        assert_eq!(ask.signature.to_string(), "(int) -> int");
+        assert_eq!((ask.func)(&[RtValue::from(3)], &mut rets), Ok(NativeStatus::Suspended));
+
+        natives.register("is_even", |x: i32| x % 2 == 0).unwrap();
+        let is_even = natives.overloads("is_even").next().unwrap();
+        assert_eq!(is_even.signature.to_string(), "(int) -> bool");
+        assert_eq!((is_even.func)(&[RtValue::from(4)], &mut rets), Ok(NativeStatus::Returned));
+        assert!(bool::from_value(rets[0]));
+
+        let signature = NativeSignature { params: vec![Type21::Int32; 3], rets: vec![Type21::Int32] };
+        unsafe { natives.register_raw("min_of", signature.clone(), builtin_min) }.unwrap();
+        assert!(unsafe { natives.register_raw("min_of", signature, builtin_max) }.is_err());
+        let min_of = natives.overloads("min_of").next().unwrap();
+        assert_eq!(min_of.signature.to_string(), "(int, int, int) -> int");
+        let args = [RtValue::from(4), RtValue::from(-2), RtValue::from(7)];
+        assert_eq!((min_of.func)(&args, &mut rets), Ok(NativeStatus::Returned));
+        assert_eq!(i32::from_value(rets[0]), -2);
+    }
+}
@@ -0,0 +1,602 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::io_ctx::{IOContextMetadata, IOType, Type21};
+use crate::native::{NativeImport, NativeSignature};
+use crate::r25_300::compiled::{Compiled, Function};
+use crate::r25_300::insc::Insc;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::r25_300::verify::verify;
+use crate::value::RtValue;
+
+// Assembly is line based, with `#` starting a comment:
+//
+//   extern half(int) -> int
+//
+//   fn collatz [params 1, rets 1, frame 5]:
+//     mov $1, %1
+//   L0:
+//     eq %0, %1, %2
+//     jmpif %2, L1
+//     call-ffi @half(%0; [%0])
+//     jmp L0
+//   L1:
+//     ret %1
+//
+// Labels belong to the function they appear in. `@` refers to a function or an extern by name or
+// by index, `!` to an IO field by name or by byte offset, and `$` is the bit pattern of a
+// constant in hex.
+
+/// Prints `compiled` as assembly which `assemble` reads back. IO fields found in `io_metadata`
+/// are printed by name, other IO accesses by offset.
+pub fn disassemble(compiled: &Compiled, io_metadata: &IOContextMetadata) -> String {
+    disassemble_impl(compiled, io_metadata, None)
+}
+
+/// Like `disassemble`, with the line of `source` each statement was compiled from printed as a
+/// comment before its first instruction. Nothing is added for code without `Compiled::debug`.
+pub fn disassemble_with_source(compiled: &Compiled, io_metadata: &IOContextMetadata, source: &str) -> String {
+    disassemble_impl(compiled, io_metadata, Some(source))
+}
+
+fn disassemble_impl(compiled: &Compiled, io_metadata: &IOContextMetadata, source: Option<&str>) -> String {
+    let source_lines = source.map(|source| source.lines().collect::<Vec<_>>()).unwrap_or_default();
+    let io_names = io_fields(io_metadata).into_iter()
+        .map(|(name, offset)| (offset, name))
+        .collect::<HashMap<_, _>>();
+    let func_ref = |func: usize| match compiled.func.get(func) {
+        Some(func) => func.name.clone(),
+        None => func.to_string()
+    };
+    let import_ref = |import: usize| match compiled.imports.get(import) {
+        Some(native) if compiled.imports.iter().filter(|other| other.name == native.name).count() == 1 =>
+            native.name.clone(),
+        _ => import.to_string()
+    };
+    let io_ref = |offset: usize| io_names.get(&offset).cloned().unwrap_or_else(|| offset.to_string());
+    let list = |values: &[usize]| values.iter().map(|value| format!("%{}", value)).collect::<Vec<_>>().join(", ");
+
+    let mut text = String::new();
+    for import in compiled.imports.iter() {
+        writeln!(text, "extern {}{}", import.name, import.signature).unwrap();
+    }
+
+    for (func_id, func) in compiled.func.iter().enumerate() {
+        let func_debug = compiled.debug.as_ref()
+            .and_then(|debug| debug.funcs.get(func_id))
+            .filter(|_| source.is_some());
+        let mut last_line = None;
+
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        writeln!(
+            text,
+            "fn {} [params {}, rets {}, frame {}]:",
+            func.name,
+            func.params,
+            func.rets,
+            func.frame_size
+        ).unwrap();
+
+        let code = compiled.code.get(func.addr..func.addr + func.code_len).unwrap_or_default();
+        let labels = code.iter().filter_map(|insc| match insc {
+            Insc::Jmp { dst } | Insc::JmpIf { dst, .. } | Insc::TryBegin { handler: dst } => Some(*dst),
+            _ => None
+        }).collect::<BTreeSet<_>>();
+        let label = |addr: usize| match labels.iter().position(|label| *label == addr) {
+            Some(idx) if func.addr <= addr && addr < func.addr + func.code_len => format!("L{}", idx),
+            _ => addr.to_string()
+        };
+
+        for (insc_ptr, insc) in code.iter().enumerate().map(|(idx, insc)| (func.addr + idx, insc)) {
+            let entry = func_debug.and_then(|func_debug| func_debug.line_at(insc_ptr));
+            if let Some(entry) = entry.filter(|entry| last_line != Some(entry.line)) {
+                let source_line = entry.line.checked_sub(1)
+                    .and_then(|idx| source_lines.get(idx))
+                    .map_or("", |line| line.trim());
+                writeln!(text, "  # {}: {}", entry.line, source_line).unwrap();
+                last_line = Some(entry.line);
+            }
+            if labels.contains(&insc_ptr) {
+                writeln!(text, "{}:", label(insc_ptr)).unwrap();
+            }
+            let line = match insc {
+                Insc::Jmp { dst } => format!("jmp {}", label(*dst)),
+                Insc::JmpIf { check, dst } => format!("jmpif %{}, {}", check, label(*dst)),
+                Insc::TryBegin { handler } => format!("try {}", label(*handler)),
+                Insc::Call { func, args, ret_locs } =>
+                    format!("call @{}({}; [{}])", func_ref(*func), list(args), list(ret_locs)),
+                Insc::CallFFI { func, args, ret_locs } =>
+                    format!("call-ffi @{}({}; [{}])", import_ref(*func), list(args), list(ret_locs)),
+                Insc::Spawn { func, args, dst } =>
+                    format!("spawn @{}({}; %{})", func_ref(*func), list(args), dst),
+                Insc::IOSetValue { offset, src } => format!("ioset !{} %{}", io_ref(*offset), src),
+                Insc::IOGetValue { offset, dst } => format!("ioget !{} %{}", io_ref(*offset), dst),
+                insc => insc.to_string()
+            };
+            writeln!(text, "  {}", line).unwrap();
+        }
+    }
+
+    text
+}
+
+/// Reads assembly written by `disassemble` or by hand. IO fields may be referred to by name if
+/// they are in `io_metadata`, which is also used to check IO offsets unless it is empty. The
+/// result is verified but not linked.
+pub fn assemble(source: &str, io_metadata: &IOContextMetadata) -> Result<Compiled, String> {
+    let lines = source.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    // the first pass finds functions, externs and labels, so that they may be used before they
+    // are defined
+    let mut compiled = Compiled::new();
+    let mut func_ids = HashMap::new();
+    let mut labels = Vec::<HashMap<&str, usize>>::new();
+    let mut addr = 0;
+    for &(line_no, line) in lines.iter() {
+        let fail = |e: String| format!("行 {}: {}", line_no, e);
+
+        if let Some(rest) = line.strip_prefix("extern ") {
+            if !compiled.func.is_empty() {
+                return Err(fail("extern 必须写在所有函数之前".to_string()));
+            }
+            compiled.imports.push(parse_extern(rest).map_err(fail)?);
+        } else if let Some(rest) = line.strip_prefix("fn ") {
+            if let Some(func) = compiled.func.last_mut() {
+                func.code_len = addr - func.addr;
+            }
+            let func = parse_func_header(rest, addr).map_err(fail)?;
+            if func_ids.insert(func.name.clone(), compiled.func.len()).is_some() {
+                return Err(fail(format!("重复的函数定义 `{}`", func.name)));
+            }
+            compiled.func.push(func);
+            labels.push(HashMap::new());
+        } else if let Some(label) = line.strip_suffix(':') {
+            let Some(func_labels) = labels.last_mut() else {
+                return Err(fail("标签必须写在函数中".to_string()));
+            };
+            if !is_ident(label) {
+                return Err(fail(format!("无效的标签 `{}`", label)));
+            }
+            if func_labels.insert(label, addr).is_some() {
+                return Err(fail(format!("重复的标签 `{}`", label)));
+            }
+        } else if compiled.func.is_empty() {
+            return Err(fail("指令必须写在函数中".to_string()));
+        } else {
+            addr += 1;
+        }
+    }
+    if let Some(func) = compiled.func.last_mut() {
+        func.code_len = addr - func.addr;
+    }
+
+    let io_fields = io_fields(io_metadata).into_iter().collect::<HashMap<_, _>>();
+    let mut func_idx = 0;
+    for &(line_no, line) in lines.iter() {
+        if let Some(rest) = line.strip_prefix("fn ") {
+            func_idx = func_ids[parse_func_header(rest, 0).unwrap().name.as_str()];
+            continue;
+        }
+        if line.starts_with("extern ") || line.ends_with(':') {
+            continue;
+        }
+
+        let ctx = AsmContext {
+            compiled: &compiled,
+            func_ids: &func_ids,
+            labels: &labels[func_idx],
+            io_fields: &io_fields
+        };
+        let insc = ctx.parse_insc(line).map_err(|e| format!("行 {}: {}", line_no, e))?;
+        compiled.code.push(insc);
+    }
+
+    let io_size = io_metadata.iter().map(|(_, _, ty)| ty.size()).sum::<usize>();
+    verify(&compiled, (io_size != 0).then_some(io_size))?;
+    Ok(compiled)
+}
+
+/// Scalar IO fields by name, fields of structs as `var.field`, with their offsets
+fn io_fields(io_metadata: &IOContextMetadata) -> Vec<(String, usize)> {
+    fn collect(io_metadata: &IOContextMetadata, prefix: &str, offset: &mut usize, fields: &mut Vec<(String, usize)>) {
+        for (name, _, ty) in io_metadata.iter() {
+            match ty {
+                IOType::Struct(_, inner) => collect(inner, &format!("{}{}.", prefix, name), offset, fields),
+                IOType::Scalar(_) | IOType::Enum(_, _) => {
+                    fields.push((format!("{}{}", prefix, name), *offset));
+                    *offset += ty.size();
+                }
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    collect(io_metadata, "", &mut 0, &mut fields);
+    fields
+}
+
+fn is_ident(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_type(s: &str) -> Result<Type21, String> {
+    match s.trim() {
+        "int" => Ok(Type21::Int32),
+        "float" => Ok(Type21::Float32),
+        "bool" => Ok(Type21::Bool),
+        ty => Err(format!("无效的类型 `{}`", ty))
+    }
+}
+
+fn parse_types(s: &str) -> Result<Vec<Type21>, String> {
+    if s.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',').map(parse_type).collect()
+}
+
+// `name(int, float) -> void`, with the return type written as in `NativeSignature`
+fn parse_extern(s: &str) -> Result<NativeImport, String> {
+    let invalid = || format!("无法解析外部函数声明 `{}`", s);
+    let (name, rest) = s.split_once('(').ok_or_else(invalid)?;
+    let (params, rets) = rest.split_once(')').ok_or_else(invalid)?;
+    let rets = rets.trim().strip_prefix("->").ok_or_else(invalid)?.trim();
+    if !is_ident(name.trim()) {
+        return Err(invalid());
+    }
+
+    let rets = if rets == "void" {
+        Vec::new()
+    } else if let Some(rets) = rets.strip_prefix('(').and_then(|rets| rets.strip_suffix(')')) {
+        parse_types(rets)?
+    } else {
+        vec![parse_type(rets)?]
+    };
+    Ok(NativeImport {
+        name: name.trim().to_string(),
+        signature: NativeSignature { params: parse_types(params)?, rets }
+    })
+}
+
+// `name [params 1, rets 1, frame 5]:`
+fn parse_func_header(s: &str, addr: usize) -> Result<Function, String> {
+    let invalid = || format!("无法解析函数头 `fn {}`", s);
+    let (name, rest) = s.split_once('[').ok_or_else(invalid)?;
+    let attrs = rest.strip_suffix(':').and_then(|rest| rest.trim_end().strip_suffix(']')).ok_or_else(invalid)?;
+    if !is_ident(name.trim()) {
+        return Err(invalid());
+    }
+
+    let mut func = Function {
+        name: name.trim().to_string(),
+        addr,
+        frame_size: 0,
+        code_len: 0,
+        params: 0,
+        rets: 0
+    };
+    let mut seen = Vec::new();
+    for attr in attrs.split(',') {
+        let (key, value) = attr.trim().split_once(' ').ok_or_else(invalid)?;
+        let value = value.trim().parse::<usize>().map_err(|_| invalid())?;
+        match key {
+            "params" => func.params = value,
+            "rets" => func.rets = value,
+            "frame" => func.frame_size = value,
+            _ => return Err(format!("未知的函数属性 `{}`", key))
+        }
+        seen.push(key);
+    }
+    seen.sort();
+    if seen != ["frame", "params", "rets"] {
+        return Err(invalid());
+    }
+    Ok(func)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'s> {
+    // `%1`
+    Slot(usize),
+    // `$3F800000`
+    Imm(u32),
+    // `@name`, `!name`; the part after the sigil
+    Ref(&'s str),
+    Io(&'s str),
+    Word(&'s str),
+    Punct(char)
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token<'_>>, String> {
+    let word_end = |s: &str| s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(s.len());
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            '(' | ')' | '[' | ']' | ',' | ';' => (Token::Punct(c), 1),
+            '%' | '$' | '@' | '!' => {
+                let len = word_end(&rest[1..]);
+                let word = &rest[1..1 + len];
+                if word.is_empty() {
+                    return Err(format!("`{}` 后缺少名称", c));
+                }
+                let token = match c {
+                    '%' => Token::Slot(word.parse().map_err(|_| format!("无效的槽位 `%{}`", word))?),
+                    '$' => Token::Imm(u32::from_str_radix(word, 16).map_err(|_| format!("无效的常量 `${}`", word))?),
+                    '@' => Token::Ref(word),
+                    _ => Token::Io(word)
+                };
+                (token, 1 + len)
+            },
+            _ => {
+                let len = word_end(rest);
+                if len == 0 {
+                    return Err(format!("无法识别的字符 `{}`", c));
+                }
+                (Token::Word(&rest[..len]), len)
+            }
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct AsmContext<'a> {
+    compiled: &'a Compiled,
+    func_ids: &'a HashMap<String, usize>,
+    labels: &'a HashMap<&'a str, usize>,
+    io_fields: &'a HashMap<String, usize>
+}
+
+struct Operands<'s> {
+    tokens: Vec<Token<'s>>,
+    pos: usize
+}
+
+impl<'s> Operands<'s> {
+    fn next(&mut self) -> Option<Token<'s>> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token<'s>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            _ => Err(format!("缺少 `{}`", c))
+        }
+    }
+
+    fn slot(&mut self) -> Result<usize, String> {
+        match self.next() {
+            Some(Token::Slot(slot)) => Ok(slot),
+            _ => Err("缺少槽位".to_string())
+        }
+    }
+
+    // `%1, %2, %3` with exactly `N` slots
+    fn slots<const N: usize>(&mut self) -> Result<[usize; N], String> {
+        let mut slots = [0; N];
+        for (idx, slot) in slots.iter_mut().enumerate() {
+            if idx != 0 {
+                self.expect(',')?;
+            }
+            *slot = self.slot()?;
+        }
+        Ok(slots)
+    }
+
+    // slots separated by commas, until (but not including) `end`
+    fn slot_list(&mut self, end: char) -> Result<Box<[usize]>, String> {
+        let mut slots = Vec::new();
+        while self.peek() != Some(&Token::Punct(end)) {
+            if !slots.is_empty() {
+                self.expect(',')?;
+            }
+            slots.push(self.slot()?);
+        }
+        Ok(slots.into_boxed_slice())
+    }
+
+    // nothing, `%1` or `[%1, %2]`, as in `ret` and `yield`
+    fn values(&mut self) -> Result<Box<[usize]>, String> {
+        match self.peek() {
+            None => Ok(Box::new([])),
+            Some(Token::Punct('[')) => {
+                self.pos += 1;
+                let values = self.slot_list(']')?;
+                self.expect(']')?;
+                Ok(values)
+            },
+            _ => Ok(Box::new([self.slot()?]))
+        }
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.pos < self.tokens.len() {
+            return Err("指令末尾有多余的内容".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl AsmContext<'_> {
+    fn parse_insc(&self, line: &str) -> Result<Insc, String> {
+        let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut ops = Operands { tokens: tokenize(rest)?, pos: 0 };
+
+        let insc = match mnemonic {
+            "mov" => match ops.next() {
+                Some(Token::Imm(repr)) => {
+                    ops.expect(',')?;
+                    Insc::Const { value: RtValue { repr }, dst: ops.slot()? }
+                },
+                Some(Token::Slot(src)) => {
+                    ops.expect(',')?;
+                    Insc::Dup { src, dst: ops.slot()? }
+                },
+                _ => return Err("`mov` 的源操作数必须是常量或槽位".to_string())
+            },
+
+            "add" | "fadd" | "sub" | "fsub" | "mul" | "fmul" | "div" | "fdiv" | "mod"
+            | "eq" | "ne" | "lt" | "flt" | "le" | "fle" | "and" | "or" => {
+                let [lhs, rhs, dst] = ops.slots::<3>()?;
+                match mnemonic {
+                    "add" => Insc::AddInt { lhs, rhs, dst },
+                    "fadd" => Insc::AddFloat { lhs, rhs, dst },
+                    "sub" => Insc::SubInt { lhs, rhs, dst },
+                    "fsub" => Insc::SubFloat { lhs, rhs, dst },
+                    "mul" => Insc::MulInt { lhs, rhs, dst },
+                    "fmul" => Insc::MulFloat { lhs, rhs, dst },
+                    "div" => Insc::DivInt { lhs, rhs, dst },
+                    "fdiv" => Insc::DivFloat { lhs, rhs, dst },
+                    "mod" => Insc::ModInt { lhs, rhs, dst },
+                    "eq" => Insc::Eq { lhs, rhs, dst },
+                    "ne" => Insc::Ne { lhs, rhs, dst },
+                    "lt" => Insc::LtInt { lhs, rhs, dst },
+                    "flt" => Insc::LtFloat { lhs, rhs, dst },
+                    "le" => Insc::LeInt { lhs, rhs, dst },
+                    "fle" => Insc::LeFloat { lhs, rhs, dst },
+                    "and" => Insc::And { lhs, rhs, dst },
+                    _ => Insc::Or { lhs, rhs, dst }
+                }
+            },
+
+            "neg" | "fneg" | "not" | "round" | "floor" | "ceil" | "tofloat" | "f2i" | "round2i"
+            | "floor2i" | "ceil2i" | "b2i" | "i2b" | "b2f" | "f2b" => {
+                let [src, dst] = ops.slots::<2>()?;
+                match mnemonic {
+                    "neg" => Insc::NegateInt { src, dst },
+                    "fneg" => Insc::NegateFloat { src, dst },
+                    "not" => Insc::Not { src, dst },
+                    "round" => Insc::Round { src, dst },
+                    "floor" => Insc::Floor { src, dst },
+                    "ceil" => Insc::Ceil { src, dst },
+                    "tofloat" => Insc::ToFloat { src, dst },
+                    "f2i" => Insc::Float2Int { src, dst },
+                    "round2i" => Insc::Round2Int { src, dst },
+                    "floor2i" => Insc::Floor2Int { src, dst },
+                    "ceil2i" => Insc::Ceil2Int { src, dst },
+                    "b2i" => Insc::Bool2Int { src, dst },
+                    "i2b" => Insc::Int2Bool { src, dst },
+                    "b2f" => Insc::Bool2Float { src, dst },
+                    _ => Insc::Float2Bool { src, dst }
+                }
+            },
+
+            _ if mnemonic.starts_with("math.") => {
+                let name = &mnemonic[5..];
+                if let Some(op) = MathOp1::ALL.iter().find(|op| op.name() == name) {
+                    let [src, dst] = ops.slots::<2>()?;
+                    Insc::Math1 { op: *op, src, dst }
+                } else if let Some(op) = MathOp2::ALL.iter().find(|op| op.name() == name) {
+                    let [lhs, rhs, dst] = ops.slots::<3>()?;
+                    Insc::Math2 { op: *op, lhs, rhs, dst }
+                } else if let Some(op) = MathOp3::ALL.iter().find(|op| op.name() == name) {
+                    let [a, b, c, dst] = ops.slots::<4>()?;
+                    Insc::Math3 { op: *op, a, b, c, dst }
+                } else {
+                    return Err(format!("未知的数学运算 `{}`", name));
+                }
+            },
+
+            "jmp" => Insc::Jmp { dst: self.target(&mut ops)? },
+            "jmpif" => {
+                let check = ops.slot()?;
+                ops.expect(',')?;
+                Insc::JmpIf { check, dst: self.target(&mut ops)? }
+            },
+            "call" | "call-ffi" | "spawn" => {
+                let func = match ops.next() {
+                    Some(Token::Ref(name)) if mnemonic == "call-ffi" => self.import(name)?,
+                    Some(Token::Ref(name)) => self.func(name)?,
+                    _ => return Err(format!("`{}` 缺少被调用的函数", mnemonic))
+                };
+                ops.expect('(')?;
+                let args = ops.slot_list(';')?;
+                ops.expect(';')?;
+                let insc = if mnemonic == "spawn" {
+                    Insc::Spawn { func, args, dst: ops.slot()? }
+                } else {
+                    ops.expect('[')?;
+                    let ret_locs = ops.slot_list(']')?;
+                    ops.expect(']')?;
+                    if mnemonic == "call" {
+                        Insc::Call { func, args, ret_locs }
+                    } else {
+                        Insc::CallFFI { func, args, ret_locs }
+                    }
+                };
+                ops.expect(')')?;
+                insc
+            },
+            "ret" => Insc::Return { rets: ops.values()? },
+
+            "ioset" => Insc::IOSetValue { offset: self.io(&mut ops)?, src: ops.slot()? },
+            "ioget" => Insc::IOGetValue { offset: self.io(&mut ops)?, dst: ops.slot()? },
+            "try" => Insc::TryBegin { handler: self.target(&mut ops)? },
+            "endtry" => Insc::TryEnd,
+            "join" => Insc::Join { task: ops.slot()? },
+
+            "yield" => Insc::Yield { values: ops.values()? },
+            _ => return Err(format!("未知的指令 `{}`", mnemonic))
+        };
+
+        ops.finish()?;
+        Ok(insc)
+    }
+
+    // a label of the current function, or an absolute address
+    fn target(&self, ops: &mut Operands) -> Result<usize, String> {
+        match ops.next() {
+            Some(Token::Word(word)) => match word.parse::<usize>() {
+                Ok(addr) => Ok(addr),
+                Err(_) => self.labels.get(word).copied().ok_or_else(|| format!("未定义的标签 `{}`", word))
+            },
+            _ => Err("缺少跳转目标".to_string())
+        }
+    }
+
+    fn func(&self, name: &str) -> Result<usize, String> {
+        if let Ok(idx) = name.parse::<usize>() {
+            return Ok(idx);
+        }
+        self.func_ids.get(name).copied().ok_or_else(|| format!("未定义的函数 `{}`", name))
+    }
+
+    fn import(&self, name: &str) -> Result<usize, String> {
+        if let Ok(idx) = name.parse::<usize>() {
+            return Ok(idx);
+        }
+
+        let mut found = self.compiled.imports.iter().enumerate().filter(|(_, import)| import.name == name);
+        match (found.next(), found.next()) {
+            (Some((idx, _)), None) => Ok(idx),
+            (Some(_), Some(_)) => Err(format!("外部函数 `{}` 有多个重载，只能用序号引用", name)),
+            (None, _) => Err(format!("未声明的外部函数 `{}`", name))
+        }
+    }
+
+    fn io(&self, ops: &mut Operands) -> Result<usize, String> {
+        match ops.next() {
+            Some(Token::Io(name)) => match name.parse::<usize>() {
+                Ok(offset) => Ok(offset),
+                Err(_) => self.io_fields.get(name).copied().ok_or_else(|| format!("未知的 IO 变量 `{}`", name))
+            },
+            _ => Err("缺少 IO 变量".to_string())
+        }
+    }
+}
@@ -0,0 +1,700 @@
+use std::hint::unreachable_unchecked;
+use std::mem::{size_of, take};
+
+use crate::io_ctx::IOContext;
+use crate::native::{NativeFunction, NativeReturn, NativeStatus};
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::coroutine::CoroutineStatus;
+use crate::r25_300::cumbustor::{Combustor, CombustorState, Engine, EngineCode};
+use crate::r25_300::error::{int_division_fault, RuntimeError};
+use crate::r25_300::insc::Insc;
+use crate::r25_300::stack::{StackFrame, ZeroBuf};
+use crate::value::{
+    bool_to_float,
+    float_ceil_to_int,
+    float_floor_to_int,
+    float_round_to_int,
+    float_to_bool,
+    float_to_int,
+    RtValue
+};
+
+macro_rules! batch_binop {
+    ($f:ident, $s:expr, $lanes:expr, $base:expr, $lhs:expr, $rhs:expr, $dst:expr, $op:tt) => {
+        {
+            let (lhs, rhs, dst) = ($s.slot($base, *$lhs), $s.slot($base, *$rhs), $s.slot($base, *$dst));
+            for &lane in $lanes.iter() {
+                let lane = lane as usize;
+                let lhs = $s.values.get_unchecked(lhs + lane).$f;
+                let rhs = $s.values.get_unchecked(rhs + lane).$f;
+                *$s.values.get_unchecked_mut(dst + lane) = RtValue::from(lhs $op rhs);
+            }
+        }
+    }
+}
+
+macro_rules! batch_conv {
+    ($f:ident, $s:expr, $lanes:expr, $base:expr, $src:expr, $dst:expr, $conv:expr) => {
+        {
+            let (src, dst) = ($s.slot($base, *$src), $s.slot($base, *$dst));
+            for &lane in $lanes.iter() {
+                let lane = lane as usize;
+                let src = $s.values.get_unchecked(src + lane).$f;
+                *$s.values.get_unchecked_mut(dst + lane) = RtValue::from($conv(src));
+            }
+        }
+    }
+}
+
+// lanes at the same instruction of the top frame, executed together
+struct Group {
+    pc: usize,
+    lanes: Vec<u32>
+}
+
+struct Lane<'a> {
+    status: CoroutineStatus,
+    handlers: Vec<(usize, usize)>,
+    yielded: Vec<RtValue>,
+    error: Option<RuntimeError>,
+    // the scalar state and resume address of a lane which left batched execution
+    detached: Option<(CombustorState<'a>, usize)>,
+    // a detached lane which still has to run in the current resume
+    pending: bool
+}
+
+/// Runs one script over many IO contexts at once. Values of all lanes are stored as
+/// structure-of-arrays, and each instruction is dispatched once for every group of lanes which
+/// reached it. Lanes taking different branches are split into groups, executed lowest address
+/// first so that they meet again after the branch.
+///
+/// Frames are shared by all lanes, so a lane leaves batched execution and continues as an
+/// ordinary `Combustor` when its control flow can no longer be kept in step with the others: it
+/// would have to return to a frame while others are suspended in a frame above it, a native
+/// suspends it or fails into a `try` of a lower frame, or it reaches `spawn` or `join`.
+pub struct BatchCombustor<'a> {
+    width: usize,
+    // slot `i` of lane `l` is at `i * width + l`
+    values: ZeroBuf<RtValue>,
+    frames: Vec<StackFrame>,
+    // runnable groups of the top frame, by descending address
+    groups: Vec<Group>,
+    // runnable groups of each frame below the top one
+    parked: Vec<Vec<Group>>,
+    // groups stopped at `yield`, for each frame
+    yielded: Vec<Vec<Group>>,
+    // lanes which returned from the top frame, waiting for the others to leave it
+    returned: Vec<u32>,
+    // field `f` of lane `l` is at `f * width + l`
+    io: ZeroBuf<RtValue>,
+    lanes: Vec<Lane<'a>>,
+    spare_lists: Vec<Vec<u32>>,
+    in_buf: ZeroBuf<RtValue>,
+    out_buf: ZeroBuf<RtValue>,
+    // lowered code shared by the detached lanes
+    engine_code: Option<EngineCode<'a>>
+}
+
+impl<'a> BatchCombustor<'a> {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            values: ZeroBuf::with_capacity(256),
+            frames: Vec::new(),
+            groups: Vec::new(),
+            parked: Vec::new(),
+            yielded: Vec::new(),
+            returned: Vec::new(),
+            io: ZeroBuf::new(),
+            lanes: Vec::new(),
+            spare_lists: Vec::new(),
+            in_buf: ZeroBuf::with_capacity(8),
+            out_buf: ZeroBuf::with_capacity(8),
+            engine_code: None
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn status(&self, lane: usize) -> CoroutineStatus {
+        self.lanes[lane].status
+    }
+
+    /// The error which faulted a lane
+    pub fn error(&self, lane: usize) -> Option<&RuntimeError> {
+        self.lanes[lane].error.as_ref()
+    }
+
+    /// Values passed to the last `yield` of a lane, see `Combustor::yielded`
+    pub fn yielded(&self, lane: usize) -> &[RtValue] {
+        match &self.lanes[lane].detached {
+            Some((state, _)) => state.yielded(),
+            None => &self.lanes[lane].yielded
+        }
+    }
+
+    /// Number of lanes which left batched execution
+    pub fn detached_count(&self) -> usize {
+        self.lanes.iter().filter(|lane| lane.detached.is_some()).count()
+    }
+
+    pub fn suspended_native(&self, lane: usize) -> Option<&'a NativeFunction> {
+        self.lanes[lane].detached.as_ref().and_then(|(state, _)| state.suspended_native())
+    }
+
+    /// Provides the results of the native a lane is suspended on. A lane suspended by a native is
+    /// not resumed until this is called, even for natives without results.
+    pub fn finish_native<R: NativeReturn>(&mut self, lane: usize, rets: R) -> Result<(), String> {
+        match &mut self.lanes[lane].detached {
+            Some((state, _)) => state.finish_native(rets),
+            None => Err("脚本没有在等待宿主函数".to_string())
+        }
+    }
+
+    /// Starts `entry` with one lane for each context. Returns the number of lanes which are
+    /// suspended rather than finished or faulted.
+    ///
+    /// # Safety
+    /// As for `Combustor::combust`.
+    pub unsafe fn combust<CTX: IOContext>(
+        &mut self,
+        compiled: &'a Compiled,
+        entry: usize,
+        ctxs: &mut [CTX]
+    ) -> usize {
+        debug_assert!(compiled.is_linked(), "`Compiled` must be linked before running");
+        debug_assert_eq!(size_of::<CTX>() % size_of::<RtValue>(), 0);
+        let entry_fn = compiled.func.get_unchecked(entry);
+
+        self.width = ctxs.len();
+        self.frames.clear();
+        self.parked.clear();
+        self.yielded.clear();
+        self.returned.clear();
+        for group in take(&mut self.groups) {
+            self.spare_lists.push(group.lanes);
+        }
+        self.lanes.truncate(self.width);
+        for lane in self.lanes.iter_mut() {
+            lane.handlers.clear();
+            lane.error = None;
+            lane.detached = None;
+        }
+        while self.lanes.len() < self.width {
+            self.lanes.push(Lane {
+                status: CoroutineStatus::NotStarted,
+                handlers: Vec::new(),
+                yielded: Vec::new(),
+                error: None,
+                detached: None,
+                pending: false
+            });
+        }
+        for lane in self.lanes.iter_mut() {
+            lane.status = CoroutineStatus::Suspended;
+        }
+
+        self.values.resize(entry_fn.frame_size * self.width);
+        self.frames.push(StackFrame::new(0, 0, entry_fn.frame_size));
+        self.yielded.push(Vec::new());
+        if self.width != 0 {
+            let mut lanes = self.take_list();
+            lanes.extend(0..self.width as u32);
+            self.groups.push(Group { pc: entry_fn.addr, lanes });
+        }
+
+        self.run(compiled, ctxs)
+    }
+
+    /// Continues every suspended lane until it yields, finishes or faults
+    ///
+    /// # Safety
+    /// `compiled` must be the code passed to `combust`, and `ctxs` the contexts passed to it, in
+    /// the same order.
+    pub unsafe fn combust_resume<CTX: IOContext>(&mut self, compiled: &'a Compiled, ctxs: &mut [CTX]) -> usize {
+        debug_assert_eq!(ctxs.len(), self.width);
+
+        for (depth, yielded) in self.yielded.iter_mut().enumerate() {
+            let groups = if depth + 1 == self.frames.len() {
+                &mut self.groups
+            } else {
+                &mut self.parked[depth]
+            };
+            for group in yielded.drain(..) {
+                insert_group(groups, group, &mut self.spare_lists);
+            }
+        }
+
+        self.run(compiled, ctxs)
+    }
+
+    unsafe fn run<CTX: IOContext>(&mut self, compiled: &'a Compiled, ctxs: &mut [CTX]) -> usize {
+        let fields = size_of::<CTX>() / size_of::<RtValue>();
+        self.io.resize(fields * self.width);
+        for (idx, lane) in self.lanes.iter_mut().enumerate() {
+            lane.yielded.clear();
+            lane.pending = match &lane.detached {
+                Some((state, _)) => lane.status == CoroutineStatus::Suspended && state.suspended_native().is_none(),
+                None => {
+                    let ctx = &ctxs[idx] as *const CTX as *const RtValue;
+                    for field in 0..fields {
+                        *self.io.get_unchecked_mut(field * self.width + idx) = ctx.add(field).read();
+                    }
+                    false
+                }
+            };
+        }
+
+        self.run_batched(compiled, ctxs);
+
+        for (idx, lane) in self.lanes.iter_mut().enumerate() {
+            if lane.detached.is_none() {
+                let ctx = &mut ctxs[idx] as *mut CTX as *mut RtValue;
+                for field in 0..fields {
+                    ctx.add(field).write(*self.io.get_unchecked(field * self.width + idx));
+                }
+            }
+        }
+
+        for (idx, lane) in self.lanes.iter_mut().enumerate() {
+            if !lane.pending {
+                continue;
+            }
+
+            let engine_code = match &self.engine_code {
+                Some(engine_code) if engine_code.is_for(compiled, Engine::default()) => engine_code,
+                _ => self.engine_code.insert(EngineCode::new(compiled, Engine::default()))
+            };
+            let (state, resume_ptr) = lane.detached.take().unwrap();
+            let mut combustor = Combustor::with_state(&mut ctxs[idx], state);
+            combustor.set_engine_code(engine_code.clone());
+            let result = combustor.combust_resume(compiled, resume_ptr);
+            let resume_ptr = match result {
+                Ok(Some(resume_ptr)) => resume_ptr,
+                Ok(None) => {
+                    lane.status = CoroutineStatus::Finished;
+                    resume_ptr
+                },
+                Err(err) => {
+                    lane.status = CoroutineStatus::Faulted;
+                    lane.error = Some(err);
+                    resume_ptr
+                }
+            };
+            lane.detached = Some((combustor.into_state(), resume_ptr));
+            lane.pending = false;
+        }
+
+        self.lanes.iter().filter(|lane| lane.status == CoroutineStatus::Suspended).count()
+    }
+
+    unsafe fn run_batched<CTX: IOContext>(&mut self, compiled: &'a Compiled, ctxs: &mut [CTX]) {
+        loop {
+            if let Some(group) = self.groups.pop() {
+                self.step(compiled, group, ctxs);
+                continue;
+            }
+
+            let depth = self.frames.len();
+            if depth == 1 {
+                break;
+            }
+
+            if self.yielded[depth - 1].is_empty() {
+                // every lane has left the top frame
+                let frame = self.frames.pop().unwrap_unchecked();
+                self.yielded.pop();
+                self.groups = self.parked.pop().unwrap_unchecked();
+                if !self.returned.is_empty() {
+                    let mut lanes = self.take_list();
+                    std::mem::swap(&mut lanes, &mut self.returned);
+                    insert_group(&mut self.groups, Group { pc: frame.ret_addr() + 1, lanes }, &mut self.spare_lists);
+                }
+                continue;
+            }
+
+            // lanes suspended in the top frame keep it alive, so lanes below it continue alone
+            let ret_addr = self.frames.last().unwrap_unchecked().ret_addr();
+            for lane in take(&mut self.returned) {
+                self.detach(lane as usize, depth - 1, ret_addr + 1, None, ctxs);
+            }
+            for parked_depth in 0..self.parked.len() {
+                for group in take(&mut self.parked[parked_depth]) {
+                    for &lane in group.lanes.iter() {
+                        self.detach(lane as usize, parked_depth + 1, group.pc, None, ctxs);
+                    }
+                    self.spare_lists.push(group.lanes);
+                }
+            }
+            break;
+        }
+    }
+
+    unsafe fn step<CTX: IOContext>(&mut self, compiled: &'a Compiled, mut group: Group, ctxs: &mut [CTX]) {
+        let frame = *self.frames.last().unwrap_unchecked();
+        let base = frame.start_idx();
+        let lanes = &group.lanes;
+
+        match compiled.code.get_unchecked(group.pc) {
+            Insc::Const { value, dst } => {
+                let dst = self.slot(base, *dst);
+                for &lane in lanes.iter() {
+                    *self.values.get_unchecked_mut(dst + lane as usize) = *value;
+                }
+            },
+            Insc::Dup { src, dst } => {
+                let (src, dst) = (self.slot(base, *src), self.slot(base, *dst));
+                for &lane in lanes.iter() {
+                    *self.values.get_unchecked_mut(dst + lane as usize) = *self.values.get_unchecked(src + lane as usize);
+                }
+            },
+            Insc::AddInt { lhs, rhs, dst } => batch_binop!(i, self, lanes, base, lhs, rhs, dst, +),
+            Insc::AddFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, +),
+            Insc::SubInt { lhs, rhs, dst } => batch_binop!(i, self, lanes, base, lhs, rhs, dst, -),
+            Insc::SubFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, -),
+            Insc::MulInt { lhs, rhs, dst } => batch_binop!(i, self, lanes, base, lhs, rhs, dst, *),
+            Insc::MulFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, *),
+            Insc::DivInt { lhs, rhs, dst } => {
+                self.int_division(compiled, &mut group, base, [*lhs, *rhs, *dst], i32::checked_div, ctxs);
+                if group.lanes.is_empty() {
+                    self.spare_lists.push(group.lanes);
+                    return;
+                }
+            },
+            Insc::DivFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, /),
+            Insc::ModInt { lhs, rhs, dst } => {
+                self.int_division(compiled, &mut group, base, [*lhs, *rhs, *dst], i32::checked_rem, ctxs);
+                if group.lanes.is_empty() {
+                    self.spare_lists.push(group.lanes);
+                    return;
+                }
+            },
+            Insc::NegateInt { src, dst } => batch_conv!(i, self, lanes, base, src, dst, |x: i32| -x),
+            Insc::NegateFloat { src, dst } => batch_conv!(f, self, lanes, base, src, dst, |x: f32| -x),
+            Insc::Eq { lhs, rhs, dst } => batch_binop!(repr, self, lanes, base, lhs, rhs, dst, ==),
+            Insc::Ne { lhs, rhs, dst } => batch_binop!(repr, self, lanes, base, lhs, rhs, dst, !=),
+            Insc::LtInt { lhs, rhs, dst } => batch_binop!(i, self, lanes, base, lhs, rhs, dst, <),
+            Insc::LtFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, <),
+            Insc::LeInt { lhs, rhs, dst } => batch_binop!(i, self, lanes, base, lhs, rhs, dst, <=),
+            Insc::LeFloat { lhs, rhs, dst } => batch_binop!(f, self, lanes, base, lhs, rhs, dst, <=),
+            Insc::And { lhs, rhs, dst } => batch_binop!(b, self, lanes, base, lhs, rhs, dst, &&),
+            Insc::Or { lhs, rhs, dst } => batch_binop!(b, self, lanes, base, lhs, rhs, dst, ||),
+            Insc::Not { src, dst } => batch_conv!(b, self, lanes, base, src, dst, |x: bool| !x),
+            Insc::Round { src, dst } => batch_conv!(f, self, lanes, base, src, dst, f32::round),
+            Insc::Floor { src, dst } => batch_conv!(f, self, lanes, base, src, dst, f32::floor),
+            Insc::Ceil { src, dst } => batch_conv!(f, self, lanes, base, src, dst, f32::ceil),
+            Insc::ToFloat { src, dst } => batch_conv!(i, self, lanes, base, src, dst, |x: i32| x as f32),
+            Insc::Bool2Int { src, dst } => batch_conv!(b, self, lanes, base, src, dst, |x: bool| x as i32),
+            Insc::Int2Bool { src, dst } => batch_conv!(i, self, lanes, base, src, dst, |x: i32| x != 0),
+            Insc::Float2Int { src, dst } => batch_conv!(f, self, lanes, base, src, dst, float_to_int),
+            Insc::Round2Int { src, dst } => batch_conv!(f, self, lanes, base, src, dst, float_round_to_int),
+            Insc::Floor2Int { src, dst } => batch_conv!(f, self, lanes, base, src, dst, float_floor_to_int),
+            Insc::Ceil2Int { src, dst } => batch_conv!(f, self, lanes, base, src, dst, float_ceil_to_int),
+            Insc::Bool2Float { src, dst } => batch_conv!(b, self, lanes, base, src, dst, bool_to_float),
+            Insc::Float2Bool { src, dst } => batch_conv!(f, self, lanes, base, src, dst, float_to_bool),
+            Insc::Math1 { op, src, dst } => {
+                let (src, dst) = (self.slot(base, *src), self.slot(base, *dst));
+                for &lane in lanes.iter() {
+                    let lane = lane as usize;
+                    *self.values.get_unchecked_mut(dst + lane) = op.eval(*self.values.get_unchecked(src + lane));
+                }
+            },
+            Insc::Math2 { op, lhs, rhs, dst } => {
+                let (lhs, rhs, dst) = (self.slot(base, *lhs), self.slot(base, *rhs), self.slot(base, *dst));
+                for &lane in lanes.iter() {
+                    let lane = lane as usize;
+                    let value = op.eval(*self.values.get_unchecked(lhs + lane), *self.values.get_unchecked(rhs + lane));
+                    *self.values.get_unchecked_mut(dst + lane) = value;
+                }
+            },
+            Insc::Math3 { op, a, b, c, dst } => {
+                let (a, b, c) = (self.slot(base, *a), self.slot(base, *b), self.slot(base, *c));
+                let dst = self.slot(base, *dst);
+                for &lane in lanes.iter() {
+                    let lane = lane as usize;
+                    let value = op.eval(
+                        *self.values.get_unchecked(a + lane),
+                        *self.values.get_unchecked(b + lane),
+                        *self.values.get_unchecked(c + lane)
+                    );
+                    *self.values.get_unchecked_mut(dst + lane) = value;
+                }
+            },
+            Insc::Jmp { dst } => {
+                group.pc = *dst;
+                insert_group(&mut self.groups, group, &mut self.spare_lists);
+                return;
+            },
+            Insc::JmpIf { check, dst } => {
+                let check = self.slot(base, *check);
+                let mut taken = self.take_list();
+                group.lanes.retain(|&lane| {
+                    let jump = self.values.get_unchecked(check + lane as usize).b;
+                    if jump {
+                        taken.push(lane);
+                    }
+                    !jump
+                });
+
+                if taken.is_empty() {
+                    self.spare_lists.push(taken);
+                } else {
+                    insert_group(&mut self.groups, Group { pc: *dst, lanes: taken }, &mut self.spare_lists);
+                }
+                if group.lanes.is_empty() {
+                    self.spare_lists.push(group.lanes);
+                } else {
+                    group.pc += 1;
+                    insert_group(&mut self.groups, group, &mut self.spare_lists);
+                }
+                return;
+            },
+            Insc::Call { func, args, .. } => {
+                let func = compiled.func.get_unchecked(*func);
+                let start_idx = frame.end_idx();
+                let end_idx = start_idx + func.frame_size;
+                self.values.resize(end_idx * self.width);
+
+                for &lane in lanes.iter() {
+                    for (i, arg) in args.iter().enumerate() {
+                        let value = *self.values.get_unchecked(self.slot(base, *arg) + lane as usize);
+                        let dst = self.slot(start_idx, i) + lane as usize;
+                        *self.values.get_unchecked_mut(dst) = value;
+                    }
+                }
+
+                // groups of the caller wait until every lane has left the callee
+                self.frames.push(StackFrame::new(group.pc, start_idx, end_idx));
+                self.parked.push(take(&mut self.groups));
+                self.yielded.push(Vec::new());
+                group.pc = func.addr;
+                self.groups.push(group);
+                return;
+            },
+            Insc::Return { rets } => {
+                if self.frames.len() == 1 {
+                    for &lane in lanes.iter() {
+                        self.lanes.get_unchecked_mut(lane as usize).status = CoroutineStatus::Finished;
+                    }
+                } else {
+                    let caller = self.frames.get_unchecked(self.frames.len() - 2).start_idx();
+                    let Insc::Call { ret_locs, .. } = compiled.code.get_unchecked(frame.ret_addr()) else {
+                        unreachable_unchecked()
+                    };
+                    for &lane in lanes.iter() {
+                        for (ret, ret_loc) in rets.iter().zip(ret_locs.iter()) {
+                            let value = *self.values.get_unchecked(self.slot(base, *ret) + lane as usize);
+                            let dst = self.slot(caller, *ret_loc) + lane as usize;
+                            *self.values.get_unchecked_mut(dst) = value;
+                        }
+                    }
+                    self.returned.extend_from_slice(lanes);
+                }
+                self.spare_lists.push(group.lanes);
+                return;
+            },
+            Insc::IOSetValue { offset, src } => {
+                let (src, field) = (self.slot(base, *src), *offset / size_of::<RtValue>() * self.width);
+                for &lane in lanes.iter() {
+                    *self.io.get_unchecked_mut(field + lane as usize) = *self.values.get_unchecked(src + lane as usize);
+                }
+            },
+            Insc::IOGetValue { offset, dst } => {
+                let (dst, field) = (self.slot(base, *dst), *offset / size_of::<RtValue>() * self.width);
+                for &lane in lanes.iter() {
+                    *self.values.get_unchecked_mut(dst + lane as usize) = *self.io.get_unchecked(field + lane as usize);
+                }
+            },
+            Insc::CallFFI { func, args, ret_locs } => {
+                let native = compiled.ffi.get_unchecked(*func);
+                self.in_buf.resize(args.len());
+                self.out_buf.resize(ret_locs.len());
+
+                let mut returned = self.take_list();
+                for &lane in group.lanes.iter() {
+                    let lane = lane as usize;
+                    for (i, arg) in args.iter().enumerate() {
+                        *self.in_buf.get_unchecked_mut(i) = *self.values.get_unchecked(self.slot(base, *arg) + lane);
+                    }
+
+                    match (native.func)(&self.in_buf[..args.len()], &mut self.out_buf[..ret_locs.len()]) {
+                        Ok(NativeStatus::Returned) => {
+                            for (i, ret_loc) in ret_locs.iter().enumerate() {
+                                let dst = self.slot(base, *ret_loc) + lane;
+                                *self.values.get_unchecked_mut(dst) = *self.out_buf.get_unchecked(i);
+                            }
+                            returned.push(lane as u32);
+                        },
+                        Ok(NativeStatus::Suspended) => {
+                            let depth = self.frames.len();
+                            self.detach(lane, depth, group.pc + 1, Some((native, ret_locs)), ctxs);
+                        },
+                        Err(message) => self.raise(compiled, lane, group.pc, Some(&native.name), message, ctxs)
+                    }
+                }
+
+                std::mem::swap(&mut group.lanes, &mut returned);
+                self.spare_lists.push(returned);
+                if group.lanes.is_empty() {
+                    self.spare_lists.push(group.lanes);
+                    return;
+                }
+            },
+            Insc::TryBegin { handler } => {
+                let depth = self.frames.len();
+                for &lane in lanes.iter() {
+                    self.lanes.get_unchecked_mut(lane as usize).handlers.push((depth, *handler));
+                }
+            },
+            Insc::TryEnd => {
+                for &lane in lanes.iter() {
+                    self.lanes.get_unchecked_mut(lane as usize).handlers.pop();
+                }
+            },
+            Insc::Spawn { .. } | Insc::Join { .. } => {
+                // tasks are only run by the scalar `Combustor`
+                let depth = self.frames.len();
+                for &lane in lanes.iter() {
+                    self.detach(lane as usize, depth, group.pc, None, ctxs);
+                }
+                self.spare_lists.push(group.lanes);
+                return;
+            },
+            Insc::Yield { values } => {
+                for &lane in lanes.iter() {
+                    for value in values.iter() {
+                        let value = *self.values.get_unchecked(self.slot(base, *value) + lane as usize);
+                        self.lanes.get_unchecked_mut(lane as usize).yielded.push(value);
+                    }
+                }
+                group.pc += 1;
+                let depth = self.frames.len();
+                insert_group(self.yielded.get_unchecked_mut(depth - 1), group, &mut self.spare_lists);
+                return;
+            }
+        }
+
+        group.pc += 1;
+        insert_group(&mut self.groups, group, &mut self.spare_lists);
+    }
+
+    /// Runs `DivInt` or `ModInt` with `op`, removing the lanes for which it has no result from
+    /// `group` and raising an error in them
+    unsafe fn int_division<CTX: IOContext>(
+        &mut self,
+        compiled: &'a Compiled,
+        group: &mut Group,
+        base: usize,
+        [lhs, rhs, dst]: [usize; 3],
+        op: fn(i32, i32) -> Option<i32>,
+        ctxs: &mut [CTX]
+    ) {
+        let (lhs, rhs, dst) = (self.slot(base, lhs), self.slot(base, rhs), self.slot(base, dst));
+        let mut faulted = self.take_list();
+        group.lanes.retain(|&lane| {
+            let lane = lane as usize;
+            let value = op(self.values.get_unchecked(lhs + lane).i, self.values.get_unchecked(rhs + lane).i);
+            if let Some(value) = value {
+                *self.values.get_unchecked_mut(dst + lane) = RtValue::from(value);
+            } else {
+                faulted.push(lane as u32);
+            }
+            value.is_some()
+        });
+
+        for &lane in faulted.iter() {
+            let rhs = self.values.get_unchecked(rhs + lane as usize).i;
+            self.raise(compiled, lane as usize, group.pc, None, int_division_fault(rhs), ctxs);
+        }
+        self.spare_lists.push(faulted);
+    }
+
+    /// Continues a lane which raised an error at `pc` in its innermost `try` handler, or faults it
+    /// if there is none. `native` is the native which failed, if any.
+    unsafe fn raise<CTX: IOContext>(
+        &mut self,
+        compiled: &'a Compiled,
+        lane: usize,
+        pc: usize,
+        native: Option<&str>,
+        message: String,
+        ctxs: &mut [CTX]
+    ) {
+        match self.lanes.get_unchecked_mut(lane).handlers.pop() {
+            Some((depth, handler)) if depth == self.frames.len() => {
+                let mut lanes = self.take_list();
+                lanes.push(lane as u32);
+                insert_group(&mut self.groups, Group { pc: handler, lanes }, &mut self.spare_lists);
+            },
+            Some((depth, handler)) => self.detach(lane, depth, handler, None, ctxs),
+            None => {
+                let lane = self.lanes.get_unchecked_mut(lane);
+                lane.status = CoroutineStatus::Faulted;
+                let positions = self.frames.iter()
+                    .skip(1)
+                    .map(|frame| frame.ret_addr())
+                    .chain([pc])
+                    .collect::<Vec<_>>();
+                lane.error = Some(RuntimeError::new(compiled, native, message, &positions));
+            }
+        }
+    }
+
+    /// Moves a lane out of batched execution, with the first `depth` frames and continuing at
+    /// `resume_ptr`
+    unsafe fn detach<CTX: IOContext>(
+        &mut self,
+        lane: usize,
+        depth: usize,
+        resume_ptr: usize,
+        suspended: Option<(&'a NativeFunction, &'a [usize])>,
+        ctxs: &mut [CTX]
+    ) {
+        let frames = &self.frames[..depth];
+        let stack_size = frames.last().unwrap_unchecked().end_idx();
+        let values = (0..stack_size)
+            .map(|slot| *self.values.get_unchecked(slot * self.width + lane))
+            .collect::<Vec<_>>();
+
+        let ctx = &mut ctxs[lane] as *mut CTX as *mut RtValue;
+        for field in 0..size_of::<CTX>() / size_of::<RtValue>() {
+            ctx.add(field).write(*self.io.get_unchecked(field * self.width + lane));
+        }
+
+        let lane = self.lanes.get_unchecked_mut(lane);
+        let state = CombustorState::from_stack(&values, frames, &lane.handlers, suspended);
+        lane.detached = Some((state, resume_ptr));
+        lane.pending = suspended.is_none();
+    }
+
+    #[inline(always)]
+    fn slot(&self, base: usize, idx: usize) -> usize {
+        (base + idx) * self.width
+    }
+
+    fn take_list(&mut self) -> Vec<u32> {
+        let mut list = self.spare_lists.pop().unwrap_or_default();
+        list.clear();
+        list
+    }
+}
+
+impl Default for BatchCombustor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adds a group to `groups` sorted by descending address, merging it with a group at the same
+/// address
+fn insert_group(groups: &mut Vec<Group>, mut group: Group, spare_lists: &mut Vec<Vec<u32>>) {
+    match groups.binary_search_by(|other| group.pc.cmp(&other.pc)) {
+        Ok(idx) => {
+            groups[idx].lanes.append(&mut group.lanes);
+            spare_lists.push(group.lanes);
+        },
+        Err(idx) => groups.insert(idx, group)
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use crate::native::{NativeImport, NativeSignature};
+use crate::r25_300::compiled::{Compiled, Function};
+use crate::r25_300::insc::Insc;
+use crate::r25_300::verify::verify;
+use crate::value::RtValue;
+
+/// A position in the code of the function being built, which may be used before it is bound
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Label(usize);
+
+/// Builds a `Compiled` function by function without computing addresses by hand. Functions are
+/// referred to by the index `declare` returns, so they can be called before they are built.
+pub struct CompiledBuilder {
+    compiled: Compiled,
+    func_ids: HashMap<String, usize>,
+    defined: Vec<bool>
+}
+
+impl CompiledBuilder {
+    pub fn new() -> Self {
+        Self {
+            compiled: Compiled::new(),
+            func_ids: HashMap::new(),
+            defined: Vec::new()
+        }
+    }
+
+    /// Declares a function taking `params` slots and returning `rets` slots, or returns the index
+    /// of the function already declared with this name
+    pub fn declare(&mut self, name: &str, params: usize, rets: usize) -> Result<usize, String> {
+        if let Some(func_id) = self.func_ids.get(name) {
+            let func = &self.compiled.func[*func_id];
+            if func.params != params || func.rets != rets {
+                return Err(format!("函数 `{}` 的声明不一致", name));
+            }
+            return Ok(*func_id);
+        }
+
+        self.compiled.func.push(Function {
+            name: name.to_string(),
+            addr: 0,
+            frame_size: 0,
+            code_len: 0,
+            params,
+            rets
+        });
+        self.defined.push(false);
+        self.func_ids.insert(name.to_string(), self.compiled.func.len() - 1);
+        Ok(self.compiled.func.len() - 1)
+    }
+
+    /// Declares a native, or returns the index of the same native declared before
+    pub fn import(&mut self, name: &str, signature: NativeSignature) -> usize {
+        if let Some(import_id) = self.compiled.imports.iter()
+            .position(|import| import.name == name && import.signature == signature) {
+            return import_id;
+        }
+
+        self.compiled.imports.push(NativeImport { name: name.to_string(), signature });
+        self.compiled.imports.len() - 1
+    }
+
+    /// Starts the body of a function, declaring it if needed. Parameters are in slots
+    /// `0..params`; the body is added to the code when the returned builder is finished.
+    pub fn function(&mut self, name: &str, params: usize, rets: usize) -> Result<FunctionBuilder<'_>, String> {
+        let func_id = self.declare(name, params, rets)?;
+        if self.defined[func_id] {
+            return Err(format!("重复的函数定义 `{}`", name));
+        }
+
+        Ok(FunctionBuilder {
+            builder: self,
+            func_id,
+            code: Vec::new(),
+            labels: Vec::new(),
+            next_slot: params,
+            constants: HashMap::new()
+        })
+    }
+
+    /// Checks that every declared function was built and verifies the result, which is not
+    /// linked yet
+    pub fn finish(self) -> Result<Compiled, String> {
+        if let Some(func_id) = self.defined.iter().position(|defined| !defined) {
+            return Err(format!("函数 `{}` 只有声明而没有定义", self.compiled.func[func_id].name));
+        }
+
+        verify(&self.compiled, None)?;
+        Ok(self.compiled)
+    }
+}
+
+impl Default for CompiledBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the body of one function. Jump targets are labels, and the frame size is the number of
+/// slots used. Constants requested with `constant` are loaded once at the start of the function,
+/// so their slots must not be written to.
+pub struct FunctionBuilder<'a> {
+    builder: &'a mut CompiledBuilder,
+    func_id: usize,
+    code: Vec<Insc>,
+    // the body-relative address each label is bound to
+    labels: Vec<Option<usize>>,
+    next_slot: usize,
+    constants: HashMap<RtValue, usize>
+}
+
+impl FunctionBuilder<'_> {
+    pub fn func_id(&self) -> usize {
+        self.func_id
+    }
+
+    /// A fresh slot
+    pub fn alloc(&mut self) -> usize {
+        self.next_slot += 1;
+        self.next_slot - 1
+    }
+
+    /// `count` consecutive fresh slots, returning the first one
+    pub fn alloc_n(&mut self, count: usize) -> usize {
+        self.next_slot += count;
+        self.next_slot - count
+    }
+
+    /// A slot holding `value`, shared by all uses of the same value
+    pub fn constant(&mut self, value: impl Into<RtValue>) -> usize {
+        let value = value.into();
+        if let Some(slot) = self.constants.get(&value) {
+            return *slot;
+        }
+
+        let slot = self.alloc();
+        self.constants.insert(value, slot);
+        slot
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Makes `label` refer to the next instruction
+    pub fn bind(&mut self, label: Label) -> Result<(), String> {
+        match self.labels.get_mut(label.0) {
+            Some(bound @ None) => {
+                *bound = Some(self.code.len());
+                Ok(())
+            },
+            Some(Some(_)) => Err(format!("标签 {} 已经绑定过", label.0)),
+            None => Err(format!("标签 {} 不属于这个函数", label.0))
+        }
+    }
+
+    /// Adds an instruction. Targets of `Jmp`, `JmpIf` and `TryBegin` are taken as labels, so
+    /// those should be added with `jmp`, `jmp_if` and `try_begin`.
+    pub fn emit(&mut self, insc: Insc) {
+        self.code.push(insc);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.emit(Insc::Jmp { dst: label.0 });
+    }
+
+    pub fn jmp_if(&mut self, check: usize, label: Label) {
+        self.emit(Insc::JmpIf { check, dst: label.0 });
+    }
+
+    pub fn try_begin(&mut self, handler: Label) {
+        self.emit(Insc::TryBegin { handler: handler.0 });
+    }
+
+    pub fn call(&mut self, func: usize, args: &[usize], ret_locs: &[usize]) {
+        self.emit(Insc::Call { func, args: args.into(), ret_locs: ret_locs.into() });
+    }
+
+    pub fn call_ffi(&mut self, import: usize, args: &[usize], ret_locs: &[usize]) {
+        self.emit(Insc::CallFFI { func: import, args: args.into(), ret_locs: ret_locs.into() });
+    }
+
+    pub fn ret(&mut self, rets: &[usize]) {
+        self.emit(Insc::Return { rets: rets.into() });
+    }
+
+    /// Adds the function to the code. A function which does not end with a jump or a return
+    /// returns without values at the end.
+    pub fn finish(mut self) -> Result<(), String> {
+        if !matches!(self.code.last(), Some(Insc::Jmp { .. } | Insc::Return { .. })) {
+            self.ret(&[]);
+        }
+
+        let mut constants = self.constants.into_iter().collect::<Vec<_>>();
+        constants.sort_by_key(|(_, slot)| *slot);
+
+        let compiled = &mut self.builder.compiled;
+        let addr = compiled.code.len();
+        let body_addr = addr + constants.len();
+        let mut frame_size = self.next_slot;
+        for insc in self.code.iter_mut() {
+            if let Insc::Jmp { dst } | Insc::JmpIf { dst, .. } | Insc::TryBegin { handler: dst } = insc {
+                let Some(Some(target)) = self.labels.get(*dst) else {
+                    return Err(format!("函数 `{}` 中的标签 {} 没有绑定", compiled.func[self.func_id].name, dst));
+                };
+                *dst = body_addr + target;
+            }
+            insc.for_each_slot(|slot| frame_size = frame_size.max(slot + 1));
+        }
+
+        compiled.code.extend(constants.into_iter().map(|(value, dst)| Insc::Const { value, dst }));
+        compiled.code.extend(self.code);
+
+        let func = &mut compiled.func[self.func_id];
+        func.addr = addr;
+        func.code_len = compiled.code.len() - addr;
+        func.frame_size = frame_size;
+        self.builder.defined[self.func_id] = true;
+        Ok(())
+    }
+}
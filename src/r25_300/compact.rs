@@ -0,0 +1,212 @@
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::insc::Insc;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::value::RtValue;
+#[cfg(test)] use variant_count::VariantCount;
+
+/// `Insc` with 16-bit slots and function indices and 32-bit addresses and offsets, 8 bytes each.
+/// Operands which do not fit are kept in side tables of `CompactCode`: those of `Math3` in
+/// `math3`, and slot lists in `lists`.
+#[cfg_attr(test, derive(VariantCount))]
+#[derive(Debug, Clone, Copy)]
+pub enum CompactInsc {
+    Const { dst: u16, value: RtValue },
+    Dup { src: u16, dst: u16 },
+
+    AddInt { lhs: u16, rhs: u16, dst: u16 },
+    AddFloat { lhs: u16, rhs: u16, dst: u16 },
+    SubInt { lhs: u16, rhs: u16, dst: u16 },
+    SubFloat { lhs: u16, rhs: u16, dst: u16 },
+    MulInt { lhs: u16, rhs: u16, dst: u16 },
+    MulFloat { lhs: u16, rhs: u16, dst: u16 },
+    DivInt { lhs: u16, rhs: u16, dst: u16 },
+    DivFloat { lhs: u16, rhs: u16, dst: u16 },
+    ModInt { lhs: u16, rhs: u16, dst: u16 },
+
+    NegateInt { src: u16, dst: u16 },
+    NegateFloat { src: u16, dst: u16 },
+
+    Eq { lhs: u16, rhs: u16, dst: u16 },
+    Ne { lhs: u16, rhs: u16, dst: u16 },
+
+    LtInt { lhs: u16, rhs: u16, dst: u16 },
+    LtFloat { lhs: u16, rhs: u16, dst: u16 },
+    LeInt { lhs: u16, rhs: u16, dst: u16 },
+    LeFloat { lhs: u16, rhs: u16, dst: u16 },
+
+    And { lhs: u16, rhs: u16, dst: u16 },
+    Or { lhs: u16, rhs: u16, dst: u16 },
+    Not { src: u16, dst: u16 },
+
+    Round { src: u16, dst: u16 },
+    Floor { src: u16, dst: u16 },
+    Ceil { src: u16, dst: u16 },
+    ToFloat { src: u16, dst: u16 },
+    Float2Int { src: u16, dst: u16 },
+    Round2Int { src: u16, dst: u16 },
+    Floor2Int { src: u16, dst: u16 },
+    Ceil2Int { src: u16, dst: u16 },
+
+    Bool2Int { src: u16, dst: u16 },
+    Int2Bool { src: u16, dst: u16 },
+    Bool2Float { src: u16, dst: u16 },
+    Float2Bool { src: u16, dst: u16 },
+
+    Math1 { op: MathOp1, src: u16, dst: u16 },
+    Math2 { op: MathOp2, lhs: u16, rhs: u16, dst: u16 },
+    // `operands` indexes `CompactCode::math3`
+    Math3 { op: MathOp3, operands: u32 },
+
+    Jmp { dst: u32 },
+    JmpIf { check: u16, dst: u32 },
+    // `lists` indexes the arguments in `CompactCode::lists`, followed by the return locations
+    Call { func: u16, lists: u32 },
+    Return { rets: u32 },
+
+    IOSetValue { src: u16, offset: u32 },
+    IOGetValue { dst: u16, offset: u32 },
+    CallFFI { func: u16, lists: u32 },
+    TryBegin { handler: u32 },
+    TryEnd,
+    // the arguments, followed by a list of the one slot receiving the task id
+    Spawn { func: u16, lists: u32 },
+    Join { task: u16 },
+
+    Yield { values: u32 }
+}
+
+/// `Compiled::code` lowered to `CompactInsc`. Addresses are unchanged, so `code[i]` does what
+/// `Compiled::code[i]` does, and the two can be used in turns on the same stack.
+#[derive(Debug, Clone)]
+pub struct CompactCode {
+    pub code: Vec<CompactInsc>,
+    // `[a, b, c, dst]` of `Math3`
+    pub math3: Vec<[u16; 4]>,
+    // slot lists of calls, returns and yields, each stored as its length followed by the slots
+    pub lists: Vec<u16>
+}
+
+impl CompactCode {
+    /// Lowers the code of `compiled`, or returns `None` if some operand does not fit
+    pub fn lower(compiled: &Compiled) -> Option<Self> {
+        let mut compact = Self {
+            code: Vec::with_capacity(compiled.code.len()),
+            math3: Vec::new(),
+            lists: Vec::new()
+        };
+        for insc in compiled.code.iter() {
+            let insc = compact.lower_insc(insc)?;
+            compact.code.push(insc);
+        }
+        Some(compact)
+    }
+
+    /// The list at `start` in `lists`, and the start of the list following it
+    ///
+    /// # Safety
+    /// `start` must be the start of a list.
+    #[inline(always)]
+    pub unsafe fn list(&self, start: u32) -> (&[u16], u32) {
+        let start = start as usize;
+        let len = *self.lists.get_unchecked(start) as usize;
+        (self.lists.get_unchecked(start + 1..start + 1 + len), (start + 1 + len) as u32)
+    }
+
+    fn push_lists(&mut self, lists: &[&[usize]]) -> Option<u32> {
+        let start = u32::try_from(self.lists.len()).ok()?;
+        for list in lists {
+            self.lists.push(u16::try_from(list.len()).ok()?);
+            for slot in list.iter() {
+                self.lists.push(u16::try_from(*slot).ok()?);
+            }
+        }
+        Some(start)
+    }
+
+    fn lower_insc(&mut self, insc: &Insc) -> Option<CompactInsc> {
+        let s = |slot: &usize| u16::try_from(*slot).ok();
+        let w = |value: &usize| u32::try_from(*value).ok();
+
+        Some(match insc {
+            Insc::Const { value, dst } => CompactInsc::Const { dst: s(dst)?, value: *value },
+            Insc::Dup { src, dst } => CompactInsc::Dup { src: s(src)?, dst: s(dst)? },
+
+            Insc::AddInt { lhs, rhs, dst } => CompactInsc::AddInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::AddFloat { lhs, rhs, dst } => CompactInsc::AddFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::SubInt { lhs, rhs, dst } => CompactInsc::SubInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::SubFloat { lhs, rhs, dst } => CompactInsc::SubFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::MulInt { lhs, rhs, dst } => CompactInsc::MulInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::MulFloat { lhs, rhs, dst } => CompactInsc::MulFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::DivInt { lhs, rhs, dst } => CompactInsc::DivInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::DivFloat { lhs, rhs, dst } => CompactInsc::DivFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::ModInt { lhs, rhs, dst } => CompactInsc::ModInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+
+            Insc::NegateInt { src, dst } => CompactInsc::NegateInt { src: s(src)?, dst: s(dst)? },
+            Insc::NegateFloat { src, dst } => CompactInsc::NegateFloat { src: s(src)?, dst: s(dst)? },
+
+            Insc::Eq { lhs, rhs, dst } => CompactInsc::Eq { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::Ne { lhs, rhs, dst } => CompactInsc::Ne { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+
+            Insc::LtInt { lhs, rhs, dst } => CompactInsc::LtInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::LtFloat { lhs, rhs, dst } => CompactInsc::LtFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::LeInt { lhs, rhs, dst } => CompactInsc::LeInt { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::LeFloat { lhs, rhs, dst } => CompactInsc::LeFloat { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+
+            Insc::And { lhs, rhs, dst } => CompactInsc::And { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::Or { lhs, rhs, dst } => CompactInsc::Or { lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::Not { src, dst } => CompactInsc::Not { src: s(src)?, dst: s(dst)? },
+
+            Insc::Round { src, dst } => CompactInsc::Round { src: s(src)?, dst: s(dst)? },
+            Insc::Floor { src, dst } => CompactInsc::Floor { src: s(src)?, dst: s(dst)? },
+            Insc::Ceil { src, dst } => CompactInsc::Ceil { src: s(src)?, dst: s(dst)? },
+            Insc::ToFloat { src, dst } => CompactInsc::ToFloat { src: s(src)?, dst: s(dst)? },
+            Insc::Float2Int { src, dst } => CompactInsc::Float2Int { src: s(src)?, dst: s(dst)? },
+            Insc::Round2Int { src, dst } => CompactInsc::Round2Int { src: s(src)?, dst: s(dst)? },
+            Insc::Floor2Int { src, dst } => CompactInsc::Floor2Int { src: s(src)?, dst: s(dst)? },
+            Insc::Ceil2Int { src, dst } => CompactInsc::Ceil2Int { src: s(src)?, dst: s(dst)? },
+
+            Insc::Bool2Int { src, dst } => CompactInsc::Bool2Int { src: s(src)?, dst: s(dst)? },
+            Insc::Int2Bool { src, dst } => CompactInsc::Int2Bool { src: s(src)?, dst: s(dst)? },
+            Insc::Bool2Float { src, dst } => CompactInsc::Bool2Float { src: s(src)?, dst: s(dst)? },
+            Insc::Float2Bool { src, dst } => CompactInsc::Float2Bool { src: s(src)?, dst: s(dst)? },
+
+            Insc::Math1 { op, src, dst } => CompactInsc::Math1 { op: *op, src: s(src)?, dst: s(dst)? },
+            Insc::Math2 { op, lhs, rhs, dst } =>
+                CompactInsc::Math2 { op: *op, lhs: s(lhs)?, rhs: s(rhs)?, dst: s(dst)? },
+            Insc::Math3 { op, a, b, c, dst } => {
+                self.math3.push([s(a)?, s(b)?, s(c)?, s(dst)?]);
+                CompactInsc::Math3 { op: *op, operands: w(&(self.math3.len() - 1))? }
+            },
+
+            Insc::Jmp { dst } => CompactInsc::Jmp { dst: w(dst)? },
+            Insc::JmpIf { check, dst } => CompactInsc::JmpIf { check: s(check)?, dst: w(dst)? },
+            Insc::Call { func, args, ret_locs } =>
+                CompactInsc::Call { func: s(func)?, lists: self.push_lists(&[&args[..], &ret_locs[..]])? },
+            Insc::Return { rets } => CompactInsc::Return { rets: self.push_lists(&[&rets[..]])? },
+
+            Insc::IOSetValue { offset, src } => CompactInsc::IOSetValue { src: s(src)?, offset: w(offset)? },
+            Insc::IOGetValue { offset, dst } => CompactInsc::IOGetValue { dst: s(dst)?, offset: w(offset)? },
+            Insc::CallFFI { func, args, ret_locs } =>
+                CompactInsc::CallFFI { func: s(func)?, lists: self.push_lists(&[&args[..], &ret_locs[..]])? },
+            Insc::TryBegin { handler } => CompactInsc::TryBegin { handler: w(handler)? },
+            Insc::TryEnd => CompactInsc::TryEnd,
+            Insc::Spawn { func, args, dst } =>
+                CompactInsc::Spawn { func: s(func)?, lists: self.push_lists(&[&args[..], &[*dst]])? },
+            Insc::Join { task } => CompactInsc::Join { task: s(task)? },
+
+            Insc::Yield { values } => CompactInsc::Yield { values: self.push_lists(&[&values[..]])? }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::r25_300::compact::CompactInsc;
+    use crate::r25_300::insc::Insc;
+
+    #[test]
+    fn test() {
+        assert_eq!(CompactInsc::VARIANT_COUNT, Insc::VARIANT_COUNT);
+        assert_eq!(std::mem::size_of::<CompactInsc>(), 8);
+    }
+}
@@ -1,20 +1,32 @@
 use std::fmt::{Display, Formatter};
-use crate::value::RawFunction;
+use crate::native::{NativeFunction, NativeImport, NativeRegistry};
+use crate::r25_300::asm::disassemble;
+use crate::r25_300::debug::DebugInfo;
 use crate::r25_300::insc::Insc;
+use crate::r25_300::p21c::write_code;
 
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: String,
     pub addr: usize,
     pub frame_size: usize,
-    pub code_len: usize
+    pub code_len: usize,
+    // number of slots taken by the parameters and by the return values
+    pub params: usize,
+    pub rets: usize
 }
 
-#[derive(Debug, Clone)]
+/// Bytecode of a program. It is `Send + Sync`, natives in `ffi` included, so one `Compiled` can
+/// be shared by instances running on many threads.
+#[derive(Debug, Clone, Default)]
 pub struct Compiled {
     pub code: Vec<Insc>,
     pub func: Vec<Function>,
-    pub ffi: Vec<RawFunction>
+    pub imports: Vec<NativeImport>,
+    // `ffi[i]` is the native bound to `imports[i]`, empty until linked
+    pub ffi: Vec<NativeFunction>,
+    // source positions and variable names, if compiled from source
+    pub debug: Option<DebugInfo>
 }
 
 impl Compiled {
@@ -22,20 +34,63 @@ impl Compiled {
         Self {
             code: Vec::new(),
             func: Vec::new(),
-            ffi: Vec::new()
+            imports: Vec::new(),
+            ffi: Vec::new(),
+            debug: None
         }
     }
-}
 
-impl Display for Compiled {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for (idx, func) in self.func.iter().enumerate() {
-            writeln!(f, "{} ({}):", func.name, idx)?;
-            for insc in self.code[func.addr..func.addr + func.code_len].iter() {
-                writeln!(f, "  {}", insc)?;
+    pub fn find_func(&self, name: &str) -> Option<usize> {
+        self.func.iter().position(|func| func.name == name)
+    }
+
+    /// The function whose code contains the instruction at `insc_ptr`
+    pub fn func_at(&self, insc_ptr: usize) -> Option<&Function> {
+        self.func.iter().find(|func| func.addr <= insc_ptr && insc_ptr < func.addr + func.code_len)
+    }
+
+    /// Binds every import to the native in `natives` with the same name and signature, replacing
+    /// previous bindings. Nothing is changed if any import cannot be bound.
+    pub fn link(&mut self, natives: &NativeRegistry) -> Result<(), String> {
+        let mut ffi = Vec::with_capacity(self.imports.len());
+        for import in self.imports.iter() {
+            let overloads = natives.overloads(&import.name).collect::<Vec<_>>();
+            if overloads.is_empty() {
+                return Err(format!("没有找到宿主函数 `{}`", import.name));
             }
+
+            let Some(native) = overloads.iter().find(|native| native.signature == import.signature) else {
+                return Err(format!(
+                    "宿主函数 `{}` 的签名 {} 与脚本中的声明 {} 不一致",
+                    import.name,
+                    overloads.iter().map(|native| native.signature.to_string()).collect::<Vec<_>>().join("、"),
+                    import.signature
+                ));
+            };
+            ffi.push((*native).clone());
         }
 
+        self.ffi = ffi;
         Ok(())
     }
+
+    pub fn is_linked(&self) -> bool {
+        self.ffi.len() == self.imports.len()
+    }
+
+    /// A hash of the code, functions and imports as encoded in a `.p21c` file, so it is stable
+    /// across runs and platforms. Snapshots use it to detect being restored against different
+    /// bytecode.
+    pub fn code_hash(&self) -> u64 {
+        // FNV-1a
+        write_code(self)
+            .into_iter()
+            .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+}
+
+impl Display for Compiled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&disassemble(self, &Vec::new()))
+    }
 }
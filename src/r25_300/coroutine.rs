@@ -0,0 +1,191 @@
+use crate::io_ctx::IOContext;
+use crate::native::{NativeFunction, NativeReturn};
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::cumbustor::{Combustor, Engine};
+use crate::r25_300::debug::{FrameInfo, LocalVar};
+use crate::r25_300::debugger::Debugger;
+use crate::r25_300::error::RuntimeError;
+use crate::r25_300::profile::Profiler;
+use crate::r25_300::snapshot::Snapshot;
+use crate::value::RtValue;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CoroutineStatus {
+    NotStarted,
+    // stopped at a `yield` or a suspending native
+    Suspended,
+    // returned from the entry function, or cancelled
+    Finished,
+    // stopped by an uncaught native error
+    Faulted
+}
+
+/// A script function run as a coroutine. A finished, faulted or cancelled coroutine can be
+/// `reset` and run again without reallocating its stack.
+pub struct Coroutine<'a, 'ctx, CTX: IOContext> {
+    combustor: Combustor<'a, 'ctx, CTX>,
+    compiled: &'a Compiled,
+    entry: usize,
+    status: CoroutineStatus,
+    resume_ptr: usize
+}
+
+impl<'a, 'ctx, CTX> Coroutine<'a, 'ctx, CTX>
+    where CTX: IOContext
+{
+    /// # Safety
+    /// `compiled` must be well-formed and linked, as for `Combustor::combust`.
+    pub unsafe fn new(compiled: &'a Compiled, entry: usize, io_ctx: &'ctx mut CTX) -> Self {
+        assert!(entry < compiled.func.len(), "entry function {} does not exist", entry);
+
+        Self {
+            combustor: Combustor::new(io_ctx),
+            compiled,
+            entry,
+            status: CoroutineStatus::NotStarted,
+            resume_ptr: 0
+        }
+    }
+
+    /// Rebuilds a suspended coroutine from a snapshot taken against the same bytecode
+    ///
+    /// # Safety
+    /// `compiled` must be well-formed and linked, as for `Coroutine::new`.
+    pub unsafe fn restore(
+        compiled: &'a Compiled,
+        io_ctx: &'ctx mut CTX,
+        snapshot: &Snapshot
+    ) -> Result<Self, String> {
+        let mut combustor = Combustor::new(io_ctx);
+        combustor.restore(compiled, snapshot)?;
+
+        Ok(Self {
+            combustor,
+            compiled,
+            entry: snapshot.entry,
+            status: CoroutineStatus::Suspended,
+            resume_ptr: snapshot.resume_ptr
+        })
+    }
+
+    /// Only a suspended coroutine has a state worth saving. Spawned tasks cannot be captured, so
+    /// there is no snapshot while any of them is running.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        if self.status != CoroutineStatus::Suspended || self.combustor.task_count() != 0 {
+            return None;
+        }
+        Some(self.combustor.snapshot(self.compiled, self.entry, self.resume_ptr))
+    }
+
+    /// See `Combustor::set_engine`
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.combustor.set_engine(engine);
+    }
+
+    /// See `Combustor::set_debugger`. A coroutine stopped by the debugger is `Suspended`, and
+    /// resuming continues where it stopped.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.combustor.set_debugger(debugger);
+    }
+
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.combustor.debugger()
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.combustor.debugger_mut()
+    }
+
+    /// See `Combustor::set_profiler`
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.combustor.set_profiler(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.combustor.profiler()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.combustor.profiler_mut()
+    }
+
+    /// See `Combustor::paused_frames`
+    pub fn paused_frames(&self) -> Vec<FrameInfo> {
+        self.combustor.paused_frames(self.compiled)
+    }
+
+    /// See `Combustor::paused_locals`
+    pub fn paused_locals(&self, frame: usize) -> Vec<(&'a LocalVar, RtValue)> {
+        self.combustor.paused_locals(self.compiled, frame)
+    }
+
+    pub fn status(&self) -> CoroutineStatus {
+        self.status
+    }
+
+    pub fn io_ctx(&mut self) -> &mut CTX {
+        self.combustor.io_ctx
+    }
+
+    /// Values passed to the last `yield`, see `Combustor::yielded`
+    pub fn yielded(&self) -> &[RtValue] {
+        self.combustor.yielded()
+    }
+
+    pub fn suspended_native(&self) -> Option<&'a NativeFunction> {
+        self.combustor.suspended_native()
+    }
+
+    pub fn finish_native<R: NativeReturn>(&mut self, rets: R) -> Result<(), String> {
+        self.combustor.finish_native(rets)
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.combustor.task_count()
+    }
+
+    pub fn suspended_tasks(&self) -> impl Iterator<Item = (i32, &'a NativeFunction)> + '_ {
+        self.combustor.suspended_tasks()
+    }
+
+    pub fn finish_task_native<R: NativeReturn>(&mut self, task_id: i32, rets: R) -> Result<(), String> {
+        self.combustor.finish_task_native(task_id, rets)
+    }
+
+    /// Starts or continues the script until it suspends, finishes or faults. Resuming a finished
+    /// or faulted coroutine does nothing.
+    pub fn resume(&mut self) -> Result<CoroutineStatus, RuntimeError> {
+        let result = match self.status {
+            CoroutineStatus::NotStarted => unsafe { self.combustor.combust(self.compiled, self.entry) },
+            CoroutineStatus::Suspended => unsafe { self.combustor.combust_resume(self.compiled, self.resume_ptr) },
+            CoroutineStatus::Finished | CoroutineStatus::Faulted => return Ok(self.status)
+        };
+
+        match result {
+            Ok(Some(resume_ptr)) => {
+                self.resume_ptr = resume_ptr;
+                self.status = CoroutineStatus::Suspended;
+            },
+            Ok(None) => self.status = CoroutineStatus::Finished,
+            Err(err) => {
+                self.status = CoroutineStatus::Faulted;
+                return Err(err);
+            }
+        }
+        Ok(self.status)
+    }
+
+    /// Abandons a suspended script, unwinding all of its frames
+    pub fn cancel(&mut self) {
+        if self.status == CoroutineStatus::Suspended {
+            self.combustor.reset();
+            self.status = CoroutineStatus::Finished;
+        }
+    }
+
+    /// Makes the coroutine ready to run from the start again
+    pub fn reset(&mut self) {
+        self.combustor.reset();
+        self.status = CoroutineStatus::NotStarted;
+    }
+}
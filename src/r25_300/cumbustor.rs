@@ -1,16 +1,101 @@
-use xjbutil::zvec::ZeroVec;
+use std::hint::unreachable_unchecked;
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
+use smallvec::SmallVec;
+
 use crate::io_ctx::IOContext;
-use crate::r25_300::compiled::Compiled;
+use crate::native::{NativeFunction, NativeReturn, NativeStatus};
+use crate::r25_300::compact::{CompactCode, CompactInsc};
+use crate::r25_300::compiled::{Compiled, Function};
+use crate::r25_300::debug::{FrameInfo, LocalVar};
+use crate::r25_300::debugger::Debugger;
+use crate::r25_300::decoded::{DecodedCode, DecodedInsc, MAX_FUSED};
+use crate::r25_300::error::{int_division_fault, RuntimeError};
 use crate::r25_300::insc::Insc;
-use crate::r25_300::stack::Stack;
-use crate::value::RtValue;
+use crate::r25_300::profile::Profiler;
+use crate::r25_300::snapshot::{FrameSnapshot, Snapshot};
+use crate::r25_300::stack::{Stack, StackFrame, ZeroBuf};
+use crate::value::{
+    bool_to_float,
+    float_ceil_to_int,
+    float_floor_to_int,
+    float_round_to_int,
+    float_to_bool,
+    float_to_int,
+    RtValue
+};
+
+// slots are `usize` in `Insc`, `u16` in `CompactInsc` and `u16` byte offsets in `DecodedInsc`,
+// addresses and offsets `usize` and `u32`
+trait Operand {
+    fn idx(self) -> usize;
+}
+
+impl Operand for &usize {
+    #[inline(always)]
+    fn idx(self) -> usize { *self }
+}
+
+impl Operand for &u16 {
+    #[inline(always)]
+    fn idx(self) -> usize { *self as usize }
+}
+
+impl Operand for &u32 {
+    #[inline(always)]
+    fn idx(self) -> usize { *self as usize }
+}
+
+// slot 0 of the current frame, which the slots of `DecodedInsc` are byte offsets from. It takes
+// the stack like `StackFrame` so that the engines share `impl_dispatch`.
+#[derive(Clone, Copy)]
+struct FrameBase(*mut u8);
+
+impl FrameBase {
+    #[inline(always)]
+    unsafe fn new(stack: &mut Stack, frame: &StackFrame) -> Self {
+        Self(frame.base_ptr(stack) as *mut u8)
+    }
+
+    #[inline(always)]
+    unsafe fn get_value(self, _stack: &Stack, offset: usize) -> RtValue {
+        (self.0.add(offset) as *const RtValue).read()
+    }
+
+    #[inline(always)]
+    unsafe fn set_value(self, _stack: &mut Stack, offset: usize, value: RtValue) {
+        (self.0.add(offset) as *mut RtValue).write(value);
+    }
+}
 
 macro_rules! impl_binop {
     ($f:ident, $s:expr, $cf:expr, $lhs:expr, $rhs:expr, $dst:expr, $op:tt) => {
         {
-            let lhs = $cf.get_value($s, *$lhs).$f;
-            let rhs = $cf.get_value($s, *$rhs).$f;
-            $cf.set_value($s, *$dst, RtValue::from(lhs $op rhs));
+            let lhs = $cf.get_value($s, $lhs.idx()).$f;
+            let rhs = $cf.get_value($s, $rhs.idx()).$f;
+            $cf.set_value($s, $dst.idx(), RtValue::from(lhs $op rhs));
+        }
+    }
+}
+
+// integer division and remainder, raising an error if there is no result
+macro_rules! impl_int_division {
+    (
+        $s:ident, $compiled:expr, $cf:ident, $slots:ident, $insc_ptr:ident, $resync:expr,
+        $lhs:expr, $rhs:expr, $dst:expr, $op:ident
+    ) => {
+        {
+            let lhs = $slots.get_value(&$s.stack, $lhs.idx()).i;
+            let rhs = $slots.get_value(&$s.stack, $rhs.idx()).i;
+            match lhs.$op(rhs) {
+                Some(value) => $slots.set_value(&mut $s.stack, $dst.idx(), RtValue::from(value)),
+                None => {
+                    $insc_ptr = $s.raise($compiled, $insc_ptr, &mut $cf, None, int_division_fault(rhs))?;
+                    $resync;
+                    continue;
+                }
+            }
         }
     }
 }
@@ -18,8 +103,17 @@ macro_rules! impl_binop {
 macro_rules! impl_uop {
     ($f:ident, $s:expr, $cf:expr, $src:expr, $dst:expr, $op:tt) => {
         {
-            let src = $cf.get_value($s, *$src).$f;
-            $cf.set_value($s, *$dst, RtValue::from($op src));
+            let src = $cf.get_value($s, $src.idx()).$f;
+            $cf.set_value($s, $dst.idx(), RtValue::from($op src));
+        }
+    }
+}
+
+macro_rules! impl_conv {
+    ($f:ident, $s:expr, $cf:expr, $src:expr, $dst:expr, $conv:expr) => {
+        {
+            let src = $cf.get_value($s, $src.idx()).$f;
+            $cf.set_value($s, $dst.idx(), RtValue::from($conv(src)));
         }
     }
 }
@@ -27,190 +121,1208 @@ macro_rules! impl_uop {
 macro_rules! impl_uop_fn {
     ($f:ident, $s:expr, $cf:expr, $src:expr, $dst:expr, $op:ident) => {
         {
-            let src = $cf.get_value($s, *$src).$f;
-            $cf.set_value($s, *$dst, RtValue::from(src.$op()));
+            let src = $cf.get_value($s, $src.idx()).$f;
+            $cf.set_value($s, $dst.idx(), RtValue::from(src.$op()));
+        }
+    }
+}
+
+// the instructions of `$code`, an `Insc` or a `CompactInsc`, which are left to `Combustor::control`
+macro_rules! control_insc {
+    ($code:ident) => {
+        $code::Call { .. }
+        | $code::Return { .. }
+        | $code::CallFFI { .. }
+        | $code::TryBegin { .. }
+        | $code::TryEnd
+        | $code::Spawn { .. }
+        | $code::Join { .. }
+        | $code::Yield { .. }
+    }
+}
+
+// runs one instruction of `$code`, an `Insc`, `CompactInsc` or `DecodedInsc`, which share their
+// variants. Operands are read and written through `$slots`, the current frame or its base.
+// `$control_code` is the code `control` reads its instructions from. `$math3` gives the operation
+// and the operands of `Math3`, which are stored differently, and `$control` matches the
+// instructions left to `control`, after which `$resync` updates `$slots`. The remaining arms run
+// the instructions `$code` has in addition.
+macro_rules! impl_dispatch {
+    (
+        $code:ident, $insc:expr, $s:ident, $compiled:expr, $control_code:expr, $cf:ident, $slots:ident,
+        $insc_ptr:ident, $math3:pat => $math3_operands:expr, $control:pat, $resync:expr
+        $(, $extra:pat => $extra_body:expr)*
+    ) => {
+        match $insc {
+            $code::Const { value, dst } =>
+                $slots.set_value(&mut $s.stack, dst.idx(), *value),
+            $code::Dup { src, dst } => {
+                let value = $slots.get_value(&$s.stack, src.idx());
+                $slots.set_value(&mut $s.stack, dst.idx(), value);
+            },
+            $code::AddInt { lhs, rhs, dst } =>
+                impl_binop!(i, &mut $s.stack, $slots, lhs, rhs, dst, +),
+            $code::AddFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, +),
+            $code::SubInt { lhs, rhs, dst } =>
+                impl_binop!(i, &mut $s.stack, $slots, lhs, rhs, dst, -),
+            $code::SubFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, -),
+            $code::MulInt { lhs, rhs, dst } =>
+                impl_binop!(i, &mut $s.stack, $slots, lhs, rhs, dst, *),
+            $code::MulFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, *),
+            $code::DivInt { lhs, rhs, dst } =>
+                impl_int_division!($s, $compiled, $cf, $slots, $insc_ptr, $resync, lhs, rhs, dst, checked_div),
+            $code::DivFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, /),
+            $code::ModInt { lhs, rhs, dst } =>
+                impl_int_division!($s, $compiled, $cf, $slots, $insc_ptr, $resync, lhs, rhs, dst, checked_rem),
+            $code::NegateInt { src, dst } =>
+                impl_uop!(i, &mut $s.stack, $slots, src, dst, -),
+            $code::NegateFloat { src, dst } =>
+                impl_uop!(f, &mut $s.stack, $slots, src, dst, -),
+            $code::Eq { lhs, rhs, dst } =>
+                impl_binop!(repr, &mut $s.stack, $slots, lhs, rhs, dst, ==),
+            $code::Ne { lhs, rhs, dst } =>
+                impl_binop!(repr, &mut $s.stack, $slots, lhs, rhs, dst, !=),
+            $code::LtInt { lhs, rhs, dst } =>
+                impl_binop!(i, &mut $s.stack, $slots, lhs, rhs, dst, <),
+            $code::LtFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, <),
+            $code::LeInt { lhs, rhs, dst } =>
+                impl_binop!(i, &mut $s.stack, $slots, lhs, rhs, dst, <=),
+            $code::LeFloat { lhs, rhs, dst } =>
+                impl_binop!(f, &mut $s.stack, $slots, lhs, rhs, dst, <=),
+            $code::And { lhs, rhs, dst } =>
+                impl_binop!(b, &mut $s.stack, $slots, lhs, rhs, dst, &&),
+            $code::Or { lhs, rhs, dst } =>
+                impl_binop!(b, &mut $s.stack, $slots, lhs, rhs, dst, ||),
+            $code::Not { src, dst } =>
+                impl_uop!(b, &mut $s.stack, $slots, src, dst, !),
+            $code::Round { src, dst } =>
+                impl_uop_fn!(f, &mut $s.stack, $slots, src, dst, round),
+            $code::Floor { src, dst } =>
+                impl_uop_fn!(f, &mut $s.stack, $slots, src, dst, floor),
+            $code::Ceil { src, dst } =>
+                impl_uop_fn!(f, &mut $s.stack, $slots, src, dst, ceil),
+            $code::ToFloat { src, dst } =>
+                impl_conv!(i, &mut $s.stack, $slots, src, dst, |x| x as f32),
+            $code::Bool2Int { src, dst } =>
+                impl_conv!(b, &mut $s.stack, $slots, src, dst, |x| x as i32),
+            $code::Int2Bool { src, dst } =>
+                impl_conv!(i, &mut $s.stack, $slots, src, dst, |x| x != 0),
+            $code::Float2Int { src, dst } =>
+                impl_conv!(f, &mut $s.stack, $slots, src, dst, float_to_int),
+            $code::Round2Int { src, dst } =>
+                impl_conv!(f, &mut $s.stack, $slots, src, dst, float_round_to_int),
+            $code::Floor2Int { src, dst } =>
+                impl_conv!(f, &mut $s.stack, $slots, src, dst, float_floor_to_int),
+            $code::Ceil2Int { src, dst } =>
+                impl_conv!(f, &mut $s.stack, $slots, src, dst, float_ceil_to_int),
+            $code::Bool2Float { src, dst } =>
+                impl_conv!(b, &mut $s.stack, $slots, src, dst, bool_to_float),
+            $code::Float2Bool { src, dst } =>
+                impl_conv!(f, &mut $s.stack, $slots, src, dst, float_to_bool),
+            $code::Math1 { op, src, dst } => {
+                let src = $slots.get_value(&$s.stack, src.idx());
+                $slots.set_value(&mut $s.stack, dst.idx(), op.eval(src));
+            },
+            $code::Math2 { op, lhs, rhs, dst } => {
+                let lhs = $slots.get_value(&$s.stack, lhs.idx());
+                let rhs = $slots.get_value(&$s.stack, rhs.idx());
+                $slots.set_value(&mut $s.stack, dst.idx(), op.eval(lhs, rhs));
+            },
+            $math3 => {
+                let (op, [a, b, c, dst]) = $math3_operands;
+                let a = $slots.get_value(&$s.stack, a.idx());
+                let b = $slots.get_value(&$s.stack, b.idx());
+                let c = $slots.get_value(&$s.stack, c.idx());
+                $slots.set_value(&mut $s.stack, dst.idx(), op.eval(a, b, c));
+            },
+            $code::Jmp { dst } => {
+                $insc_ptr = dst.idx();
+                continue;
+            },
+            $code::JmpIf { check, dst } => {
+                let check = $slots.get_value(&$s.stack, check.idx()).b;
+                if check {
+                    $insc_ptr = dst.idx();
+                    continue;
+                }
+            },
+            $code::IOSetValue { offset, src } => {
+                let src = $slots.get_value(&$s.stack, src.idx());
+                (($s.io_ctx as *mut CTX as *mut u8)
+                    .add(offset.idx())
+                    as *mut RtValue)
+                    .write(src);
+            },
+            $code::IOGetValue { offset, dst } => {
+                let src = (($s.io_ctx as *const CTX as *const u8)
+                    .add(offset.idx())
+                    as *const RtValue)
+                    .read();
+                $slots.set_value(&mut $s.stack, dst.idx(), src);
+            },
+            $control => match $s.control($compiled, $control_code, $insc_ptr, &mut $cf)? {
+                Control::Next => $resync,
+                Control::Jump(dst) => {
+                    $resync;
+                    $insc_ptr = dst;
+                    continue;
+                },
+                Control::Stop(stop) => return Ok(stop)
+            },
+            $($extra => $extra_body),*
+        }
+    }
+}
+
+// an operation of `DecodedInsc` fused with the `Dup` of its result, which also uses up the fuel
+// of the `Dup`
+macro_rules! impl_binop_dup {
+    ($f:ident, $s:expr, $slots:expr, $fuel:expr, $insc_ptr:ident, $lhs:expr, $rhs:expr, $dst:expr, $copy:expr, $op:tt) => {
+        {
+            let lhs = $slots.get_value($s, $lhs.idx()).$f;
+            let rhs = $slots.get_value($s, $rhs.idx()).$f;
+            let value = RtValue::from(lhs $op rhs);
+            $slots.set_value($s, $dst.idx(), value);
+            $slots.set_value($s, $copy.idx(), value);
+            *$fuel -= 1;
+            $insc_ptr += 1;
         }
     }
 }
 
+/// How a `Combustor` runs the code. All engines give the same results, and a script may be
+/// switched to another engine between resumes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Engine {
+    /// `Compiled::code` as is
+    Insc,
+    /// `CompactCode`, or `Compiled::code` if it cannot be lowered
+    #[default]
+    Compact,
+    /// `DecodedCode`, or `Compiled::code` if it cannot be lowered
+    Decoded
+}
+
+/// `Compiled` lowered for an engine. Clones share the lowered code, so that instances running the
+/// same script lower it only once, see `Combustor::set_engine_code`.
+#[derive(Clone)]
+pub struct EngineCode<'a> {
+    compiled: &'a Compiled,
+    engine: Engine,
+    lowered: Lowered
+}
+
+#[derive(Clone)]
+enum Lowered {
+    // `Compiled::code` as is
+    None,
+    Compact(Arc<CompactCode>),
+    Decoded(Arc<DecodedCode>)
+}
+
+impl<'a> EngineCode<'a> {
+    pub fn new(compiled: &'a Compiled, engine: Engine) -> Self {
+        let lowered = match engine {
+            Engine::Insc => None,
+            Engine::Compact => CompactCode::lower(compiled).map(|code| Lowered::Compact(Arc::new(code))),
+            Engine::Decoded => DecodedCode::lower(compiled).map(|code| Lowered::Decoded(Arc::new(code)))
+        };
+        Self { compiled, engine, lowered: lowered.unwrap_or(Lowered::None) }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Whether this is `compiled` lowered for `engine`
+    pub fn is_for(&self, compiled: &Compiled, engine: Engine) -> bool {
+        ptr::eq(self.compiled, compiled) && self.engine == engine
+    }
+}
+
+// results of a suspended native call go to these slots of the frame which called it
+type RetLocs = SmallVec<[usize; 2]>;
+
+enum Stop<'a> {
+    Yield { resume_ptr: usize },
+    Return,
+    // blocked on an unfinished task; `Join` is executed again once it finishes
+    Join { insc_ptr: usize, task: i32 },
+    Native { resume_ptr: usize, native: &'a NativeFunction, ret_locs: RetLocs },
+    // the instruction at `insc_ptr` has not been executed yet
+    OutOfFuel { insc_ptr: usize },
+    // stopped by the debugger, likewise
+    Pause { insc_ptr: usize }
+}
+
+enum Control<'a> {
+    Next,
+    Jump(usize),
+    Stop(Stop<'a>)
+}
+
+// an instruction left to `Combustor::control`, with the operands it needs
+enum ControlInsc<'c, S> {
+    Call { func: usize, args: &'c [S] },
+    Return { rets: &'c [S] },
+    CallFFI { func: usize, args: &'c [S], ret_locs: &'c [S] },
+    TryBegin { handler: usize },
+    TryEnd,
+    Spawn { func: usize, args: &'c [S], dst: usize },
+    Join { task: usize },
+    Yield { values: &'c [S] }
+}
+
+// code whose control instructions `Combustor::control` runs, `Compiled::code` or `CompactCode`
+trait ControlCode {
+    // slots in operand lists, `usize` or `u16`
+    type Slot: Copy + Into<usize>;
+
+    // `insc_ptr` must be the address of a control instruction
+    unsafe fn control_insc(&self, insc_ptr: usize) -> ControlInsc<'_, Self::Slot>;
+
+    // `call_addr` must be the address of a `Call`
+    unsafe fn ret_locs(&self, call_addr: usize) -> &[Self::Slot];
+}
+
+impl ControlCode for Compiled {
+    type Slot = usize;
+
+    #[inline(always)]
+    unsafe fn control_insc(&self, insc_ptr: usize) -> ControlInsc<'_, usize> {
+        match self.code.get_unchecked(insc_ptr) {
+            Insc::Call { func, args, .. } => ControlInsc::Call { func: *func, args },
+            Insc::Return { rets } => ControlInsc::Return { rets },
+            Insc::CallFFI { func, args, ret_locs } => ControlInsc::CallFFI { func: *func, args, ret_locs },
+            Insc::TryBegin { handler } => ControlInsc::TryBegin { handler: *handler },
+            Insc::TryEnd => ControlInsc::TryEnd,
+            Insc::Spawn { func, args, dst } => ControlInsc::Spawn { func: *func, args, dst: *dst },
+            Insc::Join { task } => ControlInsc::Join { task: *task },
+            Insc::Yield { values } => ControlInsc::Yield { values },
+            _ => unreachable_unchecked()
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn ret_locs(&self, call_addr: usize) -> &[usize] {
+        let Insc::Call { ret_locs, .. } = self.code.get_unchecked(call_addr) else {
+            unreachable_unchecked()
+        };
+        ret_locs
+    }
+}
+
+impl ControlCode for CompactCode {
+    type Slot = u16;
+
+    #[inline(always)]
+    unsafe fn control_insc(&self, insc_ptr: usize) -> ControlInsc<'_, u16> {
+        match self.code.get_unchecked(insc_ptr) {
+            CompactInsc::Call { func, lists } => ControlInsc::Call { func: *func as usize, args: self.list(*lists).0 },
+            CompactInsc::Return { rets } => ControlInsc::Return { rets: self.list(*rets).0 },
+            CompactInsc::CallFFI { func, lists } => {
+                let (args, ret_locs) = self.list(*lists);
+                ControlInsc::CallFFI { func: *func as usize, args, ret_locs: self.list(ret_locs).0 }
+            },
+            CompactInsc::TryBegin { handler } => ControlInsc::TryBegin { handler: *handler as usize },
+            CompactInsc::TryEnd => ControlInsc::TryEnd,
+            CompactInsc::Spawn { func, lists } => {
+                let (args, dst) = self.list(*lists);
+                let dst = *self.list(dst).0.get_unchecked(0) as usize;
+                ControlInsc::Spawn { func: *func as usize, args, dst }
+            },
+            CompactInsc::Join { task } => ControlInsc::Join { task: *task as usize },
+            CompactInsc::Yield { values } => ControlInsc::Yield { values: self.list(*values).0 },
+            _ => unreachable_unchecked()
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn ret_locs(&self, call_addr: usize) -> &[u16] {
+        let CompactInsc::Call { lists, .. } = self.code.get_unchecked(call_addr) else {
+            unreachable_unchecked()
+        };
+        self.list(self.list(*lists).1).0
+    }
+}
+
+enum TaskWait<'a> {
+    Ready,
+    Join(i32),
+    Native(&'a NativeFunction, RetLocs),
+    Finished
+}
+
+/// A function started with `spawn`, with its own stack
+struct Task<'a> {
+    id: i32,
+    stack: Stack,
+    handlers: Vec<(usize, usize)>,
+    resume_ptr: usize,
+    wait: TaskWait<'a>,
+    // has yielded or suspended in the current resume
+    ticked: bool
+}
+
 pub struct Combustor<'a, 'ctx, CTX: IOContext> {
     pub io_ctx: &'ctx mut CTX,
 
-    stack: Stack<'a>,
-    out_buf: ZeroVec<RtValue>,
-    in_buf: ZeroVec<RtValue>,
+    // the running task's stack and `try` handlers; the main script's between resumes
+    stack: Stack,
+    out_buf: ZeroBuf<RtValue>,
+    in_buf: ZeroBuf<RtValue>,
+    // active `try` blocks as (stack depth, handler address), innermost last
+    handlers: Vec<(usize, usize)>,
+    // the suspended native call and where its results go
+    suspended: Option<(&'a NativeFunction, RetLocs)>,
+    yielded: Vec<RtValue>,
+    tasks: Vec<Task<'a>>,
+    next_task_id: i32,
+    // stacks of finished tasks, reused by `spawn`
+    spare_stacks: Vec<Stack>,
+    // instructions left in the current resume
+    fuel: u64,
+    out_of_fuel: bool,
+    engine: Engine,
+    // the code last run, lowered for `engine`
+    engine_code: Option<EngineCode<'a>>,
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    // the spawned task being run, `None` for the main script
+    current_task: Option<i32>
+}
+
+/// Everything a `Combustor` owns, so that a script can be parked without its IO context and
+/// continued later by `Combustor::with_state`
+pub struct CombustorState<'a> {
+    stack: Stack,
+    out_buf: ZeroBuf<RtValue>,
+    in_buf: ZeroBuf<RtValue>,
+    handlers: Vec<(usize, usize)>,
+    suspended: Option<(&'a NativeFunction, RetLocs)>,
+    yielded: Vec<RtValue>,
+    tasks: Vec<Task<'a>>,
+    next_task_id: i32,
+    spare_stacks: Vec<Stack>,
+    engine: Engine,
+    engine_code: Option<EngineCode<'a>>
+}
+
+impl<'a> CombustorState<'a> {
+    pub fn new() -> Self {
+        Self {
+            stack: Stack::new(),
+            out_buf: ZeroBuf::with_capacity(8),
+            in_buf: ZeroBuf::with_capacity(8),
+            handlers: Vec::new(),
+            suspended: None,
+            yielded: Vec::new(),
+            tasks: Vec::new(),
+            next_task_id: 1,
+            spare_stacks: Vec::new(),
+            engine: Engine::default(),
+            engine_code: None
+        }
+    }
+
+    /// A state in the middle of the script
+    ///
+    /// # Safety
+    /// `frames` must be consistent with each other and with `values`, and `suspended` must be the
+    /// native called right before the resume address.
+    pub unsafe fn from_stack(
+        values: &[RtValue],
+        frames: &[StackFrame],
+        handlers: &[(usize, usize)],
+        suspended: Option<(&'a NativeFunction, &[usize])>
+    ) -> Self {
+        let mut state = Self::new();
+        state.stack.restore(values, frames);
+        state.handlers.extend_from_slice(handlers);
+        state.suspended = suspended.map(|(native, ret_locs)| (native, ret_locs.into()));
+        state
+    }
+
+    /// See `Combustor::yielded`
+    pub fn yielded(&self) -> &[RtValue] {
+        &self.yielded
+    }
+
+    /// See `Combustor::suspended_native`
+    pub fn suspended_native(&self) -> Option<&'a NativeFunction> {
+        self.suspended.as_ref().map(|(native, _)| *native)
+    }
+
+    /// See `Combustor::finish_native`
+    pub fn finish_native<R: NativeReturn>(&mut self, rets: R) -> Result<(), String> {
+        let Some((native, ret_locs)) = &self.suspended else {
+            return Err("脚本没有在等待宿主函数".to_string());
+        };
+
+        write_native_results(native, ret_locs, rets, &mut self.stack, &mut self.out_buf)?;
+        self.suspended = None;
+        Ok(())
+    }
+}
+
+impl Default for CombustorState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a, 'ctx, CTX> Combustor<'a, 'ctx, CTX>
     where CTX: IOContext
 {
     pub fn new(io_ctx: &'ctx mut CTX) -> Self {
+        Self::with_state(io_ctx, CombustorState::new())
+    }
+
+    /// Continues from a state returned by `into_state`, which may have been used with another IO
+    /// context. The fuel is unlimited.
+    pub fn with_state(io_ctx: &'ctx mut CTX, state: CombustorState<'a>) -> Self {
         Self {
             io_ctx,
 
-            stack: Stack::new(),
-            out_buf: ZeroVec::with_capacity(8),
-            in_buf: ZeroVec::with_capacity(8)
+            stack: state.stack,
+            out_buf: state.out_buf,
+            in_buf: state.in_buf,
+            handlers: state.handlers,
+            suspended: state.suspended,
+            yielded: state.yielded,
+            tasks: state.tasks,
+            next_task_id: state.next_task_id,
+            spare_stacks: state.spare_stacks,
+            fuel: u64::MAX,
+            out_of_fuel: false,
+            engine: state.engine,
+            engine_code: state.engine_code,
+            debugger: None,
+            profiler: None,
+            current_task: None
+        }
+    }
+
+    pub fn into_state(self) -> CombustorState<'a> {
+        CombustorState {
+            stack: self.stack,
+            out_buf: self.out_buf,
+            in_buf: self.in_buf,
+            handlers: self.handlers,
+            suspended: self.suspended,
+            yielded: self.yielded,
+            tasks: self.tasks,
+            next_task_id: self.next_task_id,
+            spare_stacks: self.spare_stacks,
+            engine: self.engine,
+            engine_code: self.engine_code
+        }
+    }
+
+    /// Abandons the running script, if any, so the combustor can start again. Allocated buffers
+    /// are kept.
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.handlers.clear();
+        self.suspended = None;
+        self.yielded.clear();
+        for task in self.tasks.drain(..) {
+            recycle_stack(&mut self.spare_stacks, task.stack);
         }
+        self.next_task_id = 1;
+        if let Some(debugger) = &mut self.debugger {
+            debugger.reset();
+        }
+    }
+
+    /// Chooses how the following resumes run the code. The code is lowered for the engine by the
+    /// next resume and kept while the same `Compiled` is run; code which the engine cannot lower is
+    /// run by `Engine::Insc`.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Runs the following resumes with code already lowered, e.g. shared with other instances of
+    /// the script. The engine it was lowered for is chosen as by `set_engine`.
+    pub fn set_engine_code(&mut self, code: EngineCode<'a>) {
+        self.engine = code.engine;
+        self.engine_code = Some(code);
+    }
+
+    /// Attaches or detaches a debugger. While one is attached the code is run by `Engine::Insc`
+    /// and checked before every instruction.
+    pub fn set_debugger(&mut self, debugger: Option<Debugger>) {
+        self.debugger = debugger;
     }
 
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_mut()
+    }
+
+    /// Attaches or detaches a profiler. While one is attached the code is run by `Engine::Insc`,
+    /// like with a debugger.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    /// The call stack where the debugger stopped the script, outermost first
+    pub fn paused_frames(&self, compiled: &Compiled) -> Vec<FrameInfo> {
+        let Some((stack, insc_ptr)) = self.paused_stack() else {
+            return Vec::new();
+        };
+        stack.positions(insc_ptr).into_iter().map(|insc_ptr| FrameInfo::at(compiled, insc_ptr)).collect()
+    }
+
+    /// The variables alive in frame `frame` of `paused_frames`, with their values
+    pub fn paused_locals<'c>(&self, compiled: &'c Compiled, frame: usize) -> Vec<(&'c LocalVar, RtValue)> {
+        let Some((stack, insc_ptr)) = self.paused_stack() else {
+            return Vec::new();
+        };
+        let insc_ptr = stack.positions(insc_ptr).get(frame).copied();
+        let (Some(insc_ptr), Some(stack_frame)) = (insc_ptr, stack.frames().get(frame)) else {
+            return Vec::new();
+        };
+        let Some(func_debug) = compiled.func.iter()
+            .position(|func| func.addr <= insc_ptr && insc_ptr < func.addr + func.code_len)
+            .and_then(|func_id| compiled.debug.as_ref()?.funcs.get(func_id)) else {
+            return Vec::new();
+        };
+
+        func_debug.locals_at(insc_ptr)
+            .into_iter()
+            .map(|local| (local, stack.values()[stack_frame.start_idx() + local.slot]))
+            .collect()
+    }
+
+    fn paused_stack(&self) -> Option<(&Stack, usize)> {
+        let pause = self.debugger.as_ref()?.pause()?;
+        match pause.task {
+            None => Some((&self.stack, pause.insc_ptr)),
+            Some(task_id) => self.tasks.iter()
+                .find(|task| task.id == task_id)
+                .map(|task| (&task.stack, pause.insc_ptr))
+        }
+    }
+
+    /// Limits the number of instructions the following resumes may execute, including those of
+    /// spawned tasks. A script running out of fuel is suspended where it stopped, see
+    /// `out_of_fuel`.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> u64 {
+        self.fuel
+    }
+
+    /// Whether the last resume was cut short by `set_fuel` rather than stopped by the script
+    pub fn out_of_fuel(&self) -> bool {
+        self.out_of_fuel
+    }
+
+    /// Number of spawned tasks which have not finished yet
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Values passed to the last `yield`, valid until the script is resumed. Empty if the script
+    /// was suspended by `yield;` or by a native.
+    pub fn yielded(&self) -> &[RtValue] {
+        &self.yielded
+    }
+
+    /// Captures the state of the script suspended at `resume_ptr`, which was started from `entry`.
+    /// Spawned tasks are not captured, so there should be none.
+    pub fn snapshot(&self, compiled: &Compiled, entry: usize, resume_ptr: usize) -> Snapshot {
+        debug_assert_eq!(self.task_count(), 0, "snapshots do not capture spawned tasks");
+        // values above the last frame are left over from returned calls
+        let stack_size = self.stack.frames().last().map_or(0, |frame| frame.end_idx());
+        Snapshot {
+            code_hash: compiled.code_hash(),
+            entry,
+            resume_ptr,
+            values: self.stack.values()[..stack_size].iter().map(|value| unsafe { value.repr }).collect(),
+            frames: self.stack.frames().iter().map(|frame| FrameSnapshot {
+                ret_addr: frame.ret_addr(),
+                start_idx: frame.start_idx(),
+                end_idx: frame.end_idx()
+            }).collect(),
+            handlers: self.handlers.clone(),
+            suspended_native: self.suspended.is_some()
+        }
+    }
+
+    /// Puts the script back into the state captured by `snapshot`, after validating it against
+    /// `compiled`. On success it can be resumed from `snapshot.resume_ptr`.
+    ///
+    /// # Safety
+    /// `compiled` must be well-formed and linked, as for `combust`.
+    pub unsafe fn restore(&mut self, compiled: &'a Compiled, snapshot: &Snapshot) -> Result<(), String> {
+        snapshot.validate(compiled)?;
+
+        let values = snapshot.values.iter().map(|repr| RtValue { repr: *repr }).collect::<Vec<_>>();
+        let frames = snapshot.frames.iter()
+            .map(|frame| StackFrame::new(frame.ret_addr, frame.start_idx, frame.end_idx))
+            .collect::<Vec<_>>();
+
+        self.reset();
+        self.stack.restore(&values, &frames);
+        self.handlers.extend_from_slice(&snapshot.handlers);
+        if snapshot.suspended_native {
+            let Insc::CallFFI { func, ret_locs, .. } = &compiled.code[snapshot.resume_ptr - 1] else {
+                unreachable!()
+            };
+            self.suspended = Some((compiled.ffi.get_unchecked(*func), ret_locs.iter().copied().collect()));
+        }
+        Ok(())
+    }
+
+    /// The native the script is suspended on, if it was suspended by a native rather than `yield`
+    pub fn suspended_native(&self) -> Option<&'a NativeFunction> {
+        self.suspended.as_ref().map(|(native, _)| *native)
+    }
+
+    /// Provides the results of the suspended native call before resuming. Natives returning
+    /// `Suspend<()>` have no results, and the script may be resumed directly.
+    pub fn finish_native<R: NativeReturn>(&mut self, rets: R) -> Result<(), String> {
+        let Some((native, ret_locs)) = &self.suspended else {
+            return Err("脚本没有在等待宿主函数".to_string());
+        };
+
+        write_native_results(native, ret_locs, rets, &mut self.stack, &mut self.out_buf)?;
+        self.suspended = None;
+        Ok(())
+    }
+
+    /// Spawned tasks suspended by a native, with the native each one waits for
+    pub fn suspended_tasks(&self) -> impl Iterator<Item = (i32, &'a NativeFunction)> + '_ {
+        self.tasks.iter().filter_map(|task| match task.wait {
+            TaskWait::Native(native, _) => Some((task.id, native)),
+            _ => None
+        })
+    }
+
+    /// Like `finish_native`, for a spawned task. Since the host resumes the script as a whole, a
+    /// task stays blocked until this is called, even for natives without results.
+    pub fn finish_task_native<R: NativeReturn>(&mut self, task_id: i32, rets: R) -> Result<(), String> {
+        let Some(task) = self.tasks.iter_mut().find(|task| task.id == task_id) else {
+            return Err(format!("任务 {} 不存在", task_id));
+        };
+        let TaskWait::Native(native, ret_locs) = &task.wait else {
+            return Err(format!("任务 {} 没有在等待宿主函数", task_id));
+        };
+
+        write_native_results(native, ret_locs, rets, &mut task.stack, &mut self.out_buf)?;
+        task.wait = TaskWait::Ready;
+        Ok(())
+    }
+
+    /// Starts the function `entry`. Returns the address to resume from if the script yielded, or
+    /// `None` if it finished. Each resume also runs every spawned task until it yields, finishes
+    /// or blocks; tasks left when the script finishes are dropped.
+    ///
+    /// # Safety
+    /// `compiled` must be well-formed, as checked by `verify`, and linked, see `Compiled::link`.
+    /// `entry` must be the index of one of its functions.
     pub unsafe fn combust(
         &mut self,
         compiled: &'a Compiled,
         entry: usize
-    ) -> Option<usize> {
+    ) -> Result<Option<usize>, RuntimeError> {
+        debug_assert!(compiled.is_linked(), "`Compiled` must be linked before running");
         let entry_fn = compiled.func.get_unchecked(entry);
+        self.reset();
         self.stack.enter_frame(entry_fn.frame_size);
         self.combust_resume(compiled, entry_fn.addr)
     }
 
+    /// Continues the script from `insc_ptr`, as returned by the last resume
+    ///
+    /// # Safety
+    /// `compiled` must be the code the script was started with, and `insc_ptr` the address the
+    /// last resume returned.
     pub unsafe fn combust_resume(
+        &mut self,
+        compiled: &'a Compiled,
+        insc_ptr: usize
+    ) -> Result<Option<usize>, RuntimeError> {
+        if let Some((native, _)) = self.suspended.take() {
+            debug_assert!(
+                native.signature.rets.is_empty(),
+                "resuming without results for `{}`, see `Combustor::finish_native`",
+                native.name
+            );
+        }
+        self.yielded.clear();
+        self.out_of_fuel = false;
+        if let Some(debugger) = &mut self.debugger {
+            debugger.start_resume(self.io_ctx as *const CTX as *const u8);
+        }
+        for task in self.tasks.iter_mut() {
+            task.ticked = false;
+        }
+
+        let mut main_ptr = insc_ptr;
+        let mut main_ticked = false;
+        let mut main_join = None;
+        // a task blocked by `join` runs again in the same resume once the joined task finishes
+        loop {
+            let mut progress = false;
+            if !main_ticked && main_join.is_none_or(|task| self.task_finished(task)) {
+                progress = true;
+                main_join = None;
+                match self.run(compiled, main_ptr)? {
+                    Stop::Yield { resume_ptr } => {
+                        main_ptr = resume_ptr;
+                        main_ticked = true;
+                    },
+                    Stop::Return => {
+                        self.tasks.clear();
+                        return Ok(None);
+                    },
+                    Stop::Join { insc_ptr, task } => {
+                        main_ptr = insc_ptr;
+                        main_join = Some(task);
+                    },
+                    Stop::Native { resume_ptr, native, ret_locs } => {
+                        self.suspended = Some((native, ret_locs));
+                        main_ptr = resume_ptr;
+                        main_ticked = true;
+                    },
+                    Stop::OutOfFuel { insc_ptr } => {
+                        main_ptr = insc_ptr;
+                        self.out_of_fuel = true;
+                        break;
+                    },
+                    Stop::Pause { insc_ptr } => {
+                        main_ptr = insc_ptr;
+                        break;
+                    }
+                }
+            }
+
+            // tasks spawned meanwhile are appended, and start in this resume as well
+            let mut idx = 0;
+            while idx < self.tasks.len() {
+                let task = &self.tasks[idx];
+                let runnable = !task.ticked && match task.wait {
+                    TaskWait::Ready => true,
+                    TaskWait::Join(joined) => self.task_finished(joined),
+                    TaskWait::Native(..) | TaskWait::Finished => false
+                };
+                if runnable {
+                    progress = true;
+                    self.run_task(compiled, idx)?;
+                    if self.interrupted() {
+                        break;
+                    }
+                }
+                idx += 1;
+            }
+
+            if !progress || self.interrupted() {
+                break;
+            }
+        }
+
+        // joining a task which no longer exists does not block
+        for task in self.tasks.extract_if(.., |task| matches!(task.wait, TaskWait::Finished)) {
+            recycle_stack(&mut self.spare_stacks, task.stack);
+        }
+        Ok(Some(main_ptr))
+    }
+
+    // out of fuel or stopped by the debugger, so nothing else runs in this resume
+    fn interrupted(&self) -> bool {
+        self.out_of_fuel || self.debugger.as_ref().is_some_and(|debugger| debugger.pause().is_some())
+    }
+
+    fn task_finished(&self, task_id: i32) -> bool {
+        self.tasks.iter()
+            .find(|task| task.id == task_id)
+            .is_none_or(|task| matches!(task.wait, TaskWait::Finished))
+    }
+
+    unsafe fn run_task(&mut self, compiled: &'a Compiled, idx: usize) -> Result<(), RuntimeError> {
+        std::mem::swap(&mut self.stack, &mut self.tasks[idx].stack);
+        std::mem::swap(&mut self.handlers, &mut self.tasks[idx].handlers);
+        self.current_task = Some(self.tasks[idx].id);
+        let stop = self.run(compiled, self.tasks[idx].resume_ptr);
+        self.current_task = None;
+        std::mem::swap(&mut self.stack, &mut self.tasks[idx].stack);
+        std::mem::swap(&mut self.handlers, &mut self.tasks[idx].handlers);
+
+        // values yielded by tasks are dropped
+        let task = &mut self.tasks[idx];
+        task.wait = TaskWait::Ready;
+        match stop? {
+            Stop::Yield { resume_ptr } => {
+                task.resume_ptr = resume_ptr;
+                task.ticked = true;
+            },
+            Stop::Return => task.wait = TaskWait::Finished,
+            Stop::Join { insc_ptr, task: joined } => {
+                task.resume_ptr = insc_ptr;
+                task.wait = TaskWait::Join(joined);
+            },
+            Stop::Native { resume_ptr, native, ret_locs } => {
+                task.resume_ptr = resume_ptr;
+                task.wait = TaskWait::Native(native, ret_locs);
+                task.ticked = true;
+            },
+            Stop::OutOfFuel { insc_ptr } => {
+                task.resume_ptr = insc_ptr;
+                self.out_of_fuel = true;
+            },
+            Stop::Pause { insc_ptr } => task.resume_ptr = insc_ptr
+        }
+        Ok(())
+    }
+
+    unsafe fn spawn<S: Copy + Into<usize>>(&mut self, func: &Function, args: &[S], frame: StackFrame) -> i32 {
+        let mut stack = self.spare_stacks.pop().unwrap_or_default();
+        let task_frame = stack.enter_frame(func.frame_size);
+        for (i, arg) in args.iter().enumerate() {
+            task_frame.set_value(&mut stack, i, frame.get_value(&self.stack, (*arg).into()));
+        }
+
+        let id = self.next_task_id;
+        self.next_task_id += 1;
+        self.tasks.push(Task {
+            id,
+            stack,
+            handlers: Vec::new(),
+            resume_ptr: func.addr,
+            wait: TaskWait::Ready,
+            ticked: false
+        });
+        id
+    }
+
+    /// Runs the current stack from `insc_ptr` until it stops
+    unsafe fn run(&mut self, compiled: &'a Compiled, insc_ptr: usize) -> Result<Stop<'a>, RuntimeError> {
+        if self.debugger.is_some() || self.profiler.is_some() {
+            let stop = self.run_insc::<true>(compiled, insc_ptr);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.flush();
+            }
+            return stop;
+        }
+
+        let engine_code = match self.engine_code.take() {
+            Some(engine_code) if engine_code.is_for(compiled, self.engine) => engine_code,
+            _ => EngineCode::new(compiled, self.engine)
+        };
+        // the engines are not inlined here, so that each loop keeps its operands in registers
+        let stop = match &engine_code.lowered {
+            Lowered::None => self.run_insc::<false>(compiled, insc_ptr),
+            Lowered::Compact(compact) => {
+                let mut fuel = self.fuel;
+                let stop = self.run_compact(compiled, compact, insc_ptr, &mut fuel);
+                self.fuel = fuel;
+                stop
+            },
+            Lowered::Decoded(decoded) => {
+                let mut fuel = self.fuel;
+                let stop = self.run_decoded(compiled, decoded, insc_ptr, &mut fuel);
+                self.fuel = fuel;
+                stop
+            }
+        };
+        self.engine_code = Some(engine_code);
+        stop
+    }
+
+    // `HOOKS` lets the debugger and the profiler see every instruction
+    #[inline(never)]
+    unsafe fn run_insc<const HOOKS: bool>(
         &mut self,
         compiled: &'a Compiled,
         mut insc_ptr: usize
-    ) -> Option<usize> {
+    ) -> Result<Stop<'a>, RuntimeError> {
         let mut current_frame = self.stack.last_frame();
 
         loop {
-            match unsafe { compiled.code.get_unchecked(insc_ptr) } {
-                Insc::Const { value, dst } =>
-                    current_frame.set_value(&mut self.stack, *dst, *value),
-                Insc::Dup { src, dst } => {
-                    let value = current_frame.get_value(&mut self.stack, *src);
-                    current_frame.set_value(&mut self.stack, *dst, value);
-                },
-                Insc::AddInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, +),
-                Insc::AddFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, +),
-                Insc::SubInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, -),
-                Insc::SubFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, -),
-                Insc::MulInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, *),
-                Insc::MulFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, *),
-                Insc::DivInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, /),
-                Insc::DivFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, /),
-                Insc::ModInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, %),
-                Insc::NegateInt { src, dst } =>
-                    impl_uop!(i, &mut self.stack, current_frame, src, dst, -),
-                Insc::NegateFloat { src, dst } =>
-                    impl_uop!(f, &mut self.stack, current_frame, src, dst, -),
-                Insc::Eq { lhs, rhs, dst } =>
-                    impl_binop!(repr, &mut self.stack, current_frame, lhs, rhs, dst, ==),
-                Insc::Ne { lhs, rhs, dst } =>
-                    impl_binop!(repr, &mut self.stack, current_frame, lhs, rhs, dst, !=),
-                Insc::LtInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, <),
-                Insc::LtFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, <),
-                Insc::LeInt { lhs, rhs, dst } =>
-                    impl_binop!(i, &mut self.stack, current_frame, lhs, rhs, dst, <=),
-                Insc::LeFloat { lhs, rhs, dst } =>
-                    impl_binop!(f, &mut self.stack, current_frame, lhs, rhs, dst, <=),
-                Insc::And { lhs, rhs, dst } =>
-                    impl_binop!(b, &mut self.stack, current_frame, lhs, rhs, dst, &&),
-                Insc::Or { lhs, rhs, dst } =>
-                    impl_binop!(b, &mut self.stack, current_frame, lhs, rhs, dst, ||),
-                Insc::Not { src, dst } =>
-                    impl_uop!(b, &mut self.stack, current_frame, src, dst, !),
-                Insc::Round { src, dst } =>
-                    impl_uop_fn!(f, &mut self.stack, current_frame, src, dst, round),
-                Insc::Floor { src, dst } =>
-                    impl_uop_fn!(f, &mut self.stack, current_frame, src, dst, floor),
-                Insc::Ceil { src, dst } =>
-                    impl_uop_fn!(f, &mut self.stack, current_frame, src, dst, ceil),
-                Insc::ToFloat { src, dst } => {
-                    let src = current_frame.get_value(&mut self.stack, *src).i;
-                    current_frame.set_value(&mut self.stack, *dst, RtValue::from(src as f32));
-                },
-                Insc::Bool2Int { src, dst } => {
-                    let src = current_frame.get_value(&mut self.stack, *src).b;
-                    current_frame.set_value(&mut self.stack, *dst, RtValue::from(src as i32));
-                },
-                Insc::Int2Bool { src, dst } => {
-                    let src = current_frame.get_value(&mut self.stack, *src).i;
-                    current_frame.set_value(&mut self.stack, *dst, RtValue::from(src != 0));
-                },
-                Insc::Jmp { dst } => {
-                    insc_ptr = *dst;
-                    continue;
-                },
-                Insc::JmpIf { check, dst } => {
-                    let check = current_frame.get_value(&mut self.stack, *check).b;
+            if HOOKS && self.debugger.is_some() && self.debug_check(compiled, insc_ptr) {
+                return Ok(Stop::Pause { insc_ptr });
+            }
+            if self.fuel == 0 {
+                return Ok(Stop::OutOfFuel { insc_ptr });
+            }
+            self.fuel -= 1;
+            if HOOKS {
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.count(compiled, &self.stack, insc_ptr);
+                }
+            }
+
+            impl_dispatch!(
+                Insc, compiled.code.get_unchecked(insc_ptr), self, compiled, compiled, current_frame, current_frame,
+                insc_ptr, Insc::Math3 { op, a, b, c, dst } => (op, [a, b, c, dst]), control_insc!(Insc), {}
+            );
+            insc_ptr += 1;
+        }
+    }
+
+    #[inline(never)]
+    unsafe fn run_compact(
+        &mut self,
+        compiled: &'a Compiled,
+        compact: &CompactCode,
+        mut insc_ptr: usize,
+        // kept out of `self` so that it can stay in a register
+        fuel: &mut u64
+    ) -> Result<Stop<'a>, RuntimeError> {
+        let mut current_frame = self.stack.last_frame();
+
+        loop {
+            if *fuel == 0 {
+                return Ok(Stop::OutOfFuel { insc_ptr });
+            }
+            *fuel -= 1;
+
+            impl_dispatch!(
+                CompactInsc, compact.code.get_unchecked(insc_ptr), self, compiled, compact, current_frame, current_frame,
+                insc_ptr,
+                CompactInsc::Math3 { op, operands } => (op, compact.math3.get_unchecked(*operands as usize).each_ref()),
+                control_insc!(CompactInsc), {}
+            );
+            insc_ptr += 1;
+        }
+    }
+
+    #[inline(never)]
+    unsafe fn run_decoded(
+        &mut self,
+        compiled: &'a Compiled,
+        decoded: &DecodedCode,
+        mut insc_ptr: usize,
+        fuel: &mut u64
+    ) -> Result<Stop<'a>, RuntimeError> {
+        let mut current_frame = self.stack.last_frame();
+        let mut base = FrameBase::new(&mut self.stack, &current_frame);
+
+        loop {
+            // a fused instruction would run out of fuel halfway, so the rest is run one by one
+            if *fuel < MAX_FUSED {
+                return self.run_compact(compiled, &decoded.compact, insc_ptr, fuel);
+            }
+            *fuel -= 1;
+
+            impl_dispatch!(
+                DecodedInsc, decoded.code.get_unchecked(insc_ptr), self, compiled, &decoded.compact, current_frame, base,
+                insc_ptr,
+                DecodedInsc::Math3 { op, operands } => (op, decoded.math3.get_unchecked(*operands as usize).each_ref()),
+                DecodedInsc::Control, base = FrameBase::new(&mut self.stack, &current_frame),
+                DecodedInsc::AddIntDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(i, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, +),
+                DecodedInsc::AddFloatDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(f, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, +),
+                DecodedInsc::SubIntDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(i, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, -),
+                DecodedInsc::SubFloatDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(f, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, -),
+                DecodedInsc::MulIntDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(i, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, *),
+                DecodedInsc::MulFloatDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(f, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, *),
+                DecodedInsc::DivFloatDup { lhs, rhs, dst, copy } =>
+                    impl_binop_dup!(f, &mut self.stack, base, fuel, insc_ptr, lhs, rhs, dst, copy, /),
+                DecodedInsc::NotJmpIf { src, not, dst } => {
+                    let check = !base.get_value(&self.stack, src.idx()).b;
+                    base.set_value(&mut self.stack, not.idx(), RtValue::from(check));
+                    *fuel -= 1;
                     if check {
-                        insc_ptr = *dst;
+                        insc_ptr = dst.idx();
                         continue;
                     }
+                    insc_ptr += 1;
                 },
-                Insc::Call { func, args, ret_locs } => {
-                    let func = compiled.func.get_unchecked(*func);
-                    current_frame = self.stack.call_enter_frame(
-                        insc_ptr,
-                        func.frame_size,
-                        args,
-                        ret_locs
-                    );
-                    insc_ptr = func.addr;
-                    continue;
-                },
-                Insc::Return { rets } => {
-                    if let Some((frame, ret_addr)) = self.stack.exit_frame(rets) {
-                        current_frame = frame;
-                        insc_ptr = ret_addr;
+                // the `Jmp` is only run if the `JmpIf` does not jump
+                DecodedInsc::JmpIfJmp { check, dst, other } => {
+                    if base.get_value(&self.stack, check.idx()).b {
+                        insc_ptr = dst.idx();
                     } else {
-                        break;
+                        *fuel -= 1;
+                        insc_ptr = other.idx();
                     }
+                    continue;
                 },
-                Insc::IOSetValue { offset, src } => {
-                    let src = current_frame.get_value(&mut self.stack, *src);
-                    (&mut self.io_ctx as *mut _ as *mut u8)
-                        .add(*offset)
-                        .write(&src as *const _ as _);
-                },
-                Insc::IOGetValue { offset, dst } => {
-                    let src = ((&mut self.io_ctx as *const _ as *const u8)
-                        .add(*offset)
-                        as *const RtValue)
-                        .read();
-                    current_frame.set_value(&mut self.stack, *dst, src);
-                },
-                Insc::CallFFI { func, args, ret_locs } => {
-                    let arg_count = args.len();
-                    let ret_count = ret_locs.len();
-                    self.in_buf.resize(arg_count);
-                    self.out_buf.resize(ret_count);
-
-                    for i in 0..arg_count {
-                        let arg = current_frame.get_value(&mut self.stack, *args.get_unchecked(i));
-                        *self.in_buf.get_unchecked_mut(i) = arg;
-                    }
+                DecodedInsc::DupJmp { src, copy, dst } => {
+                    let value = base.get_value(&self.stack, src.idx());
+                    base.set_value(&mut self.stack, copy.idx(), value);
+                    *fuel -= 1;
+                    insc_ptr = dst.idx();
+                    continue;
+                }
+            );
+            insc_ptr += 1;
+        }
+    }
 
-                    let func = *compiled.ffi.get_unchecked(*func);
-                    (func)(
-                        self.in_buf.get_unchecked_mut(0) as *mut _,
-                        arg_count as u32,
-                        self.out_buf.get_unchecked_mut(0) as *mut _
-                    );
-
-                    for i in 0..ret_count {
-                        let ret = *self.out_buf.get_unchecked(i);
-                        let dst = *ret_locs.get_unchecked(i);
-                        current_frame.set_value(&mut self.stack, dst, ret);
-                    }
-                },
-                Insc::Yield => {
-                    return Some(insc_ptr + 1)
+    unsafe fn debug_check(&mut self, compiled: &Compiled, insc_ptr: usize) -> bool {
+        let io = self.io_ctx as *const CTX as *const u8;
+        let depth = self.stack.depth();
+        let debugger = self.debugger.as_mut().unwrap_unchecked();
+        debugger.check(compiled, io, self.current_task, depth, insc_ptr)
+    }
+
+    /// Unwinds to the innermost `try` block and returns the address of its handler, or fails the
+    /// run if there is none. `native` is the native which failed, if any.
+    #[cold]
+    unsafe fn raise(
+        &mut self,
+        compiled: &'a Compiled,
+        insc_ptr: usize,
+        current_frame: &mut StackFrame,
+        native: Option<&str>,
+        message: String
+    ) -> Result<usize, RuntimeError> {
+        let Some((depth, handler)) = self.handlers.pop() else {
+            let positions = self.stack.positions(insc_ptr);
+            return Err(RuntimeError::new(compiled, native, message, &positions));
+        };
+
+        *current_frame = self.stack.unwind(depth);
+        Ok(handler)
+    }
+
+    /// Runs an instruction which may change frames, unwind to a `try` handler or stop the run.
+    /// Every engine leaves these to this function, reading them from `code`.
+    unsafe fn control<C: ControlCode>(
+        &mut self,
+        compiled: &'a Compiled,
+        code: &C,
+        insc_ptr: usize,
+        current_frame: &mut StackFrame
+    ) -> Result<Control<'a>, RuntimeError> {
+        match code.control_insc(insc_ptr) {
+            ControlInsc::Call { func, args } => {
+                let func = compiled.func.get_unchecked(func);
+                *current_frame = self.stack.call_enter_frame(insc_ptr, func.frame_size, args);
+                Ok(Control::Jump(func.addr))
+            },
+            ControlInsc::Return { rets } => {
+                let Some((frame, ret_addr)) = self.stack.exit_frame(rets, |call_addr| code.ret_locs(call_addr)) else {
+                    return Ok(Control::Stop(Stop::Return));
+                };
+                *current_frame = frame;
+                Ok(Control::Jump(ret_addr + 1))
+            },
+            ControlInsc::CallFFI { func, args, ret_locs } => {
+                let arg_count = args.len();
+                let ret_count = ret_locs.len();
+                self.in_buf.resize(arg_count);
+                self.out_buf.resize(ret_count);
+
+                for i in 0..arg_count {
+                    let arg = current_frame.get_value(&self.stack, (*args.get_unchecked(i)).into());
+                    *self.in_buf.get_unchecked_mut(i) = arg;
+                }
+
+                let native = compiled.ffi.get_unchecked(func);
+                let start = if self.profiler.is_some() { Some(Instant::now()) } else { None };
+                let result = (native.func)(&self.in_buf[..arg_count], &mut self.out_buf[..ret_count]);
+                if let (Some(start), Some(profiler)) = (start, &mut self.profiler) {
+                    profiler.count_native(func, start.elapsed());
+                }
+                match result {
+                    Ok(NativeStatus::Returned) => {},
+                    Ok(NativeStatus::Suspended) => {
+                        let ret_locs = ret_locs.iter().map(|slot| (*slot).into()).collect();
+                        return Ok(Control::Stop(Stop::Native { resume_ptr: insc_ptr + 1, native, ret_locs }));
+                    },
+                    Err(message) =>
+                        return Ok(Control::Jump(self.raise(compiled, insc_ptr, current_frame, Some(&native.name), message)?))
+                }
+
+                for i in 0..ret_count {
+                    let ret = *self.out_buf.get_unchecked(i);
+                    let dst = *ret_locs.get_unchecked(i);
+                    current_frame.set_value(&mut self.stack, dst.into(), ret);
+                }
+                Ok(Control::Next)
+            },
+            ControlInsc::TryBegin { handler } => {
+                self.handlers.push((self.stack.depth(), handler));
+                Ok(Control::Next)
+            },
+            ControlInsc::TryEnd => {
+                self.handlers.pop();
+                Ok(Control::Next)
+            },
+            ControlInsc::Spawn { func, args, dst } => {
+                let func = compiled.func.get_unchecked(func);
+                let task_id = self.spawn(func, args, *current_frame);
+                current_frame.set_value(&mut self.stack, dst, RtValue::from(task_id));
+                Ok(Control::Next)
+            },
+            ControlInsc::Join { task } => {
+                let task_id = current_frame.get_value(&self.stack, task).i;
+                if !self.task_finished(task_id) {
+                    return Ok(Control::Stop(Stop::Join { insc_ptr, task: task_id }));
                 }
+                Ok(Control::Next)
+            },
+            ControlInsc::Yield { values } => {
+                // values yielded by tasks are dropped
+                if self.current_task.is_none() {
+                    let frame = *current_frame;
+                    self.yielded.extend(values.iter().map(|value| frame.get_value(&self.stack, (*value).into())));
+                }
+                Ok(Control::Stop(Stop::Yield { resume_ptr: insc_ptr + 1 }))
             }
-            insc_ptr += 1;
         }
+    }
+}
+
+fn recycle_stack(spare_stacks: &mut Vec<Stack>, mut stack: Stack) {
+    stack.clear();
+    spare_stacks.push(stack);
+}
+
+fn write_native_results<R: NativeReturn>(
+    native: &NativeFunction,
+    ret_locs: &[usize],
+    rets: R,
+    stack: &mut Stack,
+    out_buf: &mut ZeroBuf<RtValue>
+) -> Result<(), String> {
+    if R::reflected_types() != native.signature.rets {
+        return Err(format!(
+            "宿主函数 `{}{}` 的返回值类型与提供的值 ({}) 不一致",
+            native.name,
+            native.signature,
+            R::reflected_types().iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+        ));
+    }
 
-        None
+    out_buf.resize(ret_locs.len());
+    rets.write_values(&mut out_buf[..ret_locs.len()])?;
+    let frame = unsafe { stack.last_frame() };
+    for (i, dst) in ret_locs.iter().enumerate() {
+        unsafe { frame.set_value(stack, *dst, out_buf[i]); }
     }
+    Ok(())
 }
@@ -0,0 +1,88 @@
+use crate::io_ctx::Type21;
+use crate::r25_300::compiled::Compiled;
+
+/// Source position of the statement compiled to the instructions at `start..end`. Lines and
+/// columns count from 1.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LineEntry {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize
+}
+
+/// A named variable, or a scalar field of one such as `p.x`, which lives in `slot` of the frame
+/// while the instruction pointer is in `start..end`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LocalVar {
+    pub name: String,
+    pub slot: usize,
+    pub ty: Type21,
+    pub start: usize,
+    pub end: usize
+}
+
+/// A frame of the call stack of a script, at the instruction `insc_ptr` of the function `func`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FrameInfo {
+    pub func: String,
+    pub insc_ptr: usize,
+    // source line of the instruction, if known
+    pub line: Option<usize>
+}
+
+impl FrameInfo {
+    pub fn at(compiled: &Compiled, insc_ptr: usize) -> Self {
+        Self {
+            func: compiled.func_at(insc_ptr).map_or_else(String::new, |func| func.name.clone()),
+            insc_ptr,
+            line: compiled.debug.as_ref()
+                .and_then(|debug| debug.line_at(compiled, insc_ptr))
+                .map(|entry| entry.line)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FuncDebug {
+    // sorted by address and not overlapping; instructions not covered have no position
+    pub lines: Vec<LineEntry>,
+    // in declaration order, so a later variable shadows an earlier one of the same name
+    pub locals: Vec<LocalVar>
+}
+
+/// Maps the code of a `Compiled` back to the source it was compiled from. `funcs[i]` describes
+/// `Compiled::func[i]`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DebugInfo {
+    // name of the source file, empty if unknown
+    pub file: String,
+    pub funcs: Vec<FuncDebug>
+}
+
+impl DebugInfo {
+    pub fn line_at(&self, compiled: &Compiled, insc_ptr: usize) -> Option<&LineEntry> {
+        let func_id = compiled.func.iter()
+            .position(|func| func.addr <= insc_ptr && insc_ptr < func.addr + func.code_len)?;
+        self.funcs.get(func_id)?.line_at(insc_ptr)
+    }
+}
+
+impl FuncDebug {
+    pub fn line_at(&self, insc_ptr: usize) -> Option<&LineEntry> {
+        let idx = self.lines.partition_point(|entry| entry.end <= insc_ptr);
+        self.lines.get(idx).filter(|entry| entry.start <= insc_ptr)
+    }
+
+    /// Variables alive at `insc_ptr`, without the ones shadowed by a later variable
+    pub fn locals_at(&self, insc_ptr: usize) -> Vec<&LocalVar> {
+        let alive = self.locals.iter()
+            .filter(|local| local.start <= insc_ptr && insc_ptr < local.end)
+            .collect::<Vec<_>>();
+        alive.iter()
+            .enumerate()
+            .filter(|(idx, local)| alive[idx + 1..].iter().all(|later| later.name != local.name))
+            .map(|(_, local)| *local)
+            .collect()
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+
+use crate::r25_300::compiled::Compiled;
+use crate::value::RtValue;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StepMode {
+    /// To the next line, entering calls
+    In,
+    /// To the next line of the same function or of a caller
+    Over,
+    /// Until the current function returns
+    Out
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+    /// The IO value at `offset` was changed by the instruction at `insc_ptr`, the one run last
+    Watch { offset: usize, old: RtValue, new: RtValue, insc_ptr: usize }
+}
+
+/// Where and why the script was stopped. The instruction at `insc_ptr` has not been executed yet.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Pause {
+    pub reason: PauseReason,
+    // the spawned task which was stopped, `None` for the main script
+    pub task: Option<i32>,
+    pub insc_ptr: usize,
+    // number of frames on the stopped stack
+    pub depth: usize
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    mode: StepMode,
+    task: Option<i32>,
+    depth: usize,
+    line: Option<usize>
+}
+
+/// Breakpoints, watchpoints and stepping for a `Combustor`, see `Combustor::set_debugger`. When
+/// the script stops, the resume returns as if it had run out of fuel, and resuming again
+/// continues from where it stopped.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    // IO offsets with the value last seen there
+    watches: Vec<(usize, RtValue)>,
+    step: Option<Step>,
+    pause: Option<Pause>,
+    // the last pause, which must not stop the script again when it is resumed
+    resumed_from: Option<(Option<i32>, usize)>,
+    // the instruction checked last, which has run by the next check
+    last_insc_ptr: Option<usize>
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the script before it executes the instruction at `insc_ptr`
+    pub fn add_breakpoint(&mut self, insc_ptr: usize) {
+        self.breakpoints.insert(insc_ptr);
+    }
+
+    /// Adds a breakpoint at the start of every statement on `line`, returning their addresses
+    pub fn add_line_breakpoint(&mut self, compiled: &Compiled, line: usize) -> Result<Vec<usize>, String> {
+        let addrs = line_addrs(compiled, line)?;
+        self.breakpoints.extend(addrs.iter().copied());
+        Ok(addrs)
+    }
+
+    pub fn remove_breakpoint(&mut self, insc_ptr: usize) -> bool {
+        self.breakpoints.remove(&insc_ptr)
+    }
+
+    /// Removes the breakpoints `add_line_breakpoint` added for `line`, returning the addresses
+    /// which had one
+    pub fn remove_line_breakpoint(&mut self, compiled: &Compiled, line: usize) -> Result<Vec<usize>, String> {
+        let mut addrs = line_addrs(compiled, line)?;
+        addrs.retain(|addr| self.breakpoints.remove(addr));
+        Ok(addrs)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stops the script after an instruction changes the IO value at byte `offset`. Changes made
+    /// by the host between resumes are not reported.
+    pub fn add_watch(&mut self, offset: usize) {
+        if !self.watches.iter().any(|(watched, _)| *watched == offset) {
+            self.watches.push((offset, RtValue::from(0)));
+        }
+    }
+
+    pub fn remove_watch(&mut self, offset: usize) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|(watched, _)| *watched != offset);
+        self.watches.len() != len
+    }
+
+    /// Why the last resume stopped, or `None` if the debugger did not stop it
+    pub fn pause(&self) -> Option<&Pause> {
+        self.pause.as_ref()
+    }
+
+    /// Makes the next resume stop again after one step from the current pause. Breakpoints and
+    /// watchpoints still stop the script earlier, and the step is dropped when it does.
+    pub fn step(&mut self, compiled: &Compiled, mode: StepMode) -> Result<(), String> {
+        let Some(pause) = &self.pause else {
+            return Err("脚本没有停下".to_string());
+        };
+
+        self.step = Some(Step {
+            mode,
+            task: pause.task,
+            depth: pause.depth,
+            line: line_at(compiled, pause.insc_ptr)
+        });
+        Ok(())
+    }
+
+    /// Prepares for a resume of the script, reading the watched values from `io` so that only
+    /// the changes made by the script are reported
+    ///
+    /// # Safety
+    /// `io` must point to an IO context with a value at every watched offset.
+    pub unsafe fn start_resume(&mut self, io: *const u8) {
+        if let Some(pause) = self.pause.take() {
+            self.resumed_from = Some((pause.task, pause.insc_ptr));
+        }
+        self.last_insc_ptr = None;
+        for (offset, value) in self.watches.iter_mut() {
+            *value = (io.add(*offset) as *const RtValue).read();
+        }
+    }
+
+    /// Forgets the pause and the step in progress, when the script is started again
+    pub fn reset(&mut self) {
+        self.step = None;
+        self.pause = None;
+        self.resumed_from = None;
+        self.last_insc_ptr = None;
+    }
+
+    /// Called before each instruction while a debugger is attached. Returns whether the script
+    /// stops before the instruction at `insc_ptr` of `task`, whose stack is `depth` frames deep.
+    ///
+    /// # Safety
+    /// `io` must point to an IO context with a value at every watched offset.
+    pub unsafe fn check(
+        &mut self,
+        compiled: &Compiled,
+        io: *const u8,
+        task: Option<i32>,
+        depth: usize,
+        insc_ptr: usize
+    ) -> bool {
+        // the watched values were read when the resume started, so nothing changed before the
+        // first instruction
+        let changed_by = self.last_insc_ptr.replace(insc_ptr).unwrap_or(insc_ptr);
+        let mut reason = None;
+        for (offset, value) in self.watches.iter_mut() {
+            let new = (io.add(*offset) as *const RtValue).read();
+            if new != *value && reason.is_none() {
+                reason = Some(PauseReason::Watch { offset: *offset, old: *value, new, insc_ptr: changed_by });
+            }
+            *value = new;
+        }
+
+        // the instruction the script stopped before is run without checking it again
+        if self.resumed_from == Some((task, insc_ptr)) {
+            self.resumed_from = None;
+        } else if reason.is_none() {
+            if self.breakpoints.contains(&insc_ptr) {
+                reason = Some(PauseReason::Breakpoint);
+            } else if self.step.is_some_and(|step| step.task == task && step_done(step, compiled, depth, insc_ptr)) {
+                reason = Some(PauseReason::Step);
+            }
+        }
+
+        let Some(reason) = reason else {
+            return false;
+        };
+        self.step = None;
+        self.resumed_from = None;
+        self.pause = Some(Pause { reason, task, insc_ptr, depth });
+        true
+    }
+}
+
+fn line_addrs(compiled: &Compiled, line: usize) -> Result<Vec<usize>, String> {
+    let Some(debug) = &compiled.debug else {
+        return Err("字节码没有调试信息".to_string());
+    };
+
+    let addrs = debug.funcs.iter()
+        .flat_map(|func| func.lines.iter())
+        .filter(|entry| entry.line == line)
+        .map(|entry| entry.start)
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(format!("行 {} 没有对应的代码", line));
+    }
+    Ok(addrs)
+}
+
+fn line_at(compiled: &Compiled, insc_ptr: usize) -> Option<usize> {
+    compiled.debug.as_ref()?.line_at(compiled, insc_ptr).map(|entry| entry.line)
+}
+
+// without debug info, every instruction is a line of its own
+fn step_done(step: Step, compiled: &Compiled, depth: usize, insc_ptr: usize) -> bool {
+    let new_line = || step.line.is_none() || line_at(compiled, insc_ptr) != step.line;
+    match step.mode {
+        StepMode::In => depth != step.depth || new_line(),
+        StepMode::Over => depth < step.depth || (depth == step.depth && new_line()),
+        StepMode::Out => depth < step.depth
+    }
+}
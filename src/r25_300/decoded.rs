@@ -0,0 +1,223 @@
+use std::mem::size_of;
+
+use crate::r25_300::compact::{CompactCode, CompactInsc};
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::value::RtValue;
+
+/// `CompactInsc` with slots resolved to byte offsets from slot 0 of the running frame, so that
+/// operands are read through one base pointer. Instructions which change frames or stop the run
+/// are all `Control`, and are run from `DecodedCode::compact`. Some pairs of instructions are
+/// fused into one, see `fuse`.
+#[derive(Debug, Clone, Copy)]
+pub enum DecodedInsc {
+    Const { dst: u16, value: RtValue },
+    Dup { src: u16, dst: u16 },
+
+    AddInt { lhs: u16, rhs: u16, dst: u16 },
+    AddFloat { lhs: u16, rhs: u16, dst: u16 },
+    SubInt { lhs: u16, rhs: u16, dst: u16 },
+    SubFloat { lhs: u16, rhs: u16, dst: u16 },
+    MulInt { lhs: u16, rhs: u16, dst: u16 },
+    MulFloat { lhs: u16, rhs: u16, dst: u16 },
+    DivInt { lhs: u16, rhs: u16, dst: u16 },
+    DivFloat { lhs: u16, rhs: u16, dst: u16 },
+    ModInt { lhs: u16, rhs: u16, dst: u16 },
+
+    NegateInt { src: u16, dst: u16 },
+    NegateFloat { src: u16, dst: u16 },
+
+    Eq { lhs: u16, rhs: u16, dst: u16 },
+    Ne { lhs: u16, rhs: u16, dst: u16 },
+
+    LtInt { lhs: u16, rhs: u16, dst: u16 },
+    LtFloat { lhs: u16, rhs: u16, dst: u16 },
+    LeInt { lhs: u16, rhs: u16, dst: u16 },
+    LeFloat { lhs: u16, rhs: u16, dst: u16 },
+
+    And { lhs: u16, rhs: u16, dst: u16 },
+    Or { lhs: u16, rhs: u16, dst: u16 },
+    Not { src: u16, dst: u16 },
+
+    Round { src: u16, dst: u16 },
+    Floor { src: u16, dst: u16 },
+    Ceil { src: u16, dst: u16 },
+    ToFloat { src: u16, dst: u16 },
+    Float2Int { src: u16, dst: u16 },
+    Round2Int { src: u16, dst: u16 },
+    Floor2Int { src: u16, dst: u16 },
+    Ceil2Int { src: u16, dst: u16 },
+
+    Bool2Int { src: u16, dst: u16 },
+    Int2Bool { src: u16, dst: u16 },
+    Bool2Float { src: u16, dst: u16 },
+    Float2Bool { src: u16, dst: u16 },
+
+    Math1 { op: MathOp1, src: u16, dst: u16 },
+    Math2 { op: MathOp2, lhs: u16, rhs: u16, dst: u16 },
+    // `operands` indexes `DecodedCode::math3`
+    Math3 { op: MathOp3, operands: u32 },
+
+    Jmp { dst: u32 },
+    JmpIf { check: u16, dst: u32 },
+
+    IOSetValue { src: u16, offset: u32 },
+    IOGetValue { dst: u16, offset: u32 },
+
+    Control,
+
+    // an operation followed by `Dup { src: dst, dst: copy }`
+    AddIntDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    AddFloatDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    SubIntDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    SubFloatDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    MulIntDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    MulFloatDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    DivFloatDup { lhs: u16, rhs: u16, dst: u16, copy: u16 },
+    // `Not { src, dst: not }` followed by `JmpIf { check: not, dst }`
+    NotJmpIf { src: u16, not: u16, dst: u32 },
+    // `JmpIf { check, dst }` followed by `Jmp { dst: other }`
+    JmpIfJmp { check: u16, dst: u32, other: u32 },
+    // `Dup { src, dst: copy }` followed by `Jmp { dst }`
+    DupJmp { src: u16, copy: u16, dst: u32 }
+}
+
+/// The most instructions a `DecodedInsc` runs
+pub const MAX_FUSED: u64 = 2;
+
+/// `Compiled::code` lowered to `DecodedInsc`, at the same addresses as `Compiled::code` and
+/// `compact`. A fused instruction runs the instruction at its address and the one after it,
+/// which is kept as well for jumps to its address.
+#[derive(Debug, Clone)]
+pub struct DecodedCode {
+    pub code: Vec<DecodedInsc>,
+    // `[a, b, c, dst]` of `Math3`
+    pub math3: Vec<[u16; 4]>,
+    pub compact: CompactCode
+}
+
+impl DecodedCode {
+    /// Lowers the code of `compiled`, or returns `None` if it cannot be lowered to `CompactCode`
+    /// or some slot is too far from the base of its frame
+    pub fn lower(compiled: &Compiled) -> Option<Self> {
+        let compact = CompactCode::lower(compiled)?;
+        let mut code = compact.code.iter().map(decode_insc).collect::<Option<Vec<_>>>()?;
+        // in order, so that `code[i]` is fused into `code[i - 1]` before being fused itself
+        for i in 1..code.len() {
+            if let Some(fused) = fuse(&code[i - 1], &code[i]) {
+                code[i - 1] = fused;
+            }
+        }
+        let math3 = compact.math3.iter()
+            .map(|operands| Some([o(&operands[0])?, o(&operands[1])?, o(&operands[2])?, o(&operands[3])?]))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { code, math3, compact })
+    }
+}
+
+// the byte offset of a slot from slot 0
+fn o(slot: &u16) -> Option<u16> {
+    slot.checked_mul(size_of::<RtValue>() as u16)
+}
+
+fn decode_insc(insc: &CompactInsc) -> Option<DecodedInsc> {
+    Some(match insc {
+        CompactInsc::Const { dst, value } => DecodedInsc::Const { dst: o(dst)?, value: *value },
+        CompactInsc::Dup { src, dst } => DecodedInsc::Dup { src: o(src)?, dst: o(dst)? },
+
+        CompactInsc::AddInt { lhs, rhs, dst } => DecodedInsc::AddInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::AddFloat { lhs, rhs, dst } => DecodedInsc::AddFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::SubInt { lhs, rhs, dst } => DecodedInsc::SubInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::SubFloat { lhs, rhs, dst } => DecodedInsc::SubFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::MulInt { lhs, rhs, dst } => DecodedInsc::MulInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::MulFloat { lhs, rhs, dst } => DecodedInsc::MulFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::DivInt { lhs, rhs, dst } => DecodedInsc::DivInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::DivFloat { lhs, rhs, dst } => DecodedInsc::DivFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::ModInt { lhs, rhs, dst } => DecodedInsc::ModInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+
+        CompactInsc::NegateInt { src, dst } => DecodedInsc::NegateInt { src: o(src)?, dst: o(dst)? },
+        CompactInsc::NegateFloat { src, dst } => DecodedInsc::NegateFloat { src: o(src)?, dst: o(dst)? },
+
+        CompactInsc::Eq { lhs, rhs, dst } => DecodedInsc::Eq { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::Ne { lhs, rhs, dst } => DecodedInsc::Ne { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+
+        CompactInsc::LtInt { lhs, rhs, dst } => DecodedInsc::LtInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::LtFloat { lhs, rhs, dst } => DecodedInsc::LtFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::LeInt { lhs, rhs, dst } => DecodedInsc::LeInt { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::LeFloat { lhs, rhs, dst } => DecodedInsc::LeFloat { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+
+        CompactInsc::And { lhs, rhs, dst } => DecodedInsc::And { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::Or { lhs, rhs, dst } => DecodedInsc::Or { lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::Not { src, dst } => DecodedInsc::Not { src: o(src)?, dst: o(dst)? },
+
+        CompactInsc::Round { src, dst } => DecodedInsc::Round { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Floor { src, dst } => DecodedInsc::Floor { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Ceil { src, dst } => DecodedInsc::Ceil { src: o(src)?, dst: o(dst)? },
+        CompactInsc::ToFloat { src, dst } => DecodedInsc::ToFloat { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Float2Int { src, dst } => DecodedInsc::Float2Int { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Round2Int { src, dst } => DecodedInsc::Round2Int { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Floor2Int { src, dst } => DecodedInsc::Floor2Int { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Ceil2Int { src, dst } => DecodedInsc::Ceil2Int { src: o(src)?, dst: o(dst)? },
+
+        CompactInsc::Bool2Int { src, dst } => DecodedInsc::Bool2Int { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Int2Bool { src, dst } => DecodedInsc::Int2Bool { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Bool2Float { src, dst } => DecodedInsc::Bool2Float { src: o(src)?, dst: o(dst)? },
+        CompactInsc::Float2Bool { src, dst } => DecodedInsc::Float2Bool { src: o(src)?, dst: o(dst)? },
+
+        CompactInsc::Math1 { op, src, dst } => DecodedInsc::Math1 { op: *op, src: o(src)?, dst: o(dst)? },
+        CompactInsc::Math2 { op, lhs, rhs, dst } =>
+            DecodedInsc::Math2 { op: *op, lhs: o(lhs)?, rhs: o(rhs)?, dst: o(dst)? },
+        CompactInsc::Math3 { op, operands } => DecodedInsc::Math3 { op: *op, operands: *operands },
+
+        CompactInsc::Jmp { dst } => DecodedInsc::Jmp { dst: *dst },
+        CompactInsc::JmpIf { check, dst } => DecodedInsc::JmpIf { check: o(check)?, dst: *dst },
+
+        CompactInsc::IOSetValue { src, offset } => DecodedInsc::IOSetValue { src: o(src)?, offset: *offset },
+        CompactInsc::IOGetValue { dst, offset } => DecodedInsc::IOGetValue { dst: o(dst)?, offset: *offset },
+
+        CompactInsc::Call { .. }
+        | CompactInsc::Return { .. }
+        | CompactInsc::CallFFI { .. }
+        | CompactInsc::TryBegin { .. }
+        | CompactInsc::TryEnd
+        | CompactInsc::Spawn { .. }
+        | CompactInsc::Join { .. }
+        | CompactInsc::Yield { .. } => DecodedInsc::Control
+    })
+}
+
+// the instruction running `first` and then `second`, if there is one
+fn fuse(first: &DecodedInsc, second: &DecodedInsc) -> Option<DecodedInsc> {
+    let &DecodedInsc::Dup { src, dst: copy } = second else {
+        return match (*first, *second) {
+            (DecodedInsc::Not { src, dst: not }, DecodedInsc::JmpIf { check, dst }) if check == not =>
+                Some(DecodedInsc::NotJmpIf { src, not, dst }),
+            (DecodedInsc::JmpIf { check, dst }, DecodedInsc::Jmp { dst: other }) =>
+                Some(DecodedInsc::JmpIfJmp { check, dst, other }),
+            (DecodedInsc::Dup { src, dst: copy }, DecodedInsc::Jmp { dst }) =>
+                Some(DecodedInsc::DupJmp { src, copy, dst }),
+            _ => None
+        };
+    };
+
+    Some(match *first {
+        DecodedInsc::AddInt { lhs, rhs, dst } if dst == src => DecodedInsc::AddIntDup { lhs, rhs, dst, copy },
+        DecodedInsc::AddFloat { lhs, rhs, dst } if dst == src => DecodedInsc::AddFloatDup { lhs, rhs, dst, copy },
+        DecodedInsc::SubInt { lhs, rhs, dst } if dst == src => DecodedInsc::SubIntDup { lhs, rhs, dst, copy },
+        DecodedInsc::SubFloat { lhs, rhs, dst } if dst == src => DecodedInsc::SubFloatDup { lhs, rhs, dst, copy },
+        DecodedInsc::MulInt { lhs, rhs, dst } if dst == src => DecodedInsc::MulIntDup { lhs, rhs, dst, copy },
+        DecodedInsc::MulFloat { lhs, rhs, dst } if dst == src => DecodedInsc::MulFloatDup { lhs, rhs, dst, copy },
+        DecodedInsc::DivFloat { lhs, rhs, dst } if dst == src => DecodedInsc::DivFloatDup { lhs, rhs, dst, copy },
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::r25_300::decoded::DecodedInsc;
+
+    #[test]
+    fn test() {
+        assert_eq!(std::mem::size_of::<DecodedInsc>(), 12);
+    }
+}
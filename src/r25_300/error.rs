@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::debug::FrameInfo;
+
+/// An error raised by a native function or by an instruction, such as an integer division by zero,
+/// and not caught by the script
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuntimeError {
+    // the native which failed, `None` if the instruction itself failed
+    pub native: Option<String>,
+    pub message: String,
+    // the script function containing the failing instruction, and its address
+    pub func: String,
+    pub insc_ptr: usize,
+    // the call stack at the failing instruction, innermost first
+    pub backtrace: Vec<FrameInfo>
+}
+
+impl RuntimeError {
+    /// `positions` are the addresses of the frames, outermost first, see `Stack::positions`
+    pub fn new(compiled: &Compiled, native: Option<&str>, message: String, positions: &[usize]) -> Self {
+        let backtrace = positions.iter()
+            .rev()
+            .map(|insc_ptr| FrameInfo::at(compiled, *insc_ptr))
+            .collect::<Vec<_>>();
+        Self {
+            native: native.map(str::to_string),
+            message,
+            func: backtrace.first().map_or_else(String::new, |frame| frame.func.clone()),
+            insc_ptr: positions.last().copied().unwrap_or_default(),
+            backtrace
+        }
+    }
+}
+
+/// With `{:#}`, the backtrace follows the message in the layout of a Rust panic
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(native) = &self.native {
+            write!(f, "宿主函数 `{}` 出错: ", native)?;
+        } else {
+            write!(f, "运行时错误: ")?;
+        }
+        write!(f, "{} (位于函数 `{}`，指令 {})", self.message, self.func, self.insc_ptr)?;
+
+        if f.alternate() && !self.backtrace.is_empty() {
+            write!(f, "\n栈回溯:")?;
+            for (idx, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n{:>4}: {} (指令 {})", idx, frame.func, frame.insc_ptr)?;
+                if let Some(line) = frame.line {
+                    write!(f, "\n             at 行 {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// The message of an integer division or remainder by `rhs` which has no result
+pub fn int_division_fault(rhs: i32) -> String {
+    if rhs == 0 {
+        "不能除以 0".to_string()
+    } else {
+        "整数除法溢出".to_string()
+    }
+}
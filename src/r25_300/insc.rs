@@ -1,4 +1,5 @@
 use std::fmt::{Display, Formatter};
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
 use crate::value::RtValue;
 #[cfg(test)] use variant_count::VariantCount;
 
@@ -37,9 +38,19 @@ pub enum Insc {
     Floor { src: usize, dst: usize },
     Ceil { src: usize, dst: usize },
     ToFloat { src: usize, dst: usize },
+    Float2Int { src: usize, dst: usize },
+    Round2Int { src: usize, dst: usize },
+    Floor2Int { src: usize, dst: usize },
+    Ceil2Int { src: usize, dst: usize },
 
     Bool2Int { src: usize, dst: usize },
     Int2Bool { src: usize, dst: usize },
+    Bool2Float { src: usize, dst: usize },
+    Float2Bool { src: usize, dst: usize },
+
+    Math1 { op: MathOp1, src: usize, dst: usize },
+    Math2 { op: MathOp2, lhs: usize, rhs: usize, dst: usize },
+    Math3 { op: MathOp3, a: usize, b: usize, c: usize, dst: usize },
 
     Jmp { dst: usize },
     JmpIf { check: usize, dst: usize },
@@ -49,51 +60,126 @@ pub enum Insc {
     IOSetValue { offset: usize, src: usize },
     IOGetValue { offset: usize, dst: usize },
     CallFFI { func: usize, args: Box<[usize]>, ret_locs: Box<[usize]> },
+    // installs an error handler at `handler` for the current frame; `TryEnd` removes it
+    TryBegin { handler: usize },
+    TryEnd,
+    // starts `func` as a separate task, storing its id (an int) into `dst`
+    Spawn { func: usize, args: Box<[usize]>, dst: usize },
+    // blocks until the task whose id is in `task` finishes
+    Join { task: usize },
 
-    Yield
+    // `values` are handed to the host, see `Combustor::yielded`
+    Yield { values: Box<[usize]> }
+}
+
+impl Insc {
+    /// Calls `f` with every stack slot the instruction reads or writes
+    pub fn for_each_slot(&self, mut f: impl FnMut(usize)) {
+        match self {
+            Insc::Const { dst, .. } => f(*dst),
+
+            Insc::Dup { src, dst }
+            | Insc::NegateInt { src, dst }
+            | Insc::NegateFloat { src, dst }
+            | Insc::Not { src, dst }
+            | Insc::Round { src, dst }
+            | Insc::Floor { src, dst }
+            | Insc::Ceil { src, dst }
+            | Insc::ToFloat { src, dst }
+            | Insc::Float2Int { src, dst }
+            | Insc::Round2Int { src, dst }
+            | Insc::Floor2Int { src, dst }
+            | Insc::Ceil2Int { src, dst }
+            | Insc::Bool2Int { src, dst }
+            | Insc::Int2Bool { src, dst }
+            | Insc::Bool2Float { src, dst }
+            | Insc::Float2Bool { src, dst }
+            | Insc::Math1 { src, dst, .. } => [*src, *dst].into_iter().for_each(f),
+
+            Insc::AddInt { lhs, rhs, dst }
+            | Insc::AddFloat { lhs, rhs, dst }
+            | Insc::SubInt { lhs, rhs, dst }
+            | Insc::SubFloat { lhs, rhs, dst }
+            | Insc::MulInt { lhs, rhs, dst }
+            | Insc::MulFloat { lhs, rhs, dst }
+            | Insc::DivInt { lhs, rhs, dst }
+            | Insc::DivFloat { lhs, rhs, dst }
+            | Insc::ModInt { lhs, rhs, dst }
+            | Insc::Eq { lhs, rhs, dst }
+            | Insc::Ne { lhs, rhs, dst }
+            | Insc::LtInt { lhs, rhs, dst }
+            | Insc::LtFloat { lhs, rhs, dst }
+            | Insc::LeInt { lhs, rhs, dst }
+            | Insc::LeFloat { lhs, rhs, dst }
+            | Insc::And { lhs, rhs, dst }
+            | Insc::Or { lhs, rhs, dst }
+            | Insc::Math2 { lhs, rhs, dst, .. } => [*lhs, *rhs, *dst].into_iter().for_each(f),
+
+            Insc::Math3 { a, b, c, dst, .. } => [*a, *b, *c, *dst].into_iter().for_each(f),
+
+            Insc::Jmp { .. } | Insc::TryBegin { .. } | Insc::TryEnd => {},
+            Insc::JmpIf { check, .. } => f(*check),
+            Insc::Call { args, ret_locs, .. } | Insc::CallFFI { args, ret_locs, .. } =>
+                args.iter().chain(ret_locs.iter()).copied().for_each(f),
+            Insc::Return { rets: slots } | Insc::Yield { values: slots } => slots.iter().copied().for_each(f),
+
+            Insc::IOSetValue { src: slot, .. } | Insc::IOGetValue { dst: slot, .. } | Insc::Join { task: slot } => f(*slot),
+            Insc::Spawn { args, dst, .. } => args.iter().copied().chain([*dst]).for_each(f)
+        }
+    }
 }
 
 impl Display for Insc {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Insc::Const { value, dst } => writeln!(f, "mov ${:X}, %{}", unsafe { value.repr }, dst),
-            Insc::Dup { src, dst } => writeln!(f, "mov ${}, %{}", src, dst),
-
-            Insc::AddInt { lhs, rhs, dst } => writeln!(f, "add %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::AddFloat { lhs, rhs, dst } => writeln!(f, "fadd %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::SubInt { lhs, rhs, dst } => writeln!(f, "sub %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::SubFloat { lhs, rhs, dst } => writeln!(f, "fsub %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::MulInt { lhs, rhs, dst } => writeln!(f, "mul %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::MulFloat { lhs, rhs, dst } => writeln!(f, "fmul %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::DivInt { lhs, rhs, dst } => writeln!(f, "div %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::DivFloat { lhs, rhs, dst } => writeln!(f, "fdiv %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::ModInt { lhs, rhs, dst } => writeln!(f, "mod %{}, %{}, %{}", lhs, rhs, dst),
-
-            Insc::NegateInt { src, dst } => writeln!(f, "neg %{}, %{}", src, dst),
-            Insc::NegateFloat { src, dst } => writeln!(f, "fneg %{}, %{}", src, dst),
-
-            Insc::Eq { lhs, rhs, dst } => writeln!(f, "eq %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::Ne { lhs, rhs, dst } => writeln!(f, "ne %{}, %{}, %{}", lhs, rhs, dst),
-
-            Insc::LtInt { lhs, rhs, dst } => writeln!(f, "lt %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::LtFloat { lhs, rhs, dst } => writeln!(f, "flt %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::LeInt { lhs, rhs, dst } => writeln!(f, "le %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::LeFloat { lhs, rhs, dst } => writeln!(f, "fle %{}, %{}, %{}", lhs, rhs, dst),
-
-            Insc::And { lhs, rhs, dst } => writeln!(f, "and %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::Or { lhs, rhs, dst } => writeln!(f, "or %{}, %{}, %{}", lhs, rhs, dst),
-            Insc::Not { src, dst } => writeln!(f, "not %{}, %{}", src, dst),
-
-            Insc::Round { src, dst } => writeln!(f, "round %{}, %{}", src, dst),
-            Insc::Floor { src, dst } => writeln!(f, "floor %{}, %{}", src, dst),
-            Insc::Ceil { src, dst } => writeln!(f, "ceil %{}, %{}", src, dst),
-            Insc::ToFloat { src, dst } => writeln!(f, "tofloat %{}, %{}", src, dst),
-
-            Insc::Bool2Int { src, dst } => writeln!(f, "b2i %{}, %{}", src, dst),
-            Insc::Int2Bool { src, dst } => writeln!(f, "i2b %{}, %{}", src, dst),
-
-            Insc::Jmp { dst } => writeln!(f, "jmp {}", dst),
-            Insc::JmpIf { check, dst } => writeln!(f, "jmpif %{}, {}", check, dst),
+            Insc::Const { value, dst } => write!(f, "mov ${:X}, %{}", unsafe { value.repr }, dst),
+            Insc::Dup { src, dst } => write!(f, "mov %{}, %{}", src, dst),
+
+            Insc::AddInt { lhs, rhs, dst } => write!(f, "add %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::AddFloat { lhs, rhs, dst } => write!(f, "fadd %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::SubInt { lhs, rhs, dst } => write!(f, "sub %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::SubFloat { lhs, rhs, dst } => write!(f, "fsub %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::MulInt { lhs, rhs, dst } => write!(f, "mul %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::MulFloat { lhs, rhs, dst } => write!(f, "fmul %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::DivInt { lhs, rhs, dst } => write!(f, "div %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::DivFloat { lhs, rhs, dst } => write!(f, "fdiv %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::ModInt { lhs, rhs, dst } => write!(f, "mod %{}, %{}, %{}", lhs, rhs, dst),
+
+            Insc::NegateInt { src, dst } => write!(f, "neg %{}, %{}", src, dst),
+            Insc::NegateFloat { src, dst } => write!(f, "fneg %{}, %{}", src, dst),
+
+            Insc::Eq { lhs, rhs, dst } => write!(f, "eq %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::Ne { lhs, rhs, dst } => write!(f, "ne %{}, %{}, %{}", lhs, rhs, dst),
+
+            Insc::LtInt { lhs, rhs, dst } => write!(f, "lt %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::LtFloat { lhs, rhs, dst } => write!(f, "flt %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::LeInt { lhs, rhs, dst } => write!(f, "le %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::LeFloat { lhs, rhs, dst } => write!(f, "fle %{}, %{}, %{}", lhs, rhs, dst),
+
+            Insc::And { lhs, rhs, dst } => write!(f, "and %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::Or { lhs, rhs, dst } => write!(f, "or %{}, %{}, %{}", lhs, rhs, dst),
+            Insc::Not { src, dst } => write!(f, "not %{}, %{}", src, dst),
+
+            Insc::Round { src, dst } => write!(f, "round %{}, %{}", src, dst),
+            Insc::Floor { src, dst } => write!(f, "floor %{}, %{}", src, dst),
+            Insc::Ceil { src, dst } => write!(f, "ceil %{}, %{}", src, dst),
+            Insc::ToFloat { src, dst } => write!(f, "tofloat %{}, %{}", src, dst),
+            Insc::Float2Int { src, dst } => write!(f, "f2i %{}, %{}", src, dst),
+            Insc::Round2Int { src, dst } => write!(f, "round2i %{}, %{}", src, dst),
+            Insc::Floor2Int { src, dst } => write!(f, "floor2i %{}, %{}", src, dst),
+            Insc::Ceil2Int { src, dst } => write!(f, "ceil2i %{}, %{}", src, dst),
+
+            Insc::Bool2Int { src, dst } => write!(f, "b2i %{}, %{}", src, dst),
+            Insc::Int2Bool { src, dst } => write!(f, "i2b %{}, %{}", src, dst),
+            Insc::Bool2Float { src, dst } => write!(f, "b2f %{}, %{}", src, dst),
+            Insc::Float2Bool { src, dst } => write!(f, "f2b %{}, %{}", src, dst),
+
+            Insc::Math1 { op, src, dst } => write!(f, "math.{} %{}, %{}", op, src, dst),
+            Insc::Math2 { op, lhs, rhs, dst } => write!(f, "math.{} %{}, %{}, %{}", op, lhs, rhs, dst),
+            Insc::Math3 { op, a, b, c, dst } => write!(f, "math.{} %{}, %{}, %{}, %{}", op, a, b, c, dst),
+
+            Insc::Jmp { dst } => write!(f, "jmp {}", dst),
+            Insc::JmpIf { check, dst } => write!(f, "jmpif %{}, {}", check, dst),
             Insc::Call { func, args, ret_locs } => {
                 write!(f, "call @{}(", func)?;
                 for (idx, arg) in args.iter().enumerate() {
@@ -109,13 +195,13 @@ impl Display for Insc {
                         write!(f, ", ")?;
                     }
                 }
-                writeln!(f, "])")
+                write!(f, "])")
             }
             Insc::Return { rets } => {
-                if rets.len() == 0 {
-                    writeln!(f, "ret")
+                if rets.is_empty() {
+                    write!(f, "ret")
                 } else if rets.len() == 1 {
-                    writeln!(f, "ret %{}", rets[0])
+                    write!(f, "ret %{}", rets[0])
                 } else {
                     write!(f, "ret [")?;
                     for (idx, ret) in rets.iter().enumerate() {
@@ -124,12 +210,12 @@ impl Display for Insc {
                             write!(f, ", ")?;
                         }
                     }
-                    writeln!(f, "]")
+                    write!(f, "]")
                 }
             },
 
-            Insc::IOSetValue { offset, src } => writeln!(f, "ioset !{:X} %{}", offset, src),
-            Insc::IOGetValue { offset, dst } => writeln!(f, "ioget !{:X} %{}", offset, dst),
+            Insc::IOSetValue { offset, src } => write!(f, "ioset !{} %{}", offset, src),
+            Insc::IOGetValue { offset, dst } => write!(f, "ioget !{} %{}", offset, dst),
             Insc::CallFFI { func, args, ret_locs } => {
                 write!(f, "call-ffi @{}(", func)?;
                 for (idx, arg) in args.iter().enumerate() {
@@ -145,9 +231,37 @@ impl Display for Insc {
                         write!(f, ", ")?;
                     }
                 }
-                writeln!(f, "])")
+                write!(f, "])")
             }
-            Insc::Yield => writeln!(f, "yield"),
+            Insc::TryBegin { handler } => write!(f, "try {}", handler),
+            Insc::TryEnd => write!(f, "endtry"),
+            Insc::Spawn { func, args, dst } => {
+                write!(f, "spawn @{}(", func)?;
+                for (idx, arg) in args.iter().enumerate() {
+                    write!(f, "%{}", arg)?;
+                    if idx != args.len() - 1 {
+                        write!(f, ", ")?;
+                    }
+                }
+                write!(f, "; %{})", dst)
+            },
+            Insc::Join { task } => write!(f, "join %{}", task),
+            Insc::Yield { values } => {
+                if values.is_empty() {
+                    write!(f, "yield")
+                } else if values.len() == 1 {
+                    write!(f, "yield %{}", values[0])
+                } else {
+                    write!(f, "yield [")?;
+                    for (idx, value) in values.iter().enumerate() {
+                        write!(f, "%{}", value)?;
+                        if idx != values.len() - 1 {
+                            write!(f, ", ")?;
+                        }
+                    }
+                    write!(f, "]")
+                }
+            },
         }
     }
 }
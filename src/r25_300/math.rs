@@ -0,0 +1,267 @@
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+
+use crate::value::RtValue;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MathOp1 {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Sqrt,
+    Exp,
+    Ln,
+    AbsInt,
+    AbsFloat,
+    EaseInQuad,
+    EaseOutQuad,
+    EaseInOutQuad,
+    EaseInCubic,
+    EaseOutCubic,
+    EaseInOutCubic,
+    EaseInSine,
+    EaseOutSine,
+    EaseInOutSine,
+    EaseInExpo,
+    EaseOutExpo,
+    EaseInOutExpo
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MathOp2 {
+    Atan2,
+    Pow,
+    Fmod,
+    MinInt,
+    MinFloat,
+    MaxInt,
+    MaxFloat
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MathOp3 {
+    Lerp,
+    ClampInt,
+    ClampFloat,
+    Smoothstep
+}
+
+impl MathOp1 {
+    /// Every operation, in declaration order
+    pub const ALL: [MathOp1; 23] = [
+        MathOp1::Sin,
+        MathOp1::Cos,
+        MathOp1::Tan,
+        MathOp1::Asin,
+        MathOp1::Acos,
+        MathOp1::Atan,
+        MathOp1::Sqrt,
+        MathOp1::Exp,
+        MathOp1::Ln,
+        MathOp1::AbsInt,
+        MathOp1::AbsFloat,
+        MathOp1::EaseInQuad,
+        MathOp1::EaseOutQuad,
+        MathOp1::EaseInOutQuad,
+        MathOp1::EaseInCubic,
+        MathOp1::EaseOutCubic,
+        MathOp1::EaseInOutCubic,
+        MathOp1::EaseInSine,
+        MathOp1::EaseOutSine,
+        MathOp1::EaseInOutSine,
+        MathOp1::EaseInExpo,
+        MathOp1::EaseOutExpo,
+        MathOp1::EaseInOutExpo
+    ];
+}
+
+impl MathOp2 {
+    /// Every operation, in declaration order
+    pub const ALL: [MathOp2; 7] = [
+        MathOp2::Atan2,
+        MathOp2::Pow,
+        MathOp2::Fmod,
+        MathOp2::MinInt,
+        MathOp2::MinFloat,
+        MathOp2::MaxInt,
+        MathOp2::MaxFloat
+    ];
+}
+
+impl MathOp3 {
+    /// Every operation, in declaration order
+    pub const ALL: [MathOp3; 4] = [
+        MathOp3::Lerp,
+        MathOp3::ClampInt,
+        MathOp3::ClampFloat,
+        MathOp3::Smoothstep
+    ];
+}
+
+impl MathOp1 {
+    pub fn eval(self, x: RtValue) -> RtValue {
+        let (i, f) = unsafe { (x.i, x.f) };
+        match self {
+            MathOp1::Sin => RtValue::from(f.sin()),
+            MathOp1::Cos => RtValue::from(f.cos()),
+            MathOp1::Tan => RtValue::from(f.tan()),
+            MathOp1::Asin => RtValue::from(f.asin()),
+            MathOp1::Acos => RtValue::from(f.acos()),
+            MathOp1::Atan => RtValue::from(f.atan()),
+            MathOp1::Sqrt => RtValue::from(f.sqrt()),
+            MathOp1::Exp => RtValue::from(f.exp()),
+            MathOp1::Ln => RtValue::from(f.ln()),
+            MathOp1::AbsInt => RtValue::from(i.wrapping_abs()),
+            MathOp1::AbsFloat => RtValue::from(f.abs()),
+            MathOp1::EaseInQuad => RtValue::from(f * f),
+            MathOp1::EaseOutQuad => RtValue::from(1.0 - (1.0 - f) * (1.0 - f)),
+            MathOp1::EaseInOutQuad => RtValue::from(if f < 0.5 {
+                2.0 * f * f
+            } else {
+                1.0 - (-2.0 * f + 2.0) * (-2.0 * f + 2.0) / 2.0
+            }),
+            MathOp1::EaseInCubic => RtValue::from(f * f * f),
+            MathOp1::EaseOutCubic => RtValue::from(1.0 - (1.0 - f) * (1.0 - f) * (1.0 - f)),
+            MathOp1::EaseInOutCubic => RtValue::from(if f < 0.5 {
+                4.0 * f * f * f
+            } else {
+                1.0 - (-2.0 * f + 2.0) * (-2.0 * f + 2.0) * (-2.0 * f + 2.0) / 2.0
+            }),
+            MathOp1::EaseInSine => RtValue::from(1.0 - (f * PI / 2.0).cos()),
+            MathOp1::EaseOutSine => RtValue::from((f * PI / 2.0).sin()),
+            MathOp1::EaseInOutSine => RtValue::from(-((PI * f).cos() - 1.0) / 2.0),
+            MathOp1::EaseInExpo => RtValue::from(if f == 0.0 {
+                0.0
+            } else {
+                2.0f32.powf(10.0 * f - 10.0)
+            }),
+            MathOp1::EaseOutExpo => RtValue::from(if f == 1.0 {
+                1.0
+            } else {
+                1.0 - 2.0f32.powf(-10.0 * f)
+            }),
+            MathOp1::EaseInOutExpo => RtValue::from(if f == 0.0 {
+                0.0
+            } else if f == 1.0 {
+                1.0
+            } else if f < 0.5 {
+                2.0f32.powf(20.0 * f - 10.0) / 2.0
+            } else {
+                (2.0 - 2.0f32.powf(-20.0 * f + 10.0)) / 2.0
+            })
+        }
+    }
+}
+
+impl MathOp2 {
+    pub fn eval(self, x: RtValue, y: RtValue) -> RtValue {
+        let (xi, xf, yi, yf) = unsafe { (x.i, x.f, y.i, y.f) };
+        match self {
+            MathOp2::Atan2 => RtValue::from(xf.atan2(yf)),
+            MathOp2::Pow => RtValue::from(xf.powf(yf)),
+            MathOp2::Fmod => RtValue::from(xf % yf),
+            MathOp2::MinInt => RtValue::from(xi.min(yi)),
+            MathOp2::MinFloat => RtValue::from(xf.min(yf)),
+            MathOp2::MaxInt => RtValue::from(xi.max(yi)),
+            MathOp2::MaxFloat => RtValue::from(xf.max(yf))
+        }
+    }
+}
+
+impl MathOp3 {
+    pub fn eval(self, x: RtValue, y: RtValue, z: RtValue) -> RtValue {
+        let (xi, xf, yi, yf, zi, zf) = unsafe { (x.i, x.f, y.i, y.f, z.i, z.f) };
+        match self {
+            MathOp3::Lerp => RtValue::from(xf + (yf - xf) * zf),
+            MathOp3::ClampInt => RtValue::from(xi.max(yi).min(zi)),
+            MathOp3::ClampFloat => RtValue::from(clamp_float(xf, yf, zf)),
+            MathOp3::Smoothstep => {
+                let t = clamp_float((zf - xf) / (yf - xf), 0.0, 1.0);
+                RtValue::from(t * t * (3.0 - 2.0 * t))
+            }
+        }
+    }
+}
+
+// Unlike `f32::clamp`, never panics: `lo > hi` yields `hi` and NaN bounds are ignored, the same
+// as `fminf(fmaxf(x, lo), hi)` in C
+#[allow(clippy::manual_clamp)]
+fn clamp_float(x: f32, lo: f32, hi: f32) -> f32 {
+    x.max(lo).min(hi)
+}
+
+impl MathOp1 {
+    pub fn name(self) -> &'static str {
+        match self {
+            MathOp1::Sin => "sin",
+            MathOp1::Cos => "cos",
+            MathOp1::Tan => "tan",
+            MathOp1::Asin => "asin",
+            MathOp1::Acos => "acos",
+            MathOp1::Atan => "atan",
+            MathOp1::Sqrt => "sqrt",
+            MathOp1::Exp => "exp",
+            MathOp1::Ln => "log",
+            MathOp1::AbsInt => "abs",
+            MathOp1::AbsFloat => "fabs",
+            MathOp1::EaseInQuad => "ease_in_quad",
+            MathOp1::EaseOutQuad => "ease_out_quad",
+            MathOp1::EaseInOutQuad => "ease_in_out_quad",
+            MathOp1::EaseInCubic => "ease_in_cubic",
+            MathOp1::EaseOutCubic => "ease_out_cubic",
+            MathOp1::EaseInOutCubic => "ease_in_out_cubic",
+            MathOp1::EaseInSine => "ease_in_sine",
+            MathOp1::EaseOutSine => "ease_out_sine",
+            MathOp1::EaseInOutSine => "ease_in_out_sine",
+            MathOp1::EaseInExpo => "ease_in_expo",
+            MathOp1::EaseOutExpo => "ease_out_expo",
+            MathOp1::EaseInOutExpo => "ease_in_out_expo"
+        }
+    }
+}
+
+impl MathOp2 {
+    pub fn name(self) -> &'static str {
+        match self {
+            MathOp2::Atan2 => "atan2",
+            MathOp2::Pow => "pow",
+            MathOp2::Fmod => "fmod",
+            MathOp2::MinInt => "min",
+            MathOp2::MinFloat => "fmin",
+            MathOp2::MaxInt => "max",
+            MathOp2::MaxFloat => "fmax"
+        }
+    }
+}
+
+impl MathOp3 {
+    pub fn name(self) -> &'static str {
+        match self {
+            MathOp3::Lerp => "lerp",
+            MathOp3::ClampInt => "clamp",
+            MathOp3::ClampFloat => "fclamp",
+            MathOp3::Smoothstep => "smoothstep"
+        }
+    }
+}
+
+impl Display for MathOp1 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for MathOp2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Display for MathOp3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
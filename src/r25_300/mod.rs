@@ -1,4 +1,20 @@
 pub mod cumbustor;
+pub mod asm;
+pub mod batch;
+pub mod builder;
+pub mod compact;
 pub mod compiled;
+pub mod coroutine;
+pub mod debug;
+pub mod debugger;
+pub mod decoded;
+pub mod error;
 pub mod insc;
+pub mod math;
+pub mod p21c;
+pub mod parallel;
+pub mod profile;
+pub mod scheduler;
+pub mod snapshot;
 pub mod stack;
+pub mod verify;
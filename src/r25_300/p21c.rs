@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::io_ctx::{IOContext, Type21};
+use crate::native::{NativeImport, NativeRegistry, NativeSignature};
+use crate::r25_300::compiled::{Compiled, Function};
+use crate::r25_300::debug::{DebugInfo, FuncDebug, LineEntry, LocalVar};
+use crate::r25_300::insc::Insc;
+use crate::r25_300::math::{MathOp1, MathOp2, MathOp3};
+use crate::r25_300::verify::{verify, verify_debug};
+use crate::value::RtValue;
+
+/// Compiled bytecode as stored in a `.p21c` file. Natives are kept as imports by name and
+/// signature and bound again when the file is loaded. `debug` is an optional section which `new`
+/// fills with the `DebugInfo` of the code; when read, it is decoded back into `Compiled::debug`
+/// and checked against the code.
+///
+/// The file is a header (magic and version) followed by sections, each a 4-byte tag, a `u32`
+/// length and the content: `FUNC` (functions with their frame sizes and signatures), `IMPT`
+/// (natives), `DATA` (constants used by `Const`), `CODE` and an optional `DBUG`. All integers are
+/// little-endian.
+#[derive(Debug, Clone)]
+pub struct P21c {
+    pub compiled: Compiled,
+    pub debug: Option<Vec<u8>>
+}
+
+const MAGIC: &[u8; 4] = b"P21C";
+const VERSION: u32 = 1;
+
+const SECTION_FUNC: &[u8; 4] = b"FUNC";
+const SECTION_IMPORT: &[u8; 4] = b"IMPT";
+const SECTION_DATA: &[u8; 4] = b"DATA";
+const SECTION_CODE: &[u8; 4] = b"CODE";
+const SECTION_DEBUG: &[u8; 4] = b"DBUG";
+
+impl P21c {
+    pub fn new(compiled: Compiled) -> Self {
+        let debug = compiled.debug.as_ref().map(write_debug);
+        Self { compiled, debug }
+    }
+
+    /// The natives bound to `compiled` are not stored
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Writer { bytes: write_code(&self.compiled) };
+        if let Some(debug) = &self.debug {
+            bytes.write_section(SECTION_DEBUG, debug);
+        }
+        bytes.bytes
+    }
+
+    /// Reads and verifies a file. The result is not linked, and IO offsets are not checked
+    /// against any IO context; `load` does both.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+            return Err("不是有效的字节码文件".to_string());
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("不支持的字节码文件版本 {}", version));
+        }
+
+        let mut sections: [Option<&[u8]>; 5] = [None; 5];
+        let mut reader = Reader { bytes, cursor: 8 };
+        while reader.cursor != bytes.len() {
+            let tag = reader.read::<4>()?;
+            let len = reader.read_usize()?;
+            let content = reader.read_slice(len)?;
+
+            let Some(idx) = [SECTION_FUNC, SECTION_IMPORT, SECTION_DATA, SECTION_CODE, SECTION_DEBUG]
+                .iter()
+                .position(|section| **section == tag) else {
+                return Err(format!("未知的段 `{}`", String::from_utf8_lossy(&tag)));
+            };
+            if sections[idx].replace(content).is_some() {
+                return Err(format!("重复的段 `{}`", String::from_utf8_lossy(&tag)));
+            }
+        }
+        let [Some(func), Some(import), Some(data), Some(code), debug] = sections else {
+            return Err("字节码文件缺少必需的段".to_string());
+        };
+
+        let mut compiled = Compiled::new();
+
+        let mut reader = Reader { bytes: func, cursor: 0 };
+        for _ in 0..reader.read_usize()? {
+            compiled.func.push(Function {
+                name: reader.read_str()?,
+                addr: reader.read_usize()?,
+                code_len: reader.read_usize()?,
+                frame_size: reader.read_usize()?,
+                params: reader.read_usize()?,
+                rets: reader.read_usize()?
+            });
+        }
+        reader.finish()?;
+
+        let mut reader = Reader { bytes: import, cursor: 0 };
+        for _ in 0..reader.read_usize()? {
+            compiled.imports.push(NativeImport {
+                name: reader.read_str()?,
+                signature: NativeSignature {
+                    params: reader.read_types()?,
+                    rets: reader.read_types()?
+                }
+            });
+        }
+        reader.finish()?;
+
+        let mut reader = Reader { bytes: data, cursor: 0 };
+        let mut values = Vec::new();
+        for _ in 0..reader.read_usize()? {
+            values.push(RtValue { repr: u32::from_le_bytes(reader.read::<4>()?) });
+        }
+        reader.finish()?;
+
+        let mut reader = Reader { bytes: code, cursor: 0 };
+        for _ in 0..reader.read_usize()? {
+            compiled.code.push(reader.read_insc(&values)?);
+        }
+        reader.finish()?;
+
+        verify(&compiled, None)?;
+        if let Some(debug) = debug {
+            let debug = read_debug(debug).map_err(|e| format!("调试信息无效: {}", e))?;
+            verify_debug(&compiled, &debug)?;
+            compiled.debug = Some(debug);
+        }
+        Ok(Self { compiled, debug: debug.map(<[u8]>::to_vec) })
+    }
+
+    /// Reads a file, checks it against the IO context `CTX` and links it with `natives`, so the
+    /// result can be run directly
+    pub fn load<CTX: IOContext>(bytes: &[u8], natives: &NativeRegistry) -> Result<Self, String> {
+        let mut file = Self::from_bytes(bytes)?;
+        verify(&file.compiled, Some(size_of::<CTX>()))?;
+        file.compiled.link(natives)?;
+        Ok(file)
+    }
+}
+
+/// The header and every section but `DBUG`, which is all that affects how the code runs. Panics
+/// if some value does not fit in a `u32`.
+pub fn write_code(compiled: &Compiled) -> Vec<u8> {
+
+    let mut func = Writer::default();
+    func.write_len(compiled.func.len());
+    for function in compiled.func.iter() {
+        func.write_str(&function.name);
+        for value in [function.addr, function.code_len, function.frame_size, function.params, function.rets] {
+            func.write_u32(value);
+        }
+    }
+
+    let mut import = Writer::default();
+    import.write_len(compiled.imports.len());
+    for native in compiled.imports.iter() {
+        import.write_str(&native.name);
+        for tys in [&native.signature.params, &native.signature.rets] {
+            import.write_len(tys.len());
+            import.bytes.extend(tys.iter().map(|ty| *ty as u8));
+        }
+    }
+
+    let mut data = Vec::new();
+    let mut data_index = HashMap::new();
+    let mut code = Writer::default();
+    code.write_len(compiled.code.len());
+    for insc in compiled.code.iter() {
+        if let Insc::Const { value, .. } = insc {
+            data_index.entry(*value).or_insert_with(|| {
+                data.push(*value);
+                data.len() - 1
+            });
+        }
+        code.write_insc(insc, &data_index);
+    }
+
+    let mut data_section = Writer::default();
+    data_section.write_len(data.len());
+    for value in data.iter() {
+        data_section.bytes.extend_from_slice(&unsafe { value.repr }.to_le_bytes());
+    }
+
+    let mut bytes = Writer::default();
+    bytes.bytes.extend_from_slice(MAGIC);
+    bytes.write_u32(VERSION as usize);
+    bytes.write_section(SECTION_FUNC, &func.bytes);
+    bytes.write_section(SECTION_IMPORT, &import.bytes);
+    bytes.write_section(SECTION_DATA, &data_section.bytes);
+    bytes.write_section(SECTION_CODE, &code.bytes);
+    bytes.bytes
+}
+
+fn write_debug(debug: &DebugInfo) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.write_str(&debug.file);
+    writer.write_len(debug.funcs.len());
+    for func in debug.funcs.iter() {
+        writer.write_len(func.lines.len());
+        for entry in func.lines.iter() {
+            for value in [entry.start, entry.end, entry.line, entry.col] {
+                writer.write_u32(value);
+            }
+        }
+
+        writer.write_len(func.locals.len());
+        for local in func.locals.iter() {
+            writer.write_str(&local.name);
+            writer.bytes.push(local.ty as u8);
+            for value in [local.slot, local.start, local.end] {
+                writer.write_u32(value);
+            }
+        }
+    }
+    writer.bytes
+}
+
+fn read_debug(bytes: &[u8]) -> Result<DebugInfo, String> {
+    let mut reader = Reader { bytes, cursor: 0 };
+    let mut debug = DebugInfo { file: reader.read_str()?, funcs: Vec::new() };
+    for _ in 0..reader.read_usize()? {
+        let mut func = FuncDebug::default();
+        for _ in 0..reader.read_usize()? {
+            func.lines.push(LineEntry {
+                start: reader.read_usize()?,
+                end: reader.read_usize()?,
+                line: reader.read_usize()?,
+                col: reader.read_usize()?
+            });
+        }
+        for _ in 0..reader.read_usize()? {
+            func.locals.push(LocalVar {
+                name: reader.read_str()?,
+                ty: read_type(reader.read::<1>()?[0])?,
+                slot: reader.read_usize()?,
+                start: reader.read_usize()?,
+                end: reader.read_usize()?
+            });
+        }
+        debug.funcs.push(func);
+    }
+    reader.finish()?;
+    Ok(debug)
+}
+
+fn read_type(ty: u8) -> Result<Type21, String> {
+    match ty {
+        1 => Ok(Type21::Int32),
+        2 => Ok(Type21::Float32),
+        3 => Ok(Type21::Bool),
+        _ => Err(format!("字节码文件中的类型 {} 无效", ty))
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+    fn write_u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("value does not fit in a `.p21c` file");
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_len(&mut self, len: usize) {
+        self.write_u32(len);
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_len(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn write_list(&mut self, values: &[usize]) {
+        self.write_len(values.len());
+        for value in values.iter() {
+            self.write_u32(*value);
+        }
+    }
+
+    fn write_section(&mut self, tag: &[u8; 4], content: &[u8]) {
+        self.bytes.extend_from_slice(tag);
+        self.write_len(content.len());
+        self.bytes.extend_from_slice(content);
+    }
+
+    fn write_op(&mut self, opcode: u8, operands: &[usize]) {
+        self.bytes.push(opcode);
+        for operand in operands.iter() {
+            self.write_u32(*operand);
+        }
+    }
+
+    fn write_insc(&mut self, insc: &Insc, data_index: &HashMap<RtValue, usize>) {
+        match insc {
+            Insc::Const { value, dst } => self.write_op(0, &[data_index[value], *dst]),
+            Insc::Dup { src, dst } => self.write_op(1, &[*src, *dst]),
+
+            Insc::AddInt { lhs, rhs, dst } => self.write_op(2, &[*lhs, *rhs, *dst]),
+            Insc::AddFloat { lhs, rhs, dst } => self.write_op(3, &[*lhs, *rhs, *dst]),
+            Insc::SubInt { lhs, rhs, dst } => self.write_op(4, &[*lhs, *rhs, *dst]),
+            Insc::SubFloat { lhs, rhs, dst } => self.write_op(5, &[*lhs, *rhs, *dst]),
+            Insc::MulInt { lhs, rhs, dst } => self.write_op(6, &[*lhs, *rhs, *dst]),
+            Insc::MulFloat { lhs, rhs, dst } => self.write_op(7, &[*lhs, *rhs, *dst]),
+            Insc::DivInt { lhs, rhs, dst } => self.write_op(8, &[*lhs, *rhs, *dst]),
+            Insc::DivFloat { lhs, rhs, dst } => self.write_op(9, &[*lhs, *rhs, *dst]),
+            Insc::ModInt { lhs, rhs, dst } => self.write_op(10, &[*lhs, *rhs, *dst]),
+
+            Insc::NegateInt { src, dst } => self.write_op(11, &[*src, *dst]),
+            Insc::NegateFloat { src, dst } => self.write_op(12, &[*src, *dst]),
+
+            Insc::Eq { lhs, rhs, dst } => self.write_op(13, &[*lhs, *rhs, *dst]),
+            Insc::Ne { lhs, rhs, dst } => self.write_op(14, &[*lhs, *rhs, *dst]),
+
+            Insc::LtInt { lhs, rhs, dst } => self.write_op(15, &[*lhs, *rhs, *dst]),
+            Insc::LtFloat { lhs, rhs, dst } => self.write_op(16, &[*lhs, *rhs, *dst]),
+            Insc::LeInt { lhs, rhs, dst } => self.write_op(17, &[*lhs, *rhs, *dst]),
+            Insc::LeFloat { lhs, rhs, dst } => self.write_op(18, &[*lhs, *rhs, *dst]),
+
+            Insc::And { lhs, rhs, dst } => self.write_op(19, &[*lhs, *rhs, *dst]),
+            Insc::Or { lhs, rhs, dst } => self.write_op(20, &[*lhs, *rhs, *dst]),
+            Insc::Not { src, dst } => self.write_op(21, &[*src, *dst]),
+
+            Insc::Round { src, dst } => self.write_op(22, &[*src, *dst]),
+            Insc::Floor { src, dst } => self.write_op(23, &[*src, *dst]),
+            Insc::Ceil { src, dst } => self.write_op(24, &[*src, *dst]),
+            Insc::ToFloat { src, dst } => self.write_op(25, &[*src, *dst]),
+            Insc::Float2Int { src, dst } => self.write_op(26, &[*src, *dst]),
+            Insc::Round2Int { src, dst } => self.write_op(27, &[*src, *dst]),
+            Insc::Floor2Int { src, dst } => self.write_op(28, &[*src, *dst]),
+            Insc::Ceil2Int { src, dst } => self.write_op(29, &[*src, *dst]),
+
+            Insc::Bool2Int { src, dst } => self.write_op(30, &[*src, *dst]),
+            Insc::Int2Bool { src, dst } => self.write_op(31, &[*src, *dst]),
+            Insc::Bool2Float { src, dst } => self.write_op(32, &[*src, *dst]),
+            Insc::Float2Bool { src, dst } => self.write_op(33, &[*src, *dst]),
+
+            Insc::Math1 { op, src, dst } => self.write_op(34, &[*op as usize, *src, *dst]),
+            Insc::Math2 { op, lhs, rhs, dst } => self.write_op(35, &[*op as usize, *lhs, *rhs, *dst]),
+            Insc::Math3 { op, a, b, c, dst } => self.write_op(36, &[*op as usize, *a, *b, *c, *dst]),
+
+            Insc::Jmp { dst } => self.write_op(37, &[*dst]),
+            Insc::JmpIf { check, dst } => self.write_op(38, &[*check, *dst]),
+            Insc::Call { func, args, ret_locs } => {
+                self.write_op(39, &[*func]);
+                self.write_list(args);
+                self.write_list(ret_locs);
+            },
+            Insc::Return { rets } => {
+                self.write_op(40, &[]);
+                self.write_list(rets);
+            },
+
+            Insc::IOSetValue { offset, src } => self.write_op(41, &[*offset, *src]),
+            Insc::IOGetValue { offset, dst } => self.write_op(42, &[*offset, *dst]),
+            Insc::CallFFI { func, args, ret_locs } => {
+                self.write_op(43, &[*func]);
+                self.write_list(args);
+                self.write_list(ret_locs);
+            },
+            Insc::TryBegin { handler } => self.write_op(44, &[*handler]),
+            Insc::TryEnd => self.write_op(45, &[]),
+            Insc::Spawn { func, args, dst } => {
+                self.write_op(46, &[*func]);
+                self.write_list(args);
+                self.write_u32(*dst);
+            },
+            Insc::Join { task } => self.write_op(47, &[*task]),
+
+            Insc::Yield { values } => {
+                self.write_op(48, &[]);
+                self.write_list(values);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize
+}
+
+impl<'a> Reader<'a> {
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let Some(chunk) = self.cursor.checked_add(len).and_then(|end| self.bytes.get(self.cursor..end)) else {
+            return Err("字节码文件不完整".to_string());
+        };
+        self.cursor += len;
+        Ok(chunk)
+    }
+
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.read_slice(N)?.try_into().unwrap())
+    }
+
+    fn read_usize(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.read::<4>()?) as usize)
+    }
+
+    fn read_str(&mut self) -> Result<String, String> {
+        let len = self.read_usize()?;
+        String::from_utf8(self.read_slice(len)?.to_vec()).map_err(|_| "字节码文件中的名称无效".to_string())
+    }
+
+    fn read_list(&mut self) -> Result<Box<[usize]>, String> {
+        let len = self.read_usize()?;
+        // the list must fit in the remaining bytes, so a corrupted length cannot allocate too much
+        if len > (self.bytes.len() - self.cursor) / 4 {
+            return Err("字节码文件不完整".to_string());
+        }
+        (0..len).map(|_| self.read_usize()).collect()
+    }
+
+    fn read_types(&mut self) -> Result<Vec<Type21>, String> {
+        let len = self.read_usize()?;
+        self.read_slice(len)?.iter().map(|ty| read_type(*ty)).collect()
+    }
+
+    fn read_op<T: Copy>(&mut self, ops: &[T]) -> Result<T, String> {
+        let idx = self.read_usize()?;
+        ops.get(idx).copied().ok_or_else(|| format!("字节码文件中的数学运算 {} 无效", idx))
+    }
+
+    fn read_insc(&mut self, values: &[RtValue]) -> Result<Insc, String> {
+        let [opcode] = self.read::<1>()?;
+        let insc = match opcode {
+            0 => {
+                let idx = self.read_usize()?;
+                let Some(value) = values.get(idx) else {
+                    return Err(format!("字节码文件中的常量 {} 不存在", idx));
+                };
+                Insc::Const { value: *value, dst: self.read_usize()? }
+            },
+            1 => Insc::Dup { src: self.read_usize()?, dst: self.read_usize()? },
+
+            2 => Insc::AddInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            3 => Insc::AddFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            4 => Insc::SubInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            5 => Insc::SubFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            6 => Insc::MulInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            7 => Insc::MulFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            8 => Insc::DivInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            9 => Insc::DivFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            10 => Insc::ModInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+
+            11 => Insc::NegateInt { src: self.read_usize()?, dst: self.read_usize()? },
+            12 => Insc::NegateFloat { src: self.read_usize()?, dst: self.read_usize()? },
+
+            13 => Insc::Eq { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            14 => Insc::Ne { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+
+            15 => Insc::LtInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            16 => Insc::LtFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            17 => Insc::LeInt { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            18 => Insc::LeFloat { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+
+            19 => Insc::And { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            20 => Insc::Or { lhs: self.read_usize()?, rhs: self.read_usize()?, dst: self.read_usize()? },
+            21 => Insc::Not { src: self.read_usize()?, dst: self.read_usize()? },
+
+            22 => Insc::Round { src: self.read_usize()?, dst: self.read_usize()? },
+            23 => Insc::Floor { src: self.read_usize()?, dst: self.read_usize()? },
+            24 => Insc::Ceil { src: self.read_usize()?, dst: self.read_usize()? },
+            25 => Insc::ToFloat { src: self.read_usize()?, dst: self.read_usize()? },
+            26 => Insc::Float2Int { src: self.read_usize()?, dst: self.read_usize()? },
+            27 => Insc::Round2Int { src: self.read_usize()?, dst: self.read_usize()? },
+            28 => Insc::Floor2Int { src: self.read_usize()?, dst: self.read_usize()? },
+            29 => Insc::Ceil2Int { src: self.read_usize()?, dst: self.read_usize()? },
+
+            30 => Insc::Bool2Int { src: self.read_usize()?, dst: self.read_usize()? },
+            31 => Insc::Int2Bool { src: self.read_usize()?, dst: self.read_usize()? },
+            32 => Insc::Bool2Float { src: self.read_usize()?, dst: self.read_usize()? },
+            33 => Insc::Float2Bool { src: self.read_usize()?, dst: self.read_usize()? },
+
+            34 => Insc::Math1 { op: self.read_op(&MathOp1::ALL)?, src: self.read_usize()?, dst: self.read_usize()? },
+            35 => Insc::Math2 {
+                op: self.read_op(&MathOp2::ALL)?,
+                lhs: self.read_usize()?,
+                rhs: self.read_usize()?,
+                dst: self.read_usize()?
+            },
+            36 => Insc::Math3 {
+                op: self.read_op(&MathOp3::ALL)?,
+                a: self.read_usize()?,
+                b: self.read_usize()?,
+                c: self.read_usize()?,
+                dst: self.read_usize()?
+            },
+
+            37 => Insc::Jmp { dst: self.read_usize()? },
+            38 => Insc::JmpIf { check: self.read_usize()?, dst: self.read_usize()? },
+            39 => Insc::Call { func: self.read_usize()?, args: self.read_list()?, ret_locs: self.read_list()? },
+            40 => Insc::Return { rets: self.read_list()? },
+
+            41 => Insc::IOSetValue { offset: self.read_usize()?, src: self.read_usize()? },
+            42 => Insc::IOGetValue { offset: self.read_usize()?, dst: self.read_usize()? },
+            43 => Insc::CallFFI { func: self.read_usize()?, args: self.read_list()?, ret_locs: self.read_list()? },
+            44 => Insc::TryBegin { handler: self.read_usize()? },
+            45 => Insc::TryEnd,
+            46 => Insc::Spawn { func: self.read_usize()?, args: self.read_list()?, dst: self.read_usize()? },
+            47 => Insc::Join { task: self.read_usize()? },
+
+            48 => Insc::Yield { values: self.read_list()? },
+            _ => return Err(format!("字节码文件中的操作码 {} 无效", opcode))
+        };
+        Ok(insc)
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.cursor != self.bytes.len() {
+            return Err("字节码文件的段末尾有多余的内容".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use std::thread;
+
+use crate::io_ctx::IOContext;
+use crate::r25_300::coroutine::{Coroutine, CoroutineStatus};
+use crate::r25_300::error::RuntimeError;
+
+/// Resumes every coroutine once, spreading them over at most `threads` scoped threads. Each
+/// coroutine only touches its own IO context, so the outcome is the same as resuming them one by
+/// one; results are returned in the order of `coroutines`.
+pub fn resume_all<CTX>(
+    coroutines: &mut [Coroutine<'_, '_, CTX>],
+    threads: usize
+) -> Vec<Result<CoroutineStatus, RuntimeError>>
+    where CTX: IOContext + Send
+{
+    if coroutines.is_empty() {
+        return Vec::new();
+    }
+    let chunk_size = coroutines.len().div_ceil(threads.max(1));
+    if chunk_size == coroutines.len() {
+        return coroutines.iter_mut().map(Coroutine::resume).collect();
+    }
+
+    thread::scope(|scope| {
+        let handles = coroutines.chunks_mut(chunk_size).map(|chunk| {
+            scope.spawn(move || chunk.iter_mut().map(Coroutine::resume).collect::<Vec<_>>())
+        }).collect::<Vec<_>>();
+
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::stack::Stack;
+
+/// Counts what a `Combustor` executes, see `Combustor::set_profiler`. Counts add up over resumes
+/// and runs until `clear` is called.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    // executed instructions by address
+    counts: Vec<u64>,
+    // calls and time spent in each native, indexed like `Compiled::ffi`
+    natives: Vec<(u64, Duration)>,
+    // executed instructions by call path, as function ids from the outermost
+    stacks: HashMap<Vec<usize>, u64>,
+    // the call path of the instructions counted in `pending`, valid while the stack is `depth`
+    // frames deep
+    path: Vec<usize>,
+    depth: Option<usize>,
+    pending: u64
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Called before each executed instruction while a profiler is attached
+    pub fn count(&mut self, compiled: &Compiled, stack: &Stack, insc_ptr: usize) {
+        if self.counts.len() <= insc_ptr {
+            self.counts.resize(compiled.code.len().max(insc_ptr + 1), 0);
+        }
+        self.counts[insc_ptr] += 1;
+
+        // frames only change through calls, returns and unwinding, which all change the depth
+        if self.depth != Some(stack.depth()) {
+            self.flush();
+            self.path = stack.positions(insc_ptr)
+                .into_iter()
+                .filter_map(|insc_ptr| func_id(compiled, insc_ptr))
+                .collect();
+            self.depth = Some(stack.depth());
+        }
+        self.pending += 1;
+    }
+
+    pub fn count_native(&mut self, func: usize, time: Duration) {
+        if self.natives.len() <= func {
+            self.natives.resize(func + 1, (0, Duration::ZERO));
+        }
+        self.natives[func].0 += 1;
+        self.natives[func].1 += time;
+    }
+
+    /// Called when the combustor stops running a stack, since the next one may be another
+    pub fn flush(&mut self) {
+        if self.pending != 0 {
+            *self.stacks.entry(self.path.clone()).or_default() += self.pending;
+            self.pending = 0;
+        }
+        self.depth = None;
+    }
+
+    /// Executed instructions by address
+    pub fn insc_counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Instructions executed in each function itself, most first
+    pub fn func_counts<'c>(&self, compiled: &'c Compiled) -> Vec<(&'c str, u64)> {
+        let mut counts = compiled.func.iter()
+            .map(|func| {
+                let end = (func.addr + func.code_len).min(self.counts.len());
+                let count = self.counts.get(func.addr..end).map_or(0, |counts| counts.iter().sum());
+                (func.name.as_str(), count)
+            })
+            .filter(|(_, count)| *count != 0)
+            .collect::<Vec<_>>();
+        counts.sort_by(|(name1, count1), (name2, count2)| count2.cmp(count1).then(name1.cmp(name2)));
+        counts
+    }
+
+    /// Instructions executed for each source line as `(function, line, count)`, most first.
+    /// Empty without debug info.
+    pub fn line_counts<'c>(&self, compiled: &'c Compiled) -> Vec<(&'c str, usize, u64)> {
+        let Some(debug) = &compiled.debug else {
+            return Vec::new();
+        };
+
+        let mut counts = HashMap::<(usize, usize), u64>::new();
+        for (insc_ptr, count) in self.counts.iter().enumerate().filter(|(_, count)| **count != 0) {
+            let (Some(func_id), Some(entry)) = (func_id(compiled, insc_ptr), debug.line_at(compiled, insc_ptr)) else {
+                continue;
+            };
+            *counts.entry((func_id, entry.line)).or_default() += count;
+        }
+
+        let mut counts = counts.into_iter()
+            .map(|((func_id, line), count)| (compiled.func[func_id].name.as_str(), line, count))
+            .collect::<Vec<_>>();
+        counts.sort_by(|(_, line1, count1), (_, line2, count2)| count2.cmp(count1).then(line1.cmp(line2)));
+        counts
+    }
+
+    /// Calls and time spent in each native which was called, longest first
+    pub fn native_times<'c>(&self, compiled: &'c Compiled) -> Vec<(&'c str, u64, Duration)> {
+        let mut times = self.natives.iter()
+            .enumerate()
+            .filter(|(_, (calls, _))| *calls != 0)
+            .map(|(func, (calls, time))| (compiled.ffi[func].name.as_str(), *calls, *time))
+            .collect::<Vec<_>>();
+        times.sort_by(|(_, _, time1), (_, _, time2)| time2.cmp(time1));
+        times
+    }
+
+    /// The call paths in the folded format of flamegraph tools, one `outer;inner count` per line,
+    /// weighted by executed instructions
+    pub fn folded(&self, compiled: &Compiled) -> String {
+        let mut stacks = self.stacks.clone();
+        if self.pending != 0 {
+            *stacks.entry(self.path.clone()).or_default() += self.pending;
+        }
+
+        let mut lines = stacks.into_iter()
+            .map(|(path, count)| {
+                let names = path.iter().map(|func_id| compiled.func[*func_id].name.as_str()).collect::<Vec<_>>();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+fn func_id(compiled: &Compiled, insc_ptr: usize) -> Option<usize> {
+    compiled.func.iter().position(|func| func.addr <= insc_ptr && insc_ptr < func.addr + func.code_len)
+}
@@ -0,0 +1,277 @@
+use std::vec::Drain;
+
+use crate::io_ctx::IOContext;
+use crate::native::{NativeFunction, NativeReturn};
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::cumbustor::{Combustor, CombustorState, Engine, EngineCode};
+use crate::r25_300::error::RuntimeError;
+use crate::value::RtValue;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct InstanceId {
+    index: u32,
+    generation: u32
+}
+
+/// When a suspended instance is resumed again
+pub enum Wait<CTX> {
+    // on the next tick
+    Ready,
+    // after skipping this many ticks
+    Frames(u32),
+    // on the first tick the condition holds
+    Until(fn(&CTX) -> bool),
+    // once the host calls `Scheduler::finish_native`
+    Native
+}
+
+pub enum Event<CTX> {
+    // the entry function returned, and the instance was removed
+    Finished(InstanceId, CTX),
+    // an uncaught native error stopped the instance, which was removed
+    Faulted(InstanceId, CTX, RuntimeError),
+    // the instance used up its fuel and will continue on the next tick
+    OutOfFuel(InstanceId)
+}
+
+struct Instance<'a, CTX> {
+    ctx: CTX,
+    // only `None` while the instance is running
+    state: Option<CombustorState<'a>>,
+    entry: usize,
+    // `None` before the first tick
+    resume_ptr: Option<usize>,
+    wait: Wait<CTX>
+}
+
+struct Slot<'a, CTX> {
+    generation: u32,
+    instance: Option<Instance<'a, CTX>>
+}
+
+/// Runs many instances of scripts from one `Compiled`, each with its own IO context. Slots and
+/// stacks of removed instances are reused by later ones, so a scheduler which has reached its
+/// peak size no longer allocates. The code is lowered once for all instances.
+pub struct Scheduler<'a, CTX: IOContext> {
+    compiled: &'a Compiled,
+    engine_code: EngineCode<'a>,
+    slots: Vec<Slot<'a, CTX>>,
+    free_slots: Vec<u32>,
+    spare_states: Vec<CombustorState<'a>>,
+    events: Vec<Event<CTX>>,
+    len: usize,
+    fuel: u64
+}
+
+impl<'a, CTX> Scheduler<'a, CTX>
+    where CTX: IOContext
+{
+    /// # Safety
+    /// `compiled` must be well-formed and linked, as for `Combustor::combust`.
+    pub unsafe fn new(compiled: &'a Compiled) -> Self {
+        debug_assert!(compiled.is_linked(), "`Compiled` must be linked before running");
+
+        Self {
+            compiled,
+            engine_code: EngineCode::new(compiled, Engine::default()),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            spare_states: Vec::new(),
+            events: Vec::new(),
+            len: 0,
+            fuel: u64::MAX
+        }
+    }
+
+    /// Chooses how instances started afterwards run the code, see `Combustor::set_engine`
+    pub fn set_engine(&mut self, engine: Engine) {
+        if self.engine_code.engine() != engine {
+            self.engine_code = EngineCode::new(self.compiled, engine);
+        }
+    }
+
+    /// Limits the number of instructions each instance may execute per tick
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = fuel;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds an instance running `entry`, which starts on the next tick
+    pub fn start(&mut self, entry: usize, ctx: CTX) -> InstanceId {
+        assert!(entry < self.compiled.func.len(), "entry function {} does not exist", entry);
+
+        let instance = Instance {
+            ctx,
+            state: Some(self.spare_states.pop().unwrap_or_default()),
+            entry,
+            resume_ptr: None,
+            wait: Wait::Ready
+        };
+        self.len += 1;
+
+        if let Some(index) = self.free_slots.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.instance = Some(instance);
+            InstanceId { index, generation: slot.generation }
+        } else {
+            self.slots.push(Slot { generation: 0, instance: Some(instance) });
+            InstanceId { index: self.slots.len() as u32 - 1, generation: 0 }
+        }
+    }
+
+    /// Removes an instance without running it any further
+    pub fn remove(&mut self, id: InstanceId) -> Option<CTX> {
+        self.instance(id)?;
+        Some(self.remove_at(id.index as usize))
+    }
+
+    pub fn contains(&self, id: InstanceId) -> bool {
+        self.instance(id).is_some()
+    }
+
+    pub fn ctx(&self, id: InstanceId) -> Option<&CTX> {
+        self.instance(id).map(|instance| &instance.ctx)
+    }
+
+    pub fn ctx_mut(&mut self, id: InstanceId) -> Option<&mut CTX> {
+        self.instance_mut(id).map(|instance| &mut instance.ctx)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = InstanceId> + '_ {
+        self.slots.iter().enumerate().filter(|(_, slot)| slot.instance.is_some()).map(|(index, slot)| {
+            InstanceId { index: index as u32, generation: slot.generation }
+        })
+    }
+
+    /// Values passed to the last `yield` of an instance, see `Combustor::yielded`
+    pub fn yielded(&self, id: InstanceId) -> Option<&[RtValue]> {
+        self.instance(id).map(|instance| instance.state.as_ref().unwrap().yielded())
+    }
+
+    pub fn suspended_native(&self, id: InstanceId) -> Option<&'a NativeFunction> {
+        self.instance(id)?.state.as_ref().unwrap().suspended_native()
+    }
+
+    /// Makes a suspended instance wait before it is resumed again. An instance waiting for a native
+    /// must get its results with `finish_native` first.
+    pub fn set_wait(&mut self, id: InstanceId, wait: Wait<CTX>) -> Result<(), String> {
+        let Some(instance) = self.instance_mut(id) else {
+            return Err("实例不存在".to_string());
+        };
+        if let Wait::Native = instance.wait {
+            return Err("实例正在等待宿主函数的结果".to_string());
+        }
+
+        instance.wait = wait;
+        Ok(())
+    }
+
+    /// Provides the results of the native an instance is suspended on, see
+    /// `Combustor::finish_native`. The instance is resumed on the next tick.
+    pub fn finish_native<R: NativeReturn>(&mut self, id: InstanceId, rets: R) -> Result<(), String> {
+        let Some(instance) = self.instance_mut(id) else {
+            return Err("实例不存在".to_string());
+        };
+
+        instance.state.as_mut().unwrap().finish_native(rets)?;
+        instance.wait = Wait::Ready;
+        Ok(())
+    }
+
+    /// Resumes every instance which is not waiting, in the order of their slots. Returns what
+    /// happened to instances during the tick; the events are dropped with the iterator.
+    pub fn tick(&mut self) -> Drain<'_, Event<CTX>> {
+        self.events.clear();
+
+        for index in 0..self.slots.len() {
+            let id = InstanceId { index: index as u32, generation: self.slots[index].generation };
+            let Some(instance) = self.slots[index].instance.as_mut() else {
+                continue;
+            };
+            match &mut instance.wait {
+                Wait::Ready => {},
+                Wait::Frames(frames) => if *frames > 0 {
+                    *frames -= 1;
+                    continue;
+                },
+                Wait::Until(cond) => if !cond(&instance.ctx) {
+                    continue;
+                },
+                Wait::Native => continue
+            }
+            instance.wait = Wait::Ready;
+
+            let mut combustor = Combustor::with_state(&mut instance.ctx, instance.state.take().unwrap());
+            combustor.set_fuel(self.fuel);
+            let result = unsafe {
+                match instance.resume_ptr {
+                    None => {
+                        combustor.set_engine_code(self.engine_code.clone());
+                        combustor.combust(self.compiled, instance.entry)
+                    },
+                    Some(resume_ptr) => combustor.combust_resume(self.compiled, resume_ptr)
+                }
+            };
+            let out_of_fuel = combustor.out_of_fuel();
+            let state = combustor.into_state();
+
+            match result {
+                Ok(Some(resume_ptr)) => {
+                    if state.suspended_native().is_some() {
+                        instance.wait = Wait::Native;
+                    }
+                    instance.state = Some(state);
+                    instance.resume_ptr = Some(resume_ptr);
+                    if out_of_fuel {
+                        self.events.push(Event::OutOfFuel(id));
+                    }
+                },
+                Ok(None) => {
+                    instance.state = Some(state);
+                    let ctx = self.remove_at(index);
+                    self.events.push(Event::Finished(id, ctx));
+                },
+                Err(err) => {
+                    instance.state = Some(state);
+                    let ctx = self.remove_at(index);
+                    self.events.push(Event::Faulted(id, ctx, err));
+                }
+            }
+        }
+
+        self.events.drain(..)
+    }
+
+    fn instance(&self, id: InstanceId) -> Option<&Instance<'a, CTX>> {
+        let slot = self.slots.get(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.instance.as_ref()
+    }
+
+    fn instance_mut(&mut self, id: InstanceId) -> Option<&mut Instance<'a, CTX>> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.instance.as_mut()
+    }
+
+    fn remove_at(&mut self, index: usize) -> CTX {
+        let slot = &mut self.slots[index];
+        let instance = slot.instance.take().unwrap();
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(index as u32);
+        self.spare_states.push(instance.state.unwrap());
+        self.len -= 1;
+        instance.ctx
+    }
+}
@@ -0,0 +1,188 @@
+use crate::r25_300::compiled::Compiled;
+use crate::r25_300::insc::Insc;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FrameSnapshot {
+    // address of the `Call` which created the frame, 0 for the entry frame
+    pub ret_addr: usize,
+    pub start_idx: usize,
+    pub end_idx: usize
+}
+
+/// The state of a suspended script, owning everything it refers to. `ret_locs` of frames and of
+/// a suspended native call are not stored, since they are found again from the call
+/// instructions. The IO context is not included.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    pub code_hash: u64,
+    pub entry: usize,
+    pub resume_ptr: usize,
+    pub values: Vec<u32>,
+    pub frames: Vec<FrameSnapshot>,
+    // active `try` blocks as (stack depth, handler address), innermost last
+    pub handlers: Vec<(usize, usize)>,
+    // whether the script is suspended in the native called right before `resume_ptr`
+    pub suspended_native: bool
+}
+
+const MAGIC: &[u8; 4] = b"P21S";
+const VERSION: u32 = 1;
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut write_u64 = |value: usize| bytes.extend_from_slice(&(value as u64).to_le_bytes());
+        write_u64(self.entry);
+        write_u64(self.resume_ptr);
+        write_u64(self.suspended_native as usize);
+        write_u64(self.values.len());
+        write_u64(self.frames.len());
+        for frame in self.frames.iter() {
+            write_u64(frame.ret_addr);
+            write_u64(frame.start_idx);
+            write_u64(frame.end_idx);
+        }
+        write_u64(self.handlers.len());
+        for (depth, handler) in self.handlers.iter() {
+            write_u64(*depth);
+            write_u64(*handler);
+        }
+
+        bytes.extend_from_slice(&self.code_hash.to_le_bytes());
+        for value in self.values.iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[0..4] != MAGIC {
+            return Err("不是有效的快照数据".to_string());
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("不支持的快照版本 {}", version));
+        }
+
+        let mut reader = ByteReader { bytes, cursor: 8 };
+        let entry = reader.read_usize()?;
+        let resume_ptr = reader.read_usize()?;
+        let suspended_native = reader.read_usize()? != 0;
+        let value_count = reader.read_usize()?;
+
+        let frame_count = reader.read_usize()?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            frames.push(FrameSnapshot {
+                ret_addr: reader.read_usize()?,
+                start_idx: reader.read_usize()?,
+                end_idx: reader.read_usize()?
+            });
+        }
+
+        let handler_count = reader.read_usize()?;
+        let mut handlers = Vec::new();
+        for _ in 0..handler_count {
+            handlers.push((reader.read_usize()?, reader.read_usize()?));
+        }
+
+        let code_hash = u64::from_le_bytes(reader.read::<8>()?);
+        let mut values = Vec::new();
+        for _ in 0..value_count {
+            values.push(u32::from_le_bytes(reader.read::<4>()?));
+        }
+
+        if reader.cursor != bytes.len() {
+            return Err("快照数据末尾有多余的内容".to_string());
+        }
+
+        Ok(Self { code_hash, entry, resume_ptr, values, frames, handlers, suspended_native })
+    }
+
+    /// Checks that the snapshot describes a state `compiled` can be resumed from
+    pub fn validate(&self, compiled: &Compiled) -> Result<(), String> {
+        if self.code_hash != compiled.code_hash() {
+            return Err("快照与当前的字节码不匹配".to_string());
+        }
+        let Some(entry) = compiled.func.get(self.entry) else {
+            return Err(format!("快照的入口函数 {} 不存在", self.entry));
+        };
+
+        let Some(entry_frame) = self.frames.first() else {
+            return Err("快照中没有栈帧".to_string());
+        };
+        if *entry_frame != (FrameSnapshot { ret_addr: 0, start_idx: 0, end_idx: entry.frame_size }) {
+            return Err("快照的入口栈帧无效".to_string());
+        }
+
+        // each frame must be created by a `Call` in the function of the frame below it
+        let mut funcs = vec![entry];
+        let mut func = entry;
+        for (prev, frame) in self.frames.iter().zip(self.frames.iter().skip(1)) {
+            let callee = match compiled.code.get(frame.ret_addr) {
+                Some(Insc::Call { func: callee, .. })
+                    if func.addr <= frame.ret_addr && frame.ret_addr < func.addr + func.code_len =>
+                    &compiled.func[*callee],
+                _ => return Err(format!("快照的栈帧返回地址 {} 无效", frame.ret_addr))
+            };
+            if frame.start_idx != prev.end_idx || frame.end_idx != frame.start_idx + callee.frame_size {
+                return Err(format!("快照中函数 `{}` 的栈帧无效", callee.name));
+            }
+            func = callee;
+            funcs.push(callee);
+        }
+
+        if self.values.len() != self.frames.last().unwrap().end_idx {
+            return Err("快照的栈大小与栈帧不一致".to_string());
+        }
+        // a script out of fuel may be suspended at the start of a function
+        if self.resume_ptr < func.addr || self.resume_ptr >= func.addr + func.code_len {
+            return Err(format!("快照的恢复地址 {} 不在函数 `{}` 中", self.resume_ptr, func.name));
+        }
+        if self.suspended_native {
+            let after_call = self.resume_ptr > func.addr && matches!(
+                compiled.code[self.resume_ptr - 1],
+                Insc::CallFFI { func, .. } if func < compiled.ffi.len()
+            );
+            if !after_call {
+                return Err(format!("快照的恢复地址 {} 前没有宿主函数调用", self.resume_ptr));
+            }
+        }
+
+        // a handler is unwound to in the frame of its `try`, so it must be in that frame's function
+        let mut prev_depth = 0;
+        for (depth, handler) in self.handlers.iter() {
+            let in_func = depth.checked_sub(1)
+                .and_then(|idx| funcs.get(idx))
+                .is_some_and(|func| func.addr <= *handler && *handler < func.addr + func.code_len);
+            if *depth < prev_depth || !in_func {
+                return Err("快照中的 try 块无效".to_string());
+            }
+            prev_depth = *depth;
+        }
+
+        Ok(())
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize
+}
+
+impl ByteReader<'_> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let Some(chunk) = self.bytes.get(self.cursor..self.cursor + N) else {
+            return Err("快照数据不完整".to_string());
+        };
+        self.cursor += N;
+        Ok(chunk.try_into().unwrap())
+    }
+
+    fn read_usize(&mut self) -> Result<usize, String> {
+        usize::try_from(u64::from_le_bytes(self.read::<8>()?)).map_err(|_| "快照数据超出范围".to_string())
+    }
+}
@@ -1,78 +1,195 @@
+use std::ops::{Deref, DerefMut};
+
 use xjbutil::zvec::{ZeroVec, TrivialInit};
 use crate::value::RtValue;
 
 unsafe impl TrivialInit for RtValue {}
 
+/// A `ZeroVec` which can be sent to another thread. `ZeroVec` holds a raw pointer, so it is not
+/// `Send` by itself, but it owns its buffer just like `Vec` does.
+pub struct ZeroBuf<T: TrivialInit>(ZeroVec<T>);
+
+unsafe impl<T: TrivialInit + Send> Send for ZeroBuf<T> {}
+
+impl<T: TrivialInit> ZeroBuf<T> {
+    pub fn new() -> Self {
+        Self(ZeroVec::new())
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Self(ZeroVec::with_capacity(cap))
+    }
+}
+
+impl<T: TrivialInit> Default for ZeroBuf<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: TrivialInit> Deref for ZeroBuf<T> {
+    type Target = ZeroVec<T>;
+
+    fn deref(&self) -> &ZeroVec<T> {
+        &self.0
+    }
+}
+
+impl<T: TrivialInit> DerefMut for ZeroBuf<T> {
+    fn deref_mut(&mut self) -> &mut ZeroVec<T> {
+        &mut self.0
+    }
+}
+
+// the return locations of a frame are those of the call at `ret_addr`
 #[derive(Copy, Clone)]
-pub struct StackFrame<'a> {
+pub struct StackFrame {
     ret_addr: usize,
     start_idx: usize,
-    end_idx: usize,
-    ret_locs: &'a [usize],
+    end_idx: usize
 }
 
-impl<'a> StackFrame<'a> {
+impl StackFrame {
     #[inline(always)]
-    pub fn new(ret_addr: usize, start_idx: usize, end_idx: usize, ret_locs: &'a [usize]) -> Self {
+    pub fn new(ret_addr: usize, start_idx: usize, end_idx: usize) -> Self {
         Self {
             ret_addr,
             start_idx,
-            end_idx,
-            ret_locs
+            end_idx
         }
     }
-}
 
-impl StackFrame<'_> {
+    pub fn ret_addr(&self) -> usize { self.ret_addr }
+    pub fn start_idx(&self) -> usize { self.start_idx }
+    pub fn end_idx(&self) -> usize { self.end_idx }
+
+    /// # Safety
+    /// `idx` must be a slot of this frame, and the frame must be on `stack`.
     #[inline(always)]
-    pub unsafe fn get_value<'a>(&'a self, stack: &'_ Stack<'_>, idx: usize) -> RtValue {
+    pub unsafe fn get_value(&self, stack: &Stack, idx: usize) -> RtValue {
         *stack.values.get_unchecked(self.start_idx + idx)
     }
 
+    /// The address of slot 0, valid until the stack grows or shrinks
+    ///
+    /// # Safety
+    /// The frame must be on `stack`.
+    #[inline(always)]
+    pub unsafe fn base_ptr(&self, stack: &mut Stack) -> *mut RtValue {
+        stack.values.as_mut_ptr().add(self.start_idx)
+    }
+
+    /// # Safety
+    /// `idx` must be a slot of this frame, and the frame must be on `stack`.
     #[inline(always)]
-    pub unsafe fn set_value<'a>(&'a self, stack: &'_ mut Stack<'_>, idx: usize, value: RtValue) {
+    pub unsafe fn set_value(&self, stack: &mut Stack, idx: usize, value: RtValue) {
         *stack.values.get_unchecked_mut(self.start_idx + idx) = value;
     }
 }
 
-unsafe impl TrivialInit for StackFrame<'_> {}
+unsafe impl TrivialInit for StackFrame {}
 
-pub struct Stack<'a> {
-    values: ZeroVec<RtValue>,
-    frames: Vec<StackFrame<'a>>
+pub struct Stack {
+    values: ZeroBuf<RtValue>,
+    frames: Vec<StackFrame>
 }
 
-impl Stack<'_> {
+impl Stack {
     pub fn new() -> Self {
         Self {
-            values: ZeroVec::with_capacity(32),
+            values: ZeroBuf::with_capacity(32),
             frames: Vec::new()
         }
     }
 }
 
-impl<'a> Stack<'a> {
-    pub fn enter_frame(&mut self, frame_size: usize) -> StackFrame<'a> {
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stack {
+    pub fn enter_frame(&mut self, frame_size: usize) -> StackFrame {
         debug_assert!(self.frames.is_empty());
         debug_assert!(self.values.is_empty());
 
         self.values.resize(frame_size);
-        let frame = StackFrame::new(0, 0, frame_size, &[]);
+        let frame = StackFrame::new(0, 0, frame_size);
         self.frames.push(frame);
         frame
     }
 
-    pub unsafe fn last_frame(&self) -> StackFrame<'a> {
+    /// # Safety
+    /// The stack must have a frame.
+    pub unsafe fn last_frame(&self) -> StackFrame {
         *self.frames.last().unwrap_unchecked()
     }
 
-    pub unsafe fn call_enter_frame(
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Drops every frame above the first `depth` ones, returning the new last frame
+    ///
+    /// # Safety
+    /// `depth` must be at least 1 and at most the number of frames.
+    pub unsafe fn unwind(&mut self, depth: usize) -> StackFrame {
+        debug_assert!(depth > 0 && depth <= self.frames.len());
+
+        self.frames.truncate(depth);
+        let frame = self.last_frame();
+        self.values.resize(frame.end_idx);
+        frame
+    }
+
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    /// The address each frame is at, outermost first, when the innermost one is at `insc_ptr`:
+    /// callers are at the call they are waiting for
+    pub fn positions(&self, insc_ptr: usize) -> Vec<usize> {
+        self.frames.iter()
+            .skip(1)
+            .map(|frame| frame.ret_addr)
+            .chain(self.frames.last().map(|_| insc_ptr))
+            .collect()
+    }
+
+    pub fn values(&self) -> &[RtValue] {
+        &self.values
+    }
+
+    /// Replaces the whole stack
+    ///
+    /// # Safety
+    /// `frames` must be consistent with each other and with `values`.
+    pub unsafe fn restore(&mut self, values: &[RtValue], frames: &[StackFrame]) {
+        self.values.resize(values.len());
+        self.values.copy_from_slice(values);
+        self.frames.clear();
+        self.frames.extend_from_slice(frames);
+    }
+
+    /// Drops every frame, keeping the allocated buffers
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.values.resize(0);
+    }
+
+    /// Pushes a frame of `frame_size` slots for the call at `ret_addr`, with `args` of the current
+    /// frame copied to its first slots
+    ///
+    /// # Safety
+    /// The stack must have a frame, `args` must be slots of it and `frame_size` at least their
+    /// number.
+    pub unsafe fn call_enter_frame<S: Copy + Into<usize>>(
         &mut self,
         ret_addr: usize,
         frame_size: usize,
-        args: &[usize],
-        ret_locs: &'a [usize]
-    ) -> StackFrame<'a> {
+        args: &[S]
+    ) -> StackFrame {
         debug_assert!(!self.frames.is_empty());
 
         let last_frame = *(unsafe { self.frames.last().unwrap_unchecked() });
@@ -81,11 +198,11 @@ impl<'a> Stack<'a> {
 
         self.values.resize(end_idx);
 
-        let frame = StackFrame::new(ret_addr, start_idx, end_idx, ret_locs);
+        let frame = StackFrame::new(ret_addr, start_idx, end_idx);
         let args_count = args.len();
         for i in 0..args_count {
             let arg = unsafe { *args.get_unchecked(i) };
-            let value = last_frame.get_value(self, arg);
+            let value = last_frame.get_value(self, arg.into());
             frame.set_value(self, i, value);
         }
         self.frames.push(frame);
@@ -93,17 +210,29 @@ impl<'a> Stack<'a> {
         frame
     }
 
-    pub unsafe fn exit_frame(&mut self, rets: &[usize]) -> Option<(StackFrame<'a>, usize)> {
+    /// Pops the current frame, copying `rets` to the return locations of the call, which
+    /// `ret_locs` gives for the address of the call. Returns the caller frame with the address of
+    /// the call, or `None` if there is no caller.
+    ///
+    /// # Safety
+    /// The stack must have a frame, `rets` must be slots of it, and the caller must have a slot
+    /// at each of the first `rets.len()` return locations.
+    pub unsafe fn exit_frame<'c, S: Copy + Into<usize> + 'c>(
+        &mut self,
+        rets: &[S],
+        ret_locs: impl FnOnce(usize) -> &'c [S]
+    ) -> Option<(StackFrame, usize)> {
         debug_assert!(!self.frames.is_empty());
 
         let prev_frame = unsafe { self.frames.pop().unwrap_unchecked() };
         if let Some(&current_frame) = self.frames.last() {
+            let ret_locs = ret_locs(prev_frame.ret_addr);
             let rets_count = rets.len();
             for i in 0..rets_count {
                 let ret = unsafe { *rets.get_unchecked(i) };
-                let ret_loc = unsafe { *prev_frame.ret_locs.get_unchecked(i) };
-                let value = prev_frame.get_value(self, ret);
-                current_frame.set_value(self, ret_loc, value);
+                let ret_loc = unsafe { *ret_locs.get_unchecked(i) };
+                let value = prev_frame.get_value(self, ret.into());
+                current_frame.set_value(self, ret_loc.into(), value);
             }
             Some((current_frame, prev_frame.ret_addr))
         } else {
@@ -0,0 +1,125 @@
+use std::mem::size_of;
+
+use crate::r25_300::compiled::{Compiled, Function};
+use crate::r25_300::debug::DebugInfo;
+use crate::r25_300::insc::Insc;
+use crate::value::RtValue;
+
+/// The largest frame a function may have, in slots
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Checks that `compiled` can be run without touching memory outside of its stack frames and IO
+/// context: every frame has at most `MAX_FRAME_SIZE` slots, every slot is inside the frame of its
+/// function, every jump stays inside its function, calls match the functions and imports they
+/// refer to, and no function can run past its end.
+/// IO offsets are only checked against `io_size`, the size of the IO context in bytes, if given.
+pub fn verify(compiled: &Compiled, io_size: Option<usize>) -> Result<(), String> {
+    for func in compiled.func.iter() {
+        let in_code = func.addr.checked_add(func.code_len).is_some_and(|end| end <= compiled.code.len());
+        if func.code_len == 0 || !in_code {
+            return Err(format!("函数 `{}` 的代码范围无效", func.name));
+        }
+        if func.frame_size > MAX_FRAME_SIZE {
+            return Err(format!("函数 `{}` 的栈帧大小 {} 超过了上限 {}", func.name, func.frame_size, MAX_FRAME_SIZE));
+        }
+        if func.params > func.frame_size {
+            return Err(format!("函数 `{}` 的参数超出了栈帧", func.name));
+        }
+        if !matches!(compiled.code[func.addr + func.code_len - 1], Insc::Jmp { .. } | Insc::Return { .. }) {
+            return Err(format!("函数 `{}` 的最后一条指令既不是跳转也不是返回", func.name));
+        }
+
+        for insc_ptr in func.addr..func.addr + func.code_len {
+            verify_insc(compiled, func, insc_ptr, io_size)
+                .map_err(|e| format!("函数 `{}` 的指令 {}: {}", func.name, insc_ptr, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `debug` describes the functions of `compiled`: line entries are sorted and inside
+/// their function, and variables are in slots of its frame while it runs
+pub fn verify_debug(compiled: &Compiled, debug: &DebugInfo) -> Result<(), String> {
+    if debug.funcs.len() != compiled.func.len() {
+        return Err("调试信息中的函数个数不正确".to_string());
+    }
+
+    for (func, func_debug) in compiled.func.iter().zip(debug.funcs.iter()) {
+        let in_func = |start: usize, end: usize| func.addr <= start && start <= end && end <= func.addr + func.code_len;
+        let mut prev_end = func.addr;
+        for entry in func_debug.lines.iter() {
+            if entry.start < prev_end || !in_func(entry.start, entry.end) {
+                return Err(format!("函数 `{}` 的行号表无效", func.name));
+            }
+            prev_end = entry.end;
+        }
+        for local in func_debug.locals.iter() {
+            if local.slot >= func.frame_size || !in_func(local.start, local.end) {
+                return Err(format!("函数 `{}` 的变量 `{}` 的调试信息无效", func.name, local.name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_insc(compiled: &Compiled, func: &Function, insc_ptr: usize, io_size: Option<usize>) -> Result<(), String> {
+    let insc = &compiled.code[insc_ptr];
+    let mut bad_slot = None;
+    insc.for_each_slot(|slot| if slot >= func.frame_size {
+        bad_slot.get_or_insert(slot);
+    });
+    if let Some(slot) = bad_slot {
+        return Err(format!("槽位 %{} 超出了栈帧大小 {}", slot, func.frame_size));
+    }
+
+    let target = |dst: usize| if func.addr <= dst && dst < func.addr + func.code_len {
+        Ok(())
+    } else {
+        Err(format!("跳转目标 {} 不在函数中", dst))
+    };
+    let callee = |callee: usize, args: &[usize]| {
+        let Some(callee) = compiled.func.get(callee) else {
+            return Err(format!("函数 @{} 不存在", callee));
+        };
+        if args.len() != callee.params {
+            return Err(format!("调用函数 `{}` 的参数个数不正确", callee.name));
+        }
+        Ok(callee)
+    };
+
+    match insc {
+        Insc::Jmp { dst } | Insc::JmpIf { dst, .. } | Insc::TryBegin { handler: dst } => target(*dst),
+        Insc::Call { func: callee_id, args, ret_locs } => {
+            let callee = callee(*callee_id, args)?;
+            if ret_locs.len() != callee.rets {
+                return Err(format!("调用函数 `{}` 的返回值个数不正确", callee.name));
+            }
+            Ok(())
+        },
+        // the implicit return at the end of a function has no values
+        Insc::Return { rets } if !rets.is_empty() && rets.len() != func.rets =>
+            Err("返回值个数不正确".to_string()),
+        Insc::IOSetValue { offset, .. } | Insc::IOGetValue { offset, .. } => {
+            let in_ctx = io_size.is_none_or(|io_size| {
+                offset.checked_add(size_of::<RtValue>()).is_some_and(|end| end <= io_size)
+            });
+            if !offset.is_multiple_of(size_of::<RtValue>()) || !in_ctx {
+                return Err(format!("IO 偏移 !{} 无效", offset));
+            }
+            Ok(())
+        },
+        Insc::CallFFI { func: import, args, ret_locs } => {
+            let Some(import) = compiled.imports.get(*import) else {
+                return Err(format!("宿主函数 @{} 不存在", import));
+            };
+            if args.len() != import.signature.params.len() || ret_locs.len() != import.signature.rets.len() {
+                return Err(format!("调用宿主函数 `{}` 的参数或返回值个数不正确", import.name));
+            }
+            Ok(())
+        },
+        Insc::Spawn { func: callee_id, args, .. } => callee(*callee_id, args).map(|_| ()),
+        _ => Ok(())
+    }
+}
@@ -50,3 +50,20 @@ impl Hash for RtValue {
 }
 
 pub type RawFunction = unsafe fn(args: *mut RtValue, n_args: u32, rets: *mut RtValue);
+
+// Numeric conversions shared by the VM, consteval and the C backend (`common.inc`), so every
+// engine agrees on edge cases: float → int truncates toward zero, NaN converts to 0 and
+// out-of-range values saturate to `i32::MIN`/`i32::MAX`. float → bool is `x != 0.0`, so NaN is
+// `true` and -0.0 is `false`.
+
+#[inline(always)] pub fn float_to_int(f: f32) -> i32 { f as i32 }
+
+#[inline(always)] pub fn float_round_to_int(f: f32) -> i32 { f.round() as i32 }
+
+#[inline(always)] pub fn float_floor_to_int(f: f32) -> i32 { f.floor() as i32 }
+
+#[inline(always)] pub fn float_ceil_to_int(f: f32) -> i32 { f.ceil() as i32 }
+
+#[inline(always)] pub fn float_to_bool(f: f32) -> bool { f != 0.0 }
+
+#[inline(always)] pub fn bool_to_float(b: bool) -> f32 { if b { 1.0 } else { 0.0 } }
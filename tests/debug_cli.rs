@@ -0,0 +1,66 @@
+use std::fs::write;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_debug_cli() {
+    let source = r#"int square(int x) {
+    int y = x * x;
+    return y;
+}
+
+void entry() {
+    int i;
+    int total = 0;
+    for (i = 0; i < g_n; i = i + 1) {
+        total = total + square(i);
+    }
+    g_out = total;
+}
+"#;
+    let file = std::env::temp_dir().join(format!("pr21_debug_cli_{}.p21", std::process::id()));
+    write(&file, source).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pr21"))
+        .args(["debug", "--io", "g_n:int,g_out:int"])
+        .arg(&file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"\
+        set g_n 3\n\
+        break 2\n\
+        run\n\
+        bt\n\
+        locals 1\n\
+        print x\n\
+        next\n\
+        finish\n\
+        delete 2\n\
+        watch g_out\n\
+        c\n\
+        c\n\
+        bogus\n\
+    ").unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), [
+        "断点: 行 2 (指令 0)",
+        "断点: square 行 2: int y = x * x;",
+        "#0 square 行 2: int y = x * x;",
+        "#1 entry 行 10: total = total + square(i);",
+        "i: int = 0",
+        "total: int = 0",
+        "x = 0",
+        "square 行 3: return y;",
+        "entry 行 10: total = total + square(i);",
+        "监视点: g_out",
+        "监视点 g_out: 0 -> 5, entry 行 12: g_out = total;",
+        "脚本已结束",
+        "错误: 无法识别的命令 `bogus`"
+    ]);
+}
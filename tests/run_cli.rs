@@ -0,0 +1,53 @@
+use std::fs::{read_to_string, remove_file, write};
+use std::process::Command;
+
+#[test]
+fn test_run_profile() {
+    let source = r#"int square(int x) {
+    return x * x;
+}
+
+void entry() {
+    int i;
+    for (i = 0; i < 10; i = i + 1) {
+        g_n = g_n + square(i);
+    }
+    yield;
+    g_n = -g_n;
+}
+"#;
+    let dir = std::env::temp_dir();
+    let file = dir.join(format!("pr21_run_cli_{}.p21", std::process::id()));
+    let folded = dir.join(format!("pr21_run_cli_{}.folded", std::process::id()));
+    write(&file, source).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_pr21"))
+            .args(["run", "--io", "g_n:int"])
+            .args(args)
+            .arg(&file)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run(&[]), "g_n = -285\n");
+    assert_eq!(run(&["--frames", "1"]), "g_n = 285\n");
+
+    let report = run(&["--profile", "--folded", folded.to_str().unwrap()]);
+    let stacks = read_to_string(&folded).unwrap();
+    remove_file(&file).unwrap();
+    remove_file(&folded).unwrap();
+
+    let lines = report.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..2], ["g_n = -285", ""]);
+    let total = lines[2].strip_prefix("共执行 ").unwrap().strip_suffix(" 条指令").unwrap();
+    assert!(lines.iter().any(|line| line.trim_start().ends_with("%  square")));
+    assert!(lines.iter().any(|line| line.trim_start().ends_with("%  square:2")));
+
+    let weights = stacks.lines().map(|line| line.rsplit_once(' ').unwrap()).collect::<Vec<_>>();
+    assert_eq!(weights.iter().map(|(path, _)| *path).collect::<Vec<_>>(), ["entry", "entry;square"]);
+    let sum = weights.iter().map(|(_, count)| count.parse::<u64>().unwrap()).sum::<u64>();
+    assert_eq!(sum.to_string(), total);
+}